@@ -8,8 +8,13 @@ use anyhow::{Context, Error};
 
 use compile_shaders::{ShaderModel, ShaderType};
 
-static SOURCES: &[(&str, &str, &[(&str, &str)])] = &[
-    ("mask", "mask.hlsl", &[]),
+/// Each source file is compiled once for every variant, a variant being the output name
+/// and defines used for it. The outputs are `{name}.sm4.bin` and `{name}.sm5.bin`.
+static SOURCES: &[(&str, &[(&str, &[(&str, &str)])])] = &[
+    ("mask.hlsl", &[
+        ("mask", &[]),
+        ("mask_colorblind", &[("COLORBLIND", "1")]),
+    ]),
 ];
 
 fn main() {
@@ -17,32 +22,24 @@ fn main() {
     let out_path = Path::new(&out_path);
     assert!(out_path.exists());
     let shader_dir = Path::new("src/bw_scr/shaders");
-    for &(out_name, source, defines) in SOURCES.iter() {
+    for &(source, variants) in SOURCES.iter() {
         let source_path = shader_dir.join(source);
         println!("cargo:rerun-if-changed={}", source_path.to_str().unwrap());
-        let bin_path = out_path.join(&format!("{}.sm5.bin", out_name));
-        let asm_path = out_path.join(&format!("{}.sm5.asm", out_name));
-        compile_prism_shader(
-            &source_path,
-            &bin_path,
-            &asm_path,
-            defines,
-            shader_dir,
-            ShaderType::Pixel,
-            ShaderModel::Sm5,
-        ).unwrap_or_else(|e| panic!("Failed to compile {}: {:?}", out_name, e));
-
-        let bin_path = out_path.join(&format!("{}.sm4.bin", out_name));
-        let asm_path = out_path.join(&format!("{}.sm4.asm", out_name));
-        compile_prism_shader(
-            &source_path,
-            &bin_path,
-            &asm_path,
-            defines,
-            shader_dir,
-            ShaderType::Pixel,
-            ShaderModel::Sm4,
-        ).unwrap_or_else(|e| panic!("Failed to compile {}: {:?}", out_name, e));
+        for &(out_name, defines) in variants.iter() {
+            for &(model, model_name) in &[(ShaderModel::Sm5, "sm5"), (ShaderModel::Sm4, "sm4")] {
+                let bin_path = out_path.join(&format!("{}.{}.bin", out_name, model_name));
+                let asm_path = out_path.join(&format!("{}.{}.asm", out_name, model_name));
+                compile_prism_shader(
+                    &source_path,
+                    &bin_path,
+                    &asm_path,
+                    defines,
+                    shader_dir,
+                    ShaderType::Pixel,
+                    model,
+                ).unwrap_or_else(|e| panic!("Failed to compile {}: {:?}", out_name, e));
+            }
+        }
     }
 }
 
@@ -12,6 +12,7 @@ use winapi::um::d3dcommon::*;
 use winapi::um::winnt::HRESULT;
 use winapi::shared::winerror::{E_FAIL, S_OK};
 
+#[derive(Copy, Clone)]
 pub enum ShaderModel {
     Sm4,
     Sm5,
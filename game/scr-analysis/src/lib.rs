@@ -15,6 +15,9 @@ use scarf::exec_state::VirtualAddress as VirtualAddressTrait;
 
 pub type Patch = samase_scarf::Patch<VirtualAddress>;
 
+/// Amount of trigger actions that BW has, including the unused action 0.
+const TRIGGER_ACTION_COUNT: u32 = 60;
+
 pub struct Analysis<'e>(
     samase_scarf::Analysis<'e, ExecutionStateX86<'e>>,
     &'e BinaryFile<VirtualAddress>,
@@ -128,6 +131,11 @@ impl<'e> Analysis<'e> {
         self.0.local_player_id()
     }
 
+    /// Player whose commands are currently being processed.
+    pub fn command_user(&mut self) -> Option<Operand<'e>> {
+        self.0.command_user()
+    }
+
     pub fn local_storm_player_id(&mut self) -> Option<Operand<'e>> {
         self.0.single_player_start().local_storm_player_id
     }
@@ -241,6 +249,60 @@ impl<'e> Analysis<'e> {
         self.0.active_hidden_units().first_active_unit
     }
 
+    pub fn game_speed(&mut self) -> Option<Operand<'e>> {
+        self.eud(0x006cdfd4).map(|x| self.mem_word(x))
+    }
+
+    pub fn is_game_paused(&mut self) -> Option<Operand<'e>> {
+        self.eud(0x006509c4).map(|x| self.mem_word(x))
+    }
+
+    /// Array of milliseconds per frame for each game speed, for both normal and
+    /// alternate speeds.
+    pub fn game_speed_frame_delays(&mut self) -> Option<Operand<'e>> {
+        self.eud(0x005124d8)
+    }
+
+    /// Vision masks that are used for the local player while watching a replay,
+    /// and while playing.
+    pub fn replay_visions(&mut self) -> Option<Operand<'e>> {
+        self.eud(0x006d0f18).map(|x| self.mem_word(x))
+    }
+
+    pub fn player_visions(&mut self) -> Option<Operand<'e>> {
+        self.eud(0x0057f0b0).map(|x| self.mem_word(x))
+    }
+
+    /// Address of the trigger action function table, and the functions in it.
+    pub fn trigger_actions(&mut self) -> Option<(VirtualAddress, Vec<VirtualAddress>)> {
+        let table = self.0.trigger_actions()?;
+        let functions = (0..TRIGGER_ACTION_COUNT)
+            .map(|i| self.1.read_address(table + i * VirtualAddress::SIZE).ok())
+            .collect::<Option<Vec<_>>>()?;
+        Some((table, functions))
+    }
+
+    /// The player whose triggers are currently being executed.
+    pub fn trigger_current_player(&mut self) -> Option<Operand<'e>> {
+        self.eud(0x006509b0).map(|x| self.mem_word(x))
+    }
+
+    pub fn map_tile_flags(&mut self) -> Option<Operand<'e>> {
+        self.eud(0x006d1260).map(|x| self.mem_word(x))
+    }
+
+    pub fn client_selection(&mut self) -> Option<Operand<'e>> {
+        self.eud(0x00597208)
+    }
+
+    pub fn screen_x(&mut self) -> Option<Operand<'e>> {
+        self.eud(0x0062848c).map(|x| self.mem_word(x))
+    }
+
+    pub fn screen_y(&mut self) -> Option<Operand<'e>> {
+        self.eud(0x006284a8).map(|x| self.mem_word(x))
+    }
+
     pub fn sprites_by_y_tile_start(&mut self) -> Option<Operand<'e>> {
         self.0.sprites().sprite_hlines
     }
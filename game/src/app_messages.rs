@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
@@ -36,7 +37,117 @@ pub struct WindowMove {
     pub y: i32,
 }
 
-#[derive(Serialize, Copy, Clone)]
+#[derive(Serialize)]
+pub struct FirstContact {
+    pub scouter: u8,
+    pub scouted: u8,
+    pub frame: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnitMorph {
+    pub player: u8,
+    pub from_id: u16,
+    pub to_id: u16,
+    pub frame: u32,
+}
+
+#[derive(Serialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ResourceKind {
+    Mineral,
+    Geyser,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceNode {
+    pub kind: ResourceKind,
+    pub unit_id: u16,
+    pub x: i16,
+    pub y: i16,
+    pub amount: u16,
+}
+
+#[derive(Serialize)]
+pub struct ResourceLayout {
+    pub resources: Vec<ResourceNode>,
+}
+
+#[derive(Serialize)]
+pub struct ReplaySaved {
+    pub path: String,
+}
+
+#[derive(Serialize)]
+pub struct ActionBucket {
+    pub selection: u32,
+    pub hotkey: u32,
+    pub production: u32,
+    pub order: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerActionBuckets {
+    pub player: u8,
+    pub bucket_frames: u32,
+    pub buckets: Vec<ActionBucket>,
+}
+
+#[derive(Serialize)]
+pub struct ActionTimeline {
+    pub players: Vec<PlayerActionBuckets>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerResync {
+    pub storm_id: u8,
+    pub stalled_frames: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpectatorChanged {
+    pub storm_id: u8,
+    pub joined: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameplayStarted {
+    pub frame: u32,
+    pub unix_time: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FirstBuilding {
+    pub player: u8,
+    pub unit_id: u32,
+    pub x: i16,
+    pub y: i16,
+    pub frame: u32,
+}
+
+#[derive(Serialize)]
+pub struct SpeedChanged {
+    pub frame: u32,
+    pub speed: crate::game_thread::GameSpeed,
+}
+
+#[derive(Serialize)]
+pub struct ProxyDetected {
+    pub by: u8,
+    pub target: u8,
+    pub x: i16,
+    pub y: i16,
+    pub frame: u32,
+}
+
+#[derive(Serialize, Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Race {
     #[serde(rename = "z")]
     Zerg,
@@ -47,17 +158,82 @@ pub enum Race {
 }
 
 #[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct GamePlayerResult {
     pub result: u8,
     pub race: Race,
     pub apm: u32,
+    /// APM that doesn't count quickly repeated identical commands.
+    pub eapm: u32,
+    /// (upgrade_id, level) pairs of upgrades with a nonzero level.
+    pub upgrades: Vec<(u8, u8)>,
+    /// Bits of researched tech ids.
+    pub researched: u64,
+    pub eliminated_frame: Option<u32>,
+    /// 0 if the game didn't have teams.
+    pub team: u8,
+    pub control_group_assigns: u32,
+    pub control_group_recalls: u32,
+    pub supply_blocked_frames: u32,
+    /// Frames on which town halls other than the starting one were completed.
+    pub expansion_frames: Vec<u32>,
+    /// Name BW had for the player, may differ from the lobby name.
+    pub ingame_name: String,
+    /// Palette index of the player's color, same as in `PlayerColors`.
+    pub color: u8,
+    /// Estimated amount of tiles with the player's creep at the end, 0 for non-Zerg.
+    pub creep_tiles: u32,
+    pub resources_gathered: u32,
+    /// Units that the player killed and lost, buildings included.
+    pub units_killed: u32,
+    pub units_lost: u32,
+    /// Cost of the player's army units that died, workers and buildings excluded.
+    pub resources_lost: u32,
 }
 
 #[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct GameResults {
+    /// Game time based on frames played, see `GameThreadResults::game_time_ms`.
     #[serde(rename = "time")]
     pub time_ms: u32,
+    /// Real time that the game ran for, including pauses and lag.
+    pub wall_time_ms: u32,
     pub results: HashMap<String, GamePlayerResult>,
+    /// Ingame player ids of computer players. They aren't in `results` and aren't
+    /// counted when deciding whether the humans won.
+    pub computer_players: Vec<u8>,
+    /// The game was still in progress when these results were taken.
+    pub partial: bool,
+    pub end_reason: crate::game_thread::EndReason,
+    pub minimap: Option<Minimap>,
+    pub gameplay_start_frame: u32,
+    pub game_fingerprint: String,
+    pub paused_ms: u32,
+    pub lobby_type: LobbyType,
+    pub is_matchmaking: bool,
+    pub matchmaking_type: Option<String>,
+    pub map_pool_id: Option<u32>,
+    /// Seed of BW's RNG at the start of the game.
+    pub random_seed: u32,
+    /// Network turn rate, 0 if dynamic.
+    pub turn_rate: u8,
+    /// (team, result) pairs, with results using same values as `GamePlayerResult::result`.
+    /// Empty if the game type doesn't have teams.
+    pub team_results: Vec<(u8, u8)>,
+    /// (ingame player id, score) pairs for a post-game summary, highest score first.
+    pub rankings: Vec<(u8, f32)>,
+}
+
+/// Minimap at the end of the game, usable as a thumbnail.
+#[derive(Serialize)]
+pub struct Minimap {
+    pub width: u16,
+    pub height: u16,
+    /// Palette indices of units, same as the colors in `PlayerColors`. 0 if no units.
+    pub units: Vec<u8>,
+    /// Ground level of each tile (0-3), with 0x40 set for unwalkable and 0x80 for creep.
+    pub terrain: Vec<u8>,
 }
 
 #[derive(Serialize)]
@@ -83,6 +259,134 @@ pub struct GameSetupInfo {
     pub game_id: String,
     pub result_code: String,
     pub server_url: String,
+    /// If set, the final frame of the game is saved to user data directory once the game ends.
+    #[serde(default)]
+    pub screenshot_on_finish: bool,
+    /// If set, `/game/triggerFired` is sent for every executed trigger action in UMS games.
+    #[serde(default)]
+    pub report_triggers: bool,
+    /// If set, game results include a minimap of the game state when the game ended.
+    #[serde(default)]
+    pub results_minimap: bool,
+    /// If set, low priority messages from game thread are dropped when the async side has
+    /// this many messages that it hasn't handled yet.
+    #[serde(default)]
+    pub game_message_queue_limit: Option<usize>,
+    /// Debug option to report workers that seem to be stuck while moving or gathering.
+    #[serde(default)]
+    pub debug_pathing: bool,
+    /// Debug option to send `/game/stateHash` every frame. Expensive.
+    #[serde(default)]
+    pub debug_state_hash: bool,
+    /// Sends game thread log messages relevant to this game to `/game/log`, so they
+    /// can be stored with the game's results.
+    #[serde(default)]
+    pub game_log: bool,
+    /// If set, every player command is recorded, up to this many bytes, and written
+    /// to a file that is reported with `/game/commandLog` at the end of the game.
+    #[serde(default)]
+    pub command_log_limit: Option<u32>,
+    /// Pauses the game while its window isn't focused. Ignored in multiplayer games.
+    #[serde(default)]
+    pub pause_on_unfocus: bool,
+    /// Runs the game without rendering anything and as fast as possible, for
+    /// extracting data from replays. Only fully supported on 1.16.1; SC:R still
+    /// renders and runs at normal speed.
+    #[serde(default)]
+    pub headless: bool,
+    /// Skips creating fog sprites for neutral buildings, leaving them as BW would
+    /// normally show them. Meant for headless analysis where they'd only be overhead.
+    #[serde(default)]
+    pub disable_fow_sprites: bool,
+    /// Unit ids of neutral units that get fog sprites, instead of every neutral building.
+    #[serde(default)]
+    pub fow_sprite_unit_ids: Option<Vec<u16>>,
+    /// Gives observers vision of every player when the game starts, which they can then
+    /// toggle per player like in replays. Also keeps fog sprites of neutral buildings
+    /// around for observers when they turn vision off, as is done for replays.
+    #[serde(default)]
+    pub observer_full_vision: bool,
+    /// Inclusive range of UDP ports that the network socket may use. The first port in
+    /// the range that can be bound is used; the game fails to start if none can.
+    #[serde(default)]
+    pub port_range: Option<(u16, u16)>,
+    /// Overrides the default limits for packets received from other players.
+    #[serde(default)]
+    pub net_abuse_limits: Option<crate::snp::NetAbuseLimits>,
+    /// Encoding of packets sent to other players, has to be same for everyone in the game.
+    #[serde(default)]
+    pub wire_format: crate::snp::WireFormatKind,
+    /// Network turn rate, one of `bw::TURN_RATES`. Uses BW's default (dynamic) if not set.
+    /// Only supported on SC:R.
+    #[serde(default)]
+    pub turn_rate: Option<u8>,
+    /// Shows the game window minimized or offscreen instead of bringing it to the front.
+    #[serde(default)]
+    pub window_placement: crate::forge::WindowPlacement,
+    /// Copies every game's replay to this directory once the game ends.
+    #[serde(default)]
+    pub auto_save_replay_dir: Option<PathBuf>,
+    /// Renders at this multiple of the window size, see `forge::set_render_scale`.
+    #[serde(default)]
+    pub render_scale: Option<f32>,
+    /// Limits rendering to this many frames per second, see `forge::set_frame_cap`.
+    #[serde(default)]
+    pub frame_cap: Option<u32>,
+    /// Sends `/game/presented` after every frame drawn to the window, for overlays.
+    #[serde(default)]
+    pub present_ticks: bool,
+    /// Makes result time count from the first player action instead of frame 0, so that
+    /// replays with idle frames at start have the same duration as the live game.
+    #[serde(default)]
+    pub time_from_gameplay_start: bool,
+    /// Sends `/game/playerIdle` for players who haven't done anything in this many seconds.
+    #[serde(default)]
+    pub idle_threshold_seconds: Option<u32>,
+    /// Milestones to report with `/game/techMilestone` in addition to the default ones.
+    #[serde(default)]
+    pub extra_milestones: Vec<crate::game_thread::milestones::Rule>,
+    /// Replaces starting minerals and gas of each ingame player (After randomization,
+    /// indices match `PlayersRandomized`). Ignored in replays and matchmaking games.
+    #[serde(default)]
+    pub starting_resources: Option<[ResourceOverride; 8]>,
+    /// Handicap of each ingame player, 0 being no handicap. Not allowed in matchmaking
+    /// games. BW has no handicap mechanic that could be set, so any non-zero handicap
+    /// currently fails the game init.
+    #[serde(default)]
+    pub handicaps: Option<[u8; 8]>,
+    #[serde(default)]
+    pub lobby_type: LobbyType,
+    /// Matchmaking queue that the game was found from, only set for matchmaking games.
+    #[serde(default)]
+    pub matchmaking_type: Option<String>,
+    /// Map pool that the map was picked from, only set for matchmaking games.
+    #[serde(default)]
+    pub map_pool_id: Option<u32>,
+}
+
+/// `None` keeps the amount BW would have given.
+#[derive(Deserialize, Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[serde(default)]
+pub struct ResourceOverride {
+    pub minerals: Option<u32>,
+    pub gas: Option<u32>,
+}
+
+/// BW treats resource amounts as signed in places, so anything above this could
+/// show up as negative.
+pub const MAX_RESOURCE_AMOUNT: u32 = i32::max_value() as u32;
+
+#[derive(Deserialize, Serialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum LobbyType {
+    Custom,
+    Matchmaking,
+}
+
+impl Default for LobbyType {
+    fn default() -> LobbyType {
+        LobbyType::Custom
+    }
 }
 
 #[derive(Deserialize)]
@@ -183,3 +487,401 @@ pub struct RallyPointServer {
     pub port: u16,
     pub desc: String,
 }
+
+/// BW features that the app can use in this game, see `bw::Capabilities`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    pub trigger_reports: bool,
+    pub send_chat: bool,
+    pub palette_kinds: bool,
+    pub disable_frame_delay: bool,
+    pub pause: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendChat {
+    pub message: String,
+    pub recipients: ChatRecipients,
+}
+
+/// `"all"`, `"allies"` or `{ "player": stormId }`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChatRecipients {
+    All,
+    Allies,
+    Player(u8),
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PathingStuck {
+    pub unit_id: u32,
+    pub x: i16,
+    pub y: i16,
+    pub frame: u32,
+}
+
+#[derive(Serialize)]
+pub struct Warning {
+    pub message: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HookStates {
+    pub request_id: u64,
+    pub hooks: Vec<HookState>,
+}
+
+#[derive(Serialize)]
+pub struct HookState {
+    pub hook: crate::game_thread::frame_hooks::HookId,
+    pub enabled: bool,
+}
+
+#[derive(Serialize)]
+pub struct BwError {
+    pub text: String,
+}
+
+#[derive(Serialize)]
+pub struct WindowHandle {
+    pub hwnd: u64,
+}
+
+#[derive(Serialize)]
+pub struct Presented {
+    pub frame: u32,
+}
+
+#[derive(Serialize)]
+pub struct NetBound {
+    pub port: u16,
+}
+
+#[derive(Serialize)]
+pub struct InitFailed {
+    pub reason: String,
+}
+
+#[derive(Serialize)]
+pub struct ClipMarker {
+    pub frame: u32,
+}
+
+#[derive(Serialize)]
+pub struct PlayerAlert {
+    pub kind: crate::game_thread::AlertKind,
+    pub x: i16,
+    pub y: i16,
+    pub frame: u32,
+}
+
+#[derive(Serialize)]
+pub struct StateHash {
+    pub frame: u32,
+    /// Hex string, as JavaScript numbers can't hold all u64 values.
+    pub hash: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayIncompatible {
+    pub replay_version: crate::replay::ReplayFormat,
+    pub game_version: crate::replay::ReplayFormat,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameLoopStall {
+    pub since_ms: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameLoopResumed {
+    pub stalled_ms: u64,
+}
+
+#[derive(Serialize)]
+pub struct GameLog {
+    pub level: String,
+    pub target: &'static str,
+    pub message: String,
+    pub frame: Option<u32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionQuality {
+    pub storm_id: u8,
+    pub late_turns: u32,
+    pub dropped_turns: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetAbuse {
+    pub storm_id: u8,
+    pub reason: String,
+}
+
+#[derive(Serialize)]
+pub struct LiveApm {
+    pub frame: u32,
+    /// Indexed by ingame player id.
+    pub apm: [u16; 8],
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerIdle {
+    pub storm_id: u8,
+    pub idle_ms: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerActive {
+    pub storm_id: u8,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GamePaused {
+    pub by_storm_id: u8,
+    pub paused: bool,
+    pub frame: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelectedUnit {
+    pub unit_id: u16,
+    pub order: u8,
+    pub target_unit_id: Option<u16>,
+    pub target_x: i16,
+    pub target_y: i16,
+}
+
+#[derive(Serialize)]
+pub struct SelectionDetails {
+    pub frame: u32,
+    pub units: Vec<SelectedUnit>,
+}
+
+/// Only the position is reported; SC:R's zoom level isn't known, so the screen is
+/// assumed to show BW's normal view.
+#[derive(Serialize)]
+pub struct CameraMove {
+    pub frame: u32,
+    pub x: i16,
+    pub y: i16,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IncomeRate {
+    pub frame: u32,
+    /// Indexed by ingame player id.
+    pub minerals_per_min: [u32; 8],
+    pub gas_per_min: [u32; 8],
+}
+
+#[derive(Serialize)]
+pub struct ArmyValue {
+    pub frame: u32,
+    /// Indexed by ingame player id.
+    pub values: [u32; 8],
+}
+
+#[derive(Serialize)]
+pub struct TriggerFired {
+    pub player: u8,
+    pub action: u16,
+    pub frame: u32,
+}
+
+#[derive(Serialize)]
+pub struct TechMilestone {
+    pub player: u8,
+    pub milestone: crate::game_thread::milestones::Milestone,
+    pub frame: u32,
+}
+
+#[derive(Serialize)]
+pub struct ActualSetup {
+    pub controllers: [u8; 8],
+    pub races: [Option<Race>; 8],
+    pub teams: [u8; 8],
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameStartMetadata {
+    pub map_tile_width: u16,
+    pub map_tile_height: u16,
+    pub random_seed: u32,
+    pub turn_rate: u8,
+    pub game_fingerprint: String,
+}
+
+#[derive(Serialize)]
+pub struct PlayerColors {
+    /// Palette index for each ingame player id.
+    pub colors: [u8; 8],
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceDepleted {
+    pub unit_id: u32,
+    pub x: i16,
+    pub y: i16,
+    pub frame: u32,
+}
+
+#[derive(Serialize)]
+pub struct Screenshot {
+    pub path: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CurrentFrame {
+    pub request_id: u64,
+    pub frame: u32,
+}
+
+/// See `game_thread::GameStateSnapshot`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CurrentGameState {
+    pub request_id: u64,
+    pub frame: u32,
+    /// `None` if the BW version doesn't support reading these.
+    pub paused: Option<bool>,
+    pub speed: Option<u8>,
+    pub lagging: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnitSnapshots {
+    pub request_id: u64,
+    pub units: Vec<UnitSnapshot>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnitDump {
+    pub request_id: u64,
+    pub dump: String,
+}
+
+/// Hitpoints, shields and energy are in 1/256ths, like BW stores them.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnitSnapshot {
+    pub unit_id: u16,
+    pub player: u8,
+    pub x: i16,
+    pub y: i16,
+    pub hitpoints: i32,
+    pub shields: i32,
+    pub energy: u16,
+    pub order: u8,
+    pub target_unit_id: Option<u16>,
+    pub target_x: Option<i16>,
+    pub target_y: Option<i16>,
+}
+
+/// A tool asking for information about a replay file, answered without involving BW.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayRequest {
+    pub request_id: Option<u64>,
+    pub path: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtractReplayMap {
+    pub request_id: Option<u64>,
+    pub replay_path: String,
+    pub out_path: String,
+}
+
+/// Reply to replay requests that only report whether they succeeded, such as
+/// `ExtractReplayMap`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayRequestResult {
+    pub request_id: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayHeaderReply {
+    pub request_id: Option<u64>,
+    /// `None` if the replay couldn't be read, `error` has the reason then.
+    pub header: Option<ReplayHeader>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayChatReply {
+    pub request_id: Option<u64>,
+    /// Empty if the replay couldn't be read, `error` has the reason then.
+    pub messages: Vec<ReplayChatMessage>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayChatMessage {
+    pub frame: u32,
+    pub storm_id: u8,
+    pub text: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayHeader {
+    pub format: crate::replay::ReplayFormat,
+    pub frames: u32,
+    pub game_title: String,
+    pub map_name: String,
+    pub map_tile_width: u16,
+    pub map_tile_height: u16,
+    pub players: Vec<ReplayPlayer>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayPlayer {
+    pub player_id: u8,
+    pub name: String,
+    pub race: Option<Race>,
+    pub team: u8,
+    pub is_computer: bool,
+}
+
+#[derive(Serialize)]
+pub struct CommandLog {
+    pub path: String,
+    pub truncated: bool,
+}
+
+/// Reply to a game request that can only succeed or fail.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestResult {
+    pub request_id: u64,
+    pub error: Option<String>,
+}
@@ -1,3 +1,4 @@
+use std::path::Path;
 use std::time::{Duration};
 
 use futures::select;
@@ -10,8 +11,13 @@ use tokio_tungstenite::tungstenite;
 use tokio_tungstenite::tungstenite::handshake::client::Response as HandshakeResponse;
 use tokio_tungstenite::tungstenite::Message as WsMessage;
 
+use crate::app_messages::{
+    self, ExtractReplayMap, ReplayChatReply, ReplayHeaderReply, ReplayRequest,
+    ReplayRequestResult,
+};
 use crate::cancel_token::SharedCanceler;
 use crate::game_state::{self, GameStateMessage};
+use crate::replay;
 
 pub type SendMessages = mpsc::Sender<WsMessage>;
 
@@ -161,9 +167,99 @@ fn handle_app_message<'a>(text: String) -> Result<MessageResult, HandleMessageEr
         "allowStart" => {
             Ok(MessageResult::Game(GameStateMessage::AllowStart))
         }
+        "replayHeader" => {
+            let request: ReplayRequest =
+                serde_json::from_value(payload).context(("Invalid replay request", &*text))?;
+            reply("/replay/header", replay_header(request))
+        }
+        "replayChat" => {
+            let request: ReplayRequest =
+                serde_json::from_value(payload).context(("Invalid replay request", &*text))?;
+            reply("/replay/chat", replay_chat(request))
+        }
+        "extractReplayMap" => {
+            let request: ExtractReplayMap =
+                serde_json::from_value(payload).context(("Invalid replay request", &*text))?;
+            let result =
+                replay::extract_map(Path::new(&request.replay_path), Path::new(&request.out_path));
+            let reply_data = ReplayRequestResult {
+                request_id: request.request_id,
+                error: result.err().map(|e| e.to_string()),
+            };
+            reply("/replay/mapExtracted", reply_data)
+        }
         "quit" => Ok(MessageResult::Stop),
         "cleanup_and_quit" => Ok(MessageResult::Game(GameStateMessage::CleanupQuit)),
-        _ => Err(HandleMessageError::UnknownCommand(message.command)),
+        "resetForNextGame" => Ok(MessageResult::Game(GameStateMessage::ResetForNextGame)),
+        _ => match game_state::app_game_request(&message.command, payload) {
+            Some(Ok((request, request_id))) => {
+                Ok(MessageResult::Game(GameStateMessage::GameRequest(request, request_id)))
+            }
+            Some(Err(e)) => Err(HandleMessageError::Serde(e, "Invalid request", text)),
+            None => Err(HandleMessageError::UnknownCommand(message.command)),
+        },
+    }
+}
+
+fn reply<T: Serialize>(command: &str, data: T) -> Result<MessageResult, HandleMessageError> {
+    encode_message(command, data)
+        .map(MessageResult::WebSocket)
+        .ok_or(HandleMessageError::EncodeReply)
+}
+
+fn replay_header(request: ReplayRequest) -> ReplayHeaderReply {
+    let (header, error) = match replay::parse_header(Path::new(&request.path)) {
+        Ok(header) => {
+            let players = header
+                .players
+                .into_iter()
+                .map(|player| app_messages::ReplayPlayer {
+                    player_id: player.player_id.0,
+                    name: player.name,
+                    race: player.race,
+                    team: player.team,
+                    is_computer: player.is_computer,
+                })
+                .collect();
+            let header = app_messages::ReplayHeader {
+                format: header.format,
+                frames: header.frames,
+                game_title: header.game_title,
+                map_name: header.map_name,
+                map_tile_width: header.map_tile_width,
+                map_tile_height: header.map_tile_height,
+                players,
+            };
+            (Some(header), None)
+        }
+        Err(e) => (None, Some(e.to_string())),
+    };
+    ReplayHeaderReply {
+        request_id: request.request_id,
+        header,
+        error,
+    }
+}
+
+fn replay_chat(request: ReplayRequest) -> ReplayChatReply {
+    let (messages, error) = match replay::extract_chat(Path::new(&request.path)) {
+        Ok(messages) => {
+            let messages = messages
+                .into_iter()
+                .map(|x| app_messages::ReplayChatMessage {
+                    frame: x.frame,
+                    storm_id: x.storm_id.0,
+                    text: x.text,
+                })
+                .collect();
+            (messages, None)
+        }
+        Err(e) => (Vec::new(), Some(e.to_string())),
+    };
+    ReplayChatReply {
+        request_id: request.request_id,
+        messages,
+        error,
     }
 }
 
@@ -177,6 +273,9 @@ quick_error! {
         UnknownCommand(cmd: String) {
             display("Unknown command '{}'", cmd)
         }
+        EncodeReply {
+            display("Couldn't encode reply")
+        }
     }
 }
 
@@ -1,5 +1,7 @@
+pub mod commands;
 pub mod list;
 pub mod unit;
+pub mod unit_costs;
 
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
@@ -19,6 +21,192 @@ pub fn set_bw_impl(bw: Arc<dyn Bw>) {
     *BW_IMPL.write().unwrap() = Some(bw);
 }
 
+/// Copy of the commonly inspected state of an unit, safe to pass outside game thread.
+#[derive(Clone, Debug)]
+pub struct UnitSnapshot {
+    pub unit_id: u16,
+    pub player: u8,
+    pub position: Point,
+    /// Hitpoints, shields and energy are in 1/256ths, like BW stores them.
+    pub hitpoints: i32,
+    pub shields: i32,
+    pub energy: u16,
+    pub order: u8,
+    /// Unit id and position of the order target, if there is one.
+    pub target: Option<(u16, Point)>,
+}
+
+/// What a selected unit is doing, see `selection_orders`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct UnitOrder {
+    pub unit_id: u16,
+    pub order: u8,
+    pub target_unit_id: Option<u16>,
+    pub target_pos: Point,
+}
+
+/// BW features that only some versions implement, sent to the app once BW has been
+/// initialized so that it can disable what isn't available.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Capabilities {
+    /// Whether executed trigger actions get reported to `game_thread::on_trigger_action`.
+    pub trigger_reports: bool,
+    /// Whether `Bw::send_chat` can send messages.
+    pub send_chat: bool,
+    /// Whether `Bw::set_palette` adjusts colors separately for each `PaletteKind`.
+    /// If not, all kinds other than `Default` only make the fog of war easier to see.
+    pub palette_kinds: bool,
+    /// Whether `Bw::disable_frame_delay` works, letting headless games run faster
+    /// than the game speed.
+    pub disable_frame_delay: bool,
+    /// Whether `Bw::set_paused` works, e.g. for pausing when the window loses focus.
+    pub pause: bool,
+}
+
+/// Network turn rates that SC:R allows choosing, 0 being dynamic turn rate.
+pub const TURN_RATES: &[u8] = &[0, 8, 12, 16, 20, 24];
+
+/// Turn rate that the lobby was created or joined with, see `Bw::turn_rate`.
+pub fn turn_rate() -> u8 {
+    with_bw(|bw| bw.turn_rate())
+}
+
+/// Supply counts of a single race, in BW's half-supply units (A zergling is 1).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Supply {
+    pub used: u32,
+    pub provided: u32,
+    /// The 200 supply cap, unless a map changes it.
+    pub max: u32,
+}
+
+/// Supply of the player's own race. `None` if the player's race isn't valid.
+pub unsafe fn player_supply(player: u8) -> Option<Supply> {
+    let (game, players) = with_bw(|bw| (bw.game(), bw.players()));
+    let race = (*players.add(player as usize)).race as usize;
+    // Copying, as `Game` is packed and can't be referenced into.
+    let supplies = (*game).supplies;
+    let supplies = supplies.get(race)?;
+    let player = player as usize;
+    Some(Supply {
+        used: supplies.used[player],
+        provided: supplies.provided[player],
+        max: supplies.max[player],
+    })
+}
+
+/// Map width and height in tiles.
+pub unsafe fn map_dimensions() -> (u16, u16) {
+    let game = with_bw(|bw| bw.game());
+    ((*game).map_width_tiles, (*game).map_height_tiles)
+}
+
+/// Flags in `Bw::map_tile_flags`. The low 16 bits are visibility and exploration.
+pub const TILE_UNWALKABLE: u32 = 0x0004_0000;
+pub const TILE_CREEP: u32 = 0x0040_0000;
+pub const TILE_VERY_HIGH: u32 = 0x0100_0000;
+pub const TILE_MIDDLE: u32 = 0x0200_0000;
+pub const TILE_HIGH: u32 = 0x0400_0000;
+
+/// Bits of `Minimap::terrain` in addition to the ground level.
+pub const MINIMAP_UNWALKABLE: u8 = 0x40;
+pub const MINIMAP_CREEP: u8 = 0x80;
+
+/// One pixel per map tile, rows from top to bottom.
+pub struct Minimap {
+    pub width: u16,
+    pub height: u16,
+    /// Minimap color (palette index) of the player owning a unit on the tile,
+    /// 0 if there are no units.
+    pub units: Vec<u8>,
+    /// Ground level of the tile (0 low, 1 middle, 2 high, 3 very high), with
+    /// `MINIMAP_UNWALKABLE` and `MINIMAP_CREEP` bits.
+    pub terrain: Vec<u8>,
+}
+
+/// Draws a minimap of the current game from BW's map tile flags and unit positions.
+///
+/// This doesn't read BW's own minimap, as SC:R renders it on GPU, so terrain is given
+/// as ground levels for the app to color, and units using their player's minimap color.
+/// `None` if the BW version doesn't have the tile flags.
+pub unsafe fn render_minimap() -> Option<Minimap> {
+    let (game, units, tile_flags) =
+        with_bw(|bw| (bw.game(), bw.active_units(), bw.map_tile_flags()));
+    if game.is_null() || tile_flags.is_null() {
+        return None;
+    }
+    let (width, height) = ((*game).map_width_tiles, (*game).map_height_tiles);
+    let tile_count = width as usize * height as usize;
+    let terrain = std::slice::from_raw_parts(tile_flags, tile_count)
+        .iter()
+        .map(|&flags| minimap_terrain(flags))
+        .collect();
+    let mut pixels = vec![0u8; tile_count];
+    for unit in units {
+        let player = unit.player() as usize;
+        let position = (**unit).position;
+        let x = (position.x.max(0) as usize / 32).min(width.saturating_sub(1) as usize);
+        let y = (position.y.max(0) as usize / 32).min(height.saturating_sub(1) as usize);
+        if let Some(&color) = (*game).player_minimap_color.get(player) {
+            pixels[y * width as usize + x] = color;
+        }
+    }
+    Some(Minimap {
+        width,
+        height,
+        units: pixels,
+        terrain,
+    })
+}
+
+fn minimap_terrain(tile_flags: u32) -> u8 {
+    let level = if tile_flags & TILE_VERY_HIGH != 0 {
+        3
+    } else if tile_flags & TILE_HIGH != 0 {
+        2
+    } else if tile_flags & TILE_MIDDLE != 0 {
+        1
+    } else {
+        0
+    };
+    let unwalkable = match tile_flags & TILE_UNWALKABLE != 0 {
+        true => MINIMAP_UNWALKABLE,
+        false => 0,
+    };
+    let creep = match tile_flags & TILE_CREEP != 0 {
+        true => MINIMAP_CREEP,
+        false => 0,
+    };
+    level | unwalkable | creep
+}
+
+pub unsafe fn unit_snapshot(unit: unit::Unit) -> UnitSnapshot {
+    let target = unit.target().map(|x| (x.id(), (**x).position));
+    UnitSnapshot {
+        unit_id: unit.id(),
+        player: unit.player(),
+        position: (**unit).position,
+        hitpoints: (**unit).hitpoints,
+        shields: (**unit).shields,
+        energy: (**unit).energy,
+        order: (**unit).order,
+        target,
+    }
+}
+
+/// Orders of the units that the local player has selected.
+pub unsafe fn selection_orders() -> Vec<UnitOrder> {
+    with_bw(|bw| bw.client_selection())
+        .into_iter()
+        .map(|unit| UnitOrder {
+            unit_id: unit.id(),
+            order: unit.order(),
+            target_unit_id: unit.target().map(|x| x.id()),
+            target_pos: unit.order_target_pos(),
+        })
+        .collect()
+}
+
 lazy_static::lazy_static! {
     static ref BW_IMPL: RwLock<Option<Arc<dyn Bw>>> = RwLock::new(None);
 }
@@ -30,6 +218,15 @@ lazy_static::lazy_static! {
 /// structures that are more versatile.
 pub trait Bw: Sync + Send {
     unsafe fn run_game_loop(&self);
+    /// Makes the game advance frames as fast as possible instead of waiting for the
+    /// game speed's frame delay. Returns false if not supported.
+    unsafe fn disable_frame_delay(&self) -> bool;
+    /// Pauses or unpauses the game locally, without showing BW's pause menu.
+    /// Only valid for single player games and replays. Returns false if not supported.
+    unsafe fn set_paused(&self, paused: bool) -> bool;
+    /// `None` if the BW version doesn't support reading these.
+    unsafe fn is_paused(&self) -> Option<bool>;
+    unsafe fn game_speed(&self) -> Option<u8>;
     unsafe fn clean_up_for_exit(&self);
     unsafe fn init_sprites(&self);
     unsafe fn remaining_game_init(&self, local_player_name: &str);
@@ -37,6 +234,16 @@ pub trait Bw: Sync + Send {
     unsafe fn init_game_network(&self);
     unsafe fn do_lobby_game_init(&self, seed: u32);
     unsafe fn try_finish_lobby_game_init(&self) -> bool;
+    /// Random seed from the lobby game init command that BW used to start the game.
+    /// Valid once the game has been initialized.
+    unsafe fn game_seed(&self) -> u32;
+    /// Newest replay format that this BW version is able to play.
+    fn replay_format(&self) -> crate::replay::ReplayFormat;
+    /// Sets the network turn rate (one of `TURN_RATES`) that will be used when
+    /// creating or joining a lobby. Returns false if not supported.
+    fn set_turn_rate(&self, turn_rate: u8) -> bool;
+    /// 0 if the turn rate is dynamic, or BW doesn't have a turn rate setting.
+    fn turn_rate(&self) -> u8;
 
     /// Inits player's info from storm to starcraft.
     /// Called once player has joined and is visible to storm.
@@ -57,12 +264,56 @@ pub trait Bw: Sync + Send {
     ) -> Result<(), u32>;
     unsafe fn game(&self) -> *mut Game;
     unsafe fn players(&self) -> *mut Player;
+    /// Ingame player id of the local player. Observers don't have a valid id (>= 8).
+    unsafe fn local_player_id(&self) -> u32;
     unsafe fn set_player_name(&self, id: u8, name: &str);
+    /// Name that BW has for ingame player `id`, decoded from the encoding that the BW
+    /// version uses.
+    unsafe fn player_name(&self, id: u8) -> String;
 
     unsafe fn active_units(&self) -> unit::UnitIterator;
+    /// Units that the local player has currently selected.
+    unsafe fn client_selection(&self) -> Vec<unit::Unit>;
+    /// Map pixel coordinates of the top left corner of the local player's screen.
+    unsafe fn screen_position(&self) -> Point;
+    /// Map pixels that the local player's screen shows, not counting the console.
+    fn game_screen_size(&self) -> (u16, u16);
+    /// Moves the local player's screen, aligned down to 8 pixels. The position isn't
+    /// clamped, callers have to keep the screen inside the map (see `game_screen_size`).
+    /// Returns false if not supported.
+    unsafe fn set_screen_position(&self, position: Point) -> bool;
     unsafe fn fow_sprites(&self) -> FowSpriteIterator;
-    unsafe fn create_fow_sprite(&self, unit: unit::Unit);
+    /// Returns `None` if BW has run out of sprites.
+    unsafe fn create_fow_sprite(&self, unit: unit::Unit) -> Option<()>;
+    /// Flags of each map tile (`TILE_*`), with the map's dimensions.
+    /// Null if the BW version doesn't have this.
+    unsafe fn map_tile_flags(&self) -> *mut u32;
     unsafe fn sprite_position(&self, sprite: *mut c_void) -> Point;
+    /// Bit mask of players who currently see the sprite.
+    unsafe fn sprite_visibility_mask(&self, sprite: *mut c_void) -> u8;
+    /// Path where BW saved the replay of the latest game, `None` if not known.
+    fn last_replay_path(&self) -> Option<PathBuf>;
+    /// Forgets the previous game's replay, so that `last_replay_path` doesn't return it
+    /// for the next game.
+    fn clear_last_replay_path(&self);
+    /// Sends a chat message to players in `recipients` (Bits of storm ids).
+    /// Chat isn't part of the synced game state, so this can be done at any point of the game.
+    /// `message` must fit in `chat::MAX_CHAT_MESSAGE_LENGTH`.
+    unsafe fn send_chat(&self, message: &str, recipients: u8) -> Result<(), SendChatError>;
+    /// Queues a command to be sent to all players as the local player's command.
+    /// Commands are part of the synced game state, so they will only be executed once
+    /// every player has received them.
+    unsafe fn send_command(&self, command: &[u8]);
+    /// Lengths of each command id, as `commands::iter_commands` expects them.
+    fn command_lengths(&self) -> &[u32];
+
+    /// Switches rendering to a palette meant for colorblind players, or back to the
+    /// default with `PaletteKind::Default`.
+    fn set_palette(&self, kind: crate::forge::PaletteKind);
+    /// Sets which players' vision the local player sees, as a bit mask of player ids.
+    /// Only meant for observers and replays, as the vision isn't synced.
+    /// Returns false if not supported.
+    unsafe fn set_local_visions(&self, mask: u8) -> bool;
 
     /// Note: Size is unspecified, but will not change between calls.
     /// (Remastered has 12 storm players)
@@ -71,10 +322,22 @@ pub trait Bw: Sync + Send {
     unsafe fn storm_player_flags(&self) -> Vec<u32>;
 
     unsafe fn storm_set_last_error(&self, error: u32);
+
+    fn capabilities(&self) -> Capabilities;
 }
 
 pub const MAX_STORM_PLAYERS: usize = 12;
 
+/// Id of a player in storm (network) player list. Stays same for the entire game,
+/// and is also used for observers, who don't have an ingame player id.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct StormPlayerId(pub u8);
+
+/// Index of a player in BW's ingame player arrays (`players()`, most arrays in `Game`).
+/// May change once during game initialization when the players get randomized.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct GamePlayerId(pub u8);
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct GameType {
     pub primary: u8,
@@ -129,6 +392,51 @@ quick_error! {
     }
 }
 
+quick_error! {
+    #[derive(Debug, Clone)]
+    pub enum SendChatError {
+        TooLong {
+            display("Message is too long")
+        }
+        Unsupported {
+            display("Sending chat is not supported on this version of the game")
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug, Clone)]
+    pub enum MinimapPingError {
+        NotInGame {
+            display("Not in a game")
+        }
+        Replay {
+            display("Can't ping in replays")
+        }
+        OutOfBounds(x: i16, y: i16) {
+            display("({}, {}) is outside the map", x, y)
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug, Clone)]
+    pub enum IssueCommandError {
+        NotInGame {
+            display("Not in a game")
+        }
+        Replay {
+            display("Can't issue commands in replays")
+        }
+        Invalid {
+            display("Data isn't a single valid command")
+        }
+        Disallowed(id: u8) {
+            display("Command 0x{:x} can't be issued", id)
+        }
+    }
+}
+
 impl LobbyCreateError {
     pub fn from_error_code(code: u32) -> LobbyCreateError {
         match code {
@@ -158,6 +466,7 @@ impl LobbyCreateError {
 pub const GAME_STATE_ACTIVE: u32 = 0x04;
 
 pub const PLAYER_TYPE_NONE: u8 = 0x0;
+pub const PLAYER_TYPE_COMPUTER: u8 = 0x1;
 pub const PLAYER_TYPE_HUMAN: u8 = 0x2;
 pub const PLAYER_TYPE_LOBBY_COMPUTER: u8 = 0x5;
 pub const PLAYER_TYPE_OPEN: u8 = 0x6;
@@ -391,12 +700,29 @@ pub struct Point32 {
 }
 
 #[repr(C)]
+#[derive(Copy, Clone)]
 pub struct Supplies {
     pub provided: [u32; 0xc],
     pub used: [u32; 0xc],
     pub max: [u32; 0xc],
 }
 
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct TriggerAction {
+    pub location: u32,
+    pub string: u32,
+    pub sound: u32,
+    pub time: u32,
+    pub player: u32,
+    pub player2: u32,
+    pub unit_id: u16,
+    pub action_id: u8,
+    pub amount: u8,
+    pub flags: u8,
+    pub padding: [u8; 3],
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct Location {
@@ -418,7 +744,9 @@ pub struct Rect32 {
 pub struct Game {
     pub minerals: [u32; 0xc],
     pub gas: [u32; 0xc],
-    pub dc60: [u8; 0x84],
+    pub cumulative_gas: [u32; 0xc],
+    pub cumulative_minerals: [u32; 0xc],
+    pub dcc0: [u8; 0x24],
     pub map_width_tiles: u16,
     pub map_height_tiles: u16,
     pub dce8: [u8; 0x4],
@@ -739,6 +1067,15 @@ fn struct_sizes() {
     assert_eq!(size_of::<Unit>(), 0x150);
     assert_eq!(size_of::<Image>(), 0x40);
     assert_eq!(size_of::<FowSprite>(), 0x10);
+    assert_eq!(size_of::<TriggerAction>(), 0x20);
+}
+
+#[test]
+fn minimap_terrain_bits() {
+    assert_eq!(minimap_terrain(0x0001_0000), 0);
+    assert_eq!(minimap_terrain(TILE_HIGH | TILE_CREEP | 0xff), 2 | MINIMAP_CREEP);
+    assert_eq!(minimap_terrain(TILE_MIDDLE | TILE_UNWALKABLE), 1 | MINIMAP_UNWALKABLE);
+    assert_eq!(minimap_terrain(TILE_VERY_HIGH | TILE_HIGH), 3);
 }
 
 pub struct FowSpriteIterator(*mut FowSprite);
@@ -0,0 +1,525 @@
+//! Network/replay commands that players send.
+
+use super::IssueCommandError;
+
+/// Ids of commands that may be seen during a game, for both 1.16.1 and SC:R.
+/// (The `Ext` variants are SC:R only, using 32-bit unit ids; `ReplaySeek` is SC:R only too)
+///
+/// Lobby commands other than `LobbyGameInit` aren't included, as they are handled by
+/// BW before the game starts.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum CommandId {
+    KeepAlive = 0x05,
+    SaveGame = 0x06,
+    LoadGame = 0x07,
+    RestartGame = 0x08,
+    Select = 0x09,
+    ShiftSelect = 0x0a,
+    ShiftDeselect = 0x0b,
+    Build = 0x0c,
+    Vision = 0x0d,
+    Alliance = 0x0e,
+    GameSpeed = 0x0f,
+    Pause = 0x10,
+    Resume = 0x11,
+    Cheat = 0x12,
+    Hotkey = 0x13,
+    RightClick = 0x14,
+    TargetedOrder = 0x15,
+    CancelBuild = 0x18,
+    CancelMorph = 0x19,
+    Stop = 0x1a,
+    CarrierStop = 0x1b,
+    ReaverStop = 0x1c,
+    OrderNothing = 0x1d,
+    ReturnCargo = 0x1e,
+    Train = 0x1f,
+    CancelTrain = 0x20,
+    Cloak = 0x21,
+    Decloak = 0x22,
+    UnitMorph = 0x23,
+    Unsiege = 0x25,
+    Siege = 0x26,
+    TrainFighter = 0x27,
+    UnloadAll = 0x28,
+    Unload = 0x29,
+    MergeArchon = 0x2a,
+    HoldPosition = 0x2b,
+    Burrow = 0x2c,
+    Unburrow = 0x2d,
+    CancelNuke = 0x2e,
+    Lift = 0x2f,
+    Research = 0x30,
+    CancelResearch = 0x31,
+    Upgrade = 0x32,
+    CancelUpgrade = 0x33,
+    CancelAddon = 0x34,
+    BuildingMorph = 0x35,
+    Stim = 0x36,
+    Sync = 0x37,
+    LobbyGameInit = 0x48,
+    Latency = 0x55,
+    ReplaySpeed = 0x56,
+    LeaveGame = 0x57,
+    MinimapPing = 0x58,
+    MergeDarkArchon = 0x5a,
+    ReplayChat = 0x5c,
+    ReplaySeek = 0x5d,
+    RightClickExt = 0x60,
+    TargetedOrderExt = 0x61,
+    UnloadExt = 0x62,
+    SelectExt = 0x63,
+    ShiftSelectExt = 0x64,
+    ShiftDeselectExt = 0x65,
+}
+
+impl CommandId {
+    pub fn from_u8(value: u8) -> Option<CommandId> {
+        use self::CommandId::*;
+        let id = match value {
+            0x05 => KeepAlive,
+            0x06 => SaveGame,
+            0x07 => LoadGame,
+            0x08 => RestartGame,
+            0x09 => Select,
+            0x0a => ShiftSelect,
+            0x0b => ShiftDeselect,
+            0x0c => Build,
+            0x0d => Vision,
+            0x0e => Alliance,
+            0x0f => GameSpeed,
+            0x10 => Pause,
+            0x11 => Resume,
+            0x12 => Cheat,
+            0x13 => Hotkey,
+            0x14 => RightClick,
+            0x15 => TargetedOrder,
+            0x18 => CancelBuild,
+            0x19 => CancelMorph,
+            0x1a => Stop,
+            0x1b => CarrierStop,
+            0x1c => ReaverStop,
+            0x1d => OrderNothing,
+            0x1e => ReturnCargo,
+            0x1f => Train,
+            0x20 => CancelTrain,
+            0x21 => Cloak,
+            0x22 => Decloak,
+            0x23 => UnitMorph,
+            0x25 => Unsiege,
+            0x26 => Siege,
+            0x27 => TrainFighter,
+            0x28 => UnloadAll,
+            0x29 => Unload,
+            0x2a => MergeArchon,
+            0x2b => HoldPosition,
+            0x2c => Burrow,
+            0x2d => Unburrow,
+            0x2e => CancelNuke,
+            0x2f => Lift,
+            0x30 => Research,
+            0x31 => CancelResearch,
+            0x32 => Upgrade,
+            0x33 => CancelUpgrade,
+            0x34 => CancelAddon,
+            0x35 => BuildingMorph,
+            0x36 => Stim,
+            0x37 => Sync,
+            0x48 => LobbyGameInit,
+            0x55 => Latency,
+            0x56 => ReplaySpeed,
+            0x57 => LeaveGame,
+            0x58 => MinimapPing,
+            0x5a => MergeDarkArchon,
+            0x5c => ReplayChat,
+            0x5d => ReplaySeek,
+            0x60 => RightClickExt,
+            0x61 => TargetedOrderExt,
+            0x62 => UnloadExt,
+            0x63 => SelectExt,
+            0x64 => ShiftSelectExt,
+            0x65 => ShiftDeselectExt,
+            _ => return None,
+        };
+        Some(id)
+    }
+
+    /// Id of the command that `command` starts with, if any.
+    pub fn from_bytes(command: &[u8]) -> Option<CommandId> {
+        command.get(0).and_then(|&x| CommandId::from_u8(x))
+    }
+
+    /// False for commands that aren't something that the player chose to do, but are
+    /// sent by BW itself, or are meta commands such as pausing or chat.
+    pub fn is_player_action(self) -> bool {
+        use self::CommandId::*;
+        match self {
+            KeepAlive | SaveGame | LoadGame | Pause | Resume | Sync | LeaveGame |
+                ReplayChat | ReplaySeek => false,
+            _ => true,
+        }
+    }
+
+    pub fn is_selection(self) -> bool {
+        use self::CommandId::*;
+        match self {
+            Select | ShiftSelect | ShiftDeselect | SelectExt | ShiftSelectExt |
+                ShiftDeselectExt => true,
+            _ => false,
+        }
+    }
+
+    /// Building, training, morphing, researching and upgrading.
+    pub fn is_production(self) -> bool {
+        use self::CommandId::*;
+        match self {
+            Build | Train | UnitMorph | TrainFighter | Research | Upgrade |
+                BuildingMorph => true,
+            _ => false,
+        }
+    }
+}
+
+/// The ids of `CommandId` as plain bytes, for matching against command data.
+#[allow(dead_code)] // Not every command is matched on yet.
+pub mod id {
+    use super::CommandId;
+
+    pub const KEEP_ALIVE: u8 = CommandId::KeepAlive as u8;
+    pub const SAVE_GAME: u8 = CommandId::SaveGame as u8;
+    pub const LOAD_GAME: u8 = CommandId::LoadGame as u8;
+    pub const RESTART_GAME: u8 = CommandId::RestartGame as u8;
+    pub const SELECT: u8 = CommandId::Select as u8;
+    pub const SHIFT_SELECT: u8 = CommandId::ShiftSelect as u8;
+    pub const SHIFT_DESELECT: u8 = CommandId::ShiftDeselect as u8;
+    pub const BUILD: u8 = CommandId::Build as u8;
+    pub const VISION: u8 = CommandId::Vision as u8;
+    pub const ALLIANCE: u8 = CommandId::Alliance as u8;
+    pub const GAME_SPEED: u8 = CommandId::GameSpeed as u8;
+    pub const PAUSE: u8 = CommandId::Pause as u8;
+    pub const RESUME: u8 = CommandId::Resume as u8;
+    pub const CHEAT: u8 = CommandId::Cheat as u8;
+    pub const HOTKEY: u8 = CommandId::Hotkey as u8;
+    pub const RIGHT_CLICK: u8 = CommandId::RightClick as u8;
+    pub const TARGETED_ORDER: u8 = CommandId::TargetedOrder as u8;
+    pub const CANCEL_BUILD: u8 = CommandId::CancelBuild as u8;
+    pub const CANCEL_MORPH: u8 = CommandId::CancelMorph as u8;
+    pub const STOP: u8 = CommandId::Stop as u8;
+    pub const CARRIER_STOP: u8 = CommandId::CarrierStop as u8;
+    pub const REAVER_STOP: u8 = CommandId::ReaverStop as u8;
+    pub const ORDER_NOTHING: u8 = CommandId::OrderNothing as u8;
+    pub const RETURN_CARGO: u8 = CommandId::ReturnCargo as u8;
+    pub const TRAIN: u8 = CommandId::Train as u8;
+    pub const CANCEL_TRAIN: u8 = CommandId::CancelTrain as u8;
+    pub const CLOAK: u8 = CommandId::Cloak as u8;
+    pub const DECLOAK: u8 = CommandId::Decloak as u8;
+    pub const UNIT_MORPH: u8 = CommandId::UnitMorph as u8;
+    pub const UNSIEGE: u8 = CommandId::Unsiege as u8;
+    pub const SIEGE: u8 = CommandId::Siege as u8;
+    pub const TRAIN_FIGHTER: u8 = CommandId::TrainFighter as u8;
+    pub const UNLOAD_ALL: u8 = CommandId::UnloadAll as u8;
+    pub const UNLOAD: u8 = CommandId::Unload as u8;
+    pub const MERGE_ARCHON: u8 = CommandId::MergeArchon as u8;
+    pub const HOLD_POSITION: u8 = CommandId::HoldPosition as u8;
+    pub const BURROW: u8 = CommandId::Burrow as u8;
+    pub const UNBURROW: u8 = CommandId::Unburrow as u8;
+    pub const CANCEL_NUKE: u8 = CommandId::CancelNuke as u8;
+    pub const LIFT: u8 = CommandId::Lift as u8;
+    pub const RESEARCH: u8 = CommandId::Research as u8;
+    pub const CANCEL_RESEARCH: u8 = CommandId::CancelResearch as u8;
+    pub const UPGRADE: u8 = CommandId::Upgrade as u8;
+    pub const CANCEL_UPGRADE: u8 = CommandId::CancelUpgrade as u8;
+    pub const CANCEL_ADDON: u8 = CommandId::CancelAddon as u8;
+    pub const BUILDING_MORPH: u8 = CommandId::BuildingMorph as u8;
+    pub const STIM: u8 = CommandId::Stim as u8;
+    pub const SYNC: u8 = CommandId::Sync as u8;
+    pub const LOBBY_GAME_INIT: u8 = CommandId::LobbyGameInit as u8;
+    pub const LATENCY: u8 = CommandId::Latency as u8;
+    pub const REPLAY_SPEED: u8 = CommandId::ReplaySpeed as u8;
+    pub const LEAVE_GAME: u8 = CommandId::LeaveGame as u8;
+    pub const MINIMAP_PING: u8 = CommandId::MinimapPing as u8;
+    pub const MERGE_DARK_ARCHON: u8 = CommandId::MergeDarkArchon as u8;
+    pub const REPLAY_CHAT: u8 = CommandId::ReplayChat as u8;
+    pub const REPLAY_SEEK: u8 = CommandId::ReplaySeek as u8;
+    pub const RIGHT_CLICK_EXT: u8 = CommandId::RightClickExt as u8;
+    pub const TARGETED_ORDER_EXT: u8 = CommandId::TargetedOrderExt as u8;
+    pub const UNLOAD_EXT: u8 = CommandId::UnloadExt as u8;
+    pub const SELECT_EXT: u8 = CommandId::SelectExt as u8;
+    pub const SHIFT_SELECT_EXT: u8 = CommandId::ShiftSelectExt as u8;
+    pub const SHIFT_DESELECT_EXT: u8 = CommandId::ShiftDeselectExt as u8;
+}
+
+/// Command lengths for 1.16.1, followed by the commands that SC:R added. While the game
+/// runs, SC:R's lengths are read from the executable instead; this table is also used
+/// for replays that are read on their own. Only the first `COMMAND_COUNT_1161` commands
+/// exist in 1.16.1.
+/// `!0` is used for commands that don't exist or have a variable length, which
+/// `iter_commands` handles separately.
+pub const COMMAND_LENGTHS: &[u32] = &[
+    !0, !0, !0, !0, !0, 1, 33, 33, 1, 26, 26, 26, 8, 3, 5, 2,
+    1, 1, 5, 3, 10, 11, !0, !0, 1, 1, 2, 1, 1, 1, 2, 3,
+    3, 2, 2, 3, 1, 2, 2, 1, 2, 3, 1, 2, 2, 2, 1, 5,
+    2, 1, 2, 1, 1, 3, 1, 1, !0, !0, !0, !0, !0, !0, !0, !0,
+    !0, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0,
+    !0, !0, !0, !0, !0, 2, 10, 2, 5, !0, 1, !0, 82, 5, !0, 2,
+    12, 13, 5, 50, 50, 50, 4,
+];
+pub const COMMAND_COUNT_1161: usize = 0x5d;
+
+/// Order that drones are given when they are ordered to morph into a building.
+const ORDER_DRONE_START_BUILD: u8 = 0x19;
+const DRONE: u16 = 0x29;
+
+/// (from, to) unit ids of morphs that `UNIT_MORPH` and `BUILDING_MORPH` can start.
+/// The command only contains the resulting unit id, each of them can only be morphed
+/// from a single unit type.
+const MORPHS: &[(u16, u16)] = &[
+    // Larva to Zergling, Hydralisk, Ultralisk, Drone, Overlord, Mutalisk, Queen,
+    // Defiler, Scourge
+    (0x23, 0x25),
+    (0x23, 0x26),
+    (0x23, 0x27),
+    (0x23, 0x29),
+    (0x23, 0x2a),
+    (0x23, 0x2b),
+    (0x23, 0x2d),
+    (0x23, 0x2e),
+    (0x23, 0x2f),
+    // Hydralisk to Lurker, Mutalisk to Guardian and Devourer
+    (0x26, 0x67),
+    (0x2b, 0x2c),
+    (0x2b, 0x3e),
+    // Hatchery to Lair, Lair to Hive, Spire to Greater spire,
+    // Creep colony to Sunken and Spore colonies
+    (0x83, 0x84),
+    (0x84, 0x85),
+    (0x8d, 0x89),
+    (0x8f, 0x92),
+    (0x8f, 0x90),
+];
+
+/// Returns (from, to) unit ids if the command starts a Zerg morph, including drones
+/// morphing into buildings.
+///
+/// This only tells what the player asked for; BW may still refuse the morph,
+/// e.g. if the player doesn't have enough resources or the selection can't morph.
+pub fn morph(command: &[u8]) -> Option<(u16, u16)> {
+    match *command {
+        [id::UNIT_MORPH, a, b] | [id::BUILDING_MORPH, a, b] => {
+            let to = u16::from_le_bytes([a, b]);
+            MORPHS.iter().find(|x| x.1 == to).map(|&(from, _)| (from, to))
+        }
+        [id::BUILD, ORDER_DRONE_START_BUILD, _, _, _, _, a, b] => {
+            Some((DRONE, u16::from_le_bytes([a, b])))
+        }
+        _ => None,
+    }
+}
+
+/// Checks that `data` is exactly one command, and one that is fine to send on behalf
+/// of the local player during a game. Commands that save, load, restart or leave the game,
+/// change its speed, pause it, enable cheats, or are otherwise only sent by BW itself,
+/// are rejected.
+pub fn validate_issued_command(
+    data: &[u8],
+    command_lengths: &[u32],
+) -> Result<(), IssueCommandError> {
+    let mut iter = iter_commands(data, command_lengths);
+    match (iter.next(), iter.next()) {
+        (Some(command), None) if command.len() == data.len() => (),
+        _ => return Err(IssueCommandError::Invalid),
+    }
+    match data[0] {
+        id::SAVE_GAME | id::LOAD_GAME | id::RESTART_GAME | id::SYNC | id::LEAVE_GAME |
+            id::GAME_SPEED | id::PAUSE | id::RESUME | id::CHEAT | id::LOBBY_GAME_INIT |
+            id::REPLAY_CHAT | id::REPLAY_SEEK => Err(IssueCommandError::Disallowed(data[0])),
+        _ => Ok(()),
+    }
+}
+
+/// Splits a byte slice that may contain many commands to slices of individual commands.
+pub fn iter_commands<'a>(
+    slice: &'a [u8],
+    command_lengths: &'a [u32],
+) -> impl Iterator<Item = &'a [u8]> {
+    IterCommands {
+        slice,
+        command_lengths,
+    }
+}
+
+struct IterCommands<'a> {
+    slice: &'a [u8],
+    command_lengths: &'a [u32],
+}
+
+impl<'a> Iterator for IterCommands<'a> {
+    type Item = &'a [u8];
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = match *self.slice.get(0)? {
+            id::SAVE_GAME | id::LOAD_GAME => {
+                // Save/Load commands have a 0-terminated string starting at offset 5
+                // as their last field
+                self.slice.iter().enumerate().skip(5).find(|x| *x.1 == 0).map(|x| x.0 + 1)
+            }
+            id::SELECT | id::SHIFT_SELECT | id::SHIFT_DESELECT => {
+                // Old selection commands, { u8 id, u8 unit_count, u16 units[] }
+                self.slice.get(1).map(|&count| count as usize * 2 + 2)
+            }
+            id::SELECT_EXT | id::SHIFT_SELECT_EXT | id::SHIFT_DESELECT_EXT => {
+                // New selection commands, { u8 id, u8 unit_count, u32 units[] }
+                self.slice.get(1).map(|&count| count as usize * 4 + 2)
+            }
+            x => {
+                self.command_lengths.get(x as usize).map(|&len| len as usize)
+            }
+        };
+        let split = len
+            .filter(|&len| len != 0)
+            .and_then(|len| Some((self.slice.get(..len)?, self.slice.get(len..)?)));
+        match split {
+            Some((cmd, rest)) => {
+                self.slice = rest;
+                Some(cmd)
+            }
+            None => {
+                warn!("Unknown/invalid command {:x?}", self.slice);
+                self.slice = &[];
+                None
+            }
+        }
+    }
+}
+
+#[test]
+fn test_iter_commands() {
+    let lengths = &[
+        !0, !0, !0, !0, !0, 1, 33, 33, 1, 26, 26, 26, 8, 3, 5, 2,
+        1, 1, 5, 3, 10, 11, !0, !0, 1, 1, 2, 1, 1, 1, 2, 3,
+        3, 2, 2, 3, 1, 2, 2, 1, 2, 3, 1, 2, 2, 2, 1, 5,
+        2, 1, 2, 1, 1, 3, 1, 1, !0, !0, !0, !0, !0, !0, !0, !0,
+        !0, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0, !0,
+        !0, !0, !0, !0, !0, 2, 10, 2, 5, !0, 1, !0, 82, 5, !0, 2,
+        12, 13, 5, 50, 50, 50, 4, !0, !0, !0, !0, !0, !0, !0, !0, !0,
+    ];
+    let data = &[
+        0x20, 0xff, 0xff,
+        0x32, 0xff,
+        0x62, 0xff, 0xff, 0xff, 0xff,
+    ];
+    let mut iter = iter_commands(data, lengths);
+    assert_eq!(iter.next().unwrap(), &[0x20, 0xff, 0xff]);
+    assert_eq!(iter.next().unwrap(), &[0x32, 0xff]);
+    assert_eq!(iter.next().unwrap(), &[0x62, 0xff, 0xff, 0xff, 0xff]);
+    assert!(iter.next().is_none());
+
+    let data = &[
+        0x20, 0xff, 0xff,
+        0x32, 0xff,
+        0x0a, 0x03, 0xaa, 0xaa, 0xbb, 0xbb, 0xcc, 0xcc,
+        0x63, 0x02, 0xaa, 0xaa, 0xaa, 0xaa, 0xbb, 0xbb, 0xbb, 0xbb,
+        0x62, 0xff, 0xff, 0xff, 0xff,
+    ];
+    let mut iter = iter_commands(data, lengths);
+    assert_eq!(iter.next().unwrap(), &[0x20, 0xff, 0xff]);
+    assert_eq!(iter.next().unwrap(), &[0x32, 0xff]);
+    assert_eq!(iter.next().unwrap(), &[0x0a, 0x03, 0xaa, 0xaa, 0xbb, 0xbb, 0xcc, 0xcc]);
+    assert_eq!(
+        iter.next().unwrap(),
+        &[0x63, 0x02, 0xaa, 0xaa, 0xaa, 0xaa, 0xbb, 0xbb, 0xbb, 0xbb],
+    );
+    assert_eq!(iter.next().unwrap(), &[0x62, 0xff, 0xff, 0xff, 0xff]);
+    assert!(iter.next().is_none());
+
+    let data = &[
+        0x20, 0xff, 0xff,
+        0x32, 0xff,
+        0x07, 0xff, 0xff, 0xff, 0xff, b's', b'a', b'v', b'e', 0x00,
+        0x32, 0xff,
+    ];
+    let mut iter = iter_commands(data, lengths);
+    assert_eq!(iter.next().unwrap(), &[0x20, 0xff, 0xff]);
+    assert_eq!(iter.next().unwrap(), &[0x32, 0xff]);
+    assert_eq!(
+        iter.next().unwrap(),
+        &[0x07, 0xff, 0xff, 0xff, 0xff, b's', b'a', b'v', b'e', 0x00],
+    );
+    assert_eq!(iter.next().unwrap(), &[0x32, 0xff]);
+    assert!(iter.next().is_none());
+
+    let data = &[
+        0x20, 0xff, 0xff,
+        0x32, 0xff,
+        0x62, 0xff, 0xff, 0xff, // Invalid as it's too short
+    ];
+    let mut iter = iter_commands(data, lengths);
+    assert_eq!(iter.next().unwrap(), &[0x20, 0xff, 0xff]);
+    assert_eq!(iter.next().unwrap(), &[0x32, 0xff]);
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn morph_commands() {
+    // Lurker, Lair
+    assert_eq!(morph(&[0x23, 0x67, 0x00]), Some((0x26, 0x67)));
+    assert_eq!(morph(&[0x35, 0x84, 0x00]), Some((0x83, 0x84)));
+    // Drone to Spawning pool at (20, 30)
+    assert_eq!(morph(&[0x0c, 0x19, 0x14, 0x00, 0x1e, 0x00, 0x8e, 0x00]), Some((0x29, 0x8e)));
+    // SCV building a Barracks
+    assert_eq!(morph(&[0x0c, 0x1e, 0x14, 0x00, 0x1e, 0x00, 0x6f, 0x00]), None);
+    // Not a morph result, truncated command
+    assert_eq!(morph(&[0x23, 0x00, 0x00]), None);
+    assert_eq!(morph(&[0x23, 0x67]), None);
+}
+
+#[test]
+fn issued_command_validation() {
+    let lengths = &COMMAND_LENGTHS[..COMMAND_COUNT_1161];
+    // Stop
+    assert!(validate_issued_command(&[0x1a, 0x00], lengths).is_ok());
+    // Unit morph to Lurker
+    assert!(validate_issued_command(&[0x23, 0x67, 0x00], lengths).is_ok());
+    assert!(validate_issued_command(&[], lengths).is_err());
+    // Truncated, too long, two commands
+    assert!(validate_issued_command(&[0x23, 0x67], lengths).is_err());
+    assert!(validate_issued_command(&[0x1a, 0x00, 0x00], lengths).is_err());
+    assert!(validate_issued_command(&[0x1a, 0x00, 0x1a, 0x00], lengths).is_err());
+    assert!(matches!(
+        validate_issued_command(&[id::LEAVE_GAME, 0x01], lengths),
+        Err(IssueCommandError::Disallowed(id::LEAVE_GAME)),
+    ));
+    let game_control: [&[u8]; 5] = [
+        &[id::RESTART_GAME],
+        &[id::PAUSE],
+        &[id::RESUME],
+        // Fastest
+        &[id::GAME_SPEED, 0x06],
+        // u32 bit mask of enabled cheats
+        &[id::CHEAT, 0x01, 0x00, 0x00, 0x00],
+    ];
+    for &command in game_control.iter() {
+        assert!(matches!(
+            validate_issued_command(command, lengths),
+            Err(IssueCommandError::Disallowed(x)) if x == command[0],
+        ));
+    }
+}
+
+#[test]
+fn command_ids() {
+    for value in 0..=0xffu8 {
+        if let Some(id) = CommandId::from_u8(value) {
+            assert_eq!(id as u8, value);
+        }
+    }
+    assert_eq!(CommandId::from_bytes(&[0x1a, 0x00]), Some(CommandId::Stop));
+    assert_eq!(CommandId::from_bytes(&[id::REPLAY_SEEK, 0, 0, 0, 0]), Some(CommandId::ReplaySeek));
+    assert_eq!(CommandId::from_bytes(&[0x24]), None);
+    assert_eq!(CommandId::from_bytes(&[]), None);
+    assert!(CommandId::SelectExt.is_selection());
+    assert!(CommandId::BuildingMorph.is_production());
+    assert!(!CommandId::Stop.is_production());
+    assert!(!CommandId::KeepAlive.is_player_action());
+    assert!(CommandId::MinimapPing.is_player_action());
+}
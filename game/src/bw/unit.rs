@@ -59,7 +59,68 @@ impl Unit {
         unsafe { (**self).unit_id }
     }
 
+    pub fn is_worker(self) -> bool {
+        match self.id() {
+            0x07 | 0x29 | 0x40 => true,
+            _ => false,
+        }
+    }
+
+    /// Command center, Hatchery, Lair, Hive and Nexus.
+    pub fn is_town_hall(self) -> bool {
+        match self.id() {
+            0x6a | 0x83 | 0x84 | 0x85 | 0x9a => true,
+            _ => false,
+        }
+    }
+
+    /// Buildings that produce army units or attack, which a proxy or rush would be
+    /// made of. Includes Hatchery for proxy hatcheries.
+    pub fn is_military_building(self) -> bool {
+        match self.id() {
+            // Barracks, Factory, Starport, Bunker
+            0x6f | 0x71 | 0x72 | 0x7d => true,
+            // Hatchery
+            0x83 => true,
+            // Robotics facility, Gateway, Photon cannon, Stargate
+            0x9b | 0xa0 | 0xa2 | 0xa7 => true,
+            _ => false,
+        }
+    }
+
+    pub fn order(self) -> u8 {
+        unsafe { (**self).order }
+    }
+
+    pub fn target(self) -> Option<Unit> {
+        unsafe { Unit::from_ptr((**self).target) }
+    }
+
+    pub fn order_target_pos(self) -> bw::Point {
+        unsafe { (**self).order_target_pos }
+    }
+
+    pub fn is_completed(self) -> bool {
+        unsafe { (**self).flags & 0x1 != 0 }
+    }
+
     pub fn is_landed_building(self) -> bool {
         unsafe { (**self).flags & 0x2 != 0 }
     }
+
+    /// Mineral fields, geysers and the gas buildings built on top of them.
+    pub fn is_resource_container(self) -> bool {
+        match self.id() {
+            0xb0 | 0xb1 | 0xb2 | 0xbc | 0x6e | 0x95 | 0x9d => true,
+            _ => false,
+        }
+    }
+
+    /// Only meaningful if `is_resource_container()` is true.
+    pub fn resource_amount(self) -> u16 {
+        unsafe {
+            let data = &(**self).unit_specific2;
+            u16::from_le_bytes([data[0], data[1]])
+        }
+    }
 }
@@ -0,0 +1,65 @@
+//! Resource costs of units, for statistics that need to value units.
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Cost {
+    pub minerals: u32,
+    pub gas: u32,
+}
+
+impl Cost {
+    pub fn total(self) -> u32 {
+        self.minerals + self.gas
+    }
+}
+
+/// Cost of a combat unit, or `None` for workers, buildings, and other units that
+/// aren't considered to be part of an army.
+///
+/// Morphed units include the cost of the unit they were morphed from, and units that
+/// are produced in pairs (Zerglings, Scourges) have half of the pair cost.
+pub fn army_unit_cost(unit_id: u16) -> Option<Cost> {
+    let (minerals, gas) = match unit_id {
+        // Terran
+        0x00 => (50, 0),    // Marine
+        0x01 => (25, 75),   // Ghost
+        0x02 => (75, 0),    // Vulture
+        0x03 => (100, 50),  // Goliath
+        0x05 => (150, 100), // Siege tank (Tank mode)
+        0x08 => (150, 100), // Wraith
+        0x09 => (100, 225), // Science vessel
+        0x0b => (100, 100), // Dropship
+        0x0c => (400, 300), // Battlecruiser
+        0x1e => (150, 100), // Siege tank (Siege mode)
+        0x20 => (50, 25),   // Firebat
+        0x22 => (50, 25),   // Medic
+        0x3a => (250, 125), // Valkyrie
+        // Zerg
+        0x25 => (25, 0),    // Zergling
+        0x26 => (75, 25),   // Hydralisk
+        0x27 => (200, 200), // Ultralisk
+        0x2b => (100, 100), // Mutalisk
+        0x2c => (150, 200), // Guardian
+        0x2d => (100, 100), // Queen
+        0x2e => (50, 150),  // Defiler
+        0x2f => (12, 37),   // Scourge
+        0x32 => (100, 50),  // Infested terran
+        0x3e => (250, 150), // Devourer
+        0x67 => (125, 125), // Lurker
+        // Protoss
+        0x3c => (150, 100), // Corsair
+        0x3d => (125, 100), // Dark templar
+        0x3f => (250, 200), // Dark archon
+        0x41 => (100, 0),   // Zealot
+        0x42 => (125, 50),  // Dragoon
+        0x43 => (50, 150),  // High templar
+        0x44 => (100, 300), // Archon
+        0x45 => (200, 0),   // Shuttle
+        0x46 => (275, 125), // Scout
+        0x47 => (100, 350), // Arbiter
+        0x48 => (350, 250), // Carrier
+        0x53 => (200, 100), // Reaver
+        0x54 => (25, 75),   // Observer
+        _ => return None,
+    };
+    Some(Cost { minerals, gas })
+}
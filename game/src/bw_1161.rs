@@ -8,6 +8,8 @@ use std::io;
 use std::ffi::{CStr, OsStr};
 use std::path::{Path, PathBuf};
 use std::ptr::null_mut;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
 
 use libc::c_void;
 use winapi::um::winnt::HANDLE;
@@ -20,6 +22,13 @@ use crate::windows;
 
 pub struct Bw1161;
 
+/// Seed passed to `do_lobby_game_init`.
+static LOBBY_INIT_SEED: AtomicU32 = AtomicU32::new(0);
+lazy_static::lazy_static! {
+    /// Where `create_file_hook` redirected the latest LastReplay.rep.
+    static ref LAST_REPLAY_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
 mod v1161 {
     use crate::bw;
 
@@ -57,6 +66,27 @@ impl bw::Bw for Bw1161 {
         game_loop();
     }
 
+    unsafe fn disable_frame_delay(&self) -> bool {
+        // Milliseconds per frame for each game speed, for both normal and alternate speeds.
+        for delay in game_speed_frame_delays.iter_mut() {
+            *delay = 0;
+        }
+        true
+    }
+
+    unsafe fn set_paused(&self, paused: bool) -> bool {
+        *is_game_paused = paused as u32;
+        true
+    }
+
+    unsafe fn is_paused(&self) -> Option<bool> {
+        Some(*is_game_paused != 0)
+    }
+
+    unsafe fn game_speed(&self) -> Option<u8> {
+        Some(*game_speed as u8)
+    }
+
     unsafe fn clean_up_for_exit(&self) {
         clean_up_for_exit(0);
     }
@@ -88,6 +118,24 @@ impl bw::Bw for Bw1161 {
         };
         // We ask bw to handle lobby game init packet that was sent by host (storm id 0)
         on_lobby_game_init(0, &data);
+        LOBBY_INIT_SEED.store(seed, Ordering::Relaxed);
+    }
+
+    unsafe fn game_seed(&self) -> u32 {
+        LOBBY_INIT_SEED.load(Ordering::Relaxed)
+    }
+
+    fn replay_format(&self) -> crate::replay::ReplayFormat {
+        crate::replay::ReplayFormat::Legacy
+    }
+
+    fn set_turn_rate(&self, _turn_rate: u8) -> bool {
+        // 1.16.1 derives turns from the latency that SNP reports.
+        false
+    }
+
+    fn turn_rate(&self) -> u8 {
+        0
     }
 
     unsafe fn try_finish_lobby_game_init(&self) -> bool {
@@ -141,6 +189,10 @@ impl bw::Bw for Bw1161 {
         (*players).as_mut_ptr()
     }
 
+    unsafe fn local_player_id(&self) -> u32 {
+        *local_nation_id
+    }
+
     unsafe fn set_player_name(&self, id: u8, name: &str) {
         let mut buffer = [0; 25];
         for (i, &byte) in name.as_bytes().iter().take(24).enumerate() {
@@ -149,16 +201,63 @@ impl bw::Bw for Bw1161 {
         (*self.players().add(id as usize)).name = buffer;
     }
 
+    unsafe fn player_name(&self, id: u8) -> String {
+        let name = &(*self.players().add(id as usize)).name;
+        let name = &name[..name.iter().position(|&x| x == 0).unwrap_or(name.len())];
+        // Names set through `set_player_name` are UTF-8 (and may have been truncated in
+        // middle of a character), while names BW itself set use the ANSI codepage.
+        match std::str::from_utf8(name) {
+            Ok(name) => name.into(),
+            Err(e) if e.error_len().is_none() => {
+                String::from_utf8_lossy(&name[..e.valid_up_to()]).into_owned()
+            }
+            Err(_) => windows::string_from_ansi_codepage(name),
+        }
+    }
+
     unsafe fn active_units(&self) -> UnitIterator {
         UnitIterator::new(Unit::from_ptr(*first_active_unit))
     }
 
+    unsafe fn client_selection(&self) -> Vec<Unit> {
+        (*client_selection).iter().filter_map(|&x| Unit::from_ptr(x)).collect()
+    }
+
+    unsafe fn screen_position(&self) -> bw::Point {
+        bw::Point {
+            x: *screen_x as i16,
+            y: *screen_y as i16,
+        }
+    }
+
+    fn game_screen_size(&self) -> (u16, u16) {
+        // 640x480, with the bottom 80 pixels always covered by the console.
+        (640, 400)
+    }
+
+    unsafe fn set_screen_position(&self, position: bw::Point) -> bool {
+        // BW only scrolls in 8 pixel steps.
+        *screen_x = (position.x as u32) & !7;
+        *screen_y = (position.y as u32) & !7;
+        update_screen_position();
+        true
+    }
+
     unsafe fn fow_sprites(&self) -> FowSpriteIterator {
         FowSpriteIterator::new(*first_fow_sprite)
     }
 
-    unsafe fn create_fow_sprite(&self, unit: Unit) {
-        create_fow_sprite((**unit).unit_id as u32, (**unit).sprite);
+    unsafe fn create_fow_sprite(&self, unit: Unit) -> Option<()> {
+        let fow = create_fow_sprite((**unit).unit_id as u32, (**unit).sprite);
+        if fow.is_null() {
+            None
+        } else {
+            Some(())
+        }
+    }
+
+    unsafe fn map_tile_flags(&self) -> *mut u32 {
+        *map_tile_flags
     }
 
     unsafe fn sprite_position(&self, sprite: *mut c_void) -> bw::Point {
@@ -169,6 +268,55 @@ impl bw::Bw for Bw1161 {
         }
     }
 
+    unsafe fn sprite_visibility_mask(&self, sprite: *mut c_void) -> u8 {
+        (*(sprite as *mut v1161::Sprite)).visibility_mask
+    }
+
+    fn last_replay_path(&self) -> Option<PathBuf> {
+        LAST_REPLAY_PATH.lock().unwrap().clone()
+    }
+
+    fn clear_last_replay_path(&self) {
+        *LAST_REPLAY_PATH.lock().unwrap() = None;
+    }
+
+    fn set_palette(&self, kind: crate::forge::PaletteKind) {
+        crate::forge::set_palette(kind);
+    }
+
+    unsafe fn set_local_visions(&self, mask: u8) -> bool {
+        *replay_visions = mask as u32;
+        *player_visions = mask as u32;
+        true
+    }
+
+    unsafe fn send_chat(&self, message: &str, recipients: u8) -> Result<(), bw::SendChatError> {
+        if message.len() > chat::MAX_CHAT_MESSAGE_LENGTH {
+            return Err(bw::SendChatError::TooLong);
+        }
+        let mut text = Vec::with_capacity(message.len() + 1);
+        text.extend_from_slice(message.as_bytes());
+        text.push(0);
+        // Allies message with explicitly set recipients can be used to target
+        // any set of players, same as what `chat_command_hook` does.
+        let old_type = *chat_message_type;
+        let old_recipients = *chat_message_recipients;
+        *chat_message_type = bw::CHAT_MESSAGE_ALLIES;
+        *chat_message_recipients = recipients;
+        send_multiplayer_chat_message(text.as_ptr());
+        *chat_message_type = old_type;
+        *chat_message_recipients = old_recipients;
+        Ok(())
+    }
+
+    unsafe fn send_command(&self, command: &[u8]) {
+        send_command(command.as_ptr(), command.len() as u32);
+    }
+
+    fn command_lengths(&self) -> &[u32] {
+        &bw::commands::COMMAND_LENGTHS[..bw::commands::COMMAND_COUNT_1161]
+    }
+
     unsafe fn storm_players(&self) -> Vec<bw::StormPlayer> {
         (*storm_players)[..].into()
     }
@@ -180,6 +328,17 @@ impl bw::Bw for Bw1161 {
     unsafe fn storm_set_last_error(&self, error: u32) {
         storm::SErrSetLastError(error);
     }
+
+    fn capabilities(&self) -> bw::Capabilities {
+        bw::Capabilities {
+            // The trigger action table isn't hooked on 1.16.1.
+            trigger_reports: false,
+            send_chat: true,
+            palette_kinds: true,
+            disable_frame_delay: true,
+            pause: true,
+        }
+    }
 }
 
 impl Bw1161 {
@@ -242,17 +401,22 @@ whack_funcs!(stdcall, init_funcs, 0x00400000,
     0x00470150 => init_team_game_playable_slots();
     0x00486580 => maybe_receive_turns();
     0x004F3280 => send_multiplayer_chat_message(@eax *const u8);
+    0x00485BD0 => send_command(@ecx *const u8, @edx u32);
 
     0x004CDE70 => add_to_replay_data(@eax *mut bw::ReplayData, @ebx *const u8, @edi u32, u32);
     0x0048D0C0 => display_message(@edi *const u8, @eax u32);
     0x004207B0 => clean_up_for_exit(@ebx u32);
 
+    // Updates rest of the screen state from screen_x/y
+    0x0049BFD0 => update_screen_position();
+
     // Unit id, base sprite
     0x00488410 => create_fow_sprite(u32, *mut c_void) -> *mut bw::FowSprite;
 );
 
 whack_vars!(init_vars, 0x00400000,
     0x0057F0F0 => game: bw::Game;
+    0x006D1260 => map_tile_flags: *mut u32;
     0x0057EE9C => local_player_name: [u8; 25];
     0x0057F0B4 => is_multiplayer: u8;
     0x0059BB70 => current_map_folder_path: [u8; 260];
@@ -273,6 +437,12 @@ whack_vars!(init_vars, 0x00400000,
     0x006D5BC4 => timeout_bin: *mut bw::Dialog;
     0x00512684 => local_nation_id: u32;
     0x006D0F14 => is_replay: u32;
+    0x005124D8 => game_speed_frame_delays: [u32; 0xe];
+    0x006509C4 => is_game_paused: u32;
+    0x006CDFD4 => game_speed: u32;
+    0x00597208 => client_selection: [*mut bw::Unit; 0xc];
+    0x0062848C => screen_x: u32;
+    0x006284A8 => screen_y: u32;
     0x00597248 => primary_selected: *mut bw::Unit;
     0x0057EE7C => storm_id_to_human_id: [u32; 8];
     0x00512678 => current_command_player: u32;
@@ -522,15 +692,20 @@ unsafe fn create_file_hook(
         count + 1,
         chrono::Local::now().format("%Y-%m-%d")
     );
-    CreateFileW(
-        windows::winapi_str(replay_folder.join(filename)).as_ptr(),
+    let path = replay_folder.join(filename);
+    let handle = CreateFileW(
+        windows::winapi_str(&path).as_ptr(),
         access,
         share_mode,
         security_attributes as *mut _,
         creation_disposition,
         flags,
         template,
-    )
+    );
+    if handle != INVALID_HANDLE_VALUE {
+        *LAST_REPLAY_PATH.lock().unwrap() = Some(path);
+    }
+    handle
 }
 
 fn initial_number(path: &OsStr) -> u32 {
@@ -12,7 +12,21 @@ pub unsafe fn process_commands_hook(
     replay: u32,
     orig: unsafe extern fn(*const u8, u32, u32),
 ) {
-    if replay == 0 && *bw_1161::current_command_player >= 8 {
+    let player = *bw_1161::current_command_player;
+    let lengths = &bw::commands::COMMAND_LENGTHS[..bw::commands::COMMAND_COUNT_1161];
+    if replay == 0 && player < 8 {
+        let slice = std::slice::from_raw_parts(data, len as usize);
+        for command in bw::commands::iter_commands(slice, lengths) {
+            crate::game_thread::on_player_command(player as u8, command);
+        }
+    }
+    if replay != 0 {
+        let slice = std::slice::from_raw_parts(data, len as usize);
+        for command in bw::commands::iter_commands(slice, lengths) {
+            crate::game_thread::on_replay_command(command);
+        }
+    }
+    if replay == 0 && player >= 8 {
         // Replace anything sent by observers with a keep alive command, I'm quite sure there will
         // be buffer overflows otherwise.
         let buf = [0x05u8];
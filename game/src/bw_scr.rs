@@ -1,5 +1,4 @@
 mod bw_hash_table;
-mod commands;
 mod file_hook;
 mod pe_image;
 mod sdf_cache;
@@ -8,19 +7,20 @@ mod thiscall;
 
 use std::marker::PhantomData;
 use std::mem;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::ptr::{null, null_mut};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering};
 
 use byteorder::{ByteOrder, LittleEndian};
 use libc::c_void;
+use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
 use scr_analysis::scarf;
 use smallvec::SmallVec;
 use winapi::um::libloaderapi::{GetModuleHandleW};
 
-use crate::bw::{self, Bw, FowSpriteIterator};
+use crate::bw::{self, commands, Bw, FowSpriteIterator};
 use crate::bw::unit::{Unit, UnitIterator};
 use crate::game_thread;
 use crate::snp;
@@ -31,6 +31,8 @@ use thiscall::Thiscall;
 
 const NET_PLAYER_COUNT: usize = 12;
 const SHADER_ID_MASK: u32 = 0x1c;
+/// Normal and alternate delays for each of the 7 game speeds.
+const GAME_SPEED_COUNT: usize = 0xe;
 
 pub struct BwScr {
     game: Value<*mut bw::Game>,
@@ -44,6 +46,7 @@ pub struct BwScr {
     game_state: Value<u8>,
     sprites_inited: Value<u8>,
     local_player_id: Value<u32>,
+    command_user: Value<u32>,
     local_unique_player_id: Value<u32>,
     local_storm_id: Value<u32>,
     net_player_to_game: Value<*mut u32>,
@@ -51,6 +54,16 @@ pub struct BwScr {
     local_player_name: Value<*mut u8>,
     fonts: Value<*mut *mut scr::Font>,
     first_active_unit: Value<*mut bw::Unit>,
+    client_selection: Value<*mut *mut bw::Unit>,
+    screen_x: Value<u32>,
+    screen_y: Value<u32>,
+    trigger_current_player: Option<Value<u32>>,
+    map_tile_flags: Option<Value<*mut u32>>,
+    game_speed_frame_delays: Option<Value<*mut u32>>,
+    is_game_paused: Option<Value<u32>>,
+    game_speed: Option<Value<u32>>,
+    replay_visions: Option<Value<u32>>,
+    player_visions: Option<Value<u32>>,
     sprites_by_y_tile: Value<*mut *mut scr::Sprite>,
     sprites_by_y_tile_end: Value<*mut *mut scr::Sprite>,
     sprite_x: (Value<*mut *mut scr::Sprite>, u32, scarf::MemAccessSize),
@@ -99,6 +112,9 @@ pub struct BwScr {
     prism_pixel_shaders: Vec<scarf::VirtualAddress>,
     prism_renderer_vtable: scarf::VirtualAddress,
     replay_minimap_patch: Option<scr_analysis::Patch>,
+    /// Trigger action table and the original functions in it, `None` if analysis
+    /// couldn't find either of them.
+    trigger_actions: Option<(scarf::VirtualAddress, Vec<scarf::VirtualAddress>)>,
     /// Some only if hd graphics are to be disabled
     open_file: Option<scarf::VirtualAddress>,
     lobby_create_callback_offset: usize,
@@ -108,6 +124,8 @@ pub struct BwScr {
     sdf_cache: Arc<InitSdfCache>,
     is_replay_seeking: AtomicBool,
     lobby_game_init_command_seen: AtomicBool,
+    lobby_game_init_seed: AtomicU32,
+    turn_rate: AtomicU8,
     shader_replaces: ShaderReplaces,
     renderer_state: Mutex<RendererState>,
 }
@@ -778,6 +796,7 @@ impl BwScr {
             .ok_or("Process lobby commands")?;
         let send_command = analysis.send_command().ok_or("send_command")?;
         let local_player_id = analysis.local_player_id().ok_or("Local player id")?;
+        let command_user = analysis.command_user().ok_or("command_user")?;
         let local_storm_id = analysis.local_storm_player_id().ok_or("Local storm id")?;
         let local_unique_player_id = analysis.local_unique_player_id()
             .ok_or("Local unique player id")?;
@@ -807,6 +826,17 @@ impl BwScr {
         let prism_renderer_vtable = analysis.prism_renderer_vtable().ok_or("Prism renderer")?;
 
         let first_active_unit = analysis.first_active_unit().ok_or("first_active_unit")?;
+        let client_selection = analysis.client_selection().ok_or("client_selection")?;
+        let screen_x = analysis.screen_x().ok_or("screen_x")?;
+        let screen_y = analysis.screen_y().ok_or("screen_y")?;
+        let trigger_actions = analysis.trigger_actions();
+        let trigger_current_player = analysis.trigger_current_player();
+        let map_tile_flags = analysis.map_tile_flags();
+        let game_speed_frame_delays = analysis.game_speed_frame_delays();
+        let is_game_paused = analysis.is_game_paused();
+        let game_speed = analysis.game_speed();
+        let replay_visions = analysis.replay_visions();
+        let player_visions = analysis.player_visions();
         let sprite_x = analysis.sprite_x().ok_or("sprite_x")?;
         let sprite_y = analysis.sprite_y().ok_or("sprite_y")?;
         let sprites_by_y_tile = analysis.sprites_by_y_tile_start()
@@ -869,6 +899,7 @@ impl BwScr {
             game_state: Value::new(ctx, game_state),
             sprites_inited: Value::new(ctx, sprites_inited),
             local_player_id: Value::new(ctx, local_player_id),
+            command_user: Value::new(ctx, command_user),
             local_unique_player_id: Value::new(ctx, local_unique_player_id),
             local_storm_id: Value::new(ctx, local_storm_id),
             net_player_to_game: Value::new(ctx, net_player_to_game),
@@ -876,6 +907,16 @@ impl BwScr {
             local_player_name: Value::new(ctx, local_player_name),
             fonts: Value::new(ctx, fonts),
             first_active_unit: Value::new(ctx, first_active_unit),
+            client_selection: Value::new(ctx, client_selection),
+            screen_x: Value::new(ctx, screen_x),
+            screen_y: Value::new(ctx, screen_y),
+            trigger_current_player: trigger_current_player.map(|x| Value::new(ctx, x)),
+            map_tile_flags: map_tile_flags.map(|x| Value::new(ctx, x)),
+            game_speed_frame_delays: game_speed_frame_delays.map(|x| Value::new(ctx, x)),
+            is_game_paused: is_game_paused.map(|x| Value::new(ctx, x)),
+            game_speed: game_speed.map(|x| Value::new(ctx, x)),
+            replay_visions: replay_visions.map(|x| Value::new(ctx, x)),
+            player_visions: player_visions.map(|x| Value::new(ctx, x)),
             sprites_by_y_tile: Value::new(ctx, sprites_by_y_tile),
             sprites_by_y_tile_end: Value::new(ctx, sprites_by_y_tile_end),
             sprite_x: (Value::new(ctx, sprite_x.0), sprite_x.1, sprite_x.2),
@@ -913,10 +954,13 @@ impl BwScr {
             prism_pixel_shaders,
             prism_renderer_vtable,
             replay_minimap_patch,
+            trigger_actions,
             starcraft_tls_index: SendPtr(starcraft_tls_index),
             sdf_cache,
             is_replay_seeking: AtomicBool::new(false),
             lobby_game_init_command_seen: AtomicBool::new(false),
+            lobby_game_init_seed: AtomicU32::new(0),
+            turn_rate: AtomicU8::new(0),
             shader_replaces: ShaderReplaces::new(),
             renderer_state: Mutex::new(RendererState {
                 renderer: None,
@@ -967,7 +1011,11 @@ impl BwScr {
             move |data, len, are_recorded_replay_commands, orig| {
                 let slice = std::slice::from_raw_parts(data, len);
                 if are_recorded_replay_commands == 0 {
+                    let player = this.command_user.resolve();
                     for command in commands::iter_commands(slice, &this.game_command_lengths) {
+                        if player < 8 {
+                            game_thread::on_player_command(player as u8, command);
+                        }
                         match command {
                             [commands::id::REPLAY_SEEK, rest @ ..] if rest.len() == 4 => {
                                 let frame = LittleEndian::read_u32(rest);
@@ -979,6 +1027,10 @@ impl BwScr {
                             _ => (),
                         }
                     }
+                } else {
+                    for command in commands::iter_commands(slice, &this.game_command_lengths) {
+                        game_thread::on_replay_command(command);
+                    }
                 }
                 orig(data, len, are_recorded_replay_commands);
             },
@@ -1012,6 +1064,10 @@ impl BwScr {
                 let slice = std::slice::from_raw_parts(data, len);
                 if let Some(&byte) = slice.get(0) {
                     if byte == 0x48 && player == 0 {
+                        if let Some(seed) = slice.get(1..5) {
+                            let seed = LittleEndian::read_u32(seed);
+                            this.lobby_game_init_seed.store(seed, Ordering::Relaxed);
+                        }
                         this.lobby_game_init_command_seen.store(true, Ordering::Relaxed);
                     }
                 }
@@ -1041,6 +1097,7 @@ impl BwScr {
         }
 
         self.clone().patch_shaders(&mut exe, base);
+        self.patch_trigger_actions(&mut exe, base);
 
         sdf_cache::apply_sdf_cache_hooks(&self, &mut exe, base);
 
@@ -1056,6 +1113,12 @@ impl BwScr {
                 create_event_hook,
                 address - kernel32_base,
             );
+            let address = kernel32.proc_address("CreateFileW").unwrap() as usize;
+            patcher.hook_closure_address(
+                CreateFileW,
+                create_file_hook,
+                address - kernel32_base,
+            );
         }
         crate::forge::init_hooks_scr(&mut active_patcher);
         debug!("Patched.");
@@ -1070,8 +1133,13 @@ impl BwScr {
         let relative = *renderer_vtable.add(0x7) - base;
         let this = self.clone();
         exe.hook_closure_address(Renderer_Render, move |renderer, commands, width, height, orig| {
+            if crate::game_thread::is_headless() {
+                // Nothing is shown in headless games, so skip drawing altogether.
+                // The watchdog still sees the steps, so it doesn't need to know about renders.
+                return 1;
+            }
             if this.shader_replaces.has_changed() {
-                // Hot reload shaders.
+                // Hot reload shaders, or swap them to match the selected palette.
                 // Unfortunately repatching the .exe to replace shader sets in BW
                 // memory is not currently possible.
                 // Will have to write over the previously allocated scr::PrismShader slice
@@ -1118,7 +1186,10 @@ impl BwScr {
                     cmd.shader_constants[0] = use_new_mask;
                 }
             }
-            orig(renderer, commands, width, height)
+            crate::game_thread::watchdog::frame_rendered();
+            let result = orig(renderer, commands, width, height);
+            crate::forge::frame_presented();
+            result
         }, relative);
 
         // CreateShader hook
@@ -1149,6 +1220,23 @@ impl BwScr {
         }
     }
 
+    /// Points every entry of the trigger action table to `trigger_action_hook`.
+    /// Patching the table instead of hooking the functions avoids issues with actions
+    /// that share a function.
+    unsafe fn patch_trigger_actions(&self, exe: &mut whack::ModulePatcher<'_>, base: usize) {
+        let (table, functions, player) =
+            match (&self.trigger_actions, self.trigger_current_player) {
+                (Some((table, functions)), Some(player)) => (*table, functions, player),
+                _ => return,
+            };
+        let originals = functions.iter().map(|x| x.0 as usize).collect();
+        let _ = TRIGGER_ACTIONS.set((originals, player));
+        for i in 0..functions.len() {
+            let relative = table.0 as usize - base + i * mem::size_of::<usize>();
+            exe.replace_val(relative, trigger_action_hook as usize);
+        }
+    }
+
     unsafe fn update_nation_and_human_ids(&self) {
         let net_player_to_game = self.net_player_to_game.resolve();
         let net_player_to_unique = self.net_player_to_unique.resolve();
@@ -1325,6 +1413,35 @@ impl BwScr {
 }
 
 impl bw::Bw for BwScr {
+    unsafe fn disable_frame_delay(&self) -> bool {
+        let delays = match self.game_speed_frame_delays {
+            Some(x) => x.resolve(),
+            None => return false,
+        };
+        for i in 0..GAME_SPEED_COUNT {
+            *delays.add(i) = 0;
+        }
+        true
+    }
+
+    unsafe fn set_paused(&self, paused: bool) -> bool {
+        match self.is_game_paused {
+            Some(x) => {
+                x.write(paused as u32);
+                true
+            }
+            None => false,
+        }
+    }
+
+    unsafe fn is_paused(&self) -> Option<bool> {
+        self.is_game_paused.map(|x| x.resolve() != 0)
+    }
+
+    unsafe fn game_speed(&self) -> Option<u8> {
+        self.game_speed.map(|x| x.resolve() as u8)
+    }
+
     unsafe fn run_game_loop(&self) {
         loop {
             self.game_state.write(3); // Playing
@@ -1405,6 +1522,23 @@ impl bw::Bw for BwScr {
         }
     }
 
+    unsafe fn game_seed(&self) -> u32 {
+        self.lobby_game_init_seed.load(Ordering::Relaxed)
+    }
+
+    fn replay_format(&self) -> crate::replay::ReplayFormat {
+        crate::replay::ReplayFormat::Scr
+    }
+
+    fn set_turn_rate(&self, turn_rate: u8) -> bool {
+        self.turn_rate.store(turn_rate, Ordering::Relaxed);
+        true
+    }
+
+    fn turn_rate(&self) -> u8 {
+        self.turn_rate.load(Ordering::Relaxed)
+    }
+
     unsafe fn create_lobby(
         &self,
         map_path: &Path,
@@ -1416,6 +1550,7 @@ impl bw::Bw for BwScr {
         init_bw_string(&mut game_input.password, b"");
         game_input.speed = 6;
         game_input.game_type_subtype = game_type.as_u32();
+        game_input.turn_rate = self.turn_rate.load(Ordering::Relaxed) as u32;
 
         let map_dir = match map_path.parent() {
             Some(s) => s.into(),
@@ -1525,9 +1660,8 @@ impl bw::Bw for BwScr {
         add_param(b"map_tile_set", input_game_info.tileset as u32);
         add_param(b"map_width", input_game_info.map_width as u32);
         add_param(b"map_height", input_game_info.map_height as u32);
-        // Not sure if we want to change this one day. I think 0 means dynamic,
-        // which is assumed by the host as well AFAIK.
-        add_param(b"net_turn_rate", 0);
+        // 0 means dynamic. Should match what the host used.
+        add_param(b"net_turn_rate", self.turn_rate.load(Ordering::Relaxed) as u32);
         // TODO: This is actually important for EUD maps. Host gets it set correctly
         // automatically, but I think we need more code here to support EUD maps.
         add_param(b"flags", 0);
@@ -1631,6 +1765,10 @@ impl bw::Bw for BwScr {
         self.players.resolve()
     }
 
+    unsafe fn local_player_id(&self) -> u32 {
+        self.local_player_id.resolve()
+    }
+
     unsafe fn set_player_name(&self, id: u8, name: &str) {
         let mut buffer = [0; 0x60];
         for (i, &byte) in name.as_bytes().iter().take(0x5f).enumerate() {
@@ -1646,16 +1784,57 @@ impl bw::Bw for BwScr {
         long_name.copy_from_slice(&buffer[..0x60]);
     }
 
+    unsafe fn player_name(&self, id: u8) -> String {
+        let players = self.players();
+        let player_names = players.add(0x10) as *const u8;
+        let long_name = std::slice::from_raw_parts(player_names.add(id as usize * 0x60), 0x60);
+        let len = long_name.iter().position(|&x| x == 0).unwrap_or(long_name.len());
+        String::from_utf8_lossy(&long_name[..len]).into_owned()
+    }
+
     unsafe fn active_units(&self) -> UnitIterator {
         UnitIterator::new(Unit::from_ptr(self.first_active_unit.resolve()))
     }
 
+    unsafe fn client_selection(&self) -> Vec<Unit> {
+        let selection = self.client_selection.resolve();
+        (0..12).filter_map(|i| Unit::from_ptr(*selection.add(i))).collect()
+    }
+
+    unsafe fn screen_position(&self) -> bw::Point {
+        bw::Point {
+            x: self.screen_x.resolve() as i16,
+            y: self.screen_y.resolve() as i16,
+        }
+    }
+
+    fn game_screen_size(&self) -> (u16, u16) {
+        // Analysis doesn't find the size for widescreen or zoomed views, use the
+        // 4:3 size which is fine at least when clamping to the map.
+        (640, 400)
+    }
+
+    unsafe fn set_screen_position(&self, position: bw::Point) -> bool {
+        // Analysis doesn't find SC:R's function for moving the screen, but the renderer
+        // uses these directly. Same 8 pixel steps as BW normally scrolls in.
+        self.screen_x.write((position.x as u32) & !7);
+        self.screen_y.write((position.y as u32) & !7);
+        true
+    }
+
     unsafe fn fow_sprites(&self) -> FowSpriteIterator {
         FowSpriteIterator::new(self.active_fow_sprites.start.resolve())
     }
 
-    unsafe fn create_fow_sprite(&self, unit: Unit) {
-        self.create_fow_sprite_main(unit);
+    unsafe fn create_fow_sprite(&self, unit: Unit) -> Option<()> {
+        self.create_fow_sprite_main(unit)
+    }
+
+    unsafe fn map_tile_flags(&self) -> *mut u32 {
+        match self.map_tile_flags {
+            Some(x) => x.resolve(),
+            None => null_mut(),
+        }
     }
 
     unsafe fn sprite_position(&self, sprite: *mut c_void) -> bw::Point {
@@ -1666,6 +1845,49 @@ impl bw::Bw for BwScr {
         }
     }
 
+    unsafe fn sprite_visibility_mask(&self, sprite: *mut c_void) -> u8 {
+        (*(sprite as *mut scr::Sprite)).visibility_mask
+    }
+
+    fn last_replay_path(&self) -> Option<PathBuf> {
+        LAST_REPLAY_PATH.lock().clone()
+    }
+
+    fn clear_last_replay_path(&self) {
+        *LAST_REPLAY_PATH.lock() = None;
+    }
+
+    fn set_palette(&self, kind: crate::forge::PaletteKind) {
+        // SC:R renders in true color, so there's no palette to change; instead a variant
+        // of our fog mask shader that doesn't rely on hue gets swapped in on next render.
+        // Changing colors of everything else would require replacing BW's own shaders.
+        self.shader_replaces.set_palette(kind);
+    }
+
+    unsafe fn set_local_visions(&self, mask: u8) -> bool {
+        match (self.replay_visions, self.player_visions) {
+            (Some(replay_visions), Some(player_visions)) => {
+                replay_visions.write(mask as u32);
+                player_visions.write(mask as u32);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    unsafe fn send_chat(&self, _message: &str, _recipients: u8) -> Result<(), bw::SendChatError> {
+        // Analysis doesn't find the chat sending function yet.
+        Err(bw::SendChatError::Unsupported)
+    }
+
+    unsafe fn send_command(&self, command: &[u8]) {
+        (self.send_command)(command.as_ptr(), command.len());
+    }
+
+    fn command_lengths(&self) -> &[u32] {
+        &self.game_command_lengths
+    }
+
     unsafe fn storm_players(&self) -> Vec<bw::StormPlayer> {
         let ptr = self.storm_players.resolve();
         let scr_players = std::slice::from_raw_parts(ptr, NET_PLAYER_COUNT);
@@ -1694,6 +1916,19 @@ impl bw::Bw for BwScr {
     unsafe fn storm_set_last_error(&self, error: u32) {
         *self.storm_last_error_ptr() = error;
     }
+
+    fn capabilities(&self) -> bw::Capabilities {
+        bw::Capabilities {
+            trigger_reports: self.trigger_actions.is_some() &&
+                self.trigger_current_player.is_some(),
+            // Analysis doesn't find the chat sending function yet.
+            send_chat: false,
+            // See `set_palette`.
+            palette_kinds: false,
+            disable_frame_delay: self.game_speed_frame_delays.is_some(),
+            pause: self.is_game_paused.is_some(),
+        }
+    }
 }
 
 fn create_event_hook(
@@ -1718,6 +1953,55 @@ fn create_event_hook(
     }
 }
 
+lazy_static::lazy_static! {
+    /// Where `create_file_hook` saw the latest LastReplay.rep get written.
+    static ref LAST_REPLAY_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+/// Remembers where SC:R writes LastReplay.rep to, it is saved at end of every game
+/// like in 1.16.1, but the location depends on SC:R's settings.
+fn create_file_hook(
+    filename: *const u16,
+    access: u32,
+    share_mode: u32,
+    security_attributes: *mut c_void,
+    creation_disposition: u32,
+    flags: u32,
+    template: *mut c_void,
+    orig: unsafe extern fn(
+        *const u16, u32, u32, *mut c_void, u32, u32, *mut c_void,
+    ) -> *mut c_void,
+) -> *mut c_void {
+    unsafe {
+        use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+        use winapi::um::winnt::GENERIC_WRITE;
+
+        let handle = orig(
+            filename,
+            access,
+            share_mode,
+            security_attributes,
+            creation_disposition,
+            flags,
+            template,
+        );
+        if !filename.is_null() && access & GENERIC_WRITE != 0 && handle != INVALID_HANDLE_VALUE {
+            let len = (0..).find(|&i| *filename.add(i) == 0).unwrap();
+            let filename = std::slice::from_raw_parts(filename, len);
+            let name_start = filename
+                .iter()
+                .rposition(|&x| x == b'\\' as u16 || x == b'/' as u16)
+                .map(|x| x + 1)
+                .unwrap_or(0);
+            if ascii_compare_u16_u8(&filename[name_start..], b"LastReplay.rep") {
+                let path = PathBuf::from(crate::windows::os_string_from_winapi(filename));
+                *LAST_REPLAY_PATH.lock() = Some(path);
+            }
+        }
+        handle
+    }
+}
+
 fn ascii_compare_u16_u8(a: &[u16], b: &[u8]) -> bool {
     if a.len() != b.len() {
         return false;
@@ -1753,6 +2037,26 @@ fn load_snp_list_hook(
     }
 }
 
+/// Original trigger action functions, and the player whose triggers are being executed.
+static TRIGGER_ACTIONS: OnceCell<(Vec<usize>, Value<u32>)> = OnceCell::new();
+
+unsafe extern "fastcall" fn trigger_action_hook(action: *mut bw::TriggerAction) -> u32 {
+    let (functions, player) = match TRIGGER_ACTIONS.get() {
+        Some(s) => s,
+        None => return 0,
+    };
+    let id = (*action).action_id;
+    game_thread::on_trigger_action(player.resolve() as u8, id as u16);
+    match functions.get(id as usize) {
+        Some(&orig) => {
+            let orig: unsafe extern "fastcall" fn(*mut bw::TriggerAction) -> u32 =
+                mem::transmute(orig);
+            orig(action)
+        }
+        None => 0,
+    }
+}
+
 static SNP_FUNCTIONS: scr::SnpFunctions = scr::SnpFunctions {
     unk0: 0,
     free_packet: snp::free_packet,
@@ -1859,6 +2163,9 @@ mod hooks {
     whack_hooks!(stdcall, 0,
         !0 => LoadSnpList(*mut scr::SnpLoadFuncs, u32) -> u32;
         !0 => CreateEventW(*mut c_void, u32, u32, *const u16) -> *mut c_void;
+        !0 => CreateFileW(
+            *const u16, u32, u32, *mut c_void, u32, u32, *mut c_void,
+        ) -> *mut c_void;
         !0 => StepIo(@ecx *mut c_void);
         !0 => Renderer_Render(@ecx *mut c_void, *mut scr::DrawCommands, u32, u32) -> u32;
         !0 => Renderer_CreateShader(
@@ -1,7 +1,10 @@
 //! SC:R shader replacing code. Debug builds are compiled to support hot reloading
 //! of shaders, release builds always just serve precompiled binary data.
 
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use super::scr;
+use crate::forge::PaletteKind;
 
 #[cfg(debug_assertions)]
 use std::path::{Path, PathBuf};
@@ -37,20 +40,76 @@ static MASK: &[scr::PrismShader] = &[
     pixel_sm5(MASK_SM5_BIN),
 ];
 
+static MASK_COLORBLIND_SM4_BIN: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/mask_colorblind.sm4.bin"));
+static MASK_COLORBLIND_SM5_BIN: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/mask_colorblind.sm5.bin"));
+static MASK_COLORBLIND: &[scr::PrismShader] = &[
+    pixel_sm4(MASK_COLORBLIND_SM4_BIN),
+    pixel_sm5(MASK_COLORBLIND_SM5_BIN),
+];
+
 static PATCHED_SHADERS: &[(u8, &[scr::PrismShader], &str)] = &[
     (0x1c, MASK, "mask"),
 ];
 
+/// Used instead of the shaders in `PATCHED_SHADERS` when a colorblind palette is selected.
+/// These aren't hot reloaded in debug builds.
+static COLORBLIND_SHADERS: &[(u8, &[scr::PrismShader])] = &[
+    (0x1c, MASK_COLORBLIND),
+];
+
+/// Selected palette, shared between debug and release `ShaderReplaces`
+struct PaletteState {
+    colorblind: AtomicBool,
+    changed: AtomicBool,
+}
+
+impl PaletteState {
+    fn new() -> PaletteState {
+        PaletteState {
+            colorblind: AtomicBool::new(false),
+            changed: AtomicBool::new(false),
+        }
+    }
+
+    fn shader(&self, id: u8, default: &'static [scr::PrismShader]) -> &'static [scr::PrismShader] {
+        if self.colorblind.load(Ordering::Relaxed) {
+            COLORBLIND_SHADERS
+                .iter()
+                .find(|x| x.0 == id)
+                .map(|x| x.1)
+                .unwrap_or(default)
+        } else {
+            default
+        }
+    }
+}
+
+impl ShaderReplaces {
+    /// Makes the next `has_changed` call return true if the shaders used for `kind`
+    /// differ from the current ones.
+    pub fn set_palette(&self, kind: PaletteKind) {
+        let colorblind = kind != PaletteKind::Default;
+        if self.palette.colorblind.swap(colorblind, Ordering::Relaxed) != colorblind {
+            self.palette.changed.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
 #[cfg(debug_assertions)]
 pub struct ShaderReplaces {
     shaders: Mutex<Vec<(u8, &'static [scr::PrismShader], Option<(PathBuf, SystemTime)>)>>,
     // Update checking requires windows i/o for every shader file, doing that every
     // rendered frame is likely a bit excessive.
     update_throttle: Mutex<Instant>,
+    palette: PaletteState,
 }
 
 #[cfg(not(debug_assertions))]
-pub struct ShaderReplaces;
+pub struct ShaderReplaces {
+    palette: PaletteState,
+}
 
 #[cfg(debug_assertions)]
 impl ShaderReplaces {
@@ -71,6 +130,7 @@ impl ShaderReplaces {
         ShaderReplaces {
             shaders: Mutex::new(shaders),
             update_throttle: Mutex::new(Instant::now()),
+            palette: PaletteState::new(),
         }
     }
 
@@ -92,12 +152,18 @@ impl ShaderReplaces {
                     }
                 }
             }
-            shaders.iter().map(|&(id, data, _)| (id, data)).collect::<Vec<_>>()
+            shaders
+                .iter()
+                .map(|&(id, data, _)| (id, self.palette.shader(id, data)))
+                .collect::<Vec<_>>()
         };
         result.into_iter()
     }
 
     pub fn has_changed(&self) -> bool {
+        if self.palette.changed.swap(false, Ordering::Relaxed) {
+            return true;
+        }
         let now = Instant::now();
         let mut last_check = self.update_throttle.lock();
         if now.duration_since(*last_check) < Duration::from_millis(500) {
@@ -183,14 +249,16 @@ fn file_changed_time(path: &Path) -> Option<SystemTime> {
 #[cfg(not(debug_assertions))]
 impl ShaderReplaces {
     pub fn new() -> ShaderReplaces {
-        ShaderReplaces
+        ShaderReplaces {
+            palette: PaletteState::new(),
+        }
     }
 
-    pub fn iter_shaders(&self) -> impl Iterator<Item = (u8, &'static [scr::PrismShader])> {
-        PATCHED_SHADERS.iter().map(|x| (x.0, x.1))
+    pub fn iter_shaders(&self) -> impl Iterator<Item = (u8, &'static [scr::PrismShader])> + '_ {
+        PATCHED_SHADERS.iter().map(move |x| (x.0, self.palette.shader(x.0, x.1)))
     }
 
     pub fn has_changed(&self) -> bool {
-        false
+        self.palette.changed.swap(false, Ordering::Relaxed)
     }
 }
@@ -41,12 +41,19 @@ PS_OUTPUT main(VS_OUTPUT v)
         float highMaskValue = tanh(max(maskValue - MIN_FOG_MASK, 0.0) / (1.0 - MIN_FOG_MASK) * 6) *
             (MAX_FOG_MASK - MIN_FOG_MASK);
         maskValue = lowMaskValue + highMaskValue;
+#ifdef COLORBLIND
+        // The blue tint isn't visible to everyone, so separate unexplored areas by
+        // making them slightly lighter instead, which doesn't depend on hue.
+        float tint = smoothstep(MIN_FOG_MASK, MAX_FOG_MASK, maskValue) * 0.05;
+        o.frag_color = float4(tint, tint, tint, maskValue);
+#else
         o.frag_color = float4(
             0.0,
             0.0,
             // We add a slight amount of blue to unexplored areas to further increase the contrast
             smoothstep(MIN_FOG_MASK, MAX_FOG_MASK, maskValue) * 0.03,
             maskValue);
+#endif
     }
     return o;
 }
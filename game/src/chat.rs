@@ -2,12 +2,21 @@ use std::sync::Mutex;
 
 use lazy_static::lazy_static;
 
+use crate::bw::StormPlayerId;
+
+/// Longest chat message BW accepts, in bytes, not including the null terminator.
+pub const MAX_CHAT_MESSAGE_LENGTH: usize = 79;
+
 lazy_static! {
     static ref ALLY_OVERRIDE: Mutex<Option<u8>> = Mutex::new(None);
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub struct StormPlayerId(pub u8);
+pub enum ChatRecipients {
+    All,
+    Allies,
+    Player(StormPlayerId),
+}
 
 pub fn set_ally_override(storm_ids: &[StormPlayerId]) {
     let mut bits = 0u8;
@@ -25,3 +34,45 @@ pub fn clear_ally_override() {
 pub fn get_ally_override() -> Option<u8> {
     ALLY_OVERRIDE.lock().unwrap().clone()
 }
+
+/// Splits `text` to parts that fit in a single chat message. Splits at spaces if possible,
+/// and never in middle of an UTF-8 character.
+pub fn split_message(text: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut rest = text.trim();
+    while rest.len() > MAX_CHAT_MESSAGE_LENGTH {
+        let mut end = MAX_CHAT_MESSAGE_LENGTH;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if let Some(space) = rest[..end].rfind(' ') {
+            if space != 0 {
+                end = space;
+            }
+        }
+        result.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    if !rest.is_empty() {
+        result.push(rest);
+    }
+    result
+}
+
+#[test]
+fn split_long_messages() {
+    assert_eq!(split_message("gl hf"), vec!["gl hf"]);
+    assert!(split_message("   ").is_empty());
+
+    let words = "word ".repeat(30);
+    let parts = split_message(&words);
+    assert_eq!(parts.len(), 2);
+    assert!(parts.iter().all(|x| x.len() <= MAX_CHAT_MESSAGE_LENGTH && !x.ends_with(' ')));
+    assert_eq!(parts.join(" "), words.trim());
+
+    // No spaces, and a multi-byte character crossing the length limit
+    let text = format!("{}ä{}", "a".repeat(MAX_CHAT_MESSAGE_LENGTH - 1), "b".repeat(10));
+    let parts = split_message(&text);
+    assert_eq!(parts[0].len(), MAX_CHAT_MESSAGE_LENGTH - 1);
+    assert_eq!(parts[1], format!("ä{}", "b".repeat(10)));
+}
@@ -118,7 +118,8 @@ struct Vertices {
 pub struct Renderer {
     device: Device,
     swap_chain: SwapChain,
-    back_buffer_view: RenderTargetView,
+    /// Only `None` while the back buffer is being resized.
+    back_buffer_view: Option<RenderTargetView>,
     shaders: Shaders,
     textures: Textures,
     vertices: Vertices,
@@ -126,6 +127,11 @@ pub struct Renderer {
     final_viewport: D3D11_VIEWPORT,
     ddraw_width: u32,
     ddraw_height: u32,
+    /// Window client area size, which the back buffer is scaled to when presented.
+    client_width: u32,
+    client_height: u32,
+    /// Area of the client rect that BW's frame is drawn to, before `render_scale`.
+    output_rect: RECT,
 }
 
 fn create_device_and_swap_chain(
@@ -353,6 +359,17 @@ impl SwapChain {
     }
 }
 
+fn scaled_viewport(rect: &RECT, scale: f32) -> D3D11_VIEWPORT {
+    D3D11_VIEWPORT {
+        Width: (rect.right - rect.left) as f32 * scale,
+        Height: (rect.bottom - rect.top) as f32 * scale,
+        MinDepth: 0.0,
+        MaxDepth: 0.0,
+        TopLeftX: rect.left as f32 * scale,
+        TopLeftY: rect.top as f32 * scale,
+    }
+}
+
 unsafe fn client_rect(window: HWND) -> RECT {
     let mut rect: RECT = mem::zeroed();
     GetClientRect(window, &mut rect);
@@ -386,14 +403,7 @@ impl Renderer {
             .create_render_target_view(&back_buffer)
             .context("Creating render target view for back buffer")?;
         let output_rect = settings.get_output_size(&client_rect, ddraw_width, ddraw_height);
-        let final_viewport = D3D11_VIEWPORT {
-            Width: (output_rect.right - output_rect.left) as f32,
-            Height: (output_rect.bottom - output_rect.top) as f32,
-            MinDepth: 0.0,
-            MaxDepth: 0.0,
-            TopLeftX: output_rect.left as f32,
-            TopLeftY: output_rect.top as f32,
-        };
+        let final_viewport = scaled_viewport(&output_rect, 1.0);
         let shaders = Shaders::init(&device).context("Creating shaders")?;
         let textures =
             Textures::init(&device, ddraw_width, ddraw_height).context("Creating textures")?;
@@ -401,7 +411,7 @@ impl Renderer {
         Ok(Renderer {
             device,
             swap_chain,
-            back_buffer_view,
+            back_buffer_view: Some(back_buffer_view),
             shaders,
             textures,
             vertices,
@@ -409,10 +419,49 @@ impl Renderer {
             final_viewport,
             ddraw_width,
             ddraw_height,
+            client_width: width,
+            client_height: height,
+            output_rect,
         })
     }
 
+    /// Resizes the back buffer to `scale` times the window size. Must not be called
+    /// while a frame is being drawn, as all references to the back buffer have to be
+    /// released first.
+    unsafe fn resize_back_buffer(&mut self, scale: f32) -> Result<()> {
+        let width = ((self.client_width as f32 * scale) as u32).max(1);
+        let height = ((self.client_height as f32 * scale) as u32).max(1);
+        (**self.device.1).OMSetRenderTargets(0, null_mut(), null_mut());
+        self.back_buffer_view = None;
+        let result = (**self.swap_chain.0).ResizeBuffers(
+            1,
+            width,
+            height,
+            DXGI_FORMAT_R8G8B8A8_UNORM,
+            0,
+        );
+        if result != S_OK {
+            return Err(Error::from_code(result)).context("Resizing back buffer");
+        }
+        let back_buffer = self
+            .swap_chain
+            .back_buffer_texture()
+            .context("Accessing back buffer texture")?;
+        let back_buffer_view = self
+            .device
+            .create_render_target_view(&back_buffer)
+            .context("Creating render target view for back buffer")?;
+        self.back_buffer_view = Some(back_buffer_view);
+        self.final_viewport = scaled_viewport(&self.output_rect, scale);
+        Ok(())
+    }
+
     unsafe fn try_render(&mut self, pixels: &[u8]) -> Result<()> {
+        let back_buffer_view = match self.back_buffer_view {
+            Some(ref s) => *s.0,
+            // A failed resize, already reported
+            None => return Ok(()),
+        };
         assert_eq!(
             pixels.len(),
             self.ddraw_width as usize * self.ddraw_height as usize
@@ -463,7 +512,7 @@ impl Renderer {
         (**self.device.1).PSSetShaderResources(0, 2, pixel_shader_resources.as_ptr());
 
         // Render the depalettized texture
-        let render_targets = [*self.back_buffer_view.0];
+        let render_targets = [back_buffer_view];
         let viewports = [self.final_viewport];
         let pixel_shader_resources = [*self.textures.rendered_texture_view.0];
         let samplers = [*self.textures.sampler.0];
@@ -786,4 +835,10 @@ impl RenderApi for Renderer {
             error!("Rendering error {}", e);
         }
     }
+
+    fn set_render_scale(&mut self, scale: f32) {
+        if let Err(e) = unsafe { self.resize_back_buffer(scale) } {
+            error!("Couldn't change render scale: {}", e);
+        }
+    }
 }
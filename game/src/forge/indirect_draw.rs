@@ -396,6 +396,29 @@ impl IndirectDraw {
         }
         None
     }
+
+    /// Returns the current contents of the primary surface converted to RGBA,
+    /// without affecting whether the frame is considered new.
+    pub unsafe fn current_frame_rgba(&self) -> Option<(u32, u32, Vec<u8>)> {
+        let surface = self.primary_surface?;
+        let palette = (*surface).palette?;
+        let entries = &(*palette).entries;
+        let desc = (*surface).surface_desc;
+        let width = desc.dwWidth;
+        let height = desc.dwHeight;
+        let pitch = desc.lPitch as usize;
+        if pitch == 0 {
+            return None;
+        }
+        let mut out = Vec::with_capacity(width as usize * height as usize * 4);
+        for row in (*surface).surface_data.chunks(pitch).take(height as usize) {
+            for &index in row.iter().take(width as usize) {
+                let color = entries.get(index as usize)?;
+                out.extend_from_slice(&[color.peRed, color.peGreen, color.peBlue, 0xff]);
+            }
+        }
+        Some((width, height, out))
+    }
 }
 
 impl Drop for IndirectDraw {
@@ -425,6 +448,10 @@ unsafe fn maybe_initialize_renderer(this: *mut IndirectDraw) {
         if (*this).display_width != 0 {
             debug!("IndirectDraw ready to initialize renderer");
             super::with_forge(|forge| {
+                if forge.headless {
+                    debug!("Headless, not initializing renderer");
+                    return;
+                }
                 forge.renderer.initialize(
                     this,
                     window,
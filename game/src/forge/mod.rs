@@ -1,5 +1,6 @@
 mod direct_x;
 mod indirect_draw;
+mod palette;
 mod renderer;
 
 use std::cell::Cell;
@@ -7,11 +8,12 @@ use std::ffi::CStr;
 use std::io;
 use std::mem;
 use std::ptr::{null, null_mut};
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 use std::sync::{Mutex};
 
 use lazy_static::lazy_static;
 use libc::c_void;
+use serde::Deserialize;
 
 use winapi::shared::guiddef::GUID;
 use winapi::shared::minwindef::{ATOM, FARPROC, HINSTANCE, HMODULE};
@@ -29,6 +31,8 @@ use crate::windows::os_string_from_winapi;
 
 use self::renderer::Renderer;
 
+pub use self::palette::PaletteKind;
+
 mod hooks {
     use super::{
         c_void, ATOM, FARPROC, HBITMAP, HDC, HGDIOBJ, HINSTANCE, HMENU, HMODULE, HWND, POINT, RECT,
@@ -52,6 +56,7 @@ mod hooks {
     whack_export!(pub extern "system" ReleaseCapture() -> u32);
     whack_export!(pub extern "system" ShowWindow(HWND, i32) -> u32);
     whack_export!(pub extern "system" GetKeyState(i32) -> i32);
+    whack_export!(pub extern "system" MessageBoxA(HWND, *const i8, *const i8, u32) -> i32);
     whack_export!(pub extern "system" CreateCompatibleBitmap(HDC, i32, i32) -> HBITMAP);
     whack_export!(pub extern "system" DeleteObject(HGDIOBJ) -> u32);
     whack_export!(pub extern "system" GetObjectA(HGDIOBJ, u32, *mut c_void) -> u32);
@@ -76,6 +81,7 @@ mod scr_hooks {
         !0 => SetWindowPos(HWND, HWND, i32, i32, i32, i32, u32) -> u32;
         !0 => SetCursorPos(i32, i32) -> i32;
         !0 => GetWindowLongW(HWND, i32) -> u32;
+        !0 => MessageBoxW(HWND, *const u16, *const u16, u32) -> i32;
     );
 }
 
@@ -122,6 +128,9 @@ unsafe extern "system" fn wnd_proc(window: HWND, msg: u32, wparam: usize, lparam
         return DefWindowProcA(window, msg, wparam, lparam);
     }
 
+    if check_clip_marker_hotkey(msg, wparam, lparam) {
+        return 0;
+    }
     let mut lparam = lparam;
     match msg {
         WM_NCHITTEST => {
@@ -160,6 +169,11 @@ unsafe extern "system" fn wnd_proc(window: HWND, msg: u32, wparam: usize, lparam
             }
         }
         WM_MOVE => {
+            // Minimized windows get moved to (-32000, -32000), keep the position
+            // that the window will be restored to instead.
+            if IsIconic(window) != 0 {
+                return DefWindowProcA(window, msg, wparam, lparam);
+            }
             let x = (lparam & 0xffff) as i16 as i32;
             let y = (lparam >> 16) as i16 as i32;
             with_forge(|forge| {
@@ -201,7 +215,14 @@ unsafe extern "system" fn wnd_proc(window: HWND, msg: u32, wparam: usize, lparam
             let y = (lparam >> 16) as i16;
             let (fake_x, fake_y) = with_forge(|forge| {
                 // cache the actual mouse position for GetCursorPos
-                forge.real_cursor_pos = (x, y);
+                // (With raw input, the position is updated from WM_INPUT instead, unless
+                // the cursor isn't controlling the game at the moment)
+                let (x, y) = if forge.raw_input && forge.window_active {
+                    forge.real_cursor_pos
+                } else {
+                    forge.real_cursor_pos = (x, y);
+                    (x, y)
+                };
                 if forge.should_clip_cursor {
                     let clip_rect = forge.stored_cursor_rect.unwrap_or_else(|| RECT {
                         left: 0,
@@ -216,6 +237,24 @@ unsafe extern "system" fn wnd_proc(window: HWND, msg: u32, wparam: usize, lparam
             });
             lparam = (fake_x as u16 as isize) | ((fake_y as u16 as isize) << 16);
         }
+        WM_INPUT => {
+            let mut raw: RAWINPUT = mem::zeroed();
+            let mut size = mem::size_of::<RAWINPUT>() as u32;
+            let result = GetRawInputData(
+                lparam as HRAWINPUT,
+                RID_INPUT,
+                &mut raw as *mut RAWINPUT as *mut c_void,
+                &mut size,
+                mem::size_of::<RAWINPUTHEADER>() as u32,
+            );
+            if result != !0 && raw.header.dwType == RIM_TYPEMOUSE {
+                let mouse = raw.data.mouse();
+                if mouse.usFlags & MOUSE_MOVE_ABSOLUTE == 0 {
+                    with_forge(|forge| forge.apply_raw_mouse_delta(mouse.lLastX, mouse.lLastY));
+                }
+            }
+            return DefWindowProcA(window, msg, wparam, lparam);
+        }
         WM_SYSKEYDOWN => {
             if wparam as i32 == VK_MENU {
                 with_forge(|forge| {
@@ -270,6 +309,7 @@ unsafe extern "system" fn wnd_proc(window: HWND, msg: u32, wparam: usize, lparam
                     orig_wnd_proc(window, msg, wparam, lparam);
                 }
             }
+            crate::game_thread::window_focus_changed(wparam != 0);
             return DefWindowProcA(window, msg, wparam, lparam);
         }
         WM_GAME_STARTED => {
@@ -280,10 +320,14 @@ unsafe extern "system" fn wnd_proc(window: HWND, msg: u32, wparam: usize, lparam
         WM_SETCURSOR => {
             if (lparam & 0xffff) != HTCLIENT {
                 return DefWindowProcA(window, msg, wparam, lparam);
+            }
+            let show_os_cursor = with_forge(|forge| forge.hardware_cursor || !forge.window_active);
+            if show_os_cursor {
+                SetCursor(LoadCursorW(null_mut(), IDC_ARROW));
             } else {
                 SetCursor(null_mut());
-                return 0;
             }
+            return 0;
         }
         _ => (),
     }
@@ -308,6 +352,9 @@ unsafe extern "system" fn wnd_proc_scr(
     }
 
     let ret = with_scr_hooks_disabled(|| {
+        if check_clip_marker_hotkey(msg, wparam, lparam) {
+            return Some(0);
+        }
         match msg {
             WM_GAME_STARTED => {
                 msg_game_started(window);
@@ -338,6 +385,36 @@ unsafe extern "system" fn wnd_proc_scr(
     ret
 }
 
+/// Returns true if the message was a key press of the clip marker hotkey,
+/// in which case it shouldn't be passed to BW.
+unsafe fn check_clip_marker_hotkey(msg: u32, wparam: usize, lparam: isize) -> bool {
+    if msg != WM_KEYDOWN && msg != WM_SYSKEYDOWN {
+        return false;
+    }
+    let hotkey = with_forge(|forge| match forge.game_started {
+        true => forge.clip_marker_hotkey,
+        false => None,
+    });
+    let hotkey = match hotkey {
+        Some(s) => s,
+        None => return false,
+    };
+    let is_down = |key| GetKeyState(key) as u16 & 0x8000 != 0;
+    let matches = wparam as u16 == hotkey.key_code &&
+        is_down(VK_CONTROL) == hotkey.ctrl &&
+        is_down(VK_MENU) == hotkey.alt &&
+        is_down(VK_SHIFT) == hotkey.shift;
+    if !matches {
+        return false;
+    }
+    // Bit 30 is set for repeats from holding the key down
+    if lparam & 0x4000_0000 == 0 {
+        let frame = crate::bw::with_bw(|bw| (*bw.game()).frame_count);
+        send_game_msg_to_async(GameThreadMessage::ClipMarker { frame });
+    }
+    true
+}
+
 unsafe fn msg_game_started(window: HWND) {
     let mut display_change_request = None;
     with_forge(|forge| {
@@ -410,12 +487,23 @@ unsafe fn msg_timer(window: HWND, timer_id: i32) {
         // scene switchers when moving between our service and others.
         SetWindowTextA(window, "Brood War - ShieldBattery\0".as_ptr() as *const i8);
 
-        // Show the window and bring it to the front
-        ShowWindow(window, SW_SHOWNORMAL);
-        SetForegroundWindow(window);
+        let placement = with_forge(|forge| forge.window_placement);
+        match placement {
+            WindowPlacement::Normal => {
+                // Show the window and bring it to the front
+                ShowWindow(window, SW_SHOWNORMAL);
+                SetForegroundWindow(window);
+            }
+            WindowPlacement::Minimized => {
+                ShowWindow(window, SW_SHOWMINNOACTIVE);
+            }
+            WindowPlacement::Offscreen => {
+                ShowWindow(window, SW_SHOWNOACTIVATE);
+            }
+        }
 
         with_forge(|forge| {
-            if !forge.is_scr() {
+            if !forge.is_scr() && placement == WindowPlacement::Normal {
                 // Clip the cursor
                 forge.perform_scaled_clip_cursor(&RECT {
                     left: 0,
@@ -513,7 +601,7 @@ unsafe fn create_window_a(
     }
     // Access the global Forge instance to get setup parameters, but release the lock
     // before calling CreateWindowEx, as it calls wnd_proc.
-    let (style, (left, top, width, height)) = with_forge(|forge| {
+    let (style, (mut left, mut top, width, height), placement) = with_forge(|forge| {
         assert!(forge.window.is_none());
         let settings = &forge.settings;
         let area = match settings.display_mode {
@@ -540,7 +628,7 @@ unsafe fn create_window_a(
             DisplayMode::FullScreen | DisplayMode::BorderlessWindow => WS_POPUP | WS_VISIBLE,
             DisplayMode::Window => WS_POPUP | WS_VISIBLE | WS_CAPTION | WS_SYSMENU,
         };
-        (style, area)
+        (style, area, forge.window_placement)
     });
     set_dpi_aware();
 
@@ -554,6 +642,13 @@ unsafe fn create_window_a(
     AdjustWindowRect(&mut window_rect, style, 0);
     let window_width = window_rect.right - window_rect.left;
     let window_height = window_rect.bottom - window_rect.top;
+    if placement == WindowPlacement::Offscreen {
+        let (x, y) = offscreen_position();
+        left += x - window_rect.left;
+        top += y - window_rect.top;
+        window_rect.left = x;
+        window_rect.top = y;
+    }
     debug!(
         "Rewriting CreateWindowExA call to ({}, {}), {}x{}",
         window_rect.left, window_rect.top, window_width, window_height,
@@ -612,6 +707,9 @@ struct Forge {
     real_cursor_pos: (i16, i16),
     stored_cursor_rect: Option<RECT>,
     game_started: bool,
+    /// If set, the renderer is never initialized, and the window never gets shown.
+    headless: bool,
+    window_placement: WindowPlacement,
     input_disabled: bool,
     bw_window_active: bool,
     window_active: bool,
@@ -625,6 +723,14 @@ struct Forge {
     >,
     active_bitmap: Option<HBITMAP>,
     captured_window: Option<HWND>,
+    /// If false, the OS cursor is hidden while it is in client area of an active window,
+    /// leaving just the cursor that BW draws.
+    hardware_cursor: bool,
+    /// If true, cursor movement is taken from WM_INPUT instead of window mouse messages,
+    /// avoiding any pointer acceleration Windows does.
+    raw_input: bool,
+    /// Key combination that sends `GameThreadMessage::ClipMarker` during the game.
+    clip_marker_hotkey: Option<Hotkey>,
 
     /// SCR refers to the window class with ATOM returned by RegisterClassExW
     /// (And the class is named OsWindow instead of 1.16.1 SWarClass)
@@ -639,6 +745,8 @@ unsafe impl Send for Forge {}
 static LOCKING_THREAD: AtomicUsize = AtomicUsize::new(!0);
 static FORGE_WINDOW: AtomicUsize = AtomicUsize::new(0);
 static FORGE_INITED: AtomicBool = AtomicBool::new(false);
+static PRESENT_TICKS: AtomicBool = AtomicBool::new(false);
+static PRESENT_COUNT: AtomicU32 = AtomicU32::new(0);
 
 fn with_forge<F: FnOnce(&mut Forge) -> R, R>(func: F) -> R {
     let thread_id = unsafe { winapi::um::processthreadsapi::GetCurrentThreadId() as usize };
@@ -673,7 +781,13 @@ impl Forge {
     fn set_window(&mut self, window: Window) {
         assert!(self.window.is_none());
         FORGE_WINDOW.store(window.handle as usize, Ordering::Release);
+        send_game_msg_to_async(GameThreadMessage::WindowHandle {
+            hwnd: window.handle as usize as u64,
+        });
         self.window = Some(window);
+        if self.raw_input {
+            self.update_raw_input_registration();
+        }
     }
 
     fn perform_scaled_clip_cursor(&mut self, rect: &RECT) -> i32 {
@@ -740,6 +854,42 @@ impl Forge {
         None
     }
 
+    /// Moves the tracked cursor position by a WM_INPUT delta, keeping it inside the window.
+    fn apply_raw_mouse_delta(&mut self, dx: i32, dy: i32) {
+        if !self.raw_input || !self.window_active {
+            return;
+        }
+        if let Some(ref window) = self.window {
+            let (x, y) = self.real_cursor_pos;
+            let max_x = (window.mouse_resolution_width - 1).max(0);
+            let max_y = (window.mouse_resolution_height - 1).max(0);
+            let x = (x as i32).saturating_add(dx).max(0).min(max_x);
+            let y = (y as i32).saturating_add(dy).max(0).min(max_y);
+            self.real_cursor_pos = (x as i16, y as i16);
+        }
+    }
+
+    /// Registers (or unregisters) the window to receive WM_INPUT for mouse.
+    fn update_raw_input_registration(&self) {
+        let window = match self.window {
+            Some(ref s) => s.handle,
+            None => return,
+        };
+        let device = RAWINPUTDEVICE {
+            // Generic desktop controls / mouse
+            usUsagePage: 0x1,
+            usUsage: 0x2,
+            dwFlags: if self.raw_input { 0 } else { RIDEV_REMOVE },
+            hwndTarget: if self.raw_input { window } else { null_mut() },
+        };
+        unsafe {
+            let size = mem::size_of::<RAWINPUTDEVICE>() as u32;
+            if RegisterRawInputDevices(&device, 1, size) == 0 {
+                warn!("Couldn't register raw input: {}", io::Error::last_os_error());
+            }
+        }
+    }
+
     /// Converts window's client area -relative coordinates to BW's screen coordinates.
     ///
     /// That is, depending on mouse scaling, X coordinates may be converted from
@@ -1014,11 +1164,11 @@ fn render_screen(orig: unsafe extern fn()) {
     unsafe {
         orig();
     }
-    with_forge(|forge| {
-        if forge.game_started {
-            forge.renderer.render();
-        }
-    });
+    crate::game_thread::watchdog::frame_rendered();
+    let presented = with_forge(|forge| forge.game_started && forge.renderer.render());
+    if presented {
+        frame_presented();
+    }
 }
 
 fn get_system_metrics(index: i32, orig: unsafe extern fn(i32) -> i32) -> i32 {
@@ -1251,17 +1401,24 @@ fn set_window_pos(
     // we don't want to show the window yet.
     unsafe {
         debug!("SetWindowPos {:p} {},{} {},{} flags {:x}", hwnd, x, y, w, h, flags);
-        let new_flags = if !scr_hooks_disabled() && is_forge_window(hwnd) {
+        let (new_flags, offscreen) = if !scr_hooks_disabled() && is_forge_window(hwnd) {
             with_forge(|forge| {
+                let offscreen = forge.window_placement == WindowPlacement::Offscreen;
                 if forge.game_started {
-                    flags
+                    (flags, offscreen)
                 } else {
                     debug!("Adding SWP_NOACTIVATE | SWP_HIDEWINDOW as the game has not started");
-                    flags | SWP_NOACTIVATE | SWP_HIDEWINDOW
+                    (flags | SWP_NOACTIVATE | SWP_HIDEWINDOW, offscreen)
                 }
             })
         } else {
-            flags
+            (flags, false)
+        };
+        // Keep SCR from moving the window back onscreen when it changes window size or mode.
+        let (x, y) = if offscreen && flags & SWP_NOMOVE == 0 {
+            offscreen_position()
+        } else {
+            (x, y)
         };
         orig(hwnd, hwnd_after, x, y, w, h, new_flags)
     }
@@ -1520,6 +1677,12 @@ fn create_window_w(
     ) -> HWND,
 ) -> HWND {
     unsafe {
+        // Forge can't be locked while calling `orig`, so this is checked separately.
+        let offscreen = forge_inited() && !std::thread::panicking() && with_forge(|forge| {
+            forge.window_placement == WindowPlacement::Offscreen &&
+                forge.scr_window_class.map(|x| x as usize) == Some(class_name as usize)
+        });
+        let (x, y) = if offscreen { offscreen_position() } else { (x, y) };
         let window = orig(
             ex_style,
             class_name,
@@ -1582,6 +1745,68 @@ fn get_window_long_w(
     }
 }
 
+/// BW shows message boxes for some errors (e.g. failing to load a map), which would
+/// block the game thread until someone clicks them away. The window is often hidden,
+/// so instead the text is sent to the async side, and the message box is answered as
+/// if the user had closed it.
+fn message_box_a(
+    window: HWND,
+    text: *const i8,
+    caption: *const i8,
+    flags: u32,
+    orig: unsafe extern fn(HWND, *const i8, *const i8, u32) -> i32,
+) -> i32 {
+    if crate::windows::is_showing_own_message_box() {
+        return unsafe { orig(window, text, caption, flags) };
+    }
+    let (text, caption) = unsafe {
+        let text = c_str_opt(text).map(|x| x.to_string_lossy().into_owned());
+        let caption = c_str_opt(caption).map(|x| x.to_string_lossy().into_owned());
+        (text, caption)
+    };
+    report_bw_message_box(text.unwrap_or_default(), caption.unwrap_or_default(), flags)
+}
+
+fn message_box_w(
+    window: HWND,
+    text: *const u16,
+    caption: *const u16,
+    flags: u32,
+    orig: unsafe extern fn(HWND, *const u16, *const u16, u32) -> i32,
+) -> i32 {
+    if crate::windows::is_showing_own_message_box() || scr_hooks_disabled() {
+        return unsafe { orig(window, text, caption, flags) };
+    }
+    let (text, caption) = unsafe { (wide_str_opt(text), wide_str_opt(caption)) };
+    report_bw_message_box(text.unwrap_or_default(), caption.unwrap_or_default(), flags)
+}
+
+unsafe fn wide_str_opt(val: *const u16) -> Option<String> {
+    if val.is_null() {
+        return None;
+    }
+    let len = (0..).position(|i| *val.add(i) == 0).unwrap_or(0);
+    Some(String::from_utf16_lossy(std::slice::from_raw_parts(val, len)))
+}
+
+fn report_bw_message_box(text: String, caption: String, flags: u32) -> i32 {
+    warn!("Dismissing BW message box '{}': {}", caption, text);
+    send_game_msg_to_async(GameThreadMessage::BwError { text });
+    dismissed_message_box_result(flags)
+}
+
+/// Result of a message box with `flags` that was closed without choosing anything,
+/// or the most cautious answer if it can't be closed that way.
+fn dismissed_message_box_result(flags: u32) -> i32 {
+    match flags & MB_TYPEMASK {
+        MB_OK => IDOK,
+        MB_ABORTRETRYIGNORE => IDABORT,
+        MB_YESNO => IDNO,
+        // MB_OKCANCEL, MB_YESNOCANCEL, MB_RETRYCANCEL, MB_CANCELTRYCONTINUE
+        _ => IDCANCEL,
+    }
+}
+
 pub unsafe fn init_hooks_1161(patcher: &mut whack::Patcher) {
     use self::hooks::*;
     let mut starcraft = patcher.patch_exe(0x0040_0000);
@@ -1599,6 +1824,7 @@ pub unsafe fn init_hooks_1161(patcher: &mut whack::Patcher) {
     starcraft.import_hook(&b"user32"[..], ReleaseCapture, release_capture);
     starcraft.import_hook_opt(&b"user32"[..], ShowWindow, show_window);
     starcraft.import_hook_opt(&b"user32"[..], GetKeyState, get_key_state);
+    starcraft.import_hook_opt(&b"user32"[..], MessageBoxA, message_box_a);
     starcraft.import_hook_opt(
         &b"gdi32"[..],
         CreateCompatibleBitmap,
@@ -1613,6 +1839,7 @@ pub unsafe fn init_hooks_1161(patcher: &mut whack::Patcher) {
     let mut storm = patcher.patch_library("storm", 0x1500_0000);
     storm.import_hook_opt(&b"user32"[..], IsIconic, is_iconic);
     storm.import_hook_opt(&b"user32"[..], IsWindowVisible, is_window_visible);
+    storm.import_hook_opt(&b"user32"[..], MessageBoxA, message_box_a);
 }
 
 pub unsafe fn init_hooks_scr(patcher: &mut whack::Patcher) {
@@ -1654,6 +1881,8 @@ pub unsafe fn init_hooks_scr(patcher: &mut whack::Patcher) {
     patcher.hook_closure_address(SetCursorPos, scr_set_cursor_pos, address - user32_base);
     let address = user32.proc_address("GetWindowLongW").unwrap() as usize;
     patcher.hook_closure_address(GetWindowLongW, get_window_long_w, address - user32_base);
+    let address = user32.proc_address("MessageBoxW").unwrap() as usize;
+    patcher.hook_closure_address(MessageBoxW, message_box_w, address - user32_base);
 }
 
 pub fn init(settings: &serde_json::Map<String, serde_json::Value>) {
@@ -1714,6 +1943,14 @@ pub fn init(settings: &serde_json::Map<String, serde_json::Value>) {
             warn!("Using default value for maintainAspectRatio");
             true
         });
+    let hardware_cursor = settings
+        .get("hardwareCursor")
+        .and_then(|x| x.as_bool())
+        .unwrap_or(false);
+    let raw_input = settings
+        .get("rawInput")
+        .and_then(|x| x.as_bool())
+        .unwrap_or(false);
     let settings = Settings {
         mouse_sensitivity,
         display_mode,
@@ -1732,12 +1969,17 @@ pub fn init(settings: &serde_json::Map<String, serde_json::Value>) {
         real_cursor_pos: (0, 0),
         stored_cursor_rect: None,
         game_started: false,
+        headless: false,
+        window_placement: WindowPlacement::Normal,
         input_disabled: false,
         bw_window_active: false,
         window_active: false,
         real_create_sound_buffer: None,
         active_bitmap: None,
         captured_window: None,
+        hardware_cursor,
+        raw_input,
+        clip_marker_hotkey: Some(DEFAULT_CLIP_MARKER_HOTKEY),
         scr_window_class: None,
         display_change_request: None,
     });
@@ -1805,6 +2047,184 @@ pub fn hide_window() {
     }
 }
 
+/// Enables or disables using raw mouse input for cursor movement.
+pub fn set_raw_input(enabled: bool) {
+    with_forge(|forge| {
+        if forge.raw_input != enabled {
+            forge.raw_input = enabled;
+            forge.update_raw_input_registration();
+        }
+    });
+}
+
+/// Enables or disables showing the OS cursor over the game window.
+pub fn set_hardware_cursor(enabled: bool) {
+    with_forge(|forge| forge.hardware_cursor = enabled);
+}
+
+/// Key with modifiers that have to be held for it to activate.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Hotkey {
+    /// Windows virtual key code.
+    pub key_code: u16,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+}
+
+/// Ctrl + Shift + M
+const DEFAULT_CLIP_MARKER_HOTKEY: Hotkey = Hotkey {
+    key_code: b'M' as u16,
+    ctrl: true,
+    alt: false,
+    shift: true,
+};
+
+impl Hotkey {
+    /// BW's own hotkeys use at most one modifier, so requiring two keeps our
+    /// hotkeys from taking any keys away from BW.
+    fn conflicts_with_bw(&self) -> bool {
+        (self.ctrl as u8 + self.alt as u8 + self.shift as u8) < 2
+    }
+}
+
+/// Sets the hotkey for ingame clip markers, or disables them with `None`.
+/// Hotkeys that could conflict with BW's hotkeys are rejected.
+pub fn set_clip_marker_hotkey(hotkey: Option<Hotkey>) {
+    if let Some(ref hotkey) = hotkey {
+        if hotkey.conflicts_with_bw() {
+            warn!("Rejecting clip marker hotkey {:?}, it needs at least two modifiers", hotkey);
+            return;
+        }
+    }
+    with_forge(|forge| forge.clip_marker_hotkey = hotkey);
+}
+
+/// Headless mode must be set before BW initializes DirectDraw.
+/// `game_started` should not be called when headless, so the window stays hidden.
+pub fn set_headless(headless: bool) {
+    with_forge(|forge| forge.headless = headless);
+}
+
+/// How the window is shown once the game starts.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WindowPlacement {
+    Normal,
+    /// Shown minimized, without taking focus.
+    Minimized,
+    /// Shown past the right edge of the desktop, without taking focus. Meant for
+    /// automation and capture tools that don't need the window to be visible.
+    Offscreen,
+}
+
+impl Default for WindowPlacement {
+    fn default() -> WindowPlacement {
+        WindowPlacement::Normal
+    }
+}
+
+pub const MIN_RENDER_SCALE: f32 = 0.25;
+pub const MAX_RENDER_SCALE: f32 = 2.0;
+
+/// Renders BW at `scale` times the window size, which then gets scaled to fit
+/// the window when presented. Values below 1.0 help with weak GPUs, while values above
+/// it give smoother results with linear filtering. Clamped to
+/// `MIN_RENDER_SCALE..=MAX_RENDER_SCALE`.
+///
+/// Mouse coordinates are unaffected, as the window and its client area keep their size.
+/// Has no effect on SC:R, which does its own rendering.
+pub fn set_render_scale(scale: f32) {
+    let scale = if scale.is_finite() {
+        scale.max(MIN_RENDER_SCALE).min(MAX_RENDER_SCALE)
+    } else {
+        1.0
+    };
+    with_forge(|forge| forge.renderer.set_render_scale(scale));
+}
+
+/// Limits how many frames per second are presented, `None` or `Some(0)` removes the limit.
+///
+/// Only rendering is limited; frames that BW draws too soon after the previous one are
+/// dropped without waiting, so the game keeps stepping at its normal rate.
+/// Has no effect on SC:R, which does its own rendering.
+pub fn set_frame_cap(fps: Option<u32>) {
+    with_forge(|forge| forge.renderer.set_frame_cap(fps));
+}
+
+/// Sends `GameThreadMessage::Presented` every time a frame is presented, so that
+/// overlays drawing on top of the window can keep in sync with it.
+pub fn set_present_ticks(enabled: bool) {
+    PRESENT_TICKS.store(enabled, Ordering::Relaxed);
+}
+
+/// Called after BW's frame has been presented to the window.
+pub fn frame_presented() {
+    let frame = PRESENT_COUNT.fetch_add(1, Ordering::Relaxed);
+    if PRESENT_TICKS.load(Ordering::Relaxed) {
+        send_game_msg_to_async(GameThreadMessage::Presented { frame });
+    }
+}
+
+/// Must be set before BW creates its window.
+pub fn set_window_placement(placement: WindowPlacement) {
+    with_forge(|forge| forge.window_placement = placement);
+}
+
+/// Position just past the right edge of the virtual screen, so that no monitor
+/// shows the window.
+unsafe fn offscreen_position() -> (i32, i32) {
+    let x = GetSystemMetrics(SM_XVIRTUALSCREEN) + GetSystemMetrics(SM_CXVIRTUALSCREEN);
+    (x, GetSystemMetrics(SM_YVIRTUALSCREEN))
+}
+
+/// Changes the palette used when drawing BW's frames, for colorblind players.
+/// Only has an effect on 1.16.1, which renders through Forge.
+pub fn set_palette(kind: PaletteKind) {
+    with_forge(|forge| forge.renderer.set_palette_kind(kind));
+}
+
 pub fn input_disabled() -> bool {
     with_forge(|forge| forge.input_disabled)
 }
+
+/// An RGBA image of what was rendered to BW's window.
+pub struct Screenshot {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl Screenshot {
+    /// Encodes the screenshot as a 32-bit BMP file.
+    pub fn to_bmp(&self) -> Vec<u8> {
+        const HEADER_SIZE: u32 = 14 + 40;
+        let image_size = self.width * self.height * 4;
+        let mut out = Vec::with_capacity((HEADER_SIZE + image_size) as usize);
+        out.extend_from_slice(b"BM");
+        out.extend_from_slice(&(HEADER_SIZE + image_size).to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&HEADER_SIZE.to_le_bytes());
+        // BITMAPINFOHEADER
+        out.extend_from_slice(&40u32.to_le_bytes());
+        out.extend_from_slice(&(self.width as i32).to_le_bytes());
+        // Negative height = rows are top-down
+        out.extend_from_slice(&(-(self.height as i32)).to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&32u16.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes()); // BI_RGB
+        out.extend_from_slice(&image_size.to_le_bytes());
+        out.extend_from_slice(&[0u8; 16]);
+        for pixel in self.rgba.chunks(4) {
+            out.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+        }
+        out
+    }
+}
+
+/// Captures the frame that is currently shown. Returns `None` if forge isn't rendering
+/// (SC:R, or the renderer has already been destroyed).
+pub fn screenshot() -> Option<Screenshot> {
+    with_forge(|forge| forge.renderer.screenshot())
+}
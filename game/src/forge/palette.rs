@@ -0,0 +1,125 @@
+//! Palette adjustments for colorblind players.
+//!
+//! Colors are daltonized: the difference between the original color and how it is
+//! seen with the color vision deficiency is moved to channels that can still be told
+//! apart. The simulation matrices are from Machado, Oliveira & Fernandes (2009),
+//! at full severity.
+
+use serde::Deserialize;
+use winapi::um::wingdi::PALETTEENTRY;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PaletteKind {
+    Default,
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+type Matrix = [[f32; 3]; 3];
+
+const PROTANOPIA: Matrix = [
+    [0.152286, 1.052583, -0.204868],
+    [0.114503, 0.786281, 0.099216],
+    [-0.003882, -0.048116, 1.051998],
+];
+const DEUTERANOPIA: Matrix = [
+    [0.367322, 0.860646, -0.227968],
+    [0.280085, 0.672501, 0.047413],
+    [-0.011820, 0.042940, 0.968881],
+];
+const TRITANOPIA: Matrix = [
+    [1.255528, -0.076749, -0.178779],
+    [-0.078411, 0.930809, 0.148602],
+    [0.004733, 0.691367, 0.303900],
+];
+
+/// Moves red-green error to green and blue.
+const RED_GREEN_CORRECTION: Matrix = [
+    [0.0, 0.0, 0.0],
+    [0.7, 1.0, 0.0],
+    [0.7, 0.0, 1.0],
+];
+/// Moves blue-yellow error to red and green.
+const BLUE_YELLOW_CORRECTION: Matrix = [
+    [1.0, 0.0, 0.7],
+    [0.0, 1.0, 0.7],
+    [0.0, 0.0, 0.0],
+];
+
+fn multiply(matrix: &Matrix, color: [f32; 3]) -> [f32; 3] {
+    let mut out = [0.0; 3];
+    for (out, row) in out.iter_mut().zip(matrix.iter()) {
+        *out = row.iter().zip(color.iter()).map(|(a, b)| a * b).sum();
+    }
+    out
+}
+
+fn daltonize(color: [f32; 3], simulation: &Matrix, correction: &Matrix) -> [f32; 3] {
+    let simulated = multiply(simulation, color);
+    let error = [
+        color[0] - simulated[0],
+        color[1] - simulated[1],
+        color[2] - simulated[2],
+    ];
+    let shift = multiply(correction, error);
+    [color[0] + shift[0], color[1] + shift[1], color[2] + shift[2]]
+}
+
+/// Returns `palette` adjusted for `kind`. `PaletteKind::Default` keeps it unchanged.
+pub fn apply(kind: PaletteKind, palette: &[PALETTEENTRY]) -> Vec<PALETTEENTRY> {
+    let (simulation, correction) = match kind {
+        PaletteKind::Default => return palette.to_vec(),
+        PaletteKind::Protanopia => (&PROTANOPIA, &RED_GREEN_CORRECTION),
+        PaletteKind::Deuteranopia => (&DEUTERANOPIA, &RED_GREEN_CORRECTION),
+        PaletteKind::Tritanopia => (&TRITANOPIA, &BLUE_YELLOW_CORRECTION),
+    };
+    palette
+        .iter()
+        .map(|entry| {
+            let color = [entry.peRed as f32, entry.peGreen as f32, entry.peBlue as f32];
+            let result = daltonize(color, simulation, correction);
+            let to_u8 = |x: f32| x.round().max(0.0).min(255.0) as u8;
+            PALETTEENTRY {
+                peRed: to_u8(result[0]),
+                peGreen: to_u8(result[1]),
+                peBlue: to_u8(result[2]),
+                peFlags: entry.peFlags,
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn colorblind_palettes_keep_grays() {
+    let palette = (0..=255u8)
+        .step_by(15)
+        .map(|x| PALETTEENTRY {
+            peRed: x,
+            peGreen: x,
+            peBlue: x,
+            peFlags: 0,
+        })
+        .collect::<Vec<_>>();
+    for &kind in &[PaletteKind::Protanopia, PaletteKind::Deuteranopia, PaletteKind::Tritanopia] {
+        let result = apply(kind, &palette);
+        for (old, new) in palette.iter().zip(result.iter()) {
+            for &(a, b) in &[
+                (old.peRed, new.peRed),
+                (old.peGreen, new.peGreen),
+                (old.peBlue, new.peBlue),
+            ] {
+                assert!((a as i32 - b as i32).abs() <= 2, "{:?}: {} -> {}", kind, a, b);
+            }
+        }
+    }
+    let red = PALETTEENTRY {
+        peRed: 255,
+        peGreen: 0,
+        peBlue: 0,
+        peFlags: 0,
+    };
+    let result = apply(PaletteKind::Deuteranopia, &[red]);
+    assert_ne!(result[0].peBlue, 0);
+}
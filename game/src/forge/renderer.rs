@@ -7,18 +7,32 @@ use winapi::um::wingdi::PALETTEENTRY;
 
 use super::direct_x;
 use super::indirect_draw::IndirectDraw;
+use super::palette::{self, PaletteKind};
 use super::Settings;
 
 pub struct Renderer {
     renderer: Option<Box<dyn RenderApi>>,
     indirect_draw: Option<*mut IndirectDraw>,
+    palette_kind: PaletteKind,
+    /// Last palette that BW set, before any `palette_kind` adjustments.
+    bw_palette: Option<Vec<PALETTEENTRY>>,
     last_render: Instant,
     min_frame_delay: Duration,
+    /// Set by `set_frame_cap`, used instead of `min_frame_delay` if it is longer.
+    frame_cap_delay: Option<Duration>,
+    /// Render scale change that will be applied before drawing the next frame.
+    pending_render_scale: Option<f32>,
 }
 
 pub trait RenderApi {
     fn update_palette(&mut self, palettes: &[PALETTEENTRY]);
     fn render(&mut self, pixels: &[u8]);
+    /// Resizes the back buffer relative to the window size.
+    fn set_render_scale(&mut self, scale: f32);
+}
+
+fn frame_cap_delay(fps: Option<u32>) -> Option<Duration> {
+    fps.filter(|&x| x != 0).map(|fps| Duration::from_secs(1) / fps)
 }
 
 fn device_display_frequency() -> Option<u32> {
@@ -50,6 +64,8 @@ impl Renderer {
         Renderer {
             renderer: None,
             indirect_draw: None,
+            palette_kind: PaletteKind::Default,
+            bw_palette: None,
             last_render: Instant::now(),
             // We allow updates 4x as fast, as BW makes no attempt to sync to vblank.
             // This means that if we hit a two or more frames in the same vblank range,
@@ -57,30 +73,82 @@ impl Renderer {
             // be stuck waiting until BW comes back around and calls us
             // (which might be longer than the *next* vblank window, thus skipping a frame).
             min_frame_delay: Duration::from_secs(1) / display_frequency / 4,
+            frame_cap_delay: None,
+            pending_render_scale: None,
         }
     }
 
-    pub fn render(&mut self) {
+    /// Returns true if a new frame was presented.
+    pub fn render(&mut self) -> bool {
         let now = Instant::now();
-        if now.duration_since(self.last_render) < self.min_frame_delay {
-            return;
+        let delay = match self.frame_cap_delay {
+            Some(cap) => cap.max(self.min_frame_delay),
+            None => self.min_frame_delay,
+        };
+        // Frames that come too early are skipped instead of waiting for them, as BW
+        // renders on the same thread that steps the game.
+        if now.duration_since(self.last_render) < delay {
+            return false;
         }
         self.last_render = now;
 
         let renderer = match self.renderer {
             Some(ref mut s) => s,
-            None => return,
+            None => return false,
         };
         let indirect_draw = match self.indirect_draw {
             Some(s) => s,
-            None => return,
+            None => return false,
         };
+        if let Some(scale) = self.pending_render_scale.take() {
+            renderer.set_render_scale(scale);
+        }
         if let Some(palette) = unsafe { (*indirect_draw).new_palette() } {
-            renderer.update_palette(&palette);
+            renderer.update_palette(&palette::apply(self.palette_kind, &palette));
+            self.bw_palette = Some(palette);
         }
         if let Some(frame) = unsafe { (*indirect_draw).new_frame() } {
             renderer.render(&frame);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn set_palette_kind(&mut self, kind: PaletteKind) {
+        if self.palette_kind == kind {
+            return;
         }
+        self.palette_kind = kind;
+        if let Some(ref mut renderer) = self.renderer {
+            if let Some(ref palette) = self.bw_palette {
+                renderer.update_palette(&palette::apply(kind, palette));
+            }
+        }
+    }
+
+    /// The change is deferred to the next `render` call, as the back buffer can't
+    /// be resized while it is in use. If the renderer hasn't been initialized yet,
+    /// the scale is applied once it is.
+    pub fn set_render_scale(&mut self, scale: f32) {
+        self.pending_render_scale = Some(scale);
+    }
+
+    pub fn set_frame_cap(&mut self, fps: Option<u32>) {
+        self.frame_cap_delay = frame_cap_delay(fps);
+    }
+
+    /// Captures the last frame BW drew, or `None` if rendering isn't set up
+    /// (Either it was never initialized or the device is already gone).
+    pub fn screenshot(&self) -> Option<super::Screenshot> {
+        self.renderer.as_ref()?;
+        let indirect_draw = self.indirect_draw?;
+        let (width, height, rgba) = unsafe { (*indirect_draw).current_frame_rgba()? };
+        Some(super::Screenshot {
+            width,
+            height,
+            rgba,
+        })
     }
 
     pub fn initialize(
@@ -101,3 +169,11 @@ impl Renderer {
         self.renderer = Some(Box::new(renderer));
     }
 }
+
+#[test]
+fn frame_cap_delays() {
+    assert_eq!(frame_cap_delay(None), None);
+    assert_eq!(frame_cap_delay(Some(0)), None);
+    assert_eq!(frame_cap_delay(Some(1)), Some(Duration::from_secs(1)));
+    assert_eq!(frame_cap_delay(Some(40)), Some(Duration::from_millis(25)));
+}
@@ -14,18 +14,21 @@ use quick_error::quick_error;
 use tokio::sync::{mpsc, oneshot};
 
 use crate::app_messages::{
-    self, GamePlayerResult, GameResults, GameResultsReport, GameSetupInfo, LocalUser, PlayerInfo,
-    Race, Route, Settings, SetupProgress, GAME_STATUS_ERROR,
+    self, GamePlayerResult, GameResults, GameResultsReport, GameSetupInfo, LocalUser, Minimap,
+    PlayerInfo, Race, Route, Settings, SetupProgress, GAME_STATUS_ERROR,
 };
 use crate::app_socket;
-use crate::bw::{self, with_bw, GameType};
+use crate::bw::{self, with_bw, GamePlayerId, GameType, StormPlayerId};
 use crate::cancel_token::{CancelToken, Canceler, SharedCanceler};
-use crate::chat::StormPlayerId;
+use crate::chat;
 use crate::forge;
 use crate::game_thread::{
-    GameThreadMessage, GameThreadRequest, GameThreadRequestType, GameThreadResults,
+    init_failure, send_game_msg_to_async, GameThreadMessage, GameThreadRequest,
+    GameThreadRequestType, GameThreadResults,
 };
+use crate::map::{self, MapError};
 use crate::network_manager::{NetworkError, NetworkManager};
+use crate::replay::{self, ReplayFormat};
 use crate::snp;
 use crate::windows;
 
@@ -34,7 +37,8 @@ pub struct GameState {
     network: NetworkManager,
     ws_send: app_socket::SendMessages,
     internal_send: self::SendMessages,
-    init_main_thread: std::sync::mpsc::Sender<()>,
+    /// Taken when the first game is set up, BW is only initialized once per process.
+    init_main_thread: Option<std::sync::mpsc::Sender<()>>,
     send_main_thread_requests: std::sync::mpsc::Sender<GameThreadRequest>,
     running_game: Option<Canceler>,
     async_stop: SharedCanceler,
@@ -52,6 +56,9 @@ enum CanStartGame {
 enum InitState {
     WaitingForInput(IncompleteInit),
     Started(InitInProgress),
+    /// The game, or its init, has ended. Waits for the app to either quit or send
+    /// `ResetForNextGame`.
+    Finished,
 }
 
 struct IncompleteInit {
@@ -92,6 +99,89 @@ pub enum GameStateMessage {
     PlayerJoined,
     GameThread(GameThreadMessage),
     CleanupQuit,
+    /// Sent by the game setup task once the game has ended or failed to init.
+    GameFinished,
+    /// Clears what was set for the previous game once it has ended. The app then sends
+    /// the local user, routes and setup again for the next game.
+    ResetForNextGame,
+    /// A request from the app that is forwarded to the game thread as is,
+    /// see `app_game_request`.
+    GameRequest(GameThreadRequestType, Option<u64>),
+}
+
+/// Parses app commands that are requests for the game thread. The payload's `requestId`,
+/// if any, is used as the request id, so the app can match it with the reply.
+///
+/// Returns `None` if `command` isn't a game thread request.
+pub fn app_game_request(
+    command: &str,
+    payload: serde_json::Value,
+) -> Option<Result<(GameThreadRequestType, Option<u64>), serde_json::Error>> {
+    let request_id = payload.get("requestId").and_then(|x| x.as_u64());
+    let request = match command {
+        "queryFrame" => Ok(GameThreadRequestType::QueryFrame),
+        "queryGameState" => Ok(GameThreadRequestType::QueryGameState),
+        "snapshotUnits" => Ok(GameThreadRequestType::SnapshotUnits),
+        "dumpUnits" => {
+            payload_field(&payload, "format").map(|format| GameThreadRequestType::DumpUnits {
+                format,
+            })
+        }
+        "setHardwareCursor" => {
+            payload_field(&payload, "enabled").map(GameThreadRequestType::SetHardwareCursor)
+        }
+        "setRawInput" => {
+            payload_field(&payload, "enabled").map(GameThreadRequestType::SetRawInput)
+        }
+        "setClipMarkerHotkey" => {
+            payload_field(&payload, "hotkey").map(GameThreadRequestType::SetClipMarkerHotkey)
+        }
+        "setPalette" => payload_field(&payload, "kind").map(GameThreadRequestType::SetPalette),
+        "setHookEnabled" => payload_field(&payload, "hook").and_then(|hook| {
+            let enabled = payload_field(&payload, "enabled")?;
+            Ok(GameThreadRequestType::SetHookEnabled { hook, enabled })
+        }),
+        "queryHooks" => Ok(GameThreadRequestType::QueryHooks),
+        "minimapPing" => payload_field(&payload, "x").and_then(|x| {
+            let y = payload_field(&payload, "y")?;
+            Ok(GameThreadRequestType::MinimapPing { x, y })
+        }),
+        "issueCommand" => {
+            payload_field(&payload, "command").map(GameThreadRequestType::IssueCommand)
+        }
+        "setRenderScale" => {
+            payload_field(&payload, "scale").map(GameThreadRequestType::SetRenderScale)
+        }
+        "moveCamera" => payload_field(&payload, "x").and_then(|x| {
+            let y = payload_field(&payload, "y")?;
+            let smooth = payload_field(&payload, "smooth")?;
+            Ok(GameThreadRequestType::MoveCamera { x, y, smooth })
+        }),
+        "setLocalVisions" => {
+            payload_field(&payload, "mask").map(GameThreadRequestType::SetLocalVisions)
+        }
+        "setFrameCap" => payload_field(&payload, "fps").map(GameThreadRequestType::SetFrameCap),
+        "sendChat" => serde_json::from_value::<app_messages::SendChat>(payload).map(|x| {
+            let recipients = match x.recipients {
+                app_messages::ChatRecipients::All => chat::ChatRecipients::All,
+                app_messages::ChatRecipients::Allies => chat::ChatRecipients::Allies,
+                app_messages::ChatRecipients::Player(id) => {
+                    chat::ChatRecipients::Player(StormPlayerId(id))
+                }
+            };
+            GameThreadRequestType::SendChat { message: x.message, recipients }
+        }),
+        _ => return None,
+    };
+    Some(request.map(|request| (request, request_id)))
+}
+
+/// Deserializes a single field of an app command's payload.
+fn payload_field<T: serde::de::DeserializeOwned>(
+    payload: &serde_json::Value,
+    name: &str,
+) -> Result<T, serde_json::Error> {
+    T::deserialize(payload.get(name).unwrap_or(&serde_json::Value::Null))
 }
 
 impl GameSetupInfo {
@@ -118,6 +208,9 @@ quick_error! {
         InitInProgress {
             display("Game init is already in progress")
         }
+        NotReset {
+            display("Previous game has ended, but resetForNextGame wasn't sent")
+        }
         SettingsNotSet {
             display("Settings not set")
         }
@@ -157,6 +250,27 @@ quick_error! {
         MissingMapInfo(desc: &'static str) {
             display("Missing map info '{}'", desc)
         }
+        ReplayIncompatible(replay: ReplayFormat, game: ReplayFormat) {
+            display("Replay format {:?} can't be played by this version ({:?})", replay, game)
+        }
+        InvalidTurnRate(turn_rate: u8) {
+            display("Invalid turn rate {}", turn_rate)
+        }
+        InvalidStartingResources(amount: u32) {
+            display("Starting resource amount {} is too large", amount)
+        }
+        InvalidMap(reason: String) {
+            display("Invalid map: {}", reason)
+        }
+        BwInitFailed(reason: String) {
+            display("BW initialization failed: {}", reason)
+        }
+        HandicapInMatchmaking {
+            display("Handicaps can't be used in matchmaking games")
+        }
+        HandicapsUnsupported {
+            display("Handicaps aren't supported by BW")
+        }
     }
 }
 
@@ -243,6 +357,7 @@ impl GameState {
             InitState::Started(_) => {
                 return future::err(GameInitError::InitInProgress).boxed();
             }
+            InitState::Finished => return future::err(GameInitError::NotReset).boxed(),
         };
         let local_user = init_state.local_user.clone();
         let mut players_joined = init_state.wait_for_players().fuse();
@@ -257,9 +372,13 @@ impl GameState {
         let net_game_info_set_future = self.network.set_game_info(info.clone());
         let allow_start = self.wait_can_start_game();
 
-        self.init_main_thread
-            .send(())
-            .expect("Main thread should be waiting for a wakeup");
+        let first_game = match self.init_main_thread.take() {
+            Some(init_main_thread) => {
+                init_main_thread.send(()).expect("Main thread should be waiting for a wakeup");
+                true
+            }
+            None => false,
+        };
         async move {
             // We tell BW thread to init, and then it'll stay in forge's WndProc until we're
             // ready to start the game - remaining initialization is done from other threads.
@@ -271,8 +390,25 @@ impl GameState {
                 GameThreadRequestType::SetupInfo(info.clone()),
             );
             req.await;
-            let req = send_game_request(&game_request_send, GameThreadRequestType::Initialize);
-            req.await;
+            if first_game {
+                let req = send_game_request(&game_request_send, GameThreadRequestType::Initialize);
+                req.await;
+            }
+            if let Some(reason) = init_failure() {
+                return Err(GameInitError::BwInitFailed(reason.into()));
+            }
+            if let Some(turn_rate) = info.turn_rate {
+                set_turn_rate(turn_rate)?;
+            }
+            if let Some(ref resources) = info.starting_resources {
+                validate_starting_resources(resources)?;
+            }
+            if let Some(ref handicaps) = info.handicaps {
+                validate_handicaps(handicaps, info.lobby_type)?;
+            }
+            if info.map.is_replay != Some(true) {
+                check_map(Path::new(&info.map_path))?;
+            }
             unsafe {
                 with_bw(|bw| bw.remaining_game_init(&local_user.name));
                 if is_host {
@@ -436,10 +572,13 @@ impl GameState {
             },
             SetupGame(info) => {
                 let mut ws_send = self.ws_send.clone();
+                let mut send_messages_to_state = self.internal_send.clone();
                 let async_stop = self.async_stop.clone();
                 let game_done = self.init_game(info);
                 let task = async move {
-                    if let Err(e) = game_done.await {
+                    let result = game_done.await;
+                    let _ = send_messages_to_state.send(GameStateMessage::GameFinished).await;
+                    if let Err(e) = result {
                         let msg = format!("Failed to init game: {}", e);
                         error!("{}", msg);
 
@@ -473,6 +612,8 @@ impl GameState {
             InLobby | PlayerJoined => {
                 if let InitState::Started(ref mut state) = self.init_state {
                     state.player_joined();
+                    let peers = state.peer_storm_ids();
+                    return self.network.set_peer_storm_ids(peers).map(|_| ()).boxed();
                 } else {
                     warn!("Player joined before init was started");
                 }
@@ -491,6 +632,40 @@ impl GameState {
                 };
                 tokio::spawn(task);
             }
+            GameFinished => {
+                if let InitState::Started(_) = self.init_state {
+                    self.init_state = InitState::Finished;
+                }
+            }
+            ResetForNextGame => {
+                if let InitState::Started(_) = self.init_state {
+                    warn!("Can't reset for next game while a game is running");
+                    return future::ready(()).boxed();
+                }
+                // Also stops the previous game's task from exiting the process
+                // once it is done waiting for CleanupQuit.
+                self.running_game = None;
+                // Settings only initialize forge, which stays as is.
+                let settings_set = match self.init_state {
+                    InitState::WaitingForInput(ref state) => state.settings_set,
+                    InitState::Started(_) | InitState::Finished => true,
+                };
+                self.init_state = InitState::WaitingForInput(IncompleteInit {
+                    settings_set,
+                    local_user: None,
+                    routes_set: false,
+                });
+                self.can_start_game = CanStartGame::No(Vec::new());
+                self.network = NetworkManager::new(self.ws_send.clone());
+                return self.send_game_request(GameThreadRequestType::ResetForNextGame).boxed();
+            }
+            GameRequest(request, request_id) => {
+                // Any reply is sent as a GameThreadMessage, so no need to wait for this.
+                let (request, _) = GameThreadRequest::new(request, request_id);
+                if self.send_main_thread_requests.send(request).is_err() {
+                    warn!("Game thread has closed, dropping request from app");
+                }
+            }
         }
         future::ready(()).boxed()
     }
@@ -501,7 +676,57 @@ impl GameState {
     ) -> Pin<Box<dyn Future<Output = ()> + Send>> {
         use crate::game_thread::GameThreadMessage::*;
         match message {
-            WindowMove(..) => (),
+            WindowMove(..) |
+            FirstContact { .. } |
+            FirstBuilding { .. } |
+            ProxyDetected { .. } |
+            SpeedChanged { .. } |
+            UnitMorph { .. } |
+            GameStartMetadata { .. } |
+            ActualSetup { .. } |
+            PlayerColors(..) |
+            GameplayStarted { .. } |
+            SpectatorChanged { .. } |
+            PlayerResync { .. } |
+            ReplaySaved(..) |
+            ActionTimeline(..) |
+            ResourceLayout(..) |
+            ResourceDepleted { .. } |
+            TechMilestone { .. } |
+            Capabilities(..) |
+            TriggerFired { .. } |
+            ArmyValue { .. } |
+            PathingStuck { .. } |
+            StateHash { .. } |
+            PlayerAlert { .. } |
+            ClipMarker { .. } |
+            Warning(..) |
+            InitFailed { .. } |
+            BwError { .. } |
+            WindowHandle { .. } |
+            Presented { .. } |
+            HookStates { .. } |
+            NetAbuse { .. } |
+            ConnectionQuality { .. } |
+            Log { .. } |
+            ReplayIncompatible { .. } |
+            CommandLog { .. } |
+            LiveApm { .. } |
+            IncomeRate { .. } |
+            SelectionDetails { .. } |
+            CameraMove { .. } |
+            PlayerIdle { .. } |
+            PlayerActive { .. } |
+            GamePaused { .. } |
+            GameLoopStall { .. } |
+            GameLoopResumed { .. } |
+            Screenshot { .. } |
+            CurrentFrame { .. } |
+            UnitSnapshots { .. } |
+            UnitDump { .. } |
+            CurrentGameState { .. } |
+            MinimapPingResult { .. } |
+            IssueCommandResult { .. } => (),
             Snp(snp) => {
                 return self.network.send_snp_message(snp).map(|_| ()).boxed();
             }
@@ -547,7 +772,7 @@ struct InitInProgress {
 struct JoinedPlayer {
     name: String,
     storm_id: StormPlayerId,
-    player_id: Option<u8>,
+    player_id: Option<GamePlayerId>,
 }
 
 impl InitInProgress {
@@ -562,6 +787,17 @@ impl InitInProgress {
         }
     }
 
+    /// (lobby player id, storm id) of everyone who has joined so far.
+    fn peer_storm_ids(&self) -> Vec<(String, StormPlayerId)> {
+        self.joined_players
+            .iter()
+            .filter_map(|joined| {
+                let slot = self.setup_info.slots.iter().find(|x| x.name == joined.name)?;
+                Some((slot.id.clone(), joined.storm_id))
+            })
+            .collect()
+    }
+
     fn player_joined(&mut self) {
         let result = match unsafe { self.update_joined_state() } {
             Ok(true) => Ok(()),
@@ -598,9 +834,9 @@ impl InitInProgress {
                     }
                 })
                 .collect::<Vec<_>>();
-            crate::chat::set_ally_override(&observer_storm_ids);
+            chat::set_ally_override(&observer_storm_ids);
         } else {
-            crate::chat::clear_ally_override();
+            chat::clear_ally_override();
         }
 
         for sender in self.on_all_players_joined.drain(..) {
@@ -672,7 +908,7 @@ impl InitInProgress {
                         });
                         if let Some(bw_slot) = bw_slot {
                             (*players.add(bw_slot)).storm_id = storm_id.0 as u32;
-                            player_id = Some(bw_slot as u8);
+                            player_id = Some(GamePlayerId(bw_slot as u8));
                         } else {
                             return Err(GameInitError::UnexpectedPlayer(name.into()));
                         }
@@ -744,7 +980,7 @@ impl InitInProgress {
         for player in &self.joined_players {
             if let Some(player_id) = player.player_id {
                 let storm_id = player.storm_id.0 as usize;
-                results[storm_id] = match game_results.victory_state[player_id as usize] {
+                results[storm_id] = match game_results.victory_state(player_id) {
                     1 => GameResult::Disconnected,
                     2 => GameResult::Defeat,
                     3 => GameResult::Victory,
@@ -761,7 +997,7 @@ impl InitInProgress {
 
         let lose_type = game_results.player_lose_type;
         for storm_id in 0..8 {
-            if game_results.player_has_left[storm_id] {
+            if game_results.player_has_left(StormPlayerId(storm_id as u8)) {
                 results[storm_id] = if lose_type == Some(PlayerLoseType::UnknownDisconnect) {
                     GameResult::Playing
                 } else {
@@ -788,17 +1024,35 @@ impl InitInProgress {
                         player.name.clone(),
                         GamePlayerResult {
                             result: results[player.storm_id.0 as usize] as u8,
-                            race: match game_results.race[player_id as usize] {
+                            race: match game_results.race(player_id) {
                                 bw::RACE_ZERG => Race::Zerg,
                                 bw::RACE_TERRAN => Race::Terran,
                                 bw::RACE_PROTOSS => Race::Protoss,
                                 r => {
-                                    warn!("Invalid race ({}) for player {}", r, player_id);
+                                    warn!("Invalid race ({}) for player {}", r, player_id.0);
                                     Race::Zerg
                                 }
                             },
-                            // TODO(tec27): implement APM calculation
-                            apm: 0,
+                            apm: game_results.apm(player_id) as u32,
+                            eapm: game_results.eapm(player_id) as u32,
+                            upgrades: game_results.upgrades(player_id).to_vec(),
+                            researched: game_results.researched(player_id),
+                            eliminated_frame: game_results.eliminated_frame(player_id),
+                            team: game_results.team(player_id),
+                            control_group_assigns: game_results
+                                .control_group_assigns(player_id),
+                            control_group_recalls: game_results
+                                .control_group_recalls(player_id),
+                            supply_blocked_frames: game_results
+                                .supply_blocked_frames(player_id),
+                            expansion_frames: game_results.expansion_frames(player_id).to_vec(),
+                            ingame_name: game_results.name(player_id).into(),
+                            color: game_results.player_color(player_id),
+                            creep_tiles: game_results.creep_tiles(player_id),
+                            resources_gathered: game_results.resources_gathered(player_id),
+                            units_killed: game_results.units_killed(player_id),
+                            units_lost: game_results.units_lost(player_id),
+                            resources_lost: game_results.resources_lost(player_id),
                         },
                     ))
                 } else {
@@ -808,8 +1062,31 @@ impl InitInProgress {
             .collect();
         let message = Arc::new(GameResults {
             results,
-            // Assuming fastest speed
-            time_ms: game_results.time_ms,
+            computer_players: (0..8)
+                .filter(|&i| game_results.is_computer[i])
+                .map(|i| i as u8)
+                .collect(),
+            time_ms: game_results.game_time_ms,
+            wall_time_ms: game_results.wall_time_ms,
+            partial: game_results.partial,
+            end_reason: game_results.end_reason,
+            minimap: game_results.minimap.as_ref().map(|minimap| Minimap {
+                width: minimap.width,
+                height: minimap.height,
+                units: minimap.units.clone(),
+                terrain: minimap.terrain.clone(),
+            }),
+            random_seed: game_results.random_seed,
+            turn_rate: game_results.turn_rate,
+            team_results: game_results.team_results.clone(),
+            rankings: game_results.rank_players(),
+            gameplay_start_frame: game_results.gameplay_start_frame,
+            game_fingerprint: game_results.game_fingerprint.clone(),
+            paused_ms: game_results.paused_ms,
+            lobby_type: game_results.lobby_type,
+            is_matchmaking: game_results.is_matchmaking,
+            matchmaking_type: game_results.matchmaking_type.clone(),
+            map_pool_id: game_results.map_pool_id,
         });
         for send in self.waiting_for_result.drain(..) {
             let _ = send.send(message.clone());
@@ -819,9 +1096,87 @@ impl InitInProgress {
 
 unsafe fn create_lobby(info: &GameSetupInfo, game_type: GameType) -> Result<(), GameInitError> {
     let map_path = Path::new(&info.map_path);
+    if info.map.is_replay == Some(true) {
+        check_replay_format(map_path)?;
+    }
     with_bw(|bw| bw.create_lobby(map_path, &info.name, game_type)).map_err(|e| GameInitError::Bw(e))
 }
 
+/// Rejects maps that BW is known to fail with. Maps that can't be read because of their
+/// compression are let through, as BW can load them.
+fn check_map(path: &Path) -> Result<(), GameInitError> {
+    match map::validate(path) {
+        Ok(info) => {
+            debug!("Map {}: {:?}", path.display(), info);
+            Ok(())
+        }
+        Err(MapError::UnsupportedCompression(_)) => {
+            debug!("Skipping validation of map {}, compression isn't supported", path.display());
+            Ok(())
+        }
+        Err(e) => Err(GameInitError::InvalidMap(e.to_string())),
+    }
+}
+
+fn validate_handicaps(
+    handicaps: &[u8; 8],
+    lobby_type: app_messages::LobbyType,
+) -> Result<(), GameInitError> {
+    if handicaps.iter().all(|&x| x == 0) {
+        return Ok(());
+    }
+    if lobby_type == app_messages::LobbyType::Matchmaking {
+        return Err(GameInitError::HandicapInMatchmaking);
+    }
+    // Neither 1.16.1 nor SC:R have per-player multipliers for hit points or damage.
+    Err(GameInitError::HandicapsUnsupported)
+}
+
+fn validate_starting_resources(
+    resources: &[app_messages::ResourceOverride; 8],
+) -> Result<(), GameInitError> {
+    let too_large = resources
+        .iter()
+        .flat_map(|x| x.minerals.into_iter().chain(x.gas))
+        .find(|&amount| amount > app_messages::MAX_RESOURCE_AMOUNT);
+    match too_large {
+        Some(amount) => Err(GameInitError::InvalidStartingResources(amount)),
+        None => Ok(()),
+    }
+}
+
+fn set_turn_rate(turn_rate: u8) -> Result<(), GameInitError> {
+    if !bw::TURN_RATES.contains(&turn_rate) {
+        return Err(GameInitError::InvalidTurnRate(turn_rate));
+    }
+    if !with_bw(|bw| bw.set_turn_rate(turn_rate)) {
+        warn!("Turn rate can't be set on this version, ignoring it");
+    }
+    Ok(())
+}
+
+/// BW doesn't handle replays of newer versions well, so they get rejected before
+/// BW ever sees them.
+fn check_replay_format(path: &Path) -> Result<(), GameInitError> {
+    let replay_version = match replay::replay_format(path) {
+        Ok(o) => o,
+        Err(e) => {
+            // Let BW decide what to do with it.
+            warn!("Couldn't read replay format of {}: {}", path.display(), e);
+            return Ok(());
+        }
+    };
+    let game_version = with_bw(|bw| bw.replay_format());
+    if replay_version > game_version {
+        send_game_msg_to_async(GameThreadMessage::ReplayIncompatible {
+            replay_version,
+            game_version,
+        });
+        return Err(GameInitError::ReplayIncompatible(replay_version, game_version));
+    }
+    Ok(())
+}
+
 unsafe fn join_lobby(
     info: &GameSetupInfo,
     game_type: GameType,
@@ -1058,10 +1413,10 @@ pub async fn create_future(
             local_user: None,
             routes_set: false,
         }),
-        network: NetworkManager::new(),
+        network: NetworkManager::new(ws_send.clone()),
         ws_send,
         internal_send,
-        init_main_thread,
+        init_main_thread: Some(init_main_thread),
         send_main_thread_requests,
         running_game: None,
         async_stop,
@@ -1101,7 +1456,7 @@ fn start_game_request(
     sender: &std::sync::mpsc::Sender<GameThreadRequest>,
     request_type: GameThreadRequestType,
 ) -> Result<oneshot::Receiver<()>, ()> {
-    let (request, wait_done) = GameThreadRequest::new(request_type);
+    let (request, wait_done) = GameThreadRequest::new(request_type, None);
 
     sender.send(request).map_err(|_| ())?;
     Ok(wait_done)
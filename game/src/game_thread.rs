@@ -1,41 +1,265 @@
 //! Hooks and other code that is running on the game/main thread (As opposed to async threads).
 
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use fxhash::FxHashSet;
+use fxhash::{FxHashMap, FxHashSet};
 use lazy_static::lazy_static;
 use once_cell::sync::OnceCell;
+use quick_error::quick_error;
+use serde::Serialize;
 
-use crate::app_messages::{GameSetupInfo};
-use crate::bw::{self, with_bw};
+use crate::app_messages::{GameSetupInfo, LobbyType, Race, ResourceKind, ResourceNode};
+use crate::bw::commands::CommandId;
+use crate::bw::unit::Unit;
+use crate::bw::{self, with_bw, GamePlayerId, StormPlayerId};
+use crate::chat;
 use crate::forge;
 use crate::snp;
 
+use self::frame_hooks::HookId;
+
 lazy_static! {
     pub static ref SEND_FROM_GAME_THREAD: Mutex<Option<tokio::sync::mpsc::UnboundedSender<GameThreadMessage>>> =
         Mutex::new(None);
     pub static ref GAME_RECEIVE_REQUESTS: Mutex<Option<Receiver<GameThreadRequest>>> =
         Mutex::new(None);
+    // Global for accessing game type/slots/etc from hooks.
+    static ref SETUP_INFO: Mutex<Option<Arc<GameSetupInfo>>> = Mutex::new(None);
+    /// `done` of an `ExitCleanup` request that was received during the game loop,
+    /// see `handle_ingame_requests`.
+    static ref DEFERRED_EXIT_CLEANUP: Mutex<Option<tokio::sync::oneshot::Sender<()>>> =
+        Mutex::new(None);
+}
+
+static GAME_FINGERPRINT: OnceCell<String> = OnceCell::new();
+/// Set if `init_bw` found BW's state to be unusable.
+static INIT_FAILURE: OnceCell<String> = OnceCell::new();
+static GAME_LOOP_STARTED: AtomicBool = AtomicBool::new(false);
+static GAME_LOOP_ENDED: AtomicBool = AtomicBool::new(false);
+/// Set once either final or partial results have been sent, so that they're never
+/// sent twice for a game.
+static GAME_RESULTS_SENT: AtomicBool = AtomicBool::new(false);
+/// Frame count after the last step, so that logging doesn't have to access BW.
+static LOG_FRAME: AtomicU32 = AtomicU32::new(0);
+/// How many times the process has been reset for a next game, see `game_index`.
+static GAME_INDEX: AtomicU32 = AtomicU32::new(0);
+
+/// Logs like the `log` crate macros, and additionally sends the message
+/// as `GameThreadMessage::Log` if `game_log` was enabled in setup info.
+macro_rules! game_log {
+    ($level:ident, $($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        log!(log::Level::$level, "{}", message);
+        crate::game_thread::send_game_log(log::Level::$level, module_path!(), message);
+    }}
+}
+
+pub mod apm;
+pub mod command_log;
+pub mod focus_pause;
+pub mod frame_hooks;
+mod idle;
+pub mod milestones;
+mod score;
+pub mod unit_dump;
+pub mod watchdog;
+
+lazy_static! {
+    static ref GAME_THREAD_STATE: Mutex<GameThreadState> = Mutex::new(GameThreadState::new());
+    static ref FOW_SPRITE_FILTER: Mutex<Option<FowSpriteFilter>> = Mutex::new(None);
+}
+
+/// Decides which units get fog sprites at game start, and have them kept in replays.
+pub type FowSpriteFilter = Box<dyn Fn(&Unit) -> bool + Send>;
+
+/// Per-game state that the hooks keep track of. Reset in `after_init_game_data`,
+/// and by `reset_for_next_game`.
+struct GameThreadState {
+    /// Cached copy of what was sent in `PlayersRandomized`, with the slots of players
+    /// who left cleared once someone else takes them (See `spectator_changes`).
+    storm_to_game_id: [Option<GamePlayerId>; bw::MAX_STORM_PLAYERS],
+    /// Storm player flags as of the previous frame, `None` before the first frame.
+    storm_flags: Option<Vec<u32>>,
+    /// For each ingame player, bits of the players whose units they have seen.
+    /// (Player's own bit is always set)
+    first_contact_seen: [u8; 8],
+    /// Bits of players in game who haven't placed a building yet, `None` until
+    /// the first frame has been checked.
+    first_building_pending: Option<u8>,
+    /// Resource units that still have resources left, keyed by position.
+    /// A refinery is placed exactly over its geyser and takes over the geyser's
+    /// resources, so this way the geyser isn't considered depleted when it gets one.
+    resources: FxHashMap<(i16, i16), TrackedResource>,
+    /// Set once `PlayerColors` has been sent for this game.
+    player_colors_sent: bool,
+    /// Set once `GameplayStarted` has been sent for this game.
+    gameplay_started_sent: bool,
+    /// Bits of players who have reached a milestone, for each `milestones` rule.
+    milestones_reached: Vec<u8>,
+    /// Game time of the previous milestone check, see `interval_passed`.
+    milestones_checked: Option<u32>,
+    /// Game time when `ArmyValue` was last sent.
+    army_values_reported: Option<u32>,
+    /// Game time when `LiveApm` was last sent.
+    live_apm_reported: Option<u32>,
+    /// Game time of the previous idle player check.
+    idle_checked: Option<u32>,
+    /// Workers with orders that should make them move, keyed by unit pointer.
+    /// Only used if `debug_pathing` is set.
+    moving_workers: FxHashMap<usize, WorkerMovement>,
+    /// Hitpoints + shields, unit id and position of the local player's units,
+    /// keyed by unit pointer.
+    local_units: FxHashMap<usize, TrackedUnit>,
+    /// Frame of the last `AlertKind::UnderAttack` alert.
+    last_attack_alert: Option<u32>,
+    /// Amount of techs and upgrade levels the local player has, `None` until
+    /// the first frame has been checked.
+    local_research: Option<(u32, u32)>,
+    actions: apm::ActionCounts,
+    /// Frame on which each player's victory state became defeat.
+    eliminated_frame: [Option<u32>; 8],
+    /// See `is_supply_blocked`.
+    supply_blocked_frames: [u32; 8],
+    /// Town halls keyed by unit pointer, and whether their completion has been counted
+    /// as an expansion. `None` until the first frame has been checked.
+    town_halls: Option<FxHashMap<usize, bool>>,
+    /// Frames on which each player's town halls were completed, see `check_expansions`.
+    expansion_frames: [Vec<u32>; 8],
+    /// (start frame, speed) for each game speed the game has been played at,
+    /// see `check_game_speed`.
+    speed_segments: Vec<(u32, GameSpeed)>,
+    /// Position of each player's starting town hall, `None` until the first frame has
+    /// been checked.
+    main_bases: Option<[Option<bw::Point>; 8]>,
+    /// Bits of players who have had `ProxyDetected` sent for them.
+    proxies_reported: u8,
+    focus_pause: focus_pause::FocusPause,
+    /// Only checked if `idle_threshold_seconds` is set.
+    idle: idle::IdleTracker,
+    /// Only recorded if `command_log_limit` is set.
+    command_log: Option<command_log::CommandLog>,
+    /// Set once a warning about running out of sprites for fog sprites has been sent.
+    fow_sprites_exhausted: bool,
+    /// Game time and cumulative minerals and gas of the previous `IncomeRate` report.
+    last_income: Option<(u32, [u32; 8], [u32; 8])>,
+    /// Unit orders of the previous `SelectionDetails` report.
+    last_selection: Vec<bw::UnitOrder>,
+    /// Screen position of the previous `CameraMove` report.
+    last_camera: Option<bw::Point>,
+    /// Smooth camera move started by `MoveCamera`, as (from, to, start frame).
+    camera_pan: Option<(bw::Point, bw::Point, u32)>,
+    /// Frame of the first player action, see `note_gameplay_command`.
+    gameplay_start_frame: Option<u32>,
+    /// Time of the previous `after_step_game` call.
+    last_step: Option<Instant>,
+    /// Sum of time between each game step so far.
+    wall_time: Duration,
+    /// When a player paused the game, if it is currently paused.
+    paused_since: Option<Instant>,
+    /// Time spent paused by players, not including the current pause.
+    paused_time: Duration,
+    /// `paused_time` at the previous `after_step_game` call.
+    paused_time_at_step: Duration,
+}
+
+struct WorkerMovement {
+    position: bw::Point,
+    last_moved_frame: u32,
+    reported: bool,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct TrackedResource {
+    unit_id: u16,
+    position: bw::Point,
 }
 
-// Global for accessing game type/slots/etc from hooks.
-static SETUP_INFO: OnceCell<Arc<GameSetupInfo>> = OnceCell::new();
+struct TrackedUnit {
+    unit_id: u16,
+    position: bw::Point,
+    health: i32,
+}
+
+impl GameThreadState {
+    fn new() -> GameThreadState {
+        GameThreadState {
+            storm_to_game_id: [None; bw::MAX_STORM_PLAYERS],
+            storm_flags: None,
+            first_contact_seen: [0x1, 0x2, 0x4, 0x8, 0x10, 0x20, 0x40, 0x80],
+            first_building_pending: None,
+            resources: FxHashMap::default(),
+            player_colors_sent: false,
+            gameplay_started_sent: false,
+            milestones_reached: Vec::new(),
+            milestones_checked: None,
+            army_values_reported: None,
+            live_apm_reported: None,
+            idle_checked: None,
+            moving_workers: FxHashMap::default(),
+            local_units: FxHashMap::default(),
+            last_attack_alert: None,
+            local_research: None,
+            actions: apm::ActionCounts::default(),
+            eliminated_frame: [None; 8],
+            supply_blocked_frames: [0; 8],
+            town_halls: None,
+            expansion_frames: Default::default(),
+            speed_segments: Vec::new(),
+            main_bases: None,
+            proxies_reported: 0,
+            focus_pause: focus_pause::FocusPause::default(),
+            idle: idle::IdleTracker::default(),
+            command_log: None,
+            fow_sprites_exhausted: false,
+            last_income: None,
+            last_selection: Vec::new(),
+            last_camera: None,
+            camera_pan: None,
+            gameplay_start_frame: None,
+            last_step: None,
+            wall_time: Duration::from_secs(0),
+            paused_since: None,
+            paused_time: Duration::from_secs(0),
+            paused_time_at_step: Duration::from_secs(0),
+        }
+    }
+
+    /// Game time in milliseconds at `frame`, see `time_at_speeds_ms`.
+    fn game_time_ms(&self, frame: u32) -> u32 {
+        time_at_speeds_ms(&self.speed_segments, 0, frame)
+    }
+}
+
+fn with_game_thread_state<F: FnOnce(&mut GameThreadState) -> R, R>(func: F) -> R {
+    let mut state = GAME_THREAD_STATE.lock().unwrap();
+    func(&mut state)
+}
 
 // Async tasks request game thread to do some work
 pub struct GameThreadRequest {
     request_type: GameThreadRequestType,
+    /// Echoed back in messages that are sent as a response to this request.
+    request_id: u64,
     // These requests probably won't have any reason to return values on success.
     // If a single one does, it can send a GameThreadMessage.
     done: tokio::sync::oneshot::Sender<()>,
 }
 
 impl GameThreadRequest {
+    /// If `request_id` is `None`, an unique id is generated for the request.
     pub fn new(
         request_type: GameThreadRequestType,
+        request_id: Option<u64>,
     ) -> (GameThreadRequest, tokio::sync::oneshot::Receiver<()>) {
+        static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+        let request_id =
+            request_id.unwrap_or_else(|| NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed));
         let (done, wait_done) = tokio::sync::oneshot::channel();
-        (GameThreadRequest { request_type, done }, wait_done)
+        (GameThreadRequest { request_type, request_id, done }, wait_done)
     }
 }
 
@@ -43,26 +267,258 @@ pub enum GameThreadRequestType {
     Initialize,
     RunWndProc,
     StartGame,
+    /// If received while the game loop is running, partial results are sent right away,
+    /// but the cleanup (and completing this request) waits until the game loop ends.
     ExitCleanup,
     SetupInfo(Arc<GameSetupInfo>),
+    /// Answered with `GameThreadMessage::CurrentFrame`.
+    QueryFrame,
+    /// Answered with `GameThreadMessage::CurrentGameState`. Cheap enough to be polled.
+    QueryGameState,
+    /// Answered with `GameThreadMessage::UnitSnapshots`.
+    SnapshotUnits,
+    /// Answered with `GameThreadMessage::UnitDump`.
+    DumpUnits { format: unit_dump::DumpFormat },
+    /// Shows or hides the OS cursor over the game window.
+    SetHardwareCursor(bool),
+    /// Uses WM_INPUT mouse deltas instead of the OS cursor, see `forge::set_raw_input`.
+    SetRawInput(bool),
+    /// Changes the resolution BW is rendered at relative to window size,
+    /// see `forge::set_render_scale`.
+    SetRenderScale(f32),
+    /// Limits rendering to this many frames per second, see `forge::set_frame_cap`.
+    SetFrameCap(Option<u32>),
+    /// Sends a chat message as the local player. Only possible while the game is running,
+    /// long messages get split to several chat messages.
+    SendChat { message: String, recipients: chat::ChatRecipients },
+    /// Changes the hotkey that sends `GameThreadMessage::ClipMarker`, or disables it.
+    SetClipMarkerHotkey(Option<forge::Hotkey>),
+    /// Changes colors used in rendering for colorblind players.
+    SetPalette(forge::PaletteKind),
+    /// Turns a per-frame reporting hook on or off, see `frame_hooks`.
+    SetHookEnabled { hook: frame_hooks::HookId, enabled: bool },
+    /// Answered with `GameThreadMessage::HookStates`.
+    QueryHooks,
+    /// Pings the minimap at map pixel coordinates as the local player, on the next frame.
+    /// Answered with `GameThreadMessage::MinimapPingResult`.
+    MinimapPing { x: i16, y: i16 },
+    /// Moves the local screen's top left corner to map pixel coordinates, either
+    /// immediately or over `CAMERA_PAN_FRAMES` frames. Only allowed for replays and
+    /// observers.
+    MoveCamera { x: i16, y: i16, smooth: bool },
+    /// Sets whose vision the local screen shows, as a bit mask of ingame player ids.
+    /// Only allowed for replays and observers.
+    SetLocalVisions(u8),
+    /// Sends a raw BW command as the local player on the next frame. Meant for bots;
+    /// the command goes through BW's normal command sending, so it stays in sync in
+    /// multiplayer. Answered with `GameThreadMessage::IssueCommandResult`.
+    IssueCommand(Vec<u8>),
+    /// Clears everything that was specific to the previous game, so that the next
+    /// `SetupInfo` and `StartGame` can be handled without restarting the process.
+    /// Ignored while a game is running.
+    ResetForNextGame,
 }
 
 // Game thread sends something to async tasks
 pub enum GameThreadMessage {
     WindowMove(i32, i32),
     Snp(snp::SnpMessage),
+    /// Sent once BW has been initialized.
+    Capabilities(bw::Capabilities),
     /// Storm player id (which stays stable) -> game player id mapping.
     /// Once this message is sent, any game player ids used so far should be
     /// considered invalid and updated to match this mapping.
-    PlayersRandomized([Option<u8>; bw::MAX_STORM_PLAYERS]),
+    PlayersRandomized([Option<GamePlayerId>; bw::MAX_STORM_PLAYERS]),
+    /// Player table as BW initialized it, indexed by ingame player id. Can be compared
+    /// against `GameSetupInfo` to notice slots that BW changed (e.g. a human slot that
+    /// became a computer). `controllers` are `bw::PLAYER_TYPE_*` values.
+    ActualSetup { controllers: [u8; 8], races: [Option<Race>; 8], teams: [u8; 8] },
+    /// Information about the game that is known once BW has initialized the game.
+    GameStartMetadata {
+        map_tile_width: u16,
+        map_tile_height: u16,
+        random_seed: u32,
+        turn_rate: u8,
+        game_fingerprint: String,
+    },
+    /// Palette index of each ingame player's color, sent once at start of the game.
+    PlayerColors([u8; 8]),
+    /// Neutral mineral fields and geysers of the map, sent once at start of the game.
+    ResourceLayout(Vec<ResourceNode>),
+    /// BW stopped the game to wait for turns from a peer, who then caught back up.
+    /// `stalled_frames` is how many frames the wait was at the current game speed.
+    /// Not sent if the peer never recovers and gets dropped instead.
+    PlayerResync { storm_id: StormPlayerId, stalled_frames: u32 },
+    /// Someone who doesn't control an ingame player joined or left during the game.
+    SpectatorChanged { storm_id: StormPlayerId, joined: bool },
+    /// Sent on the first frame that the game loop steps, which is a bit later than
+    /// `StartGame` completing. `unix_time` is in milliseconds, and allows converting
+    /// frames of other messages to wall-clock time.
+    GameplayStarted { frame: u32, unix_time: u64 },
     Results(GameThreadResults),
+    /// A unit of `scouted` was seen by `scouter` for the first time this game.
+    /// Both are ingame player ids.
+    FirstContact { scouter: GamePlayerId, scouted: GamePlayerId, frame: u32 },
+    /// A mineral field or geyser ran out of resources, or was removed.
+    ResourceDepleted { unit_id: u32, x: i16, y: i16, frame: u32 },
+    TechMilestone { player: GamePlayerId, milestone: milestones::Milestone, frame: u32 },
+    /// Trigger action `action` was executed for `player`. Only sent for UMS games
+    /// that have `report_triggers` set.
+    TriggerFired { player: GamePlayerId, action: u16, frame: u32 },
+    /// Counts of turns from storm player `storm_id` that arrived late or probably had to be resent
+    /// during this game so far. Sent periodically for each peer.
+    ConnectionQuality { storm_id: StormPlayerId, late_turns: u32, dropped_turns: u32 },
+    /// Packets from storm player `storm_id` were dropped for exceeding
+    /// `snp::NetAbuseLimits`.
+    NetAbuse { storm_id: StormPlayerId, reason: snp::NetAbuseReason },
+    /// Total resource cost of each ingame player's army, sent once per game second.
+    ArmyValue { frame: u32, values: [u32; 8] },
+    /// APM of each ingame player over the last minute, sent once per game second.
+    /// Computed from player commands (see `apm::ActionCounts::live_apm`), as BW
+    /// doesn't count APM itself. Not sent for replays.
+    LiveApm { frame: u32, apm: [u16; 8] },
+    /// Resources each ingame player gathered per minute over the last
+    /// `INCOME_RATE_INTERVAL_MS` of game time.
+    IncomeRate { frame: u32, minerals_per_min: [u32; 8], gas_per_min: [u32; 8] },
+    /// Orders of the local player's selected units, sent when they change.
+    SelectionDetails { frame: u32, units: Vec<bw::UnitOrder> },
+    /// The local player's screen moved. `x` and `y` are the map pixel coordinates of
+    /// the top left corner of the screen.
+    CameraMove { frame: u32, x: i16, y: i16 },
+    /// A player hasn't done anything in `idle_threshold_seconds`.
+    /// Only sent for human players who are still in the game, and not for replays.
+    PlayerIdle { storm_id: StormPlayerId, idle_ms: u32 },
+    /// A player who was reported with `PlayerIdle` did something again.
+    PlayerActive { storm_id: StormPlayerId },
+    /// A player paused or resumed the game.
+    GamePaused { by_storm_id: StormPlayerId, paused: bool, frame: u32 },
+    /// A worker hasn't moved in a while, even though its order should make it move.
+    /// Only sent if `debug_pathing` is set.
+    PathingStuck { unit_id: u32, x: i16, y: i16, frame: u32 },
+    /// `player` (ingame player id) ordered units of `from_id` to morph to `to_id`.
+    /// Sent when the command is issued, BW may still reject the morph. Not sent for replays.
+    UnitMorph { player: GamePlayerId, from_id: u16, to_id: u16, frame: u32 },
+    /// `player` (ingame player id) placed their first building of the game.
+    /// Used for classifying openings, e.g. proxies.
+    FirstBuilding { player: GamePlayerId, unit_id: u32, x: i16, y: i16, frame: u32 },
+    /// `by` (ingame player id) placed a military building close to `target`'s main base
+    /// early in the game, see `check_proxies`. Sent once for each `by`.
+    ProxyDetected { by: GamePlayerId, target: GamePlayerId, x: i16, y: i16, frame: u32 },
+    /// Game speed changed on `frame`, see `check_game_speed`.
+    SpeedChanged { frame: u32, speed: GameSpeed },
+    /// Something went wrong in a way that doesn't prevent the game from continuing,
+    /// but the user may want to know about.
+    Warning(String),
+    /// BW's state wasn't as expected when initializing, most likely because a patch
+    /// changed it. The game won't be started.
+    InitFailed { reason: String },
+    /// The user pressed the clip marker hotkey in game, to mark a highlight.
+    ClipMarker { frame: u32 },
+    /// Sent when the local player would get an alert. Position is (0, 0) for alerts
+    /// that aren't tied to a map location.
+    PlayerAlert { kind: AlertKind, x: i16, y: i16, frame: u32 },
+    /// Every command of the game in `command_log` format, sent at the end of the game
+    /// if `command_log_limit` was set. `truncated` is set if the limit was reached.
+    CommandLog { data: Vec<u8>, truncated: bool },
+    /// The replay that was requested to be watched can't be played by this BW version.
+    /// Sent instead of starting the game.
+    ReplayIncompatible {
+        replay_version: crate::replay::ReplayFormat,
+        game_version: crate::replay::ReplayFormat,
+    },
+    /// The game thread has neither stepped nor rendered a frame in `since_ms`
+    /// milliseconds, so it is most likely hung. See `watchdog`.
+    GameLoopStall { since_ms: u64 },
+    /// A previously reported `GameLoopStall` ended after `stalled_ms` milliseconds.
+    GameLoopResumed { stalled_ms: u64 },
+    /// Game-scoped copy of a log message, sent only if `game_log` is set.
+    /// `frame` is `None` if the game loop wasn't running yet.
+    Log { level: log::Level, target: &'static str, message: String, frame: Option<u32> },
+    /// Hash of unit state after `frame` has been stepped, comparing these between clients
+    /// shows the first frame that desynced. Only sent if `debug_state_hash` is set.
+    StateHash { frame: u32, hash: u64 },
+    /// The game's replay was copied to `auto_save_replay_dir`.
+    ReplaySaved(PathBuf),
+    /// Actions of each player over the game, split by category. Sent at the end of the game.
+    ActionTimeline(Vec<apm::PlayerActionBuckets>),
+    /// Final frame of the game, if `screenshot_on_finish` was requested.
+    Screenshot { request_id: u64, screenshot: forge::Screenshot },
+    /// Response to `GameThreadRequestType::QueryFrame`.
+    CurrentFrame { request_id: u64, frame: u32 },
+    /// Response to `GameThreadRequestType::QueryGameState`.
+    CurrentGameState { request_id: u64, state: GameStateSnapshot },
+    /// Response to `GameThreadRequestType::SnapshotUnits`, containing all active units.
+    UnitSnapshots { request_id: u64, units: Vec<bw::UnitSnapshot> },
+    /// Response to `GameThreadRequestType::DumpUnits`, active units as CSV or JSON text.
+    UnitDump { request_id: u64, dump: String },
+    MinimapPingResult { request_id: u64, result: Result<(), bw::MinimapPingError> },
+    IssueCommandResult { request_id: u64, result: Result<(), bw::IssueCommandError> },
+    HookStates { request_id: u64, hooks: Vec<(frame_hooks::HookId, bool)> },
+    /// BW tried to show a message box, which was dismissed instead of being shown.
+    BwError { text: String },
+    /// Handle of BW's main window, sent once it has been created.
+    WindowHandle { hwnd: u64 },
+    /// Sent after every presented frame if `GameSetupInfo.present_ticks` is set.
+    /// `frame` counts presents, not game frames.
+    Presented { frame: u32 },
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum MessagePriority {
+    /// Never dropped.
+    Critical,
+    /// Periodic or otherwise non-essential messages, which get dropped if the async side
+    /// falls behind `game_message_queue_limit` messages.
+    Low,
+}
+
+impl GameThreadMessage {
+    pub fn priority(&self) -> MessagePriority {
+        use self::GameThreadMessage::*;
+        // Replies to app requests (`CurrentFrame`, `UnitSnapshots`, ...) are never dropped,
+        // as the app is waiting for them.
+        match self {
+            ArmyValue { .. } | ConnectionQuality { .. } | LiveApm { .. } | IncomeRate { .. } |
+                SelectionDetails { .. } | CameraMove { .. } => MessagePriority::Low,
+            _ => MessagePriority::Critical,
+        }
+    }
+}
+
+pub fn send_game_log(level: log::Level, target: &'static str, message: String) {
+    if !setup_info().map(|x| x.game_log).unwrap_or(false) {
+        return;
+    }
+    let frame = match GAME_LOOP_STARTED.load(Ordering::Relaxed) {
+        true => Some(LOG_FRAME.load(Ordering::Relaxed)),
+        false => None,
+    };
+    send_game_msg_to_async(GameThreadMessage::Log { level, target, message, frame });
+}
+
+/// Amount of messages that have been sent to async but not received yet.
+static PENDING_GAME_MESSAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// Async side is expected to call this for each `GameThreadMessage` it receives.
+pub fn game_msg_received() {
+    PENDING_GAME_MESSAGES.fetch_sub(1, Ordering::Relaxed);
 }
 
 /// Sends a message from game thread to the async system.
 pub fn send_game_msg_to_async(message: GameThreadMessage) {
+    let limit = setup_info().and_then(|x| x.game_message_queue_limit);
+    if let Some(limit) = limit {
+        let pending = PENDING_GAME_MESSAGES.load(Ordering::Relaxed);
+        if pending >= limit && message.priority() == MessagePriority::Low {
+            return;
+        }
+    }
     let send_global = SEND_FROM_GAME_THREAD.lock().unwrap();
     if let Some(ref send) = *send_global {
-        let _ = send.send(message);
+        PENDING_GAME_MESSAGES.fetch_add(1, Ordering::Relaxed);
+        if send.send(message).is_err() {
+            PENDING_GAME_MESSAGES.fetch_sub(1, Ordering::Relaxed);
+        }
     } else {
         debug!("Game thread messaging not active");
     }
@@ -70,13 +526,21 @@ pub fn send_game_msg_to_async(message: GameThreadMessage) {
 
 pub fn run_event_loop() -> ! {
     debug!("Main thread reached event loop");
-    let mut receive_requests = GAME_RECEIVE_REQUESTS.lock().unwrap();
-    let receive_requests = receive_requests
-        .take()
-        .expect("Channel to receive requests not set?");
-    while let Ok(msg) = receive_requests.recv() {
+    loop {
+        // The receiver is kept in GAME_RECEIVE_REQUESTS (and the lock released before
+        // handling the request), so that requests can also be handled during the game loop.
+        let msg = {
+            let receive_requests = GAME_RECEIVE_REQUESTS.lock().unwrap();
+            let receive_requests = receive_requests
+                .as_ref()
+                .expect("Channel to receive requests not set?");
+            match receive_requests.recv() {
+                Ok(o) => o,
+                Err(_) => break,
+            }
+        };
         unsafe {
-            handle_game_request(msg.request_type);
+            handle_game_request(msg.request_type, msg.request_id);
         }
         let _ = msg.done.send(());
     }
@@ -84,53 +548,501 @@ pub fn run_event_loop() -> ! {
     crate::wait_async_exit();
 }
 
-unsafe fn handle_game_request(request: GameThreadRequestType) {
+/// `request_id` is included in any messages sent as a response.
+unsafe fn handle_game_request(request: GameThreadRequestType, request_id: u64) {
     use self::GameThreadRequestType::*;
     match request {
         Initialize => init_bw(),
         RunWndProc => forge::run_wnd_proc(),
         StartGame => {
-            forge::game_started();
+            GAME_LOOP_STARTED.store(true, Ordering::Relaxed);
+            let headless = is_headless();
+            if headless {
+                if !with_bw(|bw| bw.disable_frame_delay()) {
+                    game_log!(Warn, "Headless game will run at normal speed");
+                }
+            } else {
+                forge::game_started();
+            }
+            watchdog::frame_stepped();
             with_bw(|bw| bw.run_game_loop());
-            debug!("Game loop ended");
-            let results = game_results();
-            send_game_msg_to_async(GameThreadMessage::Results(results));
-            forge::hide_window();
+            GAME_LOOP_ENDED.store(true, Ordering::Relaxed);
+            game_log!(Debug, "Game loop ended");
+            if !GAME_RESULTS_SENT.swap(true, Ordering::Relaxed) {
+                let results = game_results();
+                send_game_msg_to_async(GameThreadMessage::Results(results));
+                send_command_log();
+                send_action_timeline();
+            }
+            let screenshot_requested = setup_info()
+                .map(|info| info.screenshot_on_finish)
+                .unwrap_or(false);
+            if screenshot_requested {
+                match forge::screenshot() {
+                    Some(screenshot) => {
+                        let msg = GameThreadMessage::Screenshot { request_id, screenshot };
+                        send_game_msg_to_async(msg);
+                    }
+                    None => game_log!(
+                        Debug,
+                        "Skipping end of game screenshot, nothing is being rendered"
+                    ),
+                }
+            }
+            if let Some(dir) = setup_info().and_then(|x| x.auto_save_replay_dir.clone()) {
+                if !is_replay() {
+                    auto_save_replay(&dir);
+                }
+            }
+            if !headless {
+                forge::hide_window();
+            }
+            let deferred_cleanup = DEFERRED_EXIT_CLEANUP.lock().unwrap().take();
+            if let Some(done) = deferred_cleanup {
+                with_bw(|bw| bw.clean_up_for_exit());
+                let _ = done.send(());
+            }
         }
         // Saves registry settings etc.
-        ExitCleanup => {
-            with_bw(|bw| bw.clean_up_for_exit());
-        }
+        ExitCleanup => with_bw(|bw| bw.clean_up_for_exit()),
         SetupInfo(info) => {
-            if let Err(_) = SETUP_INFO.set(info) {
+            if let Some(limits) = info.net_abuse_limits {
+                snp::set_abuse_limits(limits);
+            }
+            forge::set_headless(info.headless);
+            forge::set_window_placement(info.window_placement);
+            if let Some(scale) = info.render_scale {
+                forge::set_render_scale(scale);
+            }
+            if info.frame_cap.is_some() {
+                forge::set_frame_cap(info.frame_cap);
+            }
+            forge::set_present_ticks(info.present_ticks);
+            milestones::set_extra_rules(&info.extra_milestones);
+            if let Some(ref unit_ids) = info.fow_sprite_unit_ids {
+                set_fow_sprite_filter(neutral_unit_id_filter(unit_ids.clone()));
+            }
+            let mut setup_info = SETUP_INFO.lock().unwrap();
+            if setup_info.is_some() {
                 warn!("Received second SetupInfo");
+            } else {
+                *setup_info = Some(info);
+            }
+        }
+        QueryFrame => {
+            let frame = with_bw(|bw| (*bw.game()).frame_count);
+            send_game_msg_to_async(GameThreadMessage::CurrentFrame { request_id, frame });
+        }
+        QueryGameState => {
+            let state = game_state_snapshot();
+            send_game_msg_to_async(GameThreadMessage::CurrentGameState { request_id, state });
+        }
+        SnapshotUnits => {
+            let units = with_bw(|bw| bw.active_units().map(|x| bw::unit_snapshot(x)).collect());
+            send_game_msg_to_async(GameThreadMessage::UnitSnapshots { request_id, units });
+        }
+        DumpUnits { format } => {
+            let units: Vec<_> =
+                with_bw(|bw| bw.active_units().map(|x| bw::unit_snapshot(x)).collect());
+            let dump = unit_dump::dump_units(&units, format);
+            send_game_msg_to_async(GameThreadMessage::UnitDump { request_id, dump });
+        }
+        SetHardwareCursor(enabled) => forge::set_hardware_cursor(enabled),
+        SetRawInput(enabled) => forge::set_raw_input(enabled),
+        SetRenderScale(scale) => forge::set_render_scale(scale),
+        SetFrameCap(fps) => forge::set_frame_cap(fps),
+        SendChat { .. } => game_log!(Warn, "Can't send chat when game isn't running"),
+        SetClipMarkerHotkey(hotkey) => forge::set_clip_marker_hotkey(hotkey),
+        SetPalette(kind) => with_bw(|bw| bw.set_palette(kind)),
+        SetLocalVisions(_) => game_log!(Warn, "Can't change vision when game isn't running"),
+        MoveCamera { .. } => game_log!(Warn, "Can't move camera when game isn't running"),
+        SetHookEnabled { hook, enabled } => frame_hooks::set_enabled(hook, enabled),
+        QueryHooks => {
+            let hooks = frame_hooks::states();
+            send_game_msg_to_async(GameThreadMessage::HookStates { request_id, hooks });
+        }
+        MinimapPing { .. } => {
+            let result = Err(bw::MinimapPingError::NotInGame);
+            send_game_msg_to_async(GameThreadMessage::MinimapPingResult { request_id, result });
+        }
+        IssueCommand(_) => {
+            let result = Err(bw::IssueCommandError::NotInGame);
+            send_game_msg_to_async(GameThreadMessage::IssueCommandResult { request_id, result });
+        }
+        ResetForNextGame => {
+            if game_loop_running() {
+                game_log!(Warn, "Can't reset for next game while a game is running");
+            } else {
+                reset_for_next_game();
             }
         }
     }
 }
 
+/// See `GameThreadRequestType::ResetForNextGame`.
+///
+/// Settings that the app changes with requests (render scale, palette, hotkeys, etc.)
+/// are kept, everything that setup info or the game itself decided is cleared.
+fn reset_for_next_game() {
+    *SETUP_INFO.lock().unwrap() = None;
+    GAME_LOOP_STARTED.store(false, Ordering::Relaxed);
+    GAME_LOOP_ENDED.store(false, Ordering::Relaxed);
+    GAME_RESULTS_SENT.store(false, Ordering::Relaxed);
+    LOG_FRAME.store(0, Ordering::Relaxed);
+    GAME_INDEX.fetch_add(1, Ordering::Relaxed);
+    with_bw(|bw| bw.clear_last_replay_path());
+    with_game_thread_state(|state| *state = GameThreadState::new());
+    frame_hooks::reset();
+    chat::clear_ally_override();
+    *FOW_SPRITE_FILTER.lock().unwrap() = None;
+    snp::reset_for_next_game();
+    game_log!(Debug, "Reset for next game");
+}
+
 #[derive(Eq, PartialEq, Copy, Clone)]
 pub enum PlayerLoseType {
     UnknownChecksumMismatch,
     UnknownDisconnect,
 }
 
+/// Alerts that BW would notify the local player about.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AlertKind {
+    UnderAttack,
+    UnitLost,
+    ResearchComplete,
+    UpgradeComplete,
+}
+
+/// BW's game speeds, slowest first like BW numbers them.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum GameSpeed {
+    Slowest,
+    Slower,
+    Slow,
+    Normal,
+    Fast,
+    Faster,
+    Fastest,
+}
+
+impl GameSpeed {
+    pub fn from_bw(speed: u8) -> Option<GameSpeed> {
+        use self::GameSpeed::*;
+        Some(match speed {
+            0 => Slowest,
+            1 => Slower,
+            2 => Slow,
+            3 => Normal,
+            4 => Fast,
+            5 => Faster,
+            6 => Fastest,
+            _ => return None,
+        })
+    }
+
+    /// Milliseconds between frames at this speed.
+    pub fn frame_ms(self) -> u32 {
+        use self::GameSpeed::*;
+        match self {
+            Slowest => 167,
+            Slower => 111,
+            Slow => 83,
+            Normal => 67,
+            Fast => 56,
+            Faster => 48,
+            Fastest => 42,
+        }
+    }
+}
+
+/// How the game concluded.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EndReason {
+    /// The losing players were eliminated.
+    Elimination,
+    /// Game ended because a player left the game.
+    Surrender,
+    /// UMS victory conditions were reached.
+    Objective,
+    /// No one won.
+    Draw,
+    /// The game didn't end normally (Network issues, or the process exiting mid-game).
+    Aborted,
+}
+
 pub struct GameThreadResults {
     // Index by ingame player id
     pub victory_state: [u8; 8],
     pub race: [u8; 8],
+    pub is_computer: [bool; 8],
+    pub player_color: [u8; 8],
     // Index by storm id
     pub player_has_left: [bool; 8],
     pub player_lose_type: Option<PlayerLoseType>,
-    pub time_ms: u32,
+    /// Game length computed from the frame count and the game speeds that the frames
+    /// were played at, see `time_at_speeds_ms`. If `time_from_gameplay_start` is set,
+    /// frames before `gameplay_start_frame` aren't counted.
+    pub game_time_ms: u32,
+    /// Frame on which any player first did something, 0 if nobody did.
+    pub gameplay_start_frame: u32,
+    /// See `game_fingerprint`.
+    pub game_fingerprint: String,
+    /// Game length measured from real time between game steps. Includes time that
+    /// was spent paused or waiting for lagging players.
+    pub wall_time_ms: u32,
+    /// Real time that the game was paused by players. BW doesn't step frames while
+    /// paused, so this isn't included in `game_time_ms`.
+    pub paused_ms: u32,
+    /// Set if the results were taken before game had ended.
+    pub partial: bool,
+    pub end_reason: EndReason,
+    /// Only rendered if the app asked for it with `results_minimap`.
+    pub minimap: Option<bw::Minimap>,
+    /// Actions per minute, see `apm` module for how `eapm` differs from `apm`.
+    pub apm: [u16; 8],
+    pub eapm: [u16; 8],
+    /// Seed of BW's RNG at the start of the game. Replaying the same commands with
+    /// the same seed should give identical results.
+    pub random_seed: u32,
+    /// Network turn rate, 0 if dynamic.
+    pub turn_rate: u8,
+    /// Estimated amount of tiles with creep for each Zerg player, 0 for other races.
+    pub creep_tiles: [u32; 8],
+    /// (upgrade_id, level) of every upgrade that a player has at least one level of.
+    pub upgrades: [Vec<(u8, u8)>; 8],
+    /// Bit for each tech id that a player has researched.
+    /// Includes techs that are available without researching.
+    pub researched: [u64; 8],
+    /// Frame on which the player was defeated, `None` for players who weren't.
+    pub eliminated_frame: [Option<u32>; 8],
+    /// Frames that the player spent supply blocked, see `is_supply_blocked`.
+    pub supply_blocked_frames: [u32; 8],
+    /// Frames on which the player's town halls other than the starting ones finished.
+    pub expansion_frames: [Vec<u32>; 8],
+    /// BW's team of each player. 0 if the game type doesn't have teams.
+    pub team: [u8; 8],
+    /// Names BW had for the players, which may differ from lobby names (e.g. when
+    /// they were truncated). Empty for computer and empty slots.
+    pub names: [String; 8],
+    /// (team, victory state) for every team with players, see `team_victory_states`.
+    pub team_results: Vec<(u8, u8)>,
+    /// Whether the player slot had a human or computer player.
+    pub in_game: [bool; 8],
+    /// Total minerals and gas the player has mined.
+    pub resources_gathered: [u32; 8],
+    /// Units (including buildings) killed by the player, and the player's units
+    /// that died. Spell effects that BW counts as units are excluded.
+    pub units_killed: [u32; 8],
+    pub units_lost: [u32; 8],
+    /// Minerals and gas that the player's army units that died cost.
+    /// Workers and buildings aren't valued, see `bw::unit_costs::army_unit_cost`.
+    pub resources_lost: [u32; 8],
+    /// Commands that assigned or added units to a control group, and that recalled one.
+    /// Only counted for games that are being played, not replays.
+    pub control_group_assigns: [u32; 8],
+    pub control_group_recalls: [u32; 8],
+    /// Copied from setup info, so that results can be told apart by game mode
+    /// without looking up the lobby.
+    pub lobby_type: LobbyType,
+    pub is_matchmaking: bool,
+    pub matchmaking_type: Option<String>,
+    pub map_pool_id: Option<u32>,
+}
+
+impl GameThreadResults {
+    pub fn victory_state(&self, player: GamePlayerId) -> u8 {
+        self.victory_state[player.0 as usize]
+    }
+
+    pub fn race(&self, player: GamePlayerId) -> u8 {
+        self.race[player.0 as usize]
+    }
+
+    pub fn player_color(&self, player: GamePlayerId) -> u8 {
+        self.player_color[player.0 as usize]
+    }
+
+    pub fn apm(&self, player: GamePlayerId) -> u16 {
+        self.apm[player.0 as usize]
+    }
+
+    pub fn eapm(&self, player: GamePlayerId) -> u16 {
+        self.eapm[player.0 as usize]
+    }
+
+    pub fn upgrades(&self, player: GamePlayerId) -> &[(u8, u8)] {
+        &self.upgrades[player.0 as usize]
+    }
+
+    pub fn researched(&self, player: GamePlayerId) -> u64 {
+        self.researched[player.0 as usize]
+    }
+
+    pub fn team(&self, player: GamePlayerId) -> u8 {
+        self.team[player.0 as usize]
+    }
+
+    pub fn supply_blocked_frames(&self, player: GamePlayerId) -> u32 {
+        self.supply_blocked_frames[player.0 as usize]
+    }
+
+    pub fn expansion_frames(&self, player: GamePlayerId) -> &[u32] {
+        &self.expansion_frames[player.0 as usize]
+    }
+
+    pub fn name(&self, player: GamePlayerId) -> &str {
+        &self.names[player.0 as usize]
+    }
+
+    pub fn creep_tiles(&self, player: GamePlayerId) -> u32 {
+        self.creep_tiles[player.0 as usize]
+    }
+
+    pub fn resources_gathered(&self, player: GamePlayerId) -> u32 {
+        self.resources_gathered[player.0 as usize]
+    }
+
+    pub fn units_killed(&self, player: GamePlayerId) -> u32 {
+        self.units_killed[player.0 as usize]
+    }
+
+    pub fn units_lost(&self, player: GamePlayerId) -> u32 {
+        self.units_lost[player.0 as usize]
+    }
+
+    pub fn resources_lost(&self, player: GamePlayerId) -> u32 {
+        self.resources_lost[player.0 as usize]
+    }
+
+    pub fn control_group_assigns(&self, player: GamePlayerId) -> u32 {
+        self.control_group_assigns[player.0 as usize]
+    }
+
+    pub fn control_group_recalls(&self, player: GamePlayerId) -> u32 {
+        self.control_group_recalls[player.0 as usize]
+    }
+
+    pub fn eliminated_frame(&self, player: GamePlayerId) -> Option<u32> {
+        self.eliminated_frame[player.0 as usize]
+    }
+
+    pub fn player_has_left(&self, player: StormPlayerId) -> bool {
+        self.player_has_left[player.0 as usize]
+    }
+
+    /// Ranks players in the game by `score::score` with the default weights,
+    /// returning (ingame player id, score) for each, highest score first.
+    pub fn rank_players(&self) -> Vec<(u8, f32)> {
+        self.rank_players_with(&score::ScoreWeights::default())
+    }
+
+    pub fn rank_players_with(&self, weights: &score::ScoreWeights) -> Vec<(u8, f32)> {
+        let stats = (0..8)
+            .filter(|&i| self.in_game[i])
+            .map(|i| score::PlayerStats {
+                player: i as u8,
+                apm: self.apm[i],
+                resources_gathered: self.resources_gathered[i],
+                units_killed: self.units_killed[i],
+                units_lost: self.units_lost[i],
+                won: self.victory_state[i] == 3,
+            })
+            .collect::<Vec<_>>();
+        score::rank(&stats, weights)
+    }
+
+    fn determine_end_reason(&self, is_ums: bool) -> EndReason {
+        if self.partial || self.player_lose_type.is_some() {
+            return EndReason::Aborted;
+        }
+        if !self.victory_state.iter().any(|&x| x == 3) {
+            return EndReason::Draw;
+        }
+        if is_ums {
+            EndReason::Objective
+        } else if self.player_has_left.iter().any(|&x| x) {
+            EndReason::Surrender
+        } else {
+            EndReason::Elimination
+        }
+    }
+}
+
+/// Sends `partial_game_results`, unless results have already been sent for this game.
+unsafe fn send_partial_results() {
+    if GAME_RESULTS_SENT.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    send_command_log();
+    send_action_timeline();
+    match partial_game_results() {
+        Some(results) => send_game_msg_to_async(GameThreadMessage::Results(results)),
+        None => game_log!(Warn, "Couldn't get partial results, BW state is already gone"),
+    }
+}
+
+/// Best-effort version of `game_results` for when the game is still in progress.
+unsafe fn partial_game_results() -> Option<GameThreadResults> {
+    let (game, players) = with_bw(|bw| (bw.game(), bw.players()));
+    if game.is_null() || players.is_null() {
+        return None;
+    }
+    let mut results = game_results();
+    results.partial = true;
+    results.end_reason = EndReason::Aborted;
+    Some(results)
 }
 
 unsafe fn game_results() -> GameThreadResults {
     let game = with_bw(|bw| bw.game());
     let players = with_bw(|bw| bw.players());
 
-    GameThreadResults {
-        victory_state: (*game).victory_state,
+    let has_computer_slots = setup_info()
+        .map(|info| info.slots.iter().any(|x| x.player_type == "computer"))
+        .unwrap_or(false);
+    let mut is_computer = [false; 8];
+    if has_computer_slots {
+        for i in 0..8 {
+            let player_type = (*players.add(i)).player_type;
+            is_computer[i] = match player_type {
+                bw::PLAYER_TYPE_COMPUTER | bw::PLAYER_TYPE_LOBBY_COMPUTER => true,
+                _ => false,
+            };
+        }
+    }
+    let mut allied = [[false; 8]; 8];
+    for i in 0..8 {
+        for j in 0..8 {
+            allied[i][j] = (*game).alliances[i][j] != 0 && (*game).alliances[j][i] != 0;
+        }
+    }
+    let mut team = [0u8; 8];
+    let mut in_game = [false; 8];
+    let mut names: [String; 8] = Default::default();
+    for i in 0..8 {
+        let player = players.add(i);
+        team[i] = (*player).team;
+        in_game[i] = is_in_game(player);
+        if (*player).player_type == bw::PLAYER_TYPE_HUMAN {
+            names[i] = with_bw(|bw| bw.player_name(i as u8));
+        }
+    }
+    let victory_state = coop_victory_state((*game).victory_state, &is_computer, &allied);
+    let gameplay_start_frame =
+        with_game_thread_state(|state| state.gameplay_start_frame.unwrap_or(0));
+
+    let mut results = GameThreadResults {
+        victory_state,
+        team,
+        team_results: team_victory_states(&team, &victory_state, &in_game),
+        in_game,
+        names,
+        is_computer,
+        player_color: player_colors(),
         race: {
             let mut arr = [bw::RACE_ZERG; 8];
             for i in 0..8 {
@@ -150,19 +1062,318 @@ unsafe fn game_results() -> GameThreadResults {
             2 => Some(PlayerLoseType::UnknownDisconnect),
             _ => None,
         },
-        // Assuming fastest speed
-        time_ms: (*game).frame_count.saturating_mul(42),
+        game_time_ms: {
+            let from_gameplay_start =
+                setup_info().map(|x| x.time_from_gameplay_start).unwrap_or(false);
+            let start_frame = if from_gameplay_start { gameplay_start_frame } else { 0 };
+            with_game_thread_state(|state| {
+                time_at_speeds_ms(&state.speed_segments, start_frame, (*game).frame_count)
+            })
+        },
+        gameplay_start_frame,
+        game_fingerprint: game_fingerprint().into(),
+        wall_time_ms: with_game_thread_state(|state| state.wall_time.as_millis() as u32),
+        paused_ms: with_game_thread_state(|state| {
+            let current = state.paused_since.map(|x| x.elapsed()).unwrap_or_default();
+            (state.paused_time + current).as_millis() as u32
+        }),
+        partial: false,
+        end_reason: EndReason::Elimination,
+        minimap: match setup_info().map(|x| x.results_minimap).unwrap_or(false) {
+            true => bw::render_minimap(),
+            false => None,
+        },
+        creep_tiles: with_bw(|bw| estimate_creep_tiles(&**bw)),
+        apm: with_game_thread_state(|state| {
+            state.actions.apm(state.game_time_ms((*game).frame_count))
+        }),
+        eapm: with_game_thread_state(|state| {
+            state.actions.eapm(state.game_time_ms((*game).frame_count))
+        }),
+        control_group_assigns: with_game_thread_state(|state| {
+            state.actions.control_group_assigns()
+        }),
+        control_group_recalls: with_game_thread_state(|state| {
+            state.actions.control_group_recalls()
+        }),
+        random_seed: with_bw(|bw| bw.game_seed()),
+        turn_rate: bw::turn_rate(),
+        upgrades: Default::default(),
+        researched: [0; 8],
+        eliminated_frame: with_game_thread_state(|state| state.eliminated_frame),
+        supply_blocked_frames: with_game_thread_state(|state| state.supply_blocked_frames),
+        expansion_frames: with_game_thread_state(|state| state.expansion_frames.clone()),
+        resources_gathered: [0; 8],
+        units_killed: [0; 8],
+        units_lost: [0; 8],
+        resources_lost: [0; 8],
+        lobby_type: setup_info().map(|x| x.lobby_type).unwrap_or(LobbyType::Custom),
+        is_matchmaking: false,
+        matchmaking_type: setup_info().and_then(|x| x.matchmaking_type.clone()),
+        map_pool_id: setup_info().and_then(|x| x.map_pool_id),
+    };
+    results.is_matchmaking = results.lobby_type == LobbyType::Matchmaking;
+    // Copying, as `bw::Game` is packed and can't be referenced into.
+    let (minerals, gas) = ((*game).cumulative_minerals, (*game).cumulative_gas);
+    let (kills, deaths) = ((*game).unit_kills, (*game).deaths);
+    for i in 0..8 {
+        results.resources_gathered[i] = minerals[i].saturating_add(gas[i]);
+        results.units_killed[i] = unit_count_sum(&kills, i);
+        results.units_lost[i] = unit_count_sum(&deaths, i);
+        results.resources_lost[i] = army_cost_sum(&deaths, i);
+        results.upgrades[i] =
+            upgrade_levels(&(*game).upgrade_level_sc[i], &(*game).upgrade_level_bw[i]);
+        results.researched[i] =
+            researched_techs(&(*game).tech_level_sc[i], &(*game).tech_level_bw[i]);
+    }
+    results.end_reason = results.determine_end_reason(is_ums());
+    results
+}
+
+/// Sums a player's counts from `bw::Game` per-unit-id arrays, skipping spell effects.
+fn unit_count_sum(counts: &[[u32; 0xc]; 0xe4], player: usize) -> u32 {
+    // Scanner sweep, Disruption web, Dark swarm
+    const SPELL_UNITS: &[usize] = &[0x21, 0x69, 0xca];
+    counts
+        .iter()
+        .enumerate()
+        .filter(|&(id, _)| !SPELL_UNITS.contains(&id))
+        .fold(0u32, |sum, (_, counts)| sum.saturating_add(counts[player]))
+}
+
+/// Sums the cost of a player's army units from a `bw::Game` per-unit-id array.
+fn army_cost_sum(counts: &[[u32; 0xc]; 0xe4], player: usize) -> u32 {
+    counts
+        .iter()
+        .enumerate()
+        .filter_map(|(id, counts)| {
+            let cost = bw::unit_costs::army_unit_cost(id as u16)?;
+            Some(cost.total().saturating_mul(counts[player]))
+        })
+        .fold(0u32, |sum, cost| sum.saturating_add(cost))
+}
+
+/// BW stores upgrades and techs in two arrays; the original game's ids first,
+/// followed by ones that were added in Brood War.
+fn upgrade_levels(levels_sc: &[u8], levels_bw: &[u8]) -> Vec<(u8, u8)> {
+    levels_sc
+        .iter()
+        .chain(levels_bw.iter())
+        .enumerate()
+        .filter(|&(_, &level)| level != 0)
+        .map(|(id, &level)| (id as u8, level))
+        .collect()
+}
+
+fn researched_techs(levels_sc: &[u8], levels_bw: &[u8]) -> u64 {
+    levels_sc
+        .iter()
+        .chain(levels_bw.iter())
+        .enumerate()
+        .filter(|&(_, &level)| level != 0)
+        .fold(0, |bits, (id, _)| bits | (1u64 << id))
+}
+
+/// Estimates how many tiles each Zerg player has creep on, assuming that every
+/// completed creep-providing building has fully spread creep around it.
+/// The actual creep spread also depends on terrain, so this is only an approximation.
+unsafe fn estimate_creep_tiles(bw: &dyn bw::Bw) -> [u32; 8] {
+    let game = bw.game();
+    let players = bw.players();
+    let width = (*game).map_width_tiles as i32;
+    let height = (*game).map_height_tiles as i32;
+    // Bits of players that have creep on each tile
+    let mut creep = vec![0u8; (width * height) as usize];
+    for unit in bw.active_units() {
+        let player = unit.player() as usize;
+        if player >= 8 || (*players.add(player)).race != bw::RACE_ZERG || !unit.is_completed() {
+            continue;
+        }
+        // Half of width and height of the creep area, in tiles
+        let (radius_x, radius_y) = match unit.id() {
+            // Hatchery, Lair, Hive
+            0x83 | 0x84 | 0x85 => (10, 7),
+            // Creep colony, Spore colony, Sunken colony
+            0x8f | 0x90 | 0x92 => (8, 5),
+            _ => continue,
+        };
+        let position = (**unit).position;
+        let center_x = position.x as i32 / 32;
+        let center_y = position.y as i32 / 32;
+        for y in (center_y - radius_y).max(0)..(center_y + radius_y + 1).min(height) {
+            for x in (center_x - radius_x).max(0)..(center_x + radius_x + 1).min(width) {
+                let dx = x - center_x;
+                let dy = y - center_y;
+                // Inside ellipse
+                if dx * dx * radius_y * radius_y + dy * dy * radius_x * radius_x <=
+                    radius_x * radius_x * radius_y * radius_y
+                {
+                    creep[(y * width + x) as usize] |= 1 << player;
+                }
+            }
+        }
+    }
+    let mut result = [0u32; 8];
+    for &bits in &creep {
+        for player in 0..8 {
+            if bits & (1 << player) != 0 {
+                result[player] += 1;
+            }
+        }
+    }
+    result
+}
+
+/// Palette index of each ingame player's color (Indexed by ingame player id).
+/// Slots that don't have a player in them are 0.
+unsafe fn player_colors() -> [u8; 8] {
+    let game = with_bw(|bw| bw.game());
+    let players = with_bw(|bw| bw.players());
+    let mut colors = [0; 8];
+    for i in 0..8 {
+        let player_type = (*players.add(i)).player_type;
+        if player_type != bw::PLAYER_TYPE_NONE && player_type != bw::PLAYER_TYPE_OPEN {
+            colors[i] = (*game).player_minimap_color[i];
+        }
+    }
+    colors
+}
+
+/// Adjusts victory states for games where humans play together against computers.
+///
+/// If any member of a team won, all humans on that team are considered winners, as
+/// BW may have seen some of them differently (e.g. a human who was eliminated while
+/// their computer ally kept playing). Disconnected players are left as is.
+fn coop_victory_state(
+    victory_state: [u8; 8],
+    is_computer: &[bool; 8],
+    allied: &[[bool; 8]; 8],
+) -> [u8; 8] {
+    let mut result = victory_state;
+    if !is_computer.iter().any(|&x| x) {
+        return result;
+    }
+    for human in (0..8).filter(|&i| !is_computer[i]) {
+        if victory_state[human] == 1 {
+            continue;
+        }
+        let team_won = (0..8).any(|i| (i == human || allied[human][i]) && victory_state[i] == 3);
+        if team_won {
+            result[human] = 3;
+        }
     }
+    result
+}
+
+/// Combines victory states of each team's players into a result for the team.
+/// The team won if any of its players won (Even if the others left or were eliminated),
+/// and lost if all of its players lost or disconnected. If every player disconnected,
+/// the team is considered disconnected as well.
+/// Players with team 0 (Games without teams) are not included.
+fn team_victory_states(
+    team: &[u8; 8],
+    victory_state: &[u8; 8],
+    in_game: &[bool; 8],
+) -> Vec<(u8, u8)> {
+    let mut teams = team
+        .iter()
+        .zip(in_game.iter())
+        .filter(|&(&team, &in_game)| team != 0 && in_game)
+        .map(|(&team, _)| team)
+        .collect::<Vec<u8>>();
+    teams.sort();
+    teams.dedup();
+    teams
+        .into_iter()
+        .map(|team_id| {
+            let states = (0..8)
+                .filter(|&i| in_game[i] && team[i] == team_id)
+                .map(|i| victory_state[i]);
+            let result = states.fold(1, |result, state| match (result, state) {
+                (3, _) | (_, 3) => 3,
+                (0, _) | (_, 0) => 0,
+                (2, _) | (_, 2) => 2,
+                _ => 1,
+            });
+            (team_id, result)
+        })
+        .collect()
 }
 
 // Does the rest of initialization that is being done in main thread before running forge's
 // window proc.
 unsafe fn init_bw() {
+    let checked = with_bw(|bw| validate_bw_state(bw.game(), bw.players()));
+    if let Err(e) = checked {
+        error!("Can't initialize BW: {}", e);
+        let reason = e.to_string();
+        let _ = INIT_FAILURE.set(reason.clone());
+        send_game_msg_to_async(GameThreadMessage::InitFailed { reason });
+        return;
+    }
     with_bw(|bw| {
         bw.init_sprites();
         (*bw.game()).is_bw = 1;
     });
-    debug!("Process initialized");
+    send_game_msg_to_async(GameThreadMessage::Capabilities(with_bw(|bw| bw.capabilities())));
+    let fingerprint = game_fingerprint();
+    debug!("Process initialized, game fingerprint {}", fingerprint);
+}
+
+quick_error! {
+    #[derive(Debug, Clone, Eq, PartialEq)]
+    pub enum BwStateError {
+        NullPointer(name: &'static str) {
+            display("BW's {} pointer is null", name)
+        }
+        Misaligned(name: &'static str, address: usize) {
+            display("BW's {} pointer {:x} is misaligned", name, address)
+        }
+    }
+}
+
+/// Checks the pointers that the rest of initialization writes through without
+/// checking, so that an unsupported BW version fails cleanly instead of corrupting memory.
+fn validate_bw_state(game: *mut bw::Game, players: *mut bw::Player) -> Result<(), BwStateError> {
+    fn check<T>(name: &'static str, ptr: *mut T) -> Result<(), BwStateError> {
+        if ptr.is_null() {
+            Err(BwStateError::NullPointer(name))
+        } else if ptr as usize % std::mem::align_of::<T>() != 0 {
+            Err(BwStateError::Misaligned(name, ptr as usize))
+        } else {
+            Ok(())
+        }
+    }
+    check("game", game)?;
+    check("players", players)?;
+    Ok(())
+}
+
+/// The reason `init_bw` failed, if it did.
+pub fn init_failure() -> Option<&'static str> {
+    INIT_FAILURE.get().map(|x| &**x)
+}
+
+/// Identifies the BW executable that is running, so that the server can tell which
+/// clients are running expected binaries.
+///
+/// Made from the executable's link timestamp, image size and PE checksum, which
+/// change between every BW patch, along with the replay format it supports.
+/// Computed once, and empty if the PE header can't be read.
+pub fn game_fingerprint() -> &'static str {
+    GAME_FINGERPRINT.get_or_init(|| {
+        let exe = unsafe { winapi::um::libloaderapi::GetModuleHandleW(std::ptr::null_mut()) };
+        match unsafe { crate::windows::pe_header_info(exe) } {
+            Some((timestamp, image_size, checksum)) => {
+                let format = with_bw(|bw| bw.replay_format());
+                format!("{:?}-{:08x}-{:08x}-{:08x}", format, timestamp, image_size, checksum)
+            }
+            None => {
+                warn!("Couldn't read executable's PE header");
+                String::new()
+            }
+        }
+    })
 }
 
 /// Bw impl is expected to hook the point after init_game_data and call this.
@@ -178,78 +1389,1814 @@ pub unsafe fn after_init_game_data() {
         // any possible alternate branches for save/replay/ums randomization should have been executed
         // as well.
         let mut mapping = [None; bw::MAX_STORM_PLAYERS];
+        let mut controllers = [0u8; 8];
+        let mut races = [None; 8];
+        let mut teams = [0u8; 8];
         let players = bw.players();
         for i in 0..8 {
-            let storm_id = (*players.add(i)).storm_id;
-            if let Some(out) = mapping.get_mut(storm_id as usize) {
-                *out = Some(i as u8);
+            let player = &*players.add(i);
+            if let Some(out) = mapping.get_mut(player.storm_id as usize) {
+                *out = Some(GamePlayerId(i as u8));
             }
+            controllers[i] = player.player_type;
+            races[i] = match player.race {
+                bw::RACE_ZERG => Some(Race::Zerg),
+                bw::RACE_TERRAN => Some(Race::Terran),
+                bw::RACE_PROTOSS => Some(Race::Protoss),
+                _ => None,
+            };
+            teams[i] = player.team;
         }
+        with_game_thread_state(|state| {
+            *state = GameThreadState::new();
+            state.storm_to_game_id = mapping;
+        });
         send_game_msg_to_async(GameThreadMessage::PlayersRandomized(mapping));
+        send_game_msg_to_async(GameThreadMessage::ActualSetup { controllers, races, teams });
+        apply_starting_resources(&**bw);
+        if observer_full_vision(&**bw) {
+            if !bw.set_local_visions(0xff) {
+                warn!("Observer vision can't be changed in this BW version");
+            }
+        }
         // Create fog-of-war sprites for any neutral buildings
-        if !is_ums() {
-            for unit in bw.active_units() {
-                if unit.player() == 11 && unit.is_landed_building() {
-                    bw.create_fow_sprite(unit);
-                }
+        if !is_ums() && !fow_sprites_disabled() {
+            let units = with_fow_sprite_filter(|filter| {
+                bw.active_units().filter(|unit| filter(unit)).collect::<Vec<_>>()
+            });
+            let units = units.into_iter();
+            if let Err(missing) = create_fow_sprites(units, |unit| bw.create_fow_sprite(unit)) {
+                fow_sprites_exhausted(missing);
             }
         }
+        let resources = bw
+            .active_units()
+            .filter(|unit| unit.player() == 11)
+            .filter_map(|unit| {
+                let kind = match unit.id() {
+                    0xb0 | 0xb1 | 0xb2 => ResourceKind::Mineral,
+                    0xbc => ResourceKind::Geyser,
+                    _ => return None,
+                };
+                let position = (**unit).position;
+                Some(ResourceNode {
+                    kind,
+                    unit_id: unit.id(),
+                    x: position.x,
+                    y: position.y,
+                    amount: unit.resource_amount(),
+                })
+            })
+            .collect();
+        send_game_msg_to_async(GameThreadMessage::ResourceLayout(resources));
+    });
+    send_game_start_metadata();
+}
+
+/// Writes `starting_resources` over what BW gave players, before the first frame is stepped.
+/// Values were validated when the game was initialized.
+unsafe fn apply_starting_resources(bw: &dyn bw::Bw) {
+    let info = match setup_info() {
+        Some(s) => s,
+        None => return,
+    };
+    let resources = match info.starting_resources {
+        Some(ref s) => s,
+        None => return,
+    };
+    if is_replay() {
+        return;
+    }
+    if info.lobby_type == LobbyType::Matchmaking {
+        warn!("Ignoring starting resource override in a matchmaking game");
+        return;
+    }
+    let game = bw.game();
+    for (i, resources) in resources.iter().enumerate() {
+        if let Some(minerals) = resources.minerals {
+            (*game).minerals[i] = minerals;
+        }
+        if let Some(gas) = resources.gas {
+            (*game).gas[i] = gas;
+        }
+    }
+}
+
+unsafe fn send_game_start_metadata() {
+    let (map_tile_width, map_tile_height) = bw::map_dimensions();
+    let map_data = setup_info().and_then(|x| {
+        x.map.map_data.as_ref().map(|data| (data.width, data.height))
+    });
+    if let Some((width, height)) = map_data {
+        if (width, height) != (map_tile_width, map_tile_height) {
+            warn!(
+                "Map dimensions don't match map data: {}x{}, expected {}x{}",
+                map_tile_width, map_tile_height, width, height,
+            );
+        }
+    }
+    let random_seed = with_bw(|bw| bw.game_seed());
+    send_game_msg_to_async(GameThreadMessage::GameStartMetadata {
+        map_tile_width,
+        map_tile_height,
+        random_seed,
+        turn_rate: bw::turn_rate(),
+        game_fingerprint: game_fingerprint().into(),
     });
 }
 
+/// Index of the current game among the games played by this process, 0 for the first one.
+pub fn game_index() -> u32 {
+    GAME_INDEX.load(Ordering::Relaxed)
+}
+
+/// True while the game loop is running and the game hasn't ended yet.
+pub fn game_loop_running() -> bool {
+    GAME_LOOP_STARTED.load(Ordering::Relaxed) && !GAME_LOOP_ENDED.load(Ordering::Relaxed)
+}
+
+/// Returns the setup info received from the app, or `None` if it hasn't been received yet.
+pub fn setup_info() -> Option<Arc<GameSetupInfo>> {
+    SETUP_INFO.lock().unwrap().clone()
+}
+
+/// Setup info for predicates that have only a default answer before setup info
+/// has been received. Logs once if that happens, as it likely means that some hook
+/// is being ran earlier than expected.
+fn setup_info_for_query(query: &str) -> Option<Arc<GameSetupInfo>> {
+    static LOGGED: AtomicBool = AtomicBool::new(false);
+    let info = setup_info();
+    if info.is_none() && !LOGGED.swap(true, Ordering::Relaxed) {
+        warn!("{} was queried before setup info was available", query);
+    }
+    info
+}
+
 pub fn is_ums() -> bool {
-    SETUP_INFO.get()
+    setup_info_for_query("is_ums")
         .and_then(|x| x.game_type())
         .filter(|x| x.is_ums())
         .is_some()
 }
 
 pub fn is_replay() -> bool {
-    SETUP_INFO.get()
+    setup_info_for_query("is_replay")
         .and_then(|x| x.map.is_replay)
         .unwrap_or(false)
 }
 
-/// Bw impl is expected to call this after step_game,
-/// the function that progresses game objects by a tick/frame/step.
-/// In other words, if the game isn't paused/lagging, this gets ran 24 times in second
-/// on fastest game speed.
-/// This function can be used for hooks that change gameplay state after BW has done (most of)
-/// its once-per-gameplay-frame processing but before anything gets rendered. It probably
-/// isn't too useful to us unless we end up having a need to change game rules.
-pub unsafe fn after_step_game() {
-    with_bw(|bw| {
-        if is_replay() && !is_ums() {
-            // One thing BW's step_game does is that it removes any fog sprites that were
-            // no longer in fog. Unfortunately now that we show fog sprites for unexplored
-            // resources as well, removing those fog sprites ends up being problematic if
-            // the user switches vision off from a player who had those resources explored.
-            // In such case the unexplored fog sprites would not appear and some of the
-            // expansions would show up as empty while other unexplored bases keep their
-            // fog sprites as usual.
-            // To get around this issue, check which neutral buildings don't have fog
-            // sprites and add them back.
-            // (Adding fog sprites on visible area is fine, at least in replays)
+/// Bw impl is expected to call this for every command that a player sends during the game.
+/// Not called for commands of replays.
+pub unsafe fn on_player_command(player: u8, command: &[u8]) {
+    let frame = with_bw(|bw| (*bw.game()).frame_count);
+    let command_log_limit = setup_info().and_then(|x| x.command_log_limit);
+    note_gameplay_command(frame, command);
+    if let Some((from_id, to_id)) = bw::commands::morph(command) {
+        let player = GamePlayerId(player);
+        send_game_msg_to_async(GameThreadMessage::UnitMorph { player, from_id, to_id, frame });
+    }
+    let idle_event = with_game_thread_state(|state| {
+        let now = state.game_time_ms(frame);
+        let event = state.idle.command(player, now, command);
+        event.map(|event| (event, storm_id_of(state, player)))
+    });
+    if let Some((idle::IdleEvent::Active { .. }, Some(storm_id))) = idle_event {
+        send_game_msg_to_async(GameThreadMessage::PlayerActive { storm_id });
+    }
+    match CommandId::from_bytes(command) {
+        Some(CommandId::Pause) => player_paused(player, frame, true),
+        Some(CommandId::Resume) => player_paused(player, frame, false),
+        _ => (),
+    }
+    with_game_thread_state(|state| {
+        let time_ms = state.game_time_ms(frame);
+        state.actions.add(player, frame, time_ms, command);
+        if let Some(limit) = command_log_limit {
+            state
+                .command_log
+                .get_or_insert_with(|| command_log::CommandLog::new(limit as usize))
+                .add(frame, player, command);
+        }
+    });
+}
 
-            let mut fow_sprites = FxHashSet::with_capacity_and_hasher(256, Default::default());
-            for fow in bw.fow_sprites() {
-                let sprite = (*fow).sprite;
-                let pos = bw.sprite_position(sprite);
-                fow_sprites.insert((pos.x, pos.y, (*fow).unit_id));
-            }
-            for unit in bw.active_units() {
-                if unit.player() == 11 && unit.is_landed_building() {
-                    // This currently adds fow sprites even for buildings that became
-                    // neutral after player left. It's probably fine, but if it wasn't
-                    // desired, checking that `sprite.player == 11` should only include
-                    // buildings that existed from map start
-                    let sprite = (**unit).sprite;
-                    let pos = bw.sprite_position(sprite);
-                    if fow_sprites.insert((pos.x, pos.y, unit.id())) {
-                        bw.create_fow_sprite(unit);
-                    }
-                }
+fn player_paused(player: u8, frame: u32, paused: bool) {
+    let by_storm_id = with_game_thread_state(|state| {
+        match (paused, state.paused_since) {
+            (true, None) => state.paused_since = Some(Instant::now()),
+            (false, Some(since)) => {
+                state.paused_time += since.elapsed();
+                state.paused_since = None;
             }
+            // Pausing an already paused game or resuming a running one does nothing.
+            _ => return None,
         }
+        storm_id_of(state, player)
     });
+    if let Some(by_storm_id) = by_storm_id {
+        send_game_msg_to_async(GameThreadMessage::GamePaused { by_storm_id, paused, frame });
+    }
+}
+
+fn storm_id_of(state: &GameThreadState, player: u8) -> Option<StormPlayerId> {
+    state
+        .storm_to_game_id
+        .iter()
+        .position(|&x| x == Some(GamePlayerId(player)))
+        .map(|x| StormPlayerId(x as u8))
+}
+
+const IDLE_CHECK_INTERVAL_MS: u32 = 1000;
+
+unsafe fn check_idle_players(bw: &dyn bw::Bw) {
+    let threshold_seconds = match setup_info().and_then(|x| x.idle_threshold_seconds) {
+        Some(s) => s,
+        None => return,
+    };
+    if is_replay() {
+        return;
+    }
+    let game = bw.game();
+    let frame = (*game).frame_count;
+    let check = with_game_thread_state(|state| {
+        let now = state.game_time_ms(frame);
+        interval_passed(&mut state.idle_checked, now, IDLE_CHECK_INTERVAL_MS)
+    });
+    if !check {
+        return;
+    }
+    let players = bw.players();
+    let victory_state = (*game).victory_state;
+    let humans_in_game = (0..8)
+        .filter(|&i| {
+            (*players.add(i)).player_type == bw::PLAYER_TYPE_HUMAN && victory_state[i] == 0
+        })
+        .fold(0u8, |bits, i| bits | (1 << i));
+    let threshold = threshold_seconds.saturating_mul(1000);
+    let idle = with_game_thread_state(|state| {
+        let now = state.game_time_ms(frame);
+        state
+            .idle
+            .check(now, threshold, humans_in_game)
+            .into_iter()
+            .filter_map(|event| match event {
+                idle::IdleEvent::Idle { player, idle_ms } => {
+                    Some((storm_id_of(state, player)?, idle_ms))
+                }
+                idle::IdleEvent::Active { .. } => None,
+            })
+            .collect::<Vec<_>>()
+    });
+    for (storm_id, idle_ms) in idle {
+        send_game_msg_to_async(GameThreadMessage::PlayerIdle { storm_id, idle_ms });
+    }
+}
+
+/// Bw impl is expected to call this for every command of a replay that is being watched.
+pub unsafe fn on_replay_command(command: &[u8]) {
+    let frame = with_bw(|bw| (*bw.game()).frame_count);
+    note_gameplay_command(frame, command);
+}
+
+/// Replays may start with frames where nobody does anything yet, so gameplay is
+/// considered to start from the first player action.
+fn note_gameplay_command(frame: u32, command: &[u8]) {
+    if apm::is_action(command) {
+        with_game_thread_state(|state| {
+            state.gameplay_start_frame.get_or_insert(frame);
+        });
+    }
+}
+
+/// Sends the commands recorded so far, if command recording was enabled.
+fn send_command_log() {
+    if let Some(log) = with_game_thread_state(|state| state.command_log.take()) {
+        let (data, truncated) = log.finish();
+        send_game_msg_to_async(GameThreadMessage::CommandLog { data, truncated });
+    }
+}
+
+fn send_action_timeline() {
+    let players = with_game_thread_state(|state| state.actions.timeline().players());
+    if !players.is_empty() {
+        send_game_msg_to_async(GameThreadMessage::ActionTimeline(players));
+    }
+}
+
+/// Forge is expected to call this when the game window gains or loses focus.
+/// Pauses the game while unfocused if `pause_on_unfocus` is set, unless the game
+/// is multiplayer.
+pub unsafe fn window_focus_changed(focused: bool) {
+    if !game_loop_running() {
+        return;
+    }
+    let allowed = match setup_info() {
+        Some(info) => {
+            let single_player = info.slots.iter().filter(|x| x.is_human()).count() <= 1;
+            info.pause_on_unfocus && (single_player || is_replay())
+        }
+        None => false,
+    };
+    let change = with_game_thread_state(|state| state.focus_pause.focus_changed(focused, allowed));
+    if let Some(paused) = change {
+        if !with_bw(|bw| bw.set_paused(paused)) {
+            game_log!(Warn, "Pausing isn't supported");
+        }
+    }
+}
+
+/// Bw impl is expected to call this when a trigger action is about to be executed.
+/// Does nothing unless the game is UMS and the app asked for trigger reports.
+///
+/// Note: Only called on BW versions that report `Capabilities::trigger_reports`.
+pub unsafe fn on_trigger_action(player: u8, action: u16) {
+    let enabled = setup_info().map(|x| x.report_triggers).unwrap_or(false);
+    if !enabled || !is_ums() {
+        return;
+    }
+    let frame = with_bw(|bw| (*bw.game()).frame_count);
+    let player = GamePlayerId(player);
+    send_game_msg_to_async(GameThreadMessage::TriggerFired { player, action, frame });
+}
+
+/// A step taking this long means that BW stopped the game to wait for turns, instead
+/// of the turns just arriving a bit late.
+const RESYNC_MIN_STALL: Duration = Duration::from_secs(2);
+
+/// Sends `PlayerResync` for the peers that the game waited `stall` for.
+fn report_resyncs(stall: Duration) {
+    let frame_ms = with_game_thread_state(|state| {
+        let speed = state.speed_segments.last().map(|x| x.1).unwrap_or(GameSpeed::Fastest);
+        speed.frame_ms()
+    });
+    let stalled_frames = (stall.as_millis() / frame_ms as u128) as u32;
+    for storm_id in snp::peers_caught_up_after(stall) {
+        info!("Player {} resynced after {:?}", storm_id.0, stall);
+        send_game_msg_to_async(GameThreadMessage::PlayerResync { storm_id, stalled_frames });
+    }
+}
+
+/// Bw impl is expected to call this after step_game,
+/// the function that progresses game objects by a tick/frame/step.
+/// In other words, if the game isn't paused/lagging, this gets ran 24 times in second
+/// on fastest game speed.
+/// This function can be used for hooks that change gameplay state after BW has done (most of)
+/// its once-per-gameplay-frame processing but before anything gets rendered. It probably
+/// isn't too useful to us unless we end up having a need to change game rules.
+pub unsafe fn after_step_game() {
+    let step_wait = with_game_thread_state(|state| {
+        let now = Instant::now();
+        let interval = state.last_step.map(|last| now - last);
+        if let Some(interval) = interval {
+            state.wall_time += interval;
+        }
+        state.last_step = Some(now);
+        state.focus_pause.frame_stepped();
+        // Time that the game was paused isn't waiting for turns.
+        let paused = state.paused_time - state.paused_time_at_step;
+        state.paused_time_at_step = state.paused_time;
+        interval.map(|x| x.saturating_sub(paused))
+    });
+    watchdog::frame_stepped();
+    if let Some(stall) = step_wait.filter(|&x| x >= RESYNC_MIN_STALL) {
+        report_resyncs(stall);
+    }
+    with_bw(|bw| {
+        let first_step = with_game_thread_state(|state| {
+            !std::mem::replace(&mut state.gameplay_started_sent, true)
+        });
+        if first_step {
+            let unix_time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_millis() as u64)
+                .unwrap_or(0);
+            let frame = (*bw.game()).frame_count;
+            send_game_msg_to_async(GameThreadMessage::GameplayStarted { frame, unix_time });
+        }
+        let send_colors = with_game_thread_state(|state| {
+            !std::mem::replace(&mut state.player_colors_sent, true)
+        });
+        if send_colors {
+            send_game_msg_to_async(GameThreadMessage::PlayerColors(player_colors()));
+        }
+        LOG_FRAME.store((*bw.game()).frame_count, Ordering::Relaxed);
+        check_storm_players(&**bw);
+        check_eliminations(&**bw);
+        check_first_contacts(&**bw);
+        check_first_buildings(&**bw);
+        check_expansions(&**bw);
+        check_proxies(&**bw);
+        check_game_speed(&**bw);
+        if frame_hooks::is_enabled(HookId::ResourceDepletion) {
+            check_resource_depletion(&**bw);
+        }
+        check_milestones(&**bw);
+        check_alerts(&**bw);
+        if frame_hooks::is_enabled(HookId::ArmyValue) {
+            report_army_values(&**bw);
+        }
+        if frame_hooks::is_enabled(HookId::LiveApm) {
+            report_live_apm(&**bw);
+        }
+        if frame_hooks::is_enabled(HookId::IncomeRate) {
+            report_income_rate(&**bw);
+        }
+        check_idle_players(&**bw);
+        check_supply_blocks(&**bw);
+        if frame_hooks::is_enabled(HookId::Selection) {
+            report_selection(&**bw);
+        }
+        pan_camera(&**bw);
+        if frame_hooks::is_enabled(HookId::Camera) {
+            report_camera(&**bw);
+        }
+        if setup_info().map(|x| x.debug_pathing).unwrap_or(false) {
+            check_stuck_workers(&**bw);
+        }
+        let hash_state = setup_info().map(|x| x.debug_state_hash).unwrap_or(false) &&
+            frame_hooks::is_enabled(HookId::StateHash);
+        if hash_state {
+            let frame = (*bw.game()).frame_count;
+            let hash = state_hash(&**bw);
+            send_game_msg_to_async(GameThreadMessage::StateHash { frame, hash });
+        }
+        if (is_replay() || observer_full_vision(&**bw)) && !is_ums() && !fow_sprites_disabled() {
+            // One thing BW's step_game does is that it removes any fog sprites that were
+            // no longer in fog. Unfortunately now that we show fog sprites for unexplored
+            // resources as well, removing those fog sprites ends up being problematic if
+            // the user switches vision off from a player who had those resources explored.
+            // In such case the unexplored fog sprites would not appear and some of the
+            // expansions would show up as empty while other unexplored bases keep their
+            // fog sprites as usual.
+            // To get around this issue, check which neutral buildings (or whatever
+            // `FOW_SPRITE_FILTER` selects) don't have fog sprites and add them back.
+            // (Adding fog sprites on visible area is fine, at least in replays)
+
+            let mut fow_sprites = FxHashSet::with_capacity_and_hasher(256, Default::default());
+            for fow in bw.fow_sprites() {
+                let sprite = (*fow).sprite;
+                let pos = bw.sprite_position(sprite);
+                fow_sprites.insert((pos.x, pos.y, (*fow).unit_id));
+            }
+            let units = with_fow_sprite_filter(|filter| {
+                bw.active_units()
+                    .filter(|unit| {
+                        if filter(unit) {
+                            // This currently adds fow sprites even for buildings that became
+                            // neutral after player left. It's probably fine, but if it wasn't
+                            // desired, checking that `sprite.player == 11` should only
+                            // include buildings that existed from map start
+                            let sprite = (***unit).sprite;
+                            let pos = bw.sprite_position(sprite);
+                            fow_sprites.insert((pos.x, pos.y, unit.id()))
+                        } else {
+                            false
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            });
+            let units = units.into_iter();
+            if let Err(missing) = create_fow_sprites(units, |unit| bw.create_fow_sprite(unit)) {
+                fow_sprites_exhausted(missing);
+            }
+        }
+    });
+    handle_ingame_requests();
+}
+
+/// Detects spectators joining and leaving during the game. Players who were in the game
+/// since start keep their `PlayersRandomized` mapping, leaving is detected for them from
+/// victory state instead.
+unsafe fn check_storm_players(bw: &dyn bw::Bw) {
+    let flags = bw.storm_player_flags();
+    let changes = with_game_thread_state(|state| match state.storm_flags.replace(flags.clone()) {
+        Some(old) => spectator_changes(&mut state.storm_to_game_id, &old, &flags),
+        None => Vec::new(),
+    });
+    for (storm_id, joined) in changes {
+        debug!("Spectator {} {}", storm_id, if joined { "joined" } else { "left" });
+        let storm_id = StormPlayerId(storm_id);
+        send_game_msg_to_async(GameThreadMessage::SpectatorChanged { storm_id, joined });
+    }
+}
+
+/// Returns storm ids that joined (true) or left (false) between `old` and `new` flags.
+fn spectator_changes(
+    mapping: &mut [Option<GamePlayerId>; bw::MAX_STORM_PLAYERS],
+    old: &[u32],
+    new: &[u32],
+) -> Vec<(u8, bool)> {
+    let mut result = Vec::new();
+    for (i, (&old, &new)) in old.iter().zip(new.iter()).enumerate() {
+        let game_id = match mapping.get_mut(i) {
+            Some(s) => s,
+            None => continue,
+        };
+        if old == 0 && new != 0 {
+            // The slot was either always empty, or belonged to a player who has left.
+            // Either way the one joining doesn't control any ingame player.
+            *game_id = None;
+            result.push((i as u8, true));
+        } else if old != 0 && new == 0 && game_id.is_none() {
+            result.push((i as u8, false));
+        }
+    }
+    result
+}
+
+unsafe fn check_eliminations(bw: &dyn bw::Bw) {
+    let game = bw.game();
+    let frame = (*game).frame_count;
+    let victory_state = (*game).victory_state;
+    with_game_thread_state(|state| {
+        for (eliminated, &victory_state) in state.eliminated_frame.iter_mut().zip(&victory_state) {
+            // 2 = Defeat
+            if eliminated.is_none() && victory_state == 2 {
+                *eliminated = Some(frame);
+            }
+        }
+    });
+}
+
+pub fn is_headless() -> bool {
+    setup_info().map(|x| x.headless).unwrap_or(false)
+}
+
+/// True if the local player is observing a game that has `observer_full_vision` set.
+unsafe fn observer_full_vision(bw: &dyn bw::Bw) -> bool {
+    let enabled = setup_info().map(|x| x.observer_full_vision).unwrap_or(false);
+    // Observers don't have a valid player id.
+    enabled && !is_replay() && bw.local_player_id() >= 8
+}
+
+fn fow_sprites_disabled() -> bool {
+    setup_info().map(|x| x.disable_fow_sprites).unwrap_or(false)
+}
+
+/// Replaces the default filter, which selects neutral buildings, for deciding which
+/// units get fog sprites. The filter is called from the game thread with BW's state
+/// being accessed, so it must not call back into `set_fow_sprite_filter`.
+pub fn set_fow_sprite_filter(filter: FowSpriteFilter) {
+    *FOW_SPRITE_FILTER.lock().unwrap() = Some(filter);
+}
+
+fn default_fow_sprite_filter(unit: &Unit) -> bool {
+    unit.player() == 11 && unit.is_landed_building()
+}
+
+/// Used for `GameSetupInfo.fow_sprite_unit_ids`.
+fn neutral_unit_id_filter(unit_ids: Vec<u16>) -> FowSpriteFilter {
+    Box::new(move |unit| unit.player() == 11 && unit_ids.contains(&unit.id()))
+}
+
+fn with_fow_sprite_filter<F: FnOnce(&dyn Fn(&Unit) -> bool) -> R, R>(func: F) -> R {
+    let filter = FOW_SPRITE_FILTER.lock().unwrap();
+    func(fow_sprite_filter_or_default(&filter))
+}
+
+/// The filter that `set_fow_sprite_filter` set, or the default one if there isn't any.
+fn fow_sprite_filter_or_default(filter: &Option<FowSpriteFilter>) -> &dyn Fn(&Unit) -> bool {
+    match *filter {
+        Some(ref filter) => &**filter,
+        None => &default_fow_sprite_filter,
+    }
+}
+
+/// Creates fog sprites for `units` until `create` fails, which means that BW
+/// doesn't have any more sprites available.
+/// On failure returns how many units didn't get a fog sprite.
+fn create_fow_sprites<T>(
+    mut units: impl Iterator<Item = T>,
+    mut create: impl FnMut(T) -> Option<()>,
+) -> Result<(), usize> {
+    while let Some(unit) = units.next() {
+        if create(unit).is_none() {
+            return Err(1 + units.count());
+        }
+    }
+    Ok(())
+}
+
+/// Reports running out of sprites once per game. Fog sprites for resources are
+/// just a visual aid, so the game can continue fine without them.
+fn fow_sprites_exhausted(missing: usize) {
+    let first = with_game_thread_state(|state| {
+        !std::mem::replace(&mut state.fow_sprites_exhausted, true)
+    });
+    if first {
+        let message = format!("Ran out of sprites, {} fog sprites weren't created", missing);
+        game_log!(Warn, "{}", message);
+        send_game_msg_to_async(GameThreadMessage::Warning(message));
+    }
+}
+
+/// Handles requests that were sent while the game loop is running.
+/// Requests that only make sense outside a game are ignored.
+unsafe fn handle_ingame_requests() {
+    let requests: Vec<GameThreadRequest> = {
+        let receive_requests = GAME_RECEIVE_REQUESTS.lock().unwrap();
+        match *receive_requests {
+            Some(ref recv) => recv.try_iter().collect(),
+            None => return,
+        }
+    };
+    for msg in requests {
+        use self::GameThreadRequestType::*;
+        match msg.request_type {
+            SendChat { message, recipients } => send_chat(&message, recipients),
+            MinimapPing { x, y } => {
+                let result = minimap_ping(x, y);
+                let request_id = msg.request_id;
+                send_game_msg_to_async(GameThreadMessage::MinimapPingResult { request_id, result });
+            }
+            MoveCamera { x, y, smooth } => move_camera(bw::Point { x, y }, smooth),
+            SetLocalVisions(mask) => set_local_visions(mask),
+            IssueCommand(command) => {
+                let result = issue_command(&command);
+                let request_id = msg.request_id;
+                let msg = GameThreadMessage::IssueCommandResult { request_id, result };
+                send_game_msg_to_async(msg);
+            }
+            request @ QueryFrame |
+            request @ QueryGameState |
+            request @ SnapshotUnits |
+            request @ DumpUnits { .. } |
+            request @ SetHardwareCursor(_) |
+            request @ SetRawInput(_) |
+            request @ SetRenderScale(_) |
+            request @ SetFrameCap(_) |
+            request @ SetClipMarkerHotkey(_) |
+            request @ SetPalette(_) |
+            request @ SetHookEnabled { .. } |
+            request @ QueryHooks => {
+                handle_game_request(request, msg.request_id)
+            }
+            ExitCleanup => {
+                // Exiting without the game having ended normally, send whatever results
+                // there are so they won't get lost completely. BW can't be cleaned up
+                // from the middle of the game loop, so that is done once the loop ends.
+                send_partial_results();
+                *DEFERRED_EXIT_CLEANUP.lock().unwrap() = Some(msg.done);
+                continue;
+            }
+            _ => game_log!(Warn, "Ignoring a request that can't be handled during game"),
+        }
+        let _ = msg.done.send(());
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GameStateSnapshot {
+    pub frame: u32,
+    /// `None` if the BW version doesn't support reading these.
+    pub paused: Option<bool>,
+    pub speed: Option<u8>,
+    /// True if BW had to wait for turns before the latest frame.
+    ///
+    /// Requests are handled only after frames are stepped, so while the game is paused
+    /// or waiting for turns the request gets answered once it continues, and this tells
+    /// whether it had been waiting.
+    pub lagging: bool,
+}
+
+unsafe fn game_state_snapshot() -> GameStateSnapshot {
+    with_bw(|bw| {
+        let game = bw.game();
+        let frame = if game.is_null() { 0 } else { (*game).frame_count };
+        let paused = bw.is_paused();
+        GameStateSnapshot {
+            frame,
+            paused,
+            speed: bw.game_speed(),
+            lagging: game_loop_running() &&
+                paused != Some(true) &&
+                watchdog::latest_step_lagged(),
+        }
+    })
+}
+
+/// Sends the ping as a command even in single player, as BW handles the command
+/// the same way in either case.
+unsafe fn minimap_ping(x: i16, y: i16) -> Result<(), bw::MinimapPingError> {
+    if is_replay() {
+        return Err(bw::MinimapPingError::Replay);
+    }
+    let (width, height) = bw::map_dimensions();
+    let in_bounds = x >= 0 && y >= 0 &&
+        (x as u32) < width as u32 * 32 &&
+        (y as u32) < height as u32 * 32;
+    if !in_bounds {
+        return Err(bw::MinimapPingError::OutOfBounds(x, y));
+    }
+    let mut command = [bw::commands::id::MINIMAP_PING, 0, 0, 0, 0];
+    command[1..3].copy_from_slice(&x.to_le_bytes());
+    command[3..5].copy_from_slice(&y.to_le_bytes());
+    with_bw(|bw| bw.send_command(&command));
+    Ok(())
+}
+
+/// About half a second at fastest speed.
+const CAMERA_PAN_FRAMES: u32 = 12;
+
+unsafe fn move_camera(target: bw::Point, smooth: bool) {
+    // Players could use this to see parts of the map they normally wouldn't.
+    if !is_replay() && with_bw(|bw| bw.local_player_id()) < 8 {
+        game_log!(Warn, "Camera can only be moved in replays or when observing");
+        return;
+    }
+    let (width, height) = bw::map_dimensions();
+    let screen_size = with_bw(|bw| bw.game_screen_size());
+    let target = clamp_to_map(target, width, height, screen_size);
+    if smooth {
+        let (from, frame) = with_bw(|bw| (bw.screen_position(), (*bw.game()).frame_count));
+        with_game_thread_state(|state| state.camera_pan = Some((from, target, frame)));
+    } else {
+        with_game_thread_state(|state| state.camera_pan = None);
+        if !with_bw(|bw| bw.set_screen_position(target)) {
+            game_log!(Warn, "Moving the camera isn't supported in this BW version");
+        }
+    }
+}
+
+/// Clamps the screen's top left corner so that the screen stays inside the map.
+fn clamp_to_map(
+    point: bw::Point,
+    width_tiles: u16,
+    height_tiles: u16,
+    (screen_width, screen_height): (u16, u16),
+) -> bw::Point {
+    let max = |tiles: u16, screen: u16| {
+        (tiles as i32 * 32 - screen as i32).max(0).min(i16::MAX as i32) as i16
+    };
+    let max_x = max(width_tiles, screen_width);
+    let max_y = max(height_tiles, screen_height);
+    bw::Point {
+        x: point.x.max(0).min(max_x),
+        y: point.y.max(0).min(max_y),
+    }
+}
+
+/// Position of a smooth camera move `elapsed` frames after it started.
+fn camera_pan_position(from: bw::Point, to: bw::Point, elapsed: u32) -> bw::Point {
+    let elapsed = elapsed.min(CAMERA_PAN_FRAMES) as i32;
+    let step = |from: i16, to: i16| {
+        let diff = to as i32 - from as i32;
+        (from as i32 + diff * elapsed / CAMERA_PAN_FRAMES as i32) as i16
+    };
+    bw::Point {
+        x: step(from.x, to.x),
+        y: step(from.y, to.y),
+    }
+}
+
+unsafe fn pan_camera(bw: &dyn bw::Bw) {
+    let (from, to, start) = match with_game_thread_state(|state| state.camera_pan) {
+        Some(s) => s,
+        None => return,
+    };
+    let elapsed = (*bw.game()).frame_count.saturating_sub(start);
+    if elapsed >= CAMERA_PAN_FRAMES {
+        with_game_thread_state(|state| state.camera_pan = None);
+    }
+    if !bw.set_screen_position(camera_pan_position(from, to, elapsed)) {
+        game_log!(Warn, "Moving the camera isn't supported in this BW version");
+        with_game_thread_state(|state| state.camera_pan = None);
+    }
+}
+
+unsafe fn set_local_visions(mask: u8) {
+    // Vision isn't synced, so players would see what they normally couldn't.
+    if !is_replay() && with_bw(|bw| bw.local_player_id()) < 8 {
+        game_log!(Warn, "Vision can only be changed in replays or when observing");
+        return;
+    }
+    if !with_bw(|bw| bw.set_local_visions(mask)) {
+        game_log!(Warn, "Changing vision isn't supported in this BW version");
+    }
+}
+
+unsafe fn issue_command(command: &[u8]) -> Result<(), bw::IssueCommandError> {
+    if is_replay() {
+        return Err(bw::IssueCommandError::Replay);
+    }
+    with_bw(|bw| {
+        bw::commands::validate_issued_command(command, bw.command_lengths())?;
+        bw.send_command(command);
+        Ok(())
+    })
+}
+
+unsafe fn send_chat(message: &str, recipients: chat::ChatRecipients) {
+    let recipient_bits = match recipients {
+        chat::ChatRecipients::All => 0xff,
+        chat::ChatRecipients::Player(storm_id) => 1u8.checked_shl(storm_id.0 as u32).unwrap_or(0),
+        chat::ChatRecipients::Allies => {
+            let game = with_bw(|bw| bw.game());
+            let local_player = with_bw(|bw| bw.local_player_id()) as usize;
+            let storm_to_game_id = with_game_thread_state(|state| state.storm_to_game_id);
+            let mut bits = 0u8;
+            if local_player < 8 {
+                for (storm_id, game_id) in storm_to_game_id.iter().enumerate().take(8) {
+                    if let Some(game_id) = game_id {
+                        if (*game).alliances[local_player][game_id.0 as usize] != 0 {
+                            bits |= 1 << storm_id;
+                        }
+                    }
+                }
+            }
+            bits
+        }
+    };
+    if recipient_bits == 0 {
+        game_log!(Warn, "No recipients for chat message ({:?})", recipients);
+        return;
+    }
+    for part in chat::split_message(message) {
+        if let Err(e) = with_bw(|bw| bw.send_chat(part, recipient_bits)) {
+            game_log!(Warn, "Couldn't send chat: {}", e);
+            return;
+        }
+    }
+}
+
+/// Copies the replay BW saved at end of the game to `dir`.
+fn auto_save_replay(dir: &Path) {
+    let source = match with_bw(|bw| bw.last_replay_path()) {
+        Some(s) => s,
+        None => {
+            game_log!(Warn, "Can't auto-save replay, BW's replay path isn't known");
+            return;
+        }
+    };
+    if let Err(e) = std::fs::create_dir_all(dir) {
+        game_log!(Warn, "Couldn't create replay directory '{}': {}", dir.display(), e);
+        return;
+    }
+    // Timestamps only have second precision, so a suffix is added if the name is taken.
+    let stem = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S").to_string();
+    let path = (1..)
+        .map(|i| match i {
+            1 => dir.join(format!("{}.rep", stem)),
+            i => dir.join(format!("{}_{}.rep", stem, i)),
+        })
+        .find(|path| !path.exists())
+        .unwrap();
+    match std::fs::copy(&source, &path) {
+        Ok(_) => send_game_msg_to_async(GameThreadMessage::ReplaySaved(path)),
+        Err(e) => game_log!(
+            Warn,
+            "Couldn't copy replay '{}' to '{}': {}",
+            source.display(),
+            path.display(),
+            e,
+        ),
+    }
+}
+
+/// Milestones are checked only once per game second, as the unit counts and tech levels
+/// are read for every rule and player.
+const MILESTONE_CHECK_INTERVAL_MS: u32 = 1000;
+
+const ARMY_VALUE_INTERVAL_MS: u32 = 1000;
+
+/// How many frames a worker can stay still while moving before it is considered stuck.
+const WORKER_STUCK_FRAMES: u32 = 24 * 10;
+
+unsafe fn check_stuck_workers(bw: &dyn bw::Bw) {
+    const ORDER_MOVE: u8 = 0x06;
+    const ORDER_MOVE_TO_GAS: u8 = 0x4f;
+    const ORDER_RETURN_GAS: u8 = 0x52;
+    const ORDER_MOVE_TO_MINERALS: u8 = 0x53;
+    const ORDER_RETURN_MINERALS: u8 = 0x58;
+
+    let frame = (*bw.game()).frame_count;
+    let mut stuck = Vec::new();
+    with_game_thread_state(|state| {
+        let mut current =
+            FxHashMap::with_capacity_and_hasher(state.moving_workers.len(), Default::default());
+        for unit in bw.active_units() {
+            if !unit.is_worker() {
+                continue;
+            }
+            match unit.order() {
+                ORDER_MOVE | ORDER_MOVE_TO_GAS | ORDER_RETURN_GAS | ORDER_MOVE_TO_MINERALS |
+                    ORDER_RETURN_MINERALS => (),
+                _ => continue,
+            }
+            let position = bw.sprite_position((**unit).sprite);
+            let key = *unit as usize;
+            let mut movement = match state.moving_workers.remove(&key) {
+                Some(old) if old.position == position => old,
+                _ => WorkerMovement {
+                    position,
+                    last_moved_frame: frame,
+                    reported: false,
+                },
+            };
+            let still_for = frame.saturating_sub(movement.last_moved_frame);
+            if !movement.reported && still_for >= WORKER_STUCK_FRAMES {
+                movement.reported = true;
+                stuck.push((unit.id(), position));
+            }
+            current.insert(key, movement);
+        }
+        state.moving_workers = current;
+    });
+    for (unit_id, pos) in stuck {
+        send_game_msg_to_async(GameThreadMessage::PathingStuck {
+            unit_id: unit_id as u32,
+            x: pos.x,
+            y: pos.y,
+            frame,
+        });
+    }
+}
+
+/// Hashes positions and hp/shields of all units, which should be enough to notice
+/// most desyncs on the frame they happen.
+///
+/// The active unit list is part of the synced game state, so iterating it in order
+/// gives the same result on every client as long as they are in sync.
+unsafe fn state_hash(bw: &dyn bw::Bw) -> u64 {
+    use std::hash::Hasher;
+
+    let mut hasher = fxhash::FxHasher64::default();
+    for unit in bw.active_units() {
+        let position = (**unit).position;
+        hasher.write_u16(unit.id());
+        hasher.write_u8(unit.player());
+        hasher.write_i16(position.x);
+        hasher.write_i16(position.y);
+        hasher.write_i32((**unit).hitpoints);
+        hasher.write_i32((**unit).shields);
+    }
+    hasher.finish()
+}
+
+unsafe fn report_army_values(bw: &dyn bw::Bw) {
+    let frame = (*bw.game()).frame_count;
+    let report = with_game_thread_state(|state| {
+        let now = state.game_time_ms(frame);
+        interval_passed(&mut state.army_values_reported, now, ARMY_VALUE_INTERVAL_MS)
+    });
+    if !report {
+        return;
+    }
+    let mut values = [0u32; 8];
+    for unit in bw.active_units() {
+        let player = unit.player() as usize;
+        if player >= 8 || !unit.is_completed() {
+            continue;
+        }
+        if let Some(cost) = bw::unit_costs::army_unit_cost(unit.id()) {
+            values[player] = values[player].saturating_add(cost.total());
+        }
+    }
+    send_game_msg_to_async(GameThreadMessage::ArmyValue { frame, values });
+}
+
+const LIVE_APM_INTERVAL_MS: u32 = 1000;
+
+unsafe fn report_live_apm(bw: &dyn bw::Bw) {
+    if is_replay() {
+        return;
+    }
+    let frame = (*bw.game()).frame_count;
+    let report = with_game_thread_state(|state| {
+        let now = state.game_time_ms(frame);
+        interval_passed(&mut state.live_apm_reported, now, LIVE_APM_INTERVAL_MS)
+    });
+    if !report {
+        return;
+    }
+    let apm = with_game_thread_state(|state| {
+        let now = state.game_time_ms(frame);
+        state.actions.live_apm(now)
+    });
+    send_game_msg_to_async(GameThreadMessage::LiveApm { frame, apm });
+}
+
+/// Income changes slowly enough that reporting it more often would mostly show noise
+/// from individual worker trips.
+const INCOME_RATE_INTERVAL_MS: u32 = 10_000;
+
+unsafe fn report_income_rate(bw: &dyn bw::Bw) {
+    let game = bw.game();
+    let frame = (*game).frame_count;
+    // Copying, as `bw::Game` is packed and can't be referenced into.
+    let (all_minerals, all_gas) = ((*game).cumulative_minerals, (*game).cumulative_gas);
+    let mut minerals = [0u32; 8];
+    let mut gas = [0u32; 8];
+    minerals.copy_from_slice(&all_minerals[..8]);
+    gas.copy_from_slice(&all_gas[..8]);
+    let report = with_game_thread_state(|state| {
+        let now = state.game_time_ms(frame);
+        let mut last_report = state.last_income.as_ref().map(|x| x.0);
+        if !interval_passed(&mut last_report, now, INCOME_RATE_INTERVAL_MS) {
+            return None;
+        }
+        Some((now, state.last_income.replace((now, minerals, gas))))
+    });
+    if let Some((now, Some((last_ms, last_minerals, last_gas)))) = report {
+        let elapsed_ms = now.saturating_sub(last_ms);
+        send_game_msg_to_async(GameThreadMessage::IncomeRate {
+            frame,
+            minerals_per_min: income_per_minute(&last_minerals, &minerals, elapsed_ms),
+            gas_per_min: income_per_minute(&last_gas, &gas, elapsed_ms),
+        });
+    }
+}
+
+/// Selection changes are checked only this often to limit the amount of messages
+/// sent when units keep switching orders, e.g. while attacking.
+const SELECTION_CHECK_INTERVAL: u32 = 8;
+
+unsafe fn report_selection(bw: &dyn bw::Bw) {
+    let frame = (*bw.game()).frame_count;
+    if frame % SELECTION_CHECK_INTERVAL != 0 {
+        return;
+    }
+    let units = bw::selection_orders();
+    let changed = with_game_thread_state(|state| {
+        if state.last_selection != units {
+            state.last_selection = units.clone();
+            true
+        } else {
+            false
+        }
+    });
+    if changed {
+        send_game_msg_to_async(GameThreadMessage::SelectionDetails { frame, units });
+    }
+}
+
+const CAMERA_CHECK_INTERVAL: u32 = 8;
+
+unsafe fn report_camera(bw: &dyn bw::Bw) {
+    let frame = (*bw.game()).frame_count;
+    if frame % CAMERA_CHECK_INTERVAL != 0 {
+        return;
+    }
+    let position = bw.screen_position();
+    let changed = with_game_thread_state(|state| {
+        if state.last_camera != Some(position) {
+            state.last_camera = Some(position);
+            true
+        } else {
+            false
+        }
+    });
+    if changed {
+        let msg = GameThreadMessage::CameraMove { frame, x: position.x, y: position.y };
+        send_game_msg_to_async(msg);
+    }
+}
+
+fn income_per_minute(old: &[u32; 8], new: &[u32; 8], elapsed_ms: u32) -> [u32; 8] {
+    let mut result = [0u32; 8];
+    if elapsed_ms == 0 {
+        return result;
+    }
+    for ((out, &old), &new) in result.iter_mut().zip(old.iter()).zip(new.iter()) {
+        let per_minute = new.saturating_sub(old) as u64 * 60_000 / elapsed_ms as u64;
+        *out = per_minute as u32;
+    }
+    result
+}
+
+/// Detects alerts for the local player from changes in game state.
+///
+/// This doesn't hook BW's own alert code, so that it works the same way on both
+/// 1.16.1 and SC:R without needing addresses for either.
+unsafe fn check_alerts(bw: &dyn bw::Bw) {
+    // Roughly how often BW repeats the under attack alert
+    const ATTACK_ALERT_INTERVAL: u32 = 24 * 5;
+    const ORDER_DIE: u8 = 0x00;
+
+    let game = bw.game();
+    let player = bw.local_player_id() as usize;
+    if player >= 8 || is_replay() {
+        return;
+    }
+    let frame = (*game).frame_count;
+    let mut alerts = Vec::new();
+    with_game_thread_state(|state| {
+        let mut current =
+            FxHashMap::with_capacity_and_hasher(state.local_units.len(), Default::default());
+        let mut attacked = None;
+        for unit in bw.active_units() {
+            if unit.player() as usize != player {
+                continue;
+            }
+            let key = *unit as usize;
+            let tracked = TrackedUnit {
+                unit_id: unit.id(),
+                position: (**unit).position,
+                health: (**unit).hitpoints.saturating_add((**unit).shields),
+            };
+            if let Some(old) = state.local_units.remove(&key) {
+                if old.unit_id == tracked.unit_id && old.health > tracked.health {
+                    attacked = Some(tracked.position);
+                }
+            }
+            current.insert(key, tracked);
+        }
+        // Units also leave the active unit list when they get loaded into a transport
+        // or enter a gas building, so only ones that are dying were lost. BW keeps units
+        // in a fixed array, so the struct of a removed unit is still readable.
+        for (&key, lost) in &state.local_units {
+            let unit = key as *mut bw::Unit;
+            if (*unit).order == ORDER_DIE {
+                alerts.push((AlertKind::UnitLost, lost.position));
+            }
+        }
+        state.local_units = current;
+        if let Some(pos) = attacked {
+            let can_alert = state
+                .last_attack_alert
+                .map(|last| frame.saturating_sub(last) >= ATTACK_ALERT_INTERVAL)
+                .unwrap_or(true);
+            if can_alert {
+                state.last_attack_alert = Some(frame);
+                alerts.push((AlertKind::UnderAttack, pos));
+            }
+        }
+
+        let techs = (*game).tech_level_sc[player]
+            .iter()
+            .chain((*game).tech_level_bw[player].iter())
+            .filter(|&&x| x != 0)
+            .count() as u32;
+        let upgrades = (*game).upgrade_level_sc[player]
+            .iter()
+            .chain((*game).upgrade_level_bw[player].iter())
+            .map(|&x| x as u32)
+            .sum::<u32>();
+        let no_pos = bw::Point { x: 0, y: 0 };
+        if let Some((old_techs, old_upgrades)) = state.local_research {
+            if techs > old_techs {
+                alerts.push((AlertKind::ResearchComplete, no_pos));
+            }
+            if upgrades > old_upgrades {
+                alerts.push((AlertKind::UpgradeComplete, no_pos));
+            }
+        }
+        state.local_research = Some((techs, upgrades));
+    });
+    for (kind, pos) in alerts {
+        send_game_msg_to_async(GameThreadMessage::PlayerAlert {
+            kind,
+            x: pos.x,
+            y: pos.y,
+            frame,
+        });
+    }
+}
+
+unsafe fn check_milestones(bw: &dyn bw::Bw) {
+    let game = bw.game();
+    let frame = (*game).frame_count;
+    let reached = with_game_thread_state(|state| {
+        let now = state.game_time_ms(frame);
+        if interval_passed(&mut state.milestones_checked, now, MILESTONE_CHECK_INTERVAL_MS) {
+            milestones::check(game, &mut state.milestones_reached)
+        } else {
+            Vec::new()
+        }
+    });
+    for (player, milestone) in reached {
+        let player = GamePlayerId(player);
+        send_game_msg_to_async(GameThreadMessage::TechMilestone { player, milestone, frame });
+    }
+}
+
+/// Minerals needed for the cheapest supply-using unit (Marine, Zergling or a worker).
+const SUPPLY_BLOCK_MINERALS: u32 = 50;
+
+/// A player is considered supply blocked if they have no free supply left, aren't at
+/// the supply cap, and could afford to train something if they had the supply.
+///
+/// Whether the player actually wanted to train anything isn't known, so having enough
+/// minerals for the cheapest unit is used as a stand-in for it.
+fn is_supply_blocked(supply: bw::Supply, minerals: u32) -> bool {
+    supply.used >= supply.provided &&
+        supply.provided < supply.max &&
+        minerals >= SUPPLY_BLOCK_MINERALS
+}
+
+unsafe fn check_supply_blocks(bw: &dyn bw::Bw) {
+    let game = bw.game();
+    let players = bw.players();
+    // Copying, as `bw::Game` is packed and can't be referenced into.
+    let (minerals, victory_state) = ((*game).minerals, (*game).victory_state);
+    let mut blocked = [false; 8];
+    for i in 0..8 {
+        if !is_in_game(players.add(i)) || victory_state[i] != 0 {
+            continue;
+        }
+        if let Some(supply) = bw::player_supply(i as u8) {
+            blocked[i] = is_supply_blocked(supply, minerals[i]);
+        }
+    }
+    with_game_thread_state(|state| {
+        for (frames, &blocked) in state.supply_blocked_frames.iter_mut().zip(blocked.iter()) {
+            if blocked {
+                *frames = frames.saturating_add(1);
+            }
+        }
+    });
+}
+
+unsafe fn is_in_game(player: *mut bw::Player) -> bool {
+    match (*player).player_type {
+        bw::PLAYER_TYPE_HUMAN | bw::PLAYER_TYPE_COMPUTER | bw::PLAYER_TYPE_LOBBY_COMPUTER => true,
+        _ => false,
+    }
+}
+
+/// Sends `FirstBuilding` the first time each player places a building.
+///
+/// A placed building is an incomplete unit until it finishes, while the buildings that
+/// players start with are always completed, so the first incomplete building seen
+/// is the player's first placement.
+unsafe fn check_first_buildings(bw: &dyn bw::Bw) {
+    let players = bw.players();
+    let mut placed = Vec::new();
+    with_game_thread_state(|state| {
+        let pending = state.first_building_pending.get_or_insert_with(|| {
+            (0..8)
+                .filter(|&i| is_in_game(players.add(i)))
+                .fold(0u8, |bits, i| bits | (1 << i))
+        });
+        if *pending == 0 {
+            return;
+        }
+        for unit in bw.active_units() {
+            let player = unit.player();
+            if player >= 8 || *pending & (1 << player) == 0 {
+                continue;
+            }
+            if !unit.is_landed_building() || unit.is_completed() {
+                continue;
+            }
+            *pending &= !(1 << player);
+            placed.push((player, unit.id(), bw.sprite_position((**unit).sprite)));
+        }
+    });
+    let frame = (*bw.game()).frame_count;
+    for (player, unit_id, pos) in placed {
+        send_game_msg_to_async(GameThreadMessage::FirstBuilding {
+            player: GamePlayerId(player),
+            unit_id: unit_id as u32,
+            x: pos.x,
+            y: pos.y,
+            frame,
+        });
+    }
+}
+
+/// Records the frame whenever a player's town hall finishes. The town halls that exist
+/// on the first checked frame are the starting bases, and aren't counted.
+///
+/// Each town hall is counted once, so lifting and landing a Command center elsewhere or
+/// morphing a Hatchery to Lair doesn't count as a new expansion.
+unsafe fn check_expansions(bw: &dyn bw::Bw) {
+    let halls = bw
+        .active_units()
+        .filter(|unit| unit.is_town_hall() && unit.player() < 8)
+        .map(|unit| (*unit as usize, unit.player(), unit.is_completed()))
+        .collect::<Vec<_>>();
+    let frame = (*bw.game()).frame_count;
+    with_game_thread_state(|state| {
+        match state.town_halls {
+            Some(ref mut known) => {
+                for player in update_town_halls(known, &halls) {
+                    state.expansion_frames[player as usize].push(frame);
+                }
+            }
+            None => {
+                let known = halls.iter().map(|&(unit, _, _)| (unit, true)).collect();
+                state.town_halls = Some(known);
+            }
+        }
+    });
+}
+
+/// Updates `known` to contain the currently existing town halls, given as
+/// (unit pointer, player, completed). Returns players whose town hall completed
+/// for the first time.
+fn update_town_halls(known: &mut FxHashMap<usize, bool>, halls: &[(usize, u8, bool)]) -> Vec<u8> {
+    // Unit pointers get reused once the unit dies, so forget halls that don't exist anymore.
+    known.retain(|unit, _| halls.iter().any(|x| x.0 == *unit));
+    let mut completed = Vec::new();
+    for &(unit, player, is_completed) in halls {
+        let counted = known.entry(unit).or_insert(false);
+        if is_completed && !*counted {
+            *counted = true;
+            completed.push(player);
+        }
+    }
+    completed
+}
+
+/// Sends `SpeedChanged` whenever the game speed changes, and records it in
+/// `speed_segments`. The speed seen on the first checked frame is recorded as starting
+/// from frame 0, without sending anything.
+///
+/// Does nothing on BW versions that don't support reading the game speed.
+unsafe fn check_game_speed(bw: &dyn bw::Bw) {
+    let speed = match bw.game_speed().and_then(GameSpeed::from_bw) {
+        Some(s) => s,
+        None => return,
+    };
+    let frame = (*bw.game()).frame_count;
+    let changed = with_game_thread_state(|state| match state.speed_segments.last() {
+        Some(&(_, previous)) if previous == speed => false,
+        Some(_) => {
+            state.speed_segments.push((frame, speed));
+            true
+        }
+        None => {
+            state.speed_segments.push((0, speed));
+            false
+        }
+    });
+    if changed {
+        send_game_msg_to_async(GameThreadMessage::SpeedChanged { frame, speed });
+    }
+}
+
+/// Milliseconds that frames `start_frame..end_frame` took, with speeds given as
+/// (start frame, speed). Frames before the first segment, or all frames if there are
+/// no segments, are assumed to be at fastest speed.
+fn time_at_speeds_ms(segments: &[(u32, GameSpeed)], start_frame: u32, end_frame: u32) -> u32 {
+    let mut total = 0u32;
+    let mut frame = start_frame;
+    let mut speed = GameSpeed::Fastest;
+    for &(segment_start, segment_speed) in segments {
+        let until = segment_start.min(end_frame).max(frame);
+        total = total.saturating_add((until - frame).saturating_mul(speed.frame_ms()));
+        frame = until;
+        speed = segment_speed;
+    }
+    let remaining = end_frame.saturating_sub(frame);
+    total.saturating_add(remaining.saturating_mul(speed.frame_ms()))
+}
+
+/// Buildings placed after this much game time aren't considered to be proxies.
+const PROXY_TIME_LIMIT_MS: u32 = 6 * 60_000;
+/// How close to an opponent's starting town hall a building must be placed to count
+/// as a proxy, in pixels. 24 tiles covers main bases of the usual ladder maps without
+/// reaching their naturals.
+const PROXY_DISTANCE: u32 = 24 * 32;
+
+/// Sends `ProxyDetected` when a player places a military building near an opponent's
+/// main base during the first `PROXY_TIME_LIMIT_MS` of the game.
+///
+/// Main bases are the town halls that players have on the first checked frame,
+/// so this does nothing for UMS maps, where that isn't meaningful.
+unsafe fn check_proxies(bw: &dyn bw::Bw) {
+    let game = bw.game();
+    let frame = (*game).frame_count;
+    if is_ums() {
+        return;
+    }
+    let players = bw.players();
+    let mut proxies = Vec::new();
+    with_game_thread_state(|state| {
+        if state.game_time_ms(frame) > PROXY_TIME_LIMIT_MS {
+            return;
+        }
+        let main_bases = state.main_bases.get_or_insert_with(|| {
+            let mut bases = [None; 8];
+            for unit in bw.active_units() {
+                let player = unit.player() as usize;
+                if player < 8 && unit.is_town_hall() && bases[player].is_none() {
+                    bases[player] = Some((**unit).position);
+                }
+            }
+            bases
+        });
+        for unit in bw.active_units() {
+            let owner = unit.player();
+            if owner >= 8 || state.proxies_reported & (1 << owner) != 0 {
+                continue;
+            }
+            // Only buildings that are still being built, so that the starting buildings
+            // and ones placed before the limit don't get reported later.
+            if !unit.is_military_building() || !unit.is_landed_building() || unit.is_completed()
+            {
+                continue;
+            }
+            let opponents = (0..8u8)
+                .filter(|&i| i != owner && is_in_game(players.add(i as usize)))
+                .filter(|&i| (*game).alliances[owner as usize][i as usize] == 0)
+                .fold(0u8, |bits, i| bits | (1 << i));
+            let position = (**unit).position;
+            if let Some(target) = proxy_target(position, main_bases, opponents) {
+                state.proxies_reported |= 1 << owner;
+                proxies.push((owner, target, position));
+            }
+        }
+    });
+    for (by, target, pos) in proxies {
+        send_game_msg_to_async(GameThreadMessage::ProxyDetected {
+            by: GamePlayerId(by),
+            target: GamePlayerId(target),
+            x: pos.x,
+            y: pos.y,
+            frame,
+        });
+    }
+}
+
+/// Returns the player in `opponents` (bit for each player) whose main base is closest
+/// to `position`, if it is within `PROXY_DISTANCE`.
+fn proxy_target(
+    position: bw::Point,
+    main_bases: &[Option<bw::Point>; 8],
+    opponents: u8,
+) -> Option<u8> {
+    (0..8u8)
+        .filter(|&i| opponents & (1 << i) != 0)
+        .filter_map(|i| {
+            let base = main_bases[i as usize]?;
+            let dx = (position.x as i32 - base.x as i32).abs() as u32;
+            let dy = (position.y as i32 - base.y as i32).abs() as u32;
+            Some((i, dx * dx + dy * dy))
+        })
+        .filter(|&(_, distance_sq)| distance_sq <= PROXY_DISTANCE * PROXY_DISTANCE)
+        .min_by_key(|&(_, distance_sq)| distance_sq)
+        .map(|(i, _)| i)
+}
+
+/// Sends `FirstContact` for each ordered pair of non-allied players the first time
+/// a unit of one becomes visible to the other.
+unsafe fn check_first_contacts(bw: &dyn bw::Bw) {
+    let game = bw.game();
+    let mut contacts = Vec::new();
+    with_game_thread_state(|state| {
+        if state.first_contact_seen.iter().all(|&x| x == 0xff) {
+            return;
+        }
+        for unit in bw.active_units() {
+            let owner = unit.player();
+            if owner >= 8 {
+                continue;
+            }
+            let visible_to = bw.sprite_visibility_mask((**unit).sprite);
+            for scouter in 0..8u8 {
+                if scouter == owner || visible_to & (1 << scouter) == 0 {
+                    continue;
+                }
+                let seen = &mut state.first_contact_seen[scouter as usize];
+                if *seen & (1 << owner) != 0 {
+                    continue;
+                }
+                if (*game).alliances[scouter as usize][owner as usize] != 0 {
+                    continue;
+                }
+                *seen |= 1 << owner;
+                contacts.push((scouter, owner));
+            }
+        }
+    });
+    let frame = (*game).frame_count;
+    for (scouter, scouted) in contacts {
+        let scouter = GamePlayerId(scouter);
+        let scouted = GamePlayerId(scouted);
+        send_game_msg_to_async(GameThreadMessage::FirstContact { scouter, scouted, frame });
+    }
+}
+
+/// Sends `ResourceDepleted` for resource units that were known to have resources
+/// last frame, but either have none left or don't exist anymore.
+unsafe fn check_resource_depletion(bw: &dyn bw::Bw) {
+    let game = bw.game();
+    let depleted = with_game_thread_state(|state| {
+        let mut current =
+            FxHashMap::with_capacity_and_hasher(state.resources.len(), Default::default());
+        for unit in bw.active_units() {
+            if unit.is_resource_container() && unit.resource_amount() != 0 {
+                let position = (**unit).position;
+                let resource = TrackedResource {
+                    unit_id: unit.id(),
+                    position,
+                };
+                current.insert((position.x, position.y), resource);
+            }
+        }
+        update_resources(&mut state.resources, current)
+    });
+    let frame = (*game).frame_count;
+    for resource in depleted {
+        send_game_msg_to_async(GameThreadMessage::ResourceDepleted {
+            unit_id: resource.unit_id as u32,
+            x: resource.position.x,
+            y: resource.position.y,
+            frame,
+        });
+    }
+}
+
+/// Replaces `tracked` with `current`, returning resources that were in `tracked`
+/// but had no resource in the same position in `current`.
+fn update_resources(
+    tracked: &mut FxHashMap<(i16, i16), TrackedResource>,
+    current: FxHashMap<(i16, i16), TrackedResource>,
+) -> Vec<TrackedResource> {
+    let depleted = tracked
+        .drain()
+        .filter(|(key, _)| !current.contains_key(key))
+        .map(|(_, resource)| resource)
+        .collect();
+    *tracked = current;
+    depleted
+}
+
+/// Returns true if at least `interval_ms` of game time has passed since `last`, updating
+/// `last` to `now_ms` if so. (Or if `last` is `None`)
+///
+/// Periodic checks use this instead of counting frames, as a frame is longer on
+/// speeds slower than fastest.
+fn interval_passed(last: &mut Option<u32>, now_ms: u32, interval_ms: u32) -> bool {
+    match *last {
+        Some(last) if now_ms.saturating_sub(last) < interval_ms => false,
+        _ => {
+            *last = Some(now_ms);
+            true
+        }
+    }
+}
+
+#[test]
+fn geyser_with_refinery_not_depleted() {
+    fn resources(list: &[(u16, i16, i16)]) -> FxHashMap<(i16, i16), TrackedResource> {
+        list.iter()
+            .map(|&(unit_id, x, y)| {
+                ((x, y), TrackedResource { unit_id, position: bw::Point { x, y } })
+            })
+            .collect()
+    }
+    let mut tracked = resources(&[(0xbc, 100, 200), (0xb0, 300, 200)]);
+    // Geyser got an extractor
+    let depleted = update_resources(&mut tracked, resources(&[(0x95, 100, 200), (0xb0, 300, 200)]));
+    assert!(depleted.is_empty());
+    // Extractor ran out of gas, and the mineral field got mined out
+    let depleted = update_resources(&mut tracked, resources(&[]));
+    let mut ids = depleted.iter().map(|x| x.unit_id).collect::<Vec<_>>();
+    ids.sort();
+    assert_eq!(ids, vec![0x95, 0xb0]);
+}
+
+#[test]
+fn army_cost_of_deaths() {
+    let mut deaths = [[0u32; 0xc]; 0xe4];
+    // Marines, a Siege tank, SCVs, and a Command center
+    deaths[0x00][1] = 3;
+    deaths[0x05][1] = 1;
+    deaths[0x07][1] = 4;
+    deaths[0x6a][1] = 1;
+    deaths[0x00][2] = 1;
+    deaths[0x00][11] = 10;
+    assert_eq!(army_cost_sum(&deaths, 1), 3 * 50 + 250);
+    assert_eq!(army_cost_sum(&deaths, 2), 50);
+    assert_eq!(army_cost_sum(&deaths, 0), 0);
+}
+
+#[test]
+fn team_results() {
+    let team = [1, 1, 2, 2, 3, 3, 0, 0];
+    let in_game = [true, true, true, true, true, false, true, false];
+    // Player 0 left early, but their teammate won.
+    let victory_state = [1, 3, 2, 2, 0, 0, 0, 0];
+    let result = team_victory_states(&team, &victory_state, &in_game);
+    assert_eq!(result, vec![(1, 3), (2, 2), (3, 0)]);
+
+    let victory_state = [1, 1, 2, 1, 3, 0, 0, 0];
+    let result = team_victory_states(&team, &victory_state, &in_game);
+    assert_eq!(result, vec![(1, 1), (2, 2), (3, 3)]);
+}
+
+#[test]
+fn coop_victory_with_computer_allies() {
+    // Players 0, 1 are humans, 2, 3 computers on their team, and 4, 5 computer opponents.
+    let is_computer = [false, false, true, true, true, true, false, false];
+    let mut allied = [[false; 8]; 8];
+    for &team in &[[0, 1, 2, 3], [4, 5, 4, 5]] {
+        for &a in &team {
+            for &b in &team {
+                allied[a][b] = true;
+            }
+        }
+    }
+    // Player 1 was eliminated, but their computer ally kept playing and won.
+    let victory_state = [0, 2, 3, 0, 2, 2, 0, 0];
+    let result = coop_victory_state(victory_state, &is_computer, &allied);
+    assert_eq!(result, [3, 3, 3, 0, 2, 2, 0, 0]);
+
+    // Disconnects stay as they are
+    let victory_state = [1, 3, 0, 0, 2, 2, 0, 0];
+    let result = coop_victory_state(victory_state, &is_computer, &allied);
+    assert_eq!(result, [1, 3, 0, 0, 2, 2, 0, 0]);
+
+    // Humans lost
+    let victory_state = [2, 2, 2, 2, 3, 3, 0, 0];
+    let result = coop_victory_state(victory_state, &is_computer, &allied);
+    assert_eq!(result, victory_state);
+}
+
+#[test]
+fn fow_sprite_creation_stops_when_out_of_sprites() {
+    let mut created = Vec::new();
+    let result = create_fow_sprites(0..10, |unit| {
+        if created.len() == 4 {
+            None
+        } else {
+            created.push(unit);
+            Some(())
+        }
+    });
+    assert_eq!(result, Err(6));
+    assert_eq!(created, vec![0, 1, 2, 3]);
+
+    assert_eq!(create_fow_sprites(0..10, |_| Some(())), Ok(()));
+}
+
+#[test]
+fn fow_sprite_filter_selects_units() {
+    unsafe {
+        let mut units: Vec<bw::Unit> = (0..3).map(|_| std::mem::zeroed()).collect();
+        // Neutral mineral field, neutral unit of another type and a player's building
+        for (unit, &(player, flags)) in units.iter_mut().zip(&[(11, 0x2), (11, 0), (0, 0x2)]) {
+            unit.player = player;
+            unit.flags = flags;
+            unit.unit_id = 0xb0;
+        }
+        units[1].unit_id = 0xcb;
+        let units: Vec<Unit> = units.iter_mut().filter_map(|x| Unit::from_ptr(x)).collect();
+        let selected = |filter: &Option<FowSpriteFilter>| {
+            let filter = fow_sprite_filter_or_default(filter);
+            units.iter().map(|x| filter(x)).collect::<Vec<_>>()
+        };
+        assert_eq!(selected(&None), vec![true, false, false]);
+        let filter = Some(neutral_unit_id_filter(vec![0xcb]));
+        assert_eq!(selected(&filter), vec![false, true, false]);
+    }
+}
+
+#[test]
+fn upgrade_and_tech_ids_continue_to_bw_arrays() {
+    let mut upgrades_sc = [0u8; 0x2e];
+    let mut upgrades_bw = [0u8; 0xf];
+    upgrades_sc[0x3] = 2;
+    upgrades_bw[0x1] = 1;
+    assert_eq!(upgrade_levels(&upgrades_sc, &upgrades_bw), vec![(0x3, 2), (0x2f, 1)]);
+
+    let mut techs_sc = [0u8; 0x18];
+    let mut techs_bw = [0u8; 0x14];
+    techs_sc[0x0] = 1;
+    techs_bw[0x13] = 1;
+    assert_eq!(researched_techs(&techs_sc, &techs_bw), 1 | (1 << 0x2b));
+}
+
+#[test]
+fn income_rate() {
+    let old = [50, 0, 100, 0, 0, 0, 0, 0];
+    let new = [1050, 0, 600, 0, 0, 0, 0, 0];
+    // 240 frames at fastest speed
+    let rate = income_per_minute(&old, &new, 10_080);
+    assert_eq!(rate, [5952, 0, 2976, 0, 0, 0, 0, 0]);
+    assert_eq!(income_per_minute(&old, &new, 0), [0; 8]);
+}
+
+#[test]
+fn spectators_joining_and_leaving() {
+    let mut mapping = [None; bw::MAX_STORM_PLAYERS];
+    mapping[0] = Some(GamePlayerId(1));
+    mapping[1] = Some(GamePlayerId(0));
+    let flags = |active: &[usize]| {
+        (0..bw::MAX_STORM_PLAYERS)
+            .map(|i| if active.contains(&i) { 1 } else { 0 })
+            .collect::<Vec<u32>>()
+    };
+    let changes = spectator_changes(&mut mapping, &flags(&[0, 1]), &flags(&[0, 1, 2]));
+    assert_eq!(changes, vec![(2, true)]);
+    // A player leaving isn't a spectator change, and keeps the mapping
+    let changes = spectator_changes(&mut mapping, &flags(&[0, 1, 2]), &flags(&[0, 2]));
+    assert!(changes.is_empty());
+    assert_eq!(mapping[1], Some(GamePlayerId(0)));
+    // Until someone else takes the slot
+    let changes = spectator_changes(&mut mapping, &flags(&[0, 2]), &flags(&[0, 1]));
+    assert_eq!(changes, vec![(1, true), (2, false)]);
+    assert_eq!(mapping[0], Some(GamePlayerId(1)));
+    assert_eq!(mapping[1], None);
+}
+
+#[test]
+fn bw_state_validation() {
+    use std::ptr::{null_mut, NonNull};
+    let game = NonNull::<bw::Game>::dangling().as_ptr();
+    let players = NonNull::<bw::Player>::dangling().as_ptr();
+    assert_eq!(validate_bw_state(game, players), Ok(()));
+    assert_eq!(
+        validate_bw_state(null_mut(), players),
+        Err(BwStateError::NullPointer("game")),
+    );
+    assert_eq!(
+        validate_bw_state(game, null_mut()),
+        Err(BwStateError::NullPointer("players")),
+    );
+}
+
+#[test]
+fn supply_block_heuristic() {
+    let supply = |used, provided| bw::Supply { used, provided, max: 400 };
+    assert!(is_supply_blocked(supply(18, 18), 50));
+    assert!(is_supply_blocked(supply(20, 18), 500));
+    assert!(!is_supply_blocked(supply(18, 18), 49));
+    assert!(!is_supply_blocked(supply(16, 18), 500));
+    // Maxed out isn't blocked
+    assert!(!is_supply_blocked(supply(400, 400), 500));
+    assert!(!is_supply_blocked(bw::Supply { used: 400, provided: 416, max: 400 }, 500));
+}
+
+#[test]
+fn camera_move_positions() {
+    use crate::bw::Point;
+    let screen = (640, 400);
+    let clamped = clamp_to_map(Point { x: -50, y: 4000 }, 128, 96, screen);
+    assert_eq!(clamped, Point { x: 0, y: 2672 });
+    let clamped = clamp_to_map(Point { x: 4000, y: 200 }, 128, 96, screen);
+    assert_eq!(clamped, Point { x: 3456, y: 200 });
+    let clamped = clamp_to_map(Point { x: 5000, y: 5000 }, 256, 256, screen);
+    assert_eq!(clamped, Point { x: 5000, y: 5000 });
+    // Maps smaller than the screen
+    assert_eq!(clamp_to_map(Point { x: 100, y: 100 }, 16, 10, screen), Point { x: 0, y: 0 });
+    let from = Point { x: 100, y: 1000 };
+    let to = Point { x: 400, y: 400 };
+    assert_eq!(camera_pan_position(from, to, 0), from);
+    assert_eq!(camera_pan_position(from, to, CAMERA_PAN_FRAMES / 2), Point { x: 250, y: 700 });
+    assert_eq!(camera_pan_position(from, to, CAMERA_PAN_FRAMES), to);
+    assert_eq!(camera_pan_position(from, to, CAMERA_PAN_FRAMES * 3), to);
+}
+
+#[test]
+fn expansion_detection() {
+    let mut known = FxHashMap::default();
+    // Starting town halls
+    known.insert(0x100, true);
+    known.insert(0x200, true);
+    // Expansion placed, then finished; main lifts
+    assert!(update_town_halls(&mut known, &[(0x100, 0, true), (0x200, 1, true), (0x300, 0, false)])
+        .is_empty());
+    assert_eq!(update_town_halls(&mut known, &[(0x100, 0, true), (0x300, 0, true)]), vec![0]);
+    assert!(update_town_halls(&mut known, &[(0x100, 0, true), (0x300, 0, true)]).is_empty());
+    // Player 1's main died and the unit was reused for a new Hatchery
+    assert!(update_town_halls(&mut known, &[(0x200, 1, false)]).is_empty());
+    assert_eq!(update_town_halls(&mut known, &[(0x200, 1, true)]), vec![1]);
+}
+
+#[test]
+fn proxy_targets() {
+    use crate::bw::Point;
+    let mut bases = [None; 8];
+    bases[0] = Some(Point { x: 200, y: 200 });
+    bases[1] = Some(Point { x: 3800, y: 3800 });
+    bases[2] = Some(Point { x: 3800, y: 200 });
+    let near_1 = Point { x: 3500, y: 3600 };
+    assert_eq!(proxy_target(near_1, &bases, 0b110), Some(1));
+    // Allied with player 1
+    assert_eq!(proxy_target(near_1, &bases, 0b100), None);
+    // Center of the map
+    assert_eq!(proxy_target(Point { x: 2000, y: 2000 }, &bases, 0b111), None);
+    // Closer to player 2 than player 1
+    let between = Point { x: 3800, y: 800 };
+    assert_eq!(proxy_target(between, &bases, 0b110), Some(2));
+}
+
+#[test]
+fn periodic_interval() {
+    let mut last = None;
+    assert!(interval_passed(&mut last, 0, 1000));
+    assert!(!interval_passed(&mut last, 42, 1000));
+    assert!(!interval_passed(&mut last, 999, 1000));
+    assert!(interval_passed(&mut last, 1002, 1000));
+    assert_eq!(last, Some(1002));
+    assert!(!interval_passed(&mut last, 2001, 1000));
+    assert!(interval_passed(&mut last, 2002, 1000));
+}
+
+#[test]
+fn game_time_at_speeds() {
+    assert_eq!(time_at_speeds_ms(&[], 0, 1000), 42_000);
+    let segments = [(0, GameSpeed::Fastest), (100, GameSpeed::Normal), (200, GameSpeed::Fastest)];
+    assert_eq!(time_at_speeds_ms(&segments, 0, 300), 100 * 42 + 100 * 67 + 100 * 42);
+    // Game ended during the slower segment
+    assert_eq!(time_at_speeds_ms(&segments, 0, 150), 100 * 42 + 50 * 67);
+    // Counting from gameplay start
+    assert_eq!(time_at_speeds_ms(&segments, 150, 300), 50 * 67 + 100 * 42);
+    assert_eq!(time_at_speeds_ms(&segments, 400, 300), 0);
+    assert_eq!(GameSpeed::from_bw(3), Some(GameSpeed::Normal));
+    assert_eq!(GameSpeed::from_bw(7), None);
 }
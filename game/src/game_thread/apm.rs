@@ -0,0 +1,305 @@
+//! Counting of player actions for APM statistics.
+//!
+//! Two counts are kept: raw APM counts every command that a player issues, while
+//! effective APM (EAPM) doesn't count a command if it is identical to the previous
+//! counted command of the same player, and was issued less than `EAPM_REPEAT_FRAMES`
+//! frames after it. This filters out e.g. spamming the same move order or hotkey,
+//! while holding a key down still counts once every `EAPM_REPEAT_FRAMES` frames.
+//!
+//! Neither 1.16.1 nor SC:R keep track of APM themselves, so live APM is also computed
+//! here, from raw actions during the last `LIVE_APM_WINDOW_MS` of game time.
+//! Minutes are always minutes of game time, which depends on the game speed, so
+//! callers pass the game time of commands. EAPM repeats and the timeline are
+//! still counted in frames.
+//!
+//! Control group usage is counted separately for assigning units to groups (including
+//! adding to an existing group) and recalling them.
+//!
+//! Raw actions are also kept in an `ActionTimeline`, split by `ActionCategory` into
+//! buckets of `TIMELINE_BUCKET_FRAMES` frames, for APM-over-time graphs.
+
+use std::collections::VecDeque;
+
+use crate::bw::commands::{id, CommandId};
+
+/// 10 frames is 420ms at fastest speed.
+pub const EAPM_REPEAT_FRAMES: u32 = 10;
+pub const LIVE_APM_WINDOW_MS: u32 = 60_000;
+/// About 30 seconds at fastest speed.
+pub const TIMELINE_BUCKET_FRAMES: u32 = 714;
+/// Two hours with 30-second buckets. Longer games use longer buckets instead.
+pub const MAX_TIMELINE_BUCKETS: usize = 240;
+
+#[derive(Default)]
+pub struct ActionCounts {
+    actions: [u32; 8],
+    effective_actions: [u32; 8],
+    /// Frame and data of the previous counted command of each player.
+    last_command: [Option<(u32, Vec<u8>)>; 8],
+    /// Game time of actions within `LIVE_APM_WINDOW_MS`, oldest first.
+    recent_actions: [VecDeque<u32>; 8],
+    control_group_assigns: [u32; 8],
+    control_group_recalls: [u32; 8],
+    timeline: ActionTimeline,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ActionCategory {
+    Selection = 0,
+    Hotkey,
+    /// Building, training, morphing, researching and upgrading.
+    Production,
+    /// Everything else, mostly orders given to units.
+    Order,
+}
+
+pub const ACTION_CATEGORY_COUNT: usize = 4;
+
+pub fn action_category(command: &[u8]) -> ActionCategory {
+    match CommandId::from_bytes(command) {
+        Some(id) if id.is_selection() => ActionCategory::Selection,
+        Some(CommandId::Hotkey) => ActionCategory::Hotkey,
+        Some(id) if id.is_production() => ActionCategory::Production,
+        _ => ActionCategory::Order,
+    }
+}
+
+/// Action counts of each category, in `ActionCategory` order.
+pub type ActionBucket = [u32; ACTION_CATEGORY_COUNT];
+
+pub struct PlayerActionBuckets {
+    pub player: u8,
+    pub bucket_frames: u32,
+    pub buckets: Vec<ActionBucket>,
+}
+
+pub struct ActionTimeline {
+    bucket_frames: u32,
+    /// All players have the same amount of buckets, up to the latest action of anyone.
+    buckets: Vec<[ActionBucket; 8]>,
+}
+
+impl Default for ActionTimeline {
+    fn default() -> ActionTimeline {
+        ActionTimeline {
+            bucket_frames: TIMELINE_BUCKET_FRAMES,
+            buckets: Vec::new(),
+        }
+    }
+}
+
+impl ActionTimeline {
+    fn add(&mut self, player: usize, frame: u32, category: ActionCategory) {
+        let mut index = (frame / self.bucket_frames) as usize;
+        while index >= MAX_TIMELINE_BUCKETS {
+            // Merge pairs of buckets, doubling their length.
+            self.buckets = self
+                .buckets
+                .chunks(2)
+                .map(|pair| {
+                    let mut merged = pair[0];
+                    if let Some(second) = pair.get(1) {
+                        for (out, bucket) in merged.iter_mut().zip(second.iter()) {
+                            for (out, &count) in out.iter_mut().zip(bucket.iter()) {
+                                *out = out.saturating_add(count);
+                            }
+                        }
+                    }
+                    merged
+                })
+                .collect();
+            self.bucket_frames = self.bucket_frames.saturating_mul(2);
+            index = (frame / self.bucket_frames) as usize;
+        }
+        if self.buckets.len() <= index {
+            self.buckets.resize(index + 1, [[0; ACTION_CATEGORY_COUNT]; 8]);
+        }
+        let count = &mut self.buckets[index][player][category as usize];
+        *count = count.saturating_add(1);
+    }
+
+    /// Timeline of each player who did at least one action.
+    pub fn players(&self) -> Vec<PlayerActionBuckets> {
+        (0..8)
+            .filter(|&player| {
+                self.buckets.iter().any(|x| x[player].iter().any(|&count| count != 0))
+            })
+            .map(|player| PlayerActionBuckets {
+                player: player as u8,
+                bucket_frames: self.bucket_frames,
+                buckets: self.buckets.iter().map(|x| x[player]).collect(),
+            })
+            .collect()
+    }
+}
+
+/// False for commands that aren't something that the player chose to do, such as
+/// keep alives. Unknown commands are counted as actions.
+pub fn is_action(command: &[u8]) -> bool {
+    match command.get(0) {
+        Some(&x) => CommandId::from_u8(x).map(|x| x.is_player_action()).unwrap_or(true),
+        None => false,
+    }
+}
+
+impl ActionCounts {
+    /// `time_ms` is the game time at `frame`.
+    pub fn add(&mut self, player: u8, frame: u32, time_ms: u32, command: &[u8]) {
+        let player = player as usize;
+        if player >= 8 || !is_action(command) {
+            return;
+        }
+        self.actions[player] = self.actions[player].saturating_add(1);
+        self.recent_actions[player].push_back(time_ms);
+        self.timeline.add(player, frame, action_category(command));
+        if let [id::HOTKEY, kind, _group] = *command {
+            // 0 = assign, 1 = recall, 2 = add to group
+            let counter = match kind {
+                0 | 2 => &mut self.control_group_assigns[player],
+                _ => &mut self.control_group_recalls[player],
+            };
+            *counter = counter.saturating_add(1);
+        }
+        let last = &mut self.last_command[player];
+        let repeated = match *last {
+            Some((last_frame, ref data)) => {
+                frame.wrapping_sub(last_frame) < EAPM_REPEAT_FRAMES && &data[..] == command
+            }
+            None => false,
+        };
+        if !repeated {
+            self.effective_actions[player] = self.effective_actions[player].saturating_add(1);
+            *last = Some((frame, command.into()));
+        }
+    }
+
+    /// Actions per minute over a game that lasted `time_ms` of game time.
+    pub fn apm(&self, time_ms: u32) -> [u16; 8] {
+        per_minute(&self.actions, time_ms)
+    }
+
+    pub fn eapm(&self, time_ms: u32) -> [u16; 8] {
+        per_minute(&self.effective_actions, time_ms)
+    }
+
+    pub fn control_group_assigns(&self) -> [u32; 8] {
+        self.control_group_assigns
+    }
+
+    pub fn control_group_recalls(&self) -> [u32; 8] {
+        self.control_group_recalls
+    }
+
+    pub fn timeline(&self) -> &ActionTimeline {
+        &self.timeline
+    }
+
+    /// APM over the last `LIVE_APM_WINDOW_MS` before game time `now_ms`, or since the
+    /// game started if it is shorter than that.
+    pub fn live_apm(&mut self, now_ms: u32) -> [u16; 8] {
+        let window_start = now_ms.saturating_sub(LIVE_APM_WINDOW_MS);
+        let mut counts = [0u32; 8];
+        for (count, recent) in counts.iter_mut().zip(self.recent_actions.iter_mut()) {
+            while recent.front().map(|&x| x < window_start).unwrap_or(false) {
+                recent.pop_front();
+            }
+            *count = recent.len() as u32;
+        }
+        per_minute(&counts, now_ms - window_start)
+    }
+}
+
+fn per_minute(counts: &[u32; 8], time_ms: u32) -> [u16; 8] {
+    let minutes = time_ms as f64 / 60_000.0;
+    let mut result = [0u16; 8];
+    if minutes > 0.0 {
+        for (out, &count) in result.iter_mut().zip(counts.iter()) {
+            *out = (count as f64 / minutes).round().min(u16::MAX as f64) as u16;
+        }
+    }
+    result
+}
+
+#[test]
+fn repeated_commands_collapse() {
+    let mut counts = ActionCounts::default();
+    let move_a = [0x14, 0x10, 0x00, 0x20, 0x00, 0x00, 0x00, 0xe4, 0x00, 0x00];
+    let move_b = [0x14, 0x30, 0x00, 0x20, 0x00, 0x00, 0x00, 0xe4, 0x00, 0x00];
+    // Fastest speed
+    let mut add = |player, frame, command: &[u8]| counts.add(player, frame, frame * 42, command);
+    add(0, 100, &move_a);
+    add(0, 102, &move_a);
+    add(0, 104, &move_a);
+    // Different command is always counted
+    add(0, 105, &move_b);
+    // Repeat after the window has passed
+    add(0, 115, &move_b);
+    // Keep alives and other players' commands don't affect player 0
+    add(0, 116, &[id::KEEP_ALIVE]);
+    add(1, 116, &move_b);
+    assert_eq!(counts.actions[0], 5);
+    assert_eq!(counts.effective_actions[0], 3);
+    assert_eq!(counts.actions[1], 1);
+    assert_eq!(counts.effective_actions[1], 1);
+
+    let apm = counts.apm(60_000);
+    assert_eq!(apm[0], 5);
+    assert_eq!(counts.eapm(60_000)[0], 3);
+    // Same actions in half a minute
+    assert_eq!(counts.apm(30_000)[0], 10);
+    assert_eq!(counts.apm(0), [0; 8]);
+}
+
+#[test]
+fn live_apm_window() {
+    let mut counts = ActionCounts::default();
+    let move_a = [0x14, 0x10, 0x00, 0x20, 0x00, 0x00, 0x00, 0xe4, 0x00, 0x00];
+    for frame in 0..10 {
+        counts.add(2, frame * 100, frame * 4200, &move_a);
+    }
+    // Half a minute in, 10 actions
+    assert_eq!(counts.live_apm(30_000)[2], 20);
+    // Only actions at 25200..=37800 ms are within the window
+    assert_eq!(counts.live_apm(85_200)[2], 4);
+    assert_eq!(counts.live_apm(420_000), [0; 8]);
+}
+
+#[test]
+fn control_group_counts() {
+    let mut counts = ActionCounts::default();
+    counts.add(1, 10, 0, &[id::HOTKEY, 0, 1]);
+    counts.add(1, 11, 0, &[id::HOTKEY, 2, 1]);
+    counts.add(1, 12, 0, &[id::HOTKEY, 1, 1]);
+    counts.add(1, 13, 0, &[id::HOTKEY, 1, 2]);
+    counts.add(1, 14, 0, &[id::HOTKEY, 1, 1]);
+    counts.add(3, 14, 0, &[id::HOTKEY, 1, 1]);
+    assert_eq!(counts.control_group_assigns(), [0, 2, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(counts.control_group_recalls(), [0, 3, 0, 1, 0, 0, 0, 0]);
+}
+
+#[test]
+fn action_timeline_buckets() {
+    let mut counts = ActionCounts::default();
+    let move_a = [0x14, 0x10, 0x00, 0x20, 0x00, 0x00, 0x00, 0xe4, 0x00, 0x00];
+    counts.add(0, 10, 0, &[id::SELECT, 1, 0x10, 0x00]);
+    counts.add(0, 20, 0, &move_a);
+    counts.add(0, 30, 0, &[id::TRAIN, 0x07, 0x00]);
+    counts.add(2, TIMELINE_BUCKET_FRAMES * 2, 0, &[id::HOTKEY, 1, 1]);
+    counts.add(2, TIMELINE_BUCKET_FRAMES * 2 + 1, 0, &[id::KEEP_ALIVE]);
+    let players = counts.timeline().players();
+    assert_eq!(players.len(), 2);
+    assert_eq!(players[0].player, 0);
+    assert_eq!(players[0].buckets, vec![[1, 0, 1, 1], [0; 4], [0; 4]]);
+    assert_eq!(players[1].player, 2);
+    assert_eq!(players[1].buckets, vec![[0; 4], [0; 4], [0, 1, 0, 0]]);
+
+    // Going past the limit merges buckets
+    let last_frame = TIMELINE_BUCKET_FRAMES * MAX_TIMELINE_BUCKETS as u32;
+    counts.add(0, last_frame, 0, &move_a);
+    let players = counts.timeline().players();
+    assert_eq!(players[0].bucket_frames, TIMELINE_BUCKET_FRAMES * 2);
+    assert_eq!(players[0].buckets.len(), MAX_TIMELINE_BUCKETS / 2 + 1);
+    assert_eq!(players[0].buckets[0], [1, 0, 1, 1]);
+    assert_eq!(players[0].buckets[MAX_TIMELINE_BUCKETS / 2], [0, 0, 0, 1]);
+    assert_eq!(players[1].buckets[1], [0, 1, 0, 0]);
+}
@@ -0,0 +1,63 @@
+//! Recording of every command that players send during the game.
+//!
+//! The log is a sequence of entries, each of which is
+//! `frame: u32, player: u8, length: u16` (little endian) followed by `length` bytes
+//! of the command as BW sent it.
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+const ENTRY_HEADER_SIZE: usize = 7;
+
+pub struct CommandLog {
+    data: Vec<u8>,
+    limit: usize,
+    truncated: bool,
+}
+
+impl CommandLog {
+    /// `limit` is the maximum size of the log in bytes. Commands that would go over it
+    /// are dropped, and the log is marked as truncated.
+    pub fn new(limit: usize) -> CommandLog {
+        CommandLog {
+            data: Vec::new(),
+            limit,
+            truncated: false,
+        }
+    }
+
+    pub fn add(&mut self, frame: u32, player: u8, command: &[u8]) {
+        if self.truncated || command.len() > u16::MAX as usize {
+            return;
+        }
+        if self.data.len() + ENTRY_HEADER_SIZE + command.len() > self.limit {
+            self.truncated = true;
+            return;
+        }
+        // Writes to a Vec can't fail
+        self.data.write_u32::<LittleEndian>(frame).unwrap();
+        self.data.write_u8(player).unwrap();
+        self.data.write_u16::<LittleEndian>(command.len() as u16).unwrap();
+        self.data.extend_from_slice(command);
+    }
+
+    /// Returns the log data, and whether any commands were dropped due to the size limit.
+    pub fn finish(self) -> (Vec<u8>, bool) {
+        (self.data, self.truncated)
+    }
+}
+
+#[test]
+fn command_log_truncation() {
+    let mut log = CommandLog::new(20);
+    log.add(1, 0, &[0x14, 0x1, 0x2]);
+    log.add(5, 3, &[0x13, 0x1]);
+    // Doesn't fit; anything after that is dropped even if it would fit
+    log.add(6, 3, &[0x13, 0x1, 0x2, 0x3]);
+    log.add(7, 3, &[]);
+    let (data, truncated) = log.finish();
+    assert!(truncated);
+    assert_eq!(
+        data,
+        vec![1, 0, 0, 0, 0, 3, 0, 0x14, 0x1, 0x2, 5, 0, 0, 0, 3, 2, 0, 0x13, 0x1],
+    );
+}
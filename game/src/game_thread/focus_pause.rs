@@ -0,0 +1,49 @@
+//! Pausing of single player games and replays while the game window isn't focused.
+
+#[derive(Default)]
+pub struct FocusPause {
+    /// Set while the game is paused because of losing focus. The game is only unpaused
+    /// on focus gain if it was paused by us, so that a pause the user chose stays.
+    paused: bool,
+}
+
+impl FocusPause {
+    /// Returns `Some(paused)` if pause state should be changed.
+    /// `allowed` should be false in multiplayer, or if the app didn't ask for auto-pausing.
+    pub fn focus_changed(&mut self, focused: bool, allowed: bool) -> Option<bool> {
+        if !focused && allowed && !self.paused {
+            self.paused = true;
+            Some(true)
+        } else if focused && self.paused {
+            self.paused = false;
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// The game only steps frames while unpaused, so a frame being stepped while we
+    /// think the game is paused means that it was unpaused some other way.
+    pub fn frame_stepped(&mut self) {
+        self.paused = false;
+    }
+}
+
+#[test]
+fn pause_follows_focus() {
+    let mut pause = FocusPause::default();
+    assert_eq!(pause.focus_changed(false, true), Some(true));
+    // Repeated focus loss doesn't pause again
+    assert_eq!(pause.focus_changed(false, true), None);
+    assert_eq!(pause.focus_changed(true, true), Some(false));
+    assert_eq!(pause.focus_changed(true, true), None);
+
+    // Never pauses when not allowed
+    assert_eq!(pause.focus_changed(false, false), None);
+    assert_eq!(pause.focus_changed(true, false), None);
+
+    // User unpaused while unfocused; focus gain doesn't change anything.
+    assert_eq!(pause.focus_changed(false, true), Some(true));
+    pause.frame_stepped();
+    assert_eq!(pause.focus_changed(true, true), None);
+}
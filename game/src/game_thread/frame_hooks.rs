@@ -0,0 +1,69 @@
+//! Per-frame checks in `after_step_game` that only exist for reporting, and can be turned
+//! off at runtime when nobody is looking at what they report.
+//!
+//! Everything is enabled by default, and again after `ResetForNextGame`. `StateHash`
+//! additionally requires `debug_state_hash` to be set in the setup info.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HookId {
+    ArmyValue = 0,
+    LiveApm,
+    IncomeRate,
+    ResourceDepletion,
+    Selection,
+    Camera,
+    StateHash,
+}
+
+pub const ALL_HOOKS: &[HookId] = &[
+    HookId::ArmyValue,
+    HookId::LiveApm,
+    HookId::IncomeRate,
+    HookId::ResourceDepletion,
+    HookId::Selection,
+    HookId::Camera,
+    HookId::StateHash,
+];
+
+/// Bit for each `HookId` that has been disabled.
+static DISABLED: AtomicU32 = AtomicU32::new(0);
+
+pub fn is_enabled(hook: HookId) -> bool {
+    DISABLED.load(Ordering::Relaxed) & (1 << hook as u32) == 0
+}
+
+pub fn set_enabled(hook: HookId, enabled: bool) {
+    let bit = 1 << hook as u32;
+    if enabled {
+        DISABLED.fetch_and(!bit, Ordering::Relaxed);
+    } else {
+        DISABLED.fetch_or(bit, Ordering::Relaxed);
+    }
+}
+
+/// Enables every hook.
+pub fn reset() {
+    DISABLED.store(0, Ordering::Relaxed);
+}
+
+pub fn states() -> Vec<(HookId, bool)> {
+    ALL_HOOKS.iter().map(|&hook| (hook, is_enabled(hook))).collect()
+}
+
+#[test]
+fn toggle_hooks() {
+    assert!(states().iter().all(|&(_, enabled)| enabled));
+    set_enabled(HookId::Camera, false);
+    set_enabled(HookId::StateHash, false);
+    set_enabled(HookId::StateHash, true);
+    assert!(!is_enabled(HookId::Camera));
+    assert!(is_enabled(HookId::StateHash));
+    assert!(is_enabled(HookId::ArmyValue));
+    set_enabled(HookId::Camera, true);
+    assert_eq!(states().len(), ALL_HOOKS.len());
+}
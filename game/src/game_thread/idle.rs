@@ -0,0 +1,76 @@
+//! Detection of players who have stopped issuing commands.
+//!
+//! A player is considered idle once they haven't done any action (as `apm::is_action`
+//! counts them) for the threshold amount of game time, and is reported only once until
+//! they act again. Times are milliseconds of game time, as frame length depends on
+//! game speed.
+
+use super::apm;
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum IdleEvent {
+    Idle { player: u8, idle_ms: u32 },
+    Active { player: u8 },
+}
+
+#[derive(Default)]
+pub struct IdleTracker {
+    /// Game time of each ingame player's last action, `None` if they haven't done
+    /// anything yet, in which case they are counted from start of the game.
+    last_action: [Option<u32>; 8],
+    reported: [bool; 8],
+}
+
+impl IdleTracker {
+    /// Returns `IdleEvent::Active` if the player had been reported as idle.
+    pub fn command(&mut self, player: u8, time_ms: u32, command: &[u8]) -> Option<IdleEvent> {
+        let index = player as usize;
+        if index >= 8 || !apm::is_action(command) {
+            return None;
+        }
+        self.last_action[index] = Some(time_ms);
+        if std::mem::replace(&mut self.reported[index], false) {
+            Some(IdleEvent::Active { player })
+        } else {
+            None
+        }
+    }
+
+    /// `players` has bits of players that should be checked, e.g. humans who are
+    /// still in the game.
+    pub fn check(&mut self, now_ms: u32, threshold_ms: u32, players: u8) -> Vec<IdleEvent> {
+        let mut result = Vec::new();
+        for player in 0..8u8 {
+            let index = player as usize;
+            if players & (1 << player) == 0 || self.reported[index] {
+                continue;
+            }
+            let idle_ms = now_ms.saturating_sub(self.last_action[index].unwrap_or(0));
+            if idle_ms >= threshold_ms {
+                self.reported[index] = true;
+                result.push(IdleEvent::Idle { player, idle_ms });
+            }
+        }
+        result
+    }
+}
+
+#[test]
+fn idle_detection() {
+    let move_cmd = [0x14, 0x10, 0x00, 0x20, 0x00, 0x00, 0x00, 0xe4, 0x00, 0x00];
+    let mut tracker = IdleTracker::default();
+    assert_eq!(tracker.command(0, 100, &move_cmd), None);
+    assert_eq!(tracker.check(500, 1000, 0x3), vec![]);
+    // Player 1 hasn't done anything since start of the game
+    let events = tracker.check(1000, 1000, 0x3);
+    assert_eq!(events, vec![IdleEvent::Idle { player: 1, idle_ms: 1000 }]);
+    // Keep alives don't count, already reported players aren't reported again
+    assert_eq!(tracker.command(0, 1050, &[0x05]), None);
+    let events = tracker.check(1100, 1000, 0x3);
+    assert_eq!(events, vec![IdleEvent::Idle { player: 0, idle_ms: 1000 }]);
+    assert_eq!(tracker.check(1200, 1000, 0x3), vec![]);
+    assert_eq!(tracker.command(1, 1300, &move_cmd), Some(IdleEvent::Active { player: 1 }));
+    assert_eq!(tracker.command(1, 1301, &move_cmd), None);
+    // Players not in `players` are skipped
+    assert_eq!(tracker.check(5000, 1000, 0x1), vec![]);
+}
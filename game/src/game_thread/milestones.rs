@@ -0,0 +1,134 @@
+//! Detection of tech milestones (Expanding, reaching a new tech tier, key upgrades) for casting.
+//!
+//! The milestones are checked from `Game` unit counts and tech/upgrade levels once per
+//! game second. `default_rules` contains a reasonable set for melee games; the app can add
+//! more with `GameSetupInfo::extra_milestones`.
+
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::bw;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Milestone {
+    FirstExpansion,
+    Tier2,
+    Tier3,
+    Tech(u8),
+    Upgrade(u8),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Condition {
+    /// Player has at least `count` completed units that are any of `unit_ids`.
+    #[serde(rename_all = "camelCase")]
+    CompletedUnits { unit_ids: Vec<u16>, count: u32 },
+    Tech(u8),
+    Upgrade { upgrade: u8, level: u8 },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Rule {
+    pub milestone: Milestone,
+    pub condition: Condition,
+}
+
+const TOWN_HALLS: &[u16] = &[0x6a, 0x83, 0x84, 0x85, 0x9a];
+const TIER2: &[u16] = &[0x71, 0x84, 0x85, 0x9b, 0xa7];
+const TIER3: &[u16] = &[0x74, 0x85, 0xa5, 0xa9, 0xaa];
+
+pub fn default_rules() -> Vec<Rule> {
+    let units = |milestone, unit_ids: &[u16], count| Rule {
+        milestone,
+        condition: Condition::CompletedUnits { unit_ids: unit_ids.into(), count },
+    };
+    let tech = |tech| Rule {
+        milestone: Milestone::Tech(tech),
+        condition: Condition::Tech(tech),
+    };
+    let upgrade = |upgrade| Rule {
+        milestone: Milestone::Upgrade(upgrade),
+        condition: Condition::Upgrade { upgrade, level: 1 },
+    };
+    vec![
+        units(Milestone::FirstExpansion, TOWN_HALLS, 2),
+        units(Milestone::Tier2, TIER2, 1),
+        units(Milestone::Tier3, TIER3, 1),
+        // Stim packs, Psionic storm, Lurker aspect
+        tech(0x0),
+        tech(0x13),
+        tech(0x20),
+        // Metabolic boost, Singularity charge, Leg enhancements
+        upgrade(0x1b),
+        upgrade(0x21),
+        upgrade(0x22),
+    ]
+}
+
+lazy_static! {
+    static ref RULES: Mutex<Vec<Rule>> = Mutex::new(default_rules());
+}
+
+/// Sets rules to be checked in addition to the default ones, replacing any extra rules
+/// of a previous game.
+pub fn set_extra_rules(extra: &[Rule]) {
+    *RULES.lock().unwrap() = default_rules().into_iter().chain(extra.iter().cloned()).collect();
+}
+
+unsafe fn is_reached(game: *mut bw::Game, player: usize, condition: &Condition) -> bool {
+    match *condition {
+        Condition::CompletedUnits { ref unit_ids, count } => {
+            // (Game is packed, so this can't take references to the u32 counts)
+            let total: u32 = unit_ids
+                .iter()
+                .filter(|&&id| id < 0xe4)
+                .map(|&id| (*game).completed_units_count[id as usize][player])
+                .sum();
+            total >= count
+        }
+        Condition::Tech(tech) => {
+            let tech = tech as usize;
+            let level = if tech < 0x18 {
+                (*game).tech_level_sc[player][tech]
+            } else {
+                (*game).tech_level_bw[player].get(tech - 0x18).cloned().unwrap_or(0)
+            };
+            level != 0
+        }
+        Condition::Upgrade { upgrade, level } => {
+            let upgrade = upgrade as usize;
+            let current = if upgrade < 0x2e {
+                (*game).upgrade_level_sc[player][upgrade]
+            } else {
+                (*game).upgrade_level_bw[player].get(upgrade - 0x2e).cloned().unwrap_or(0)
+            };
+            current >= level
+        }
+    }
+}
+
+/// Returns (player, milestone) pairs that were reached since last call.
+///
+/// `reached` keeps track of already reported milestones, one bit per player for each rule,
+/// and should be cleared when a new game starts.
+pub unsafe fn check(game: *mut bw::Game, reached: &mut Vec<u8>) -> Vec<(u8, Milestone)> {
+    let rules = RULES.lock().unwrap();
+    reached.resize(rules.len(), 0);
+    let mut result = Vec::new();
+    for (rule, reached) in rules.iter().zip(reached.iter_mut()) {
+        if *reached == 0xff {
+            continue;
+        }
+        for player in 0..8 {
+            if *reached & (1 << player) == 0 && is_reached(game, player, &rule.condition) {
+                *reached |= 1 << player;
+                result.push((player as u8, rule.milestone));
+            }
+        }
+    }
+    result
+}
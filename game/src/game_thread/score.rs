@@ -0,0 +1,116 @@
+//! A simple per-player score for ranking players on a post-game summary.
+//!
+//! The score of a player is
+//!
+//! ```text
+//! apm * weights.apm / 100
+//!     + (minerals + gas gathered) * weights.resources / 1000
+//!     + units killed / max(units lost, 1) * weights.kill_ratio
+//!     + weights.victory if the player won
+//! ```
+//!
+//! It isn't meant to be anything more than a rough "who did the most" measure; with the
+//! default weights an average player with a won game ends up around 8 or 9.
+
+#[derive(Copy, Clone, Debug)]
+pub struct ScoreWeights {
+    pub apm: f32,
+    pub resources: f32,
+    pub kill_ratio: f32,
+    pub victory: f32,
+}
+
+impl Default for ScoreWeights {
+    fn default() -> ScoreWeights {
+        ScoreWeights {
+            apm: 1.0,
+            resources: 0.25,
+            kill_ratio: 2.0,
+            victory: 3.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PlayerStats {
+    pub player: u8,
+    pub apm: u16,
+    pub resources_gathered: u32,
+    pub units_killed: u32,
+    pub units_lost: u32,
+    pub won: bool,
+}
+
+pub fn score(stats: &PlayerStats, weights: &ScoreWeights) -> f32 {
+    let kill_ratio = stats.units_killed as f32 / stats.units_lost.max(1) as f32;
+    let victory = if stats.won { weights.victory } else { 0.0 };
+    stats.apm as f32 * weights.apm / 100.0 +
+        stats.resources_gathered as f32 * weights.resources / 1000.0 +
+        kill_ratio * weights.kill_ratio +
+        victory
+}
+
+/// Returns (player, score) pairs, highest score first. Equal scores keep the order
+/// of `players`.
+pub fn rank(players: &[PlayerStats], weights: &ScoreWeights) -> Vec<(u8, f32)> {
+    let mut result = players
+        .iter()
+        .map(|stats| (stats.player, score(stats, weights)))
+        .collect::<Vec<_>>();
+    result.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    result
+}
+
+#[test]
+fn ranking() {
+    let weights = ScoreWeights::default();
+    let players = [
+        PlayerStats {
+            player: 0,
+            apm: 150,
+            resources_gathered: 12000,
+            units_killed: 30,
+            units_lost: 40,
+            won: false,
+        },
+        PlayerStats {
+            player: 3,
+            apm: 100,
+            resources_gathered: 10000,
+            units_killed: 40,
+            units_lost: 30,
+            won: true,
+        },
+        PlayerStats {
+            player: 4,
+            ..Default::default()
+        },
+        PlayerStats {
+            player: 5,
+            ..Default::default()
+        },
+    ];
+    let result = rank(&players, &weights);
+    let order = result.iter().map(|x| x.0).collect::<Vec<_>>();
+    assert_eq!(order, vec![3, 0, 4, 5]);
+    // 1.0 + 2.5 + 2.0 * 40 / 30 + 3.0
+    assert!((result[0].1 - 9.1667).abs() < 0.001);
+    assert_eq!(result[2].1, 0.0);
+    // No losses counts as one loss
+    let stats = PlayerStats {
+        units_killed: 5,
+        ..Default::default()
+    };
+    assert_eq!(score(&stats, &weights), 10.0);
+
+    // Only victory matters
+    let weights = ScoreWeights {
+        apm: 0.0,
+        resources: 0.0,
+        kill_ratio: 0.0,
+        victory: 1.0,
+    };
+    let result = rank(&players, &weights);
+    assert_eq!(result[0], (3, 1.0));
+    assert_eq!(result[1], (0, 0.0));
+}
@@ -0,0 +1,91 @@
+//! Text dumps of the active unit list, for inspecting game state with external tools.
+//!
+//! Each unit is written with its unit id, player, position and hitpoints. Hitpoints are
+//! in 1/256ths, like in `UnitSnapshot`. Units are in the order of BW's active unit list.
+
+use serde::{Deserialize, Serialize};
+
+use crate::bw::UnitSnapshot;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DumpFormat {
+    /// A header line followed by one `unit_id,player,x,y,hitpoints` line per unit.
+    Csv,
+    /// An array of objects with the same fields as the CSV columns.
+    Json,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DumpedUnit {
+    unit_id: u16,
+    player: u8,
+    x: i16,
+    y: i16,
+    hitpoints: i32,
+}
+
+pub fn dump_units(units: &[UnitSnapshot], format: DumpFormat) -> String {
+    let units = units.iter().map(|x| DumpedUnit {
+        unit_id: x.unit_id,
+        player: x.player,
+        x: x.position.x,
+        y: x.position.y,
+        hitpoints: x.hitpoints,
+    });
+    match format {
+        DumpFormat::Csv => {
+            let mut out = String::from("unit_id,player,x,y,hitpoints\n");
+            for unit in units {
+                out.push_str(&format!(
+                    "{},{},{},{},{}\n",
+                    unit.unit_id, unit.player, unit.x, unit.y, unit.hitpoints,
+                ));
+            }
+            out
+        }
+        DumpFormat::Json => {
+            let units = units.collect::<Vec<_>>();
+            // Serializing plain structs to a string can't fail.
+            serde_json::to_string(&units).unwrap_or_default()
+        }
+    }
+}
+
+#[test]
+fn dump_formats() {
+    use crate::bw::Point;
+    let units = [
+        UnitSnapshot {
+            unit_id: 0x29,
+            player: 1,
+            position: Point { x: 100, y: 200 },
+            hitpoints: 40 * 256,
+            shields: 40 * 256,
+            energy: 0,
+            order: 0x3,
+            target: None,
+        },
+        UnitSnapshot {
+            unit_id: 0xb0,
+            player: 11,
+            position: Point { x: -1, y: 5 },
+            hitpoints: 100000,
+            shields: 0,
+            energy: 0,
+            order: 0x3c,
+            target: None,
+        },
+    ];
+    assert_eq!(
+        dump_units(&units, DumpFormat::Csv),
+        "unit_id,player,x,y,hitpoints\n41,1,100,200,10240\n176,11,-1,5,100000\n",
+    );
+    assert_eq!(
+        dump_units(&units, DumpFormat::Json),
+        "[{\"unitId\":41,\"player\":1,\"x\":100,\"y\":200,\"hitpoints\":10240},\
+        {\"unitId\":176,\"player\":11,\"x\":-1,\"y\":5,\"hitpoints\":100000}]",
+    );
+    assert_eq!(dump_units(&[], DumpFormat::Json), "[]");
+}
@@ -0,0 +1,125 @@
+//! Detection of the game thread hanging during a game.
+//!
+//! The game thread records when it last stepped a frame and when it last rendered.
+//! BW keeps rendering while the game is paused or waiting for lagging players, so
+//! the game is only considered stalled if neither has happened in `STALL_THRESHOLD`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+
+use super::{game_loop_running, send_game_msg_to_async, GameThreadMessage};
+
+const STALL_THRESHOLD: Duration = Duration::from_secs(5);
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+lazy_static! {
+    static ref TIMESTAMP_BASE: Instant = Instant::now();
+}
+/// Milliseconds since `TIMESTAMP_BASE`.
+static LAST_STEP: AtomicU64 = AtomicU64::new(0);
+static LAST_RENDER: AtomicU64 = AtomicU64::new(0);
+/// Time between the two latest steps, in milliseconds.
+static LAST_STEP_INTERVAL: AtomicU64 = AtomicU64::new(0);
+
+/// Frames at fastest speed take 42ms, anything this much longer means that BW waited
+/// for turns from a lagging player (or was paused).
+const LAG_THRESHOLD: Duration = Duration::from_millis(250);
+
+fn now_ms() -> u64 {
+    TIMESTAMP_BASE.elapsed().as_millis() as u64
+}
+
+pub fn frame_stepped() {
+    let now = now_ms();
+    let previous = LAST_STEP.swap(now, Ordering::Relaxed);
+    LAST_STEP_INTERVAL.store(now.saturating_sub(previous), Ordering::Relaxed);
+}
+
+/// True if the latest frame took over `LAG_THRESHOLD` to step.
+pub fn latest_step_lagged() -> bool {
+    LAST_STEP_INTERVAL.load(Ordering::Relaxed) > LAG_THRESHOLD.as_millis() as u64
+}
+
+pub fn frame_rendered() {
+    LAST_RENDER.store(now_ms(), Ordering::Relaxed);
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum StallEvent {
+    Stalled { since_ms: u64 },
+    Resumed { stalled_ms: u64 },
+}
+
+#[derive(Default)]
+struct StallDetector {
+    /// Time of the last activity before the currently reported stall.
+    stall_start: Option<u64>,
+}
+
+impl StallDetector {
+    fn check(&mut self, now: u64, last_step: u64, last_render: u64) -> Option<StallEvent> {
+        let last_activity = last_step.max(last_render);
+        match self.stall_start {
+            None => {
+                let since_ms = now.saturating_sub(last_activity);
+                if since_ms >= STALL_THRESHOLD.as_millis() as u64 {
+                    self.stall_start = Some(last_activity);
+                    Some(StallEvent::Stalled { since_ms })
+                } else {
+                    None
+                }
+            }
+            Some(start) => {
+                if last_activity > start {
+                    self.stall_start = None;
+                    Some(StallEvent::Resumed { stalled_ms: last_activity - start })
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Runs forever, checking for stalls while the game loop is running.
+pub async fn run() {
+    let mut detector = StallDetector::default();
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        if !game_loop_running() {
+            detector = StallDetector::default();
+            continue;
+        }
+        let last_step = LAST_STEP.load(Ordering::Relaxed);
+        let last_render = LAST_RENDER.load(Ordering::Relaxed);
+        match detector.check(now_ms(), last_step, last_render) {
+            Some(StallEvent::Stalled { since_ms }) => {
+                warn!("Game thread hasn't stepped or rendered in {}ms", since_ms);
+                send_game_msg_to_async(GameThreadMessage::GameLoopStall { since_ms });
+            }
+            Some(StallEvent::Resumed { stalled_ms }) => {
+                info!("Game thread resumed after {}ms", stalled_ms);
+                send_game_msg_to_async(GameThreadMessage::GameLoopResumed { stalled_ms });
+            }
+            None => (),
+        }
+    }
+}
+
+#[test]
+fn stall_detection() {
+    let mut detector = StallDetector::default();
+    assert_eq!(detector.check(1000, 900, 950), None);
+    // Paused; not stepping but still rendering
+    assert_eq!(detector.check(9000, 900, 8900), None);
+    let result = detector.check(15000, 900, 9000);
+    assert_eq!(result, Some(StallEvent::Stalled { since_ms: 6000 }));
+    // Reported only once
+    assert_eq!(detector.check(16000, 900, 9000), None);
+    let result = detector.check(17000, 16800, 16900);
+    assert_eq!(result, Some(StallEvent::Resumed { stalled_ms: 7900 }));
+    assert_eq!(detector.check(17500, 17400, 17400), None);
+}
@@ -0,0 +1,292 @@
+//! Decompression of PKWare DCL implode, which 1.16.1 replays and old map archives use.
+//!
+//! This follows `blast.c` from zlib's contrib directory. The data is a two byte header
+//! (whether literals are Huffman coded, and the dictionary size), followed by a bit
+//! stream of literals and (length, distance) pairs, ending with length 519. The Huffman
+//! codes are fixed, and stored here in the same compact form as in `blast.c`.
+
+use once_cell::sync::Lazy;
+use quick_error::quick_error;
+
+const MAX_BITS: usize = 13;
+/// Length that marks the end of the data.
+const END_LENGTH: u32 = 519;
+
+quick_error! {
+    #[derive(Debug, Clone, Eq, PartialEq)]
+    pub enum ExplodeError {
+        Truncated {
+            display("Compressed data ends unexpectedly")
+        }
+        InvalidHeader {
+            display("Invalid header")
+        }
+        InvalidCode {
+            display("Invalid Huffman code")
+        }
+        InvalidDistance {
+            display("Distance goes past start of the data")
+        }
+        TooLarge {
+            display("Decompressed data is larger than expected")
+        }
+    }
+}
+
+/// Lengths of codes for each symbol, as (count - 1) << 4 | length bytes.
+const LITERAL_LENGTHS: &[u8] = &[
+    11, 124, 8, 7, 28, 7, 188, 13, 76, 4, 10, 8, 12, 10, 12, 10, 8, 23, 8, 9, 7, 6, 7, 8, 7,
+    6, 55, 8, 23, 24, 12, 11, 7, 9, 11, 12, 6, 7, 22, 5, 7, 24, 6, 11, 9, 6, 7, 22, 7, 11, 38,
+    7, 9, 8, 25, 11, 8, 11, 9, 12, 8, 12, 5, 38, 5, 38, 5, 11, 7, 5, 6, 21, 6, 10, 53, 8, 7,
+    24, 10, 27, 44, 253, 253, 253, 252, 252, 252, 13, 12, 45, 12, 45, 12, 61, 12, 45, 44,
+    173,
+];
+const LENGTH_LENGTHS: &[u8] = &[2, 35, 36, 53, 38, 23];
+const DISTANCE_LENGTHS: &[u8] = &[2, 20, 53, 230, 247, 151, 248];
+/// Base value and count of extra bits for each length symbol.
+const LENGTH_BASE: [u16; 16] = [3, 2, 4, 5, 6, 7, 8, 9, 10, 12, 16, 24, 40, 72, 136, 264];
+const LENGTH_EXTRA: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8];
+
+/// Canonical Huffman code; `count` has amount of codes of each bit length, and
+/// `symbols` the symbols ordered by their code.
+struct Huffman {
+    count: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(compact_lengths: &[u8]) -> Huffman {
+        let lengths = compact_lengths
+            .iter()
+            .flat_map(|&x| std::iter::repeat(x & 0xf).take((x >> 4) as usize + 1))
+            .collect::<Vec<u8>>();
+        let mut count = [0u16; MAX_BITS + 1];
+        for &length in &lengths {
+            count[length as usize] += 1;
+        }
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for length in 1..MAX_BITS {
+            offsets[length + 1] = offsets[length] + count[length];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman {
+            count,
+            symbols,
+        }
+    }
+}
+
+static LITERAL_CODE: Lazy<Huffman> = Lazy::new(|| Huffman::new(LITERAL_LENGTHS));
+static LENGTH_CODE: Lazy<Huffman> = Lazy::new(|| Huffman::new(LENGTH_LENGTHS));
+static DISTANCE_CODE: Lazy<Huffman> = Lazy::new(|| Huffman::new(DISTANCE_LENGTHS));
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    buffer: u32,
+    buffered_bits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    /// Reads `count` (at most 16) bits, least significant bit first.
+    fn bits(&mut self, count: u32) -> Result<u32, ExplodeError> {
+        while self.buffered_bits < count {
+            let (&byte, rest) = self.data.split_first().ok_or(ExplodeError::Truncated)?;
+            self.data = rest;
+            self.buffer |= (byte as u32) << self.buffered_bits;
+            self.buffered_bits += 8;
+        }
+        let value = self.buffer & ((1 << count) - 1);
+        self.buffer >>= count;
+        self.buffered_bits -= count;
+        Ok(value)
+    }
+
+    /// The codes are stored with their bits inverted, most significant bit first.
+    fn decode(&mut self, huffman: &Huffman) -> Result<u32, ExplodeError> {
+        let mut code = 0u32;
+        let mut first = 0u32;
+        let mut index = 0u32;
+        for &count in &huffman.count[1..] {
+            code |= self.bits(1)? ^ 1;
+            let count = count as u32;
+            if code < first + count {
+                return Ok(huffman.symbols[(index + code - first) as usize] as u32);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(ExplodeError::InvalidCode)
+    }
+}
+
+/// Decompresses `data`, which is expected to decompress to at most `max_size` bytes.
+pub fn explode(data: &[u8], max_size: usize) -> Result<Vec<u8>, ExplodeError> {
+    let mut input = BitReader {
+        data,
+        buffer: 0,
+        buffered_bits: 0,
+    };
+    let coded_literals = match input.bits(8)? {
+        0 => false,
+        1 => true,
+        _ => return Err(ExplodeError::InvalidHeader),
+    };
+    let dictionary_bits = input.bits(8)?;
+    if dictionary_bits < 4 || dictionary_bits > 6 {
+        return Err(ExplodeError::InvalidHeader);
+    }
+    let mut out = Vec::with_capacity(max_size);
+    loop {
+        if input.bits(1)? != 0 {
+            let symbol = input.decode(&LENGTH_CODE)? as usize;
+            let length =
+                LENGTH_BASE[symbol] as u32 + input.bits(LENGTH_EXTRA[symbol] as u32)?;
+            if length == END_LENGTH {
+                break;
+            }
+            let low_bits = if length == 2 { 2 } else { dictionary_bits };
+            let distance = (input.decode(&DISTANCE_CODE)? << low_bits) +
+                input.bits(low_bits)? + 1;
+            let distance = distance as usize;
+            if distance > out.len() {
+                return Err(ExplodeError::InvalidDistance);
+            }
+            if out.len() + length as usize > max_size {
+                return Err(ExplodeError::TooLarge);
+            }
+            // The copy can overlap with the bytes it writes, so it's done byte by byte.
+            for _ in 0..length {
+                out.push(out[out.len() - distance]);
+            }
+        } else {
+            let literal = match coded_literals {
+                true => input.decode(&LITERAL_CODE)?,
+                false => input.bits(8)?,
+            };
+            if out.len() == max_size {
+                return Err(ExplodeError::TooLarge);
+            }
+            out.push(literal as u8);
+        }
+    }
+    Ok(out)
+}
+
+/// Compresses data with only uncoded literals and runs of the previous byte.
+/// Enough to create test data that has to be decompressed.
+#[cfg(test)]
+pub fn implode_runs(data: &[u8]) -> Vec<u8> {
+    struct BitWriter {
+        out: Vec<u8>,
+        buffer: u32,
+        buffered_bits: u32,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, value: u32, count: u32) {
+            self.buffer |= value << self.buffered_bits;
+            self.buffered_bits += count;
+            while self.buffered_bits >= 8 {
+                self.out.push(self.buffer as u8);
+                self.buffer >>= 8;
+                self.buffered_bits -= 8;
+            }
+        }
+
+        fn code(&mut self, huffman: &Huffman, symbol: u32) {
+            let mut first = 0u32;
+            let mut index = 0usize;
+            for length in 1..=MAX_BITS {
+                let count = huffman.count[length] as usize;
+                let position = huffman.symbols[index..][..count]
+                    .iter()
+                    .position(|&x| x as u32 == symbol);
+                if let Some(position) = position {
+                    let code = first + position as u32;
+                    for bit in (0..length).rev() {
+                        self.bits(((code >> bit) & 1) ^ 1, 1);
+                    }
+                    return;
+                }
+                index += count;
+                first = (first + count as u32) << 1;
+            }
+            panic!("No code for symbol {}", symbol);
+        }
+
+        fn length(&mut self, length: u32) {
+            let symbol = (0..16)
+                .find(|&i| {
+                    let base = LENGTH_BASE[i] as u32;
+                    length >= base && length < base + (1 << LENGTH_EXTRA[i])
+                })
+                .unwrap();
+            self.bits(1, 1);
+            self.code(&LENGTH_CODE, symbol as u32);
+            self.bits(length - LENGTH_BASE[symbol] as u32, LENGTH_EXTRA[symbol] as u32);
+        }
+    }
+
+    let mut out = BitWriter {
+        out: Vec::new(),
+        buffer: 0,
+        buffered_bits: 0,
+    };
+    // Uncoded literals, smallest dictionary
+    out.bits(0, 8);
+    out.bits(4, 8);
+    let mut pos = 0;
+    while pos < data.len() {
+        let run = match pos.checked_sub(1).map(|x| data[x]) {
+            Some(previous) => data[pos..]
+                .iter()
+                .take(END_LENGTH as usize - 1)
+                .take_while(|&&x| x == previous)
+                .count(),
+            None => 0,
+        };
+        if run >= 3 {
+            out.length(run as u32);
+            // Distance 1
+            out.code(&DISTANCE_CODE, 0);
+            out.bits(0, 4);
+            pos += run;
+        } else {
+            out.bits(0, 1);
+            out.bits(data[pos] as u32, 8);
+            pos += 1;
+        }
+    }
+    out.length(END_LENGTH);
+    if out.buffered_bits != 0 {
+        out.out.push(out.buffer as u8);
+    }
+    out.out
+}
+
+#[test]
+fn explode_blast_example() {
+    // The example from blast.c
+    let data = [0x00, 0x04, 0x82, 0x24, 0x25, 0x8f, 0x80, 0x7f];
+    assert_eq!(explode(&data, 13).unwrap(), b"AIAIAIAIAIAIA");
+    assert_eq!(explode(&data, 12), Err(ExplodeError::TooLarge));
+    assert_eq!(explode(&data[..5], 13), Err(ExplodeError::Truncated));
+    assert_eq!(explode(&[0x02, 0x04, 0x82], 13), Err(ExplodeError::InvalidHeader));
+}
+
+#[test]
+fn explode_runs() {
+    let mut data = vec![0u8; 0x279];
+    data.extend((0..0x300u32).map(|x| (x * 7) as u8));
+    data.extend_from_slice(&[0x55; 0x400]);
+    let compressed = implode_runs(&data);
+    assert!(compressed.len() < data.len());
+    assert_eq!(explode(&compressed, data.len()).unwrap(), data);
+}
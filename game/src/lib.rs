@@ -16,8 +16,11 @@ mod crash_dump;
 mod forge;
 mod game_state;
 mod game_thread;
+mod implode;
+mod map;
 mod network_manager;
 mod rally_point;
+mod replay;
 mod snp;
 mod udp;
 mod windows;
@@ -351,21 +354,302 @@ async fn handle_messages_from_game_thread(
     mut ws_send: app_socket::SendMessages,
     mut game_send: game_state::SendMessages,
 ) {
-    use crate::app_messages::WindowMove;
+    use crate::app_messages::{
+        ActionBucket, ActionTimeline, ActualSetup, ArmyValue, BwError, CameraMove, Capabilities,
+        ClipMarker, CommandLog, ConnectionQuality, CurrentFrame, CurrentGameState, FirstBuilding,
+        FirstContact, GameLog, GameLoopResumed, GameLoopStall, GamePaused, GameStartMetadata,
+        GameplayStarted, HookState, HookStates, IncomeRate, InitFailed, LiveApm, NetAbuse,
+        PathingStuck, PlayerActionBuckets, PlayerActive, PlayerAlert, PlayerColors, PlayerIdle,
+        PlayerResync, Presented, ProxyDetected, ReplayIncompatible, ReplaySaved, RequestResult,
+        ResourceDepleted, ResourceLayout, Screenshot, SelectedUnit, SelectionDetails,
+        SpectatorChanged, SpeedChanged, StateHash, TechMilestone, TriggerFired, UnitDump, UnitMorph,
+        UnitSnapshot, UnitSnapshots, Warning, WindowHandle, WindowMove,
+    };
     use futures::prelude::*;
 
     let (send, mut recv) = tokio::sync::mpsc::unbounded_channel();
     *crate::game_thread::SEND_FROM_GAME_THREAD.lock().unwrap() = Some(send);
     while let Some(message) = recv.next().await {
+        crate::game_thread::game_msg_received();
         let result = match message {
             GameThreadMessage::WindowMove(x, y) => {
-                let msg = app_socket::encode_message("/game/windowMove", WindowMove { x, y });
-                if let Some(msg) = msg {
-                    ws_send.send(msg).await.map_err(|_| ())
-                } else {
+                send_to_app(&mut ws_send, "/game/windowMove", WindowMove { x, y }).await
+            }
+            GameThreadMessage::FirstContact { scouter, scouted, frame } => {
+                let msg = FirstContact { scouter: scouter.0, scouted: scouted.0, frame };
+                send_to_app(&mut ws_send, "/game/firstContact", msg).await
+            }
+            GameThreadMessage::UnitMorph { player, from_id, to_id, frame } => {
+                let msg = UnitMorph { player: player.0, from_id, to_id, frame };
+                send_to_app(&mut ws_send, "/game/unitMorph", msg).await
+            }
+            GameThreadMessage::FirstBuilding { player, unit_id, x, y, frame } => {
+                let msg = FirstBuilding { player: player.0, unit_id, x, y, frame };
+                send_to_app(&mut ws_send, "/game/firstBuilding", msg).await
+            }
+            GameThreadMessage::SpeedChanged { frame, speed } => {
+                send_to_app(&mut ws_send, "/game/speedChanged", SpeedChanged { frame, speed }).await
+            }
+            GameThreadMessage::ProxyDetected { by, target, x, y, frame } => {
+                let msg = ProxyDetected { by: by.0, target: target.0, x, y, frame };
+                send_to_app(&mut ws_send, "/game/proxyDetected", msg).await
+            }
+            GameThreadMessage::TechMilestone { player, milestone, frame } => {
+                let msg = TechMilestone { player: player.0, milestone, frame };
+                send_to_app(&mut ws_send, "/game/techMilestone", msg).await
+            }
+            GameThreadMessage::NetAbuse { storm_id, reason } => {
+                let reason = match reason {
+                    snp::NetAbuseReason::Oversized(size) => format!("oversized ({} bytes)", size),
+                    snp::NetAbuseReason::RateLimited => "rateLimited".into(),
+                };
+                let msg = NetAbuse { storm_id: storm_id.0, reason };
+                send_to_app(&mut ws_send, "/game/netAbuse", msg).await
+            }
+            GameThreadMessage::PathingStuck { unit_id, x, y, frame } => {
+                let msg = PathingStuck { unit_id, x, y, frame };
+                send_to_app(&mut ws_send, "/game/pathingStuck", msg).await
+            }
+            GameThreadMessage::ReplayIncompatible { replay_version, game_version } => {
+                let msg = ReplayIncompatible { replay_version, game_version };
+                send_to_app(&mut ws_send, "/game/replayIncompatible", msg).await
+            }
+            GameThreadMessage::GameLoopStall { since_ms } => {
+                send_to_app(&mut ws_send, "/game/gameLoopStall", GameLoopStall { since_ms }).await
+            }
+            GameThreadMessage::GameLoopResumed { stalled_ms } => {
+                let msg = GameLoopResumed { stalled_ms };
+                send_to_app(&mut ws_send, "/game/gameLoopResumed", msg).await
+            }
+            GameThreadMessage::Log { level, target, message, frame } => {
+                let level = level.to_string().to_lowercase();
+                let msg = GameLog { level, target, message, frame };
+                send_to_app(&mut ws_send, "/game/log", msg).await
+            }
+            GameThreadMessage::HookStates { request_id, hooks } => {
+                let hooks = hooks
+                    .into_iter()
+                    .map(|(hook, enabled)| HookState { hook, enabled })
+                    .collect();
+                send_to_app(&mut ws_send, "/game/hookStates", HookStates { request_id, hooks })
+                    .await
+            }
+            GameThreadMessage::BwError { text } => {
+                send_to_app(&mut ws_send, "/game/bwError", BwError { text }).await
+            }
+            GameThreadMessage::WindowHandle { hwnd } => {
+                send_to_app(&mut ws_send, "/game/windowHandle", WindowHandle { hwnd }).await
+            }
+            GameThreadMessage::Presented { frame } => {
+                send_to_app(&mut ws_send, "/game/presented", Presented { frame }).await
+            }
+            GameThreadMessage::InitFailed { reason } => {
+                send_to_app(&mut ws_send, "/game/initFailed", InitFailed { reason }).await
+            }
+            GameThreadMessage::Warning(message) => {
+                send_to_app(&mut ws_send, "/game/warning", Warning { message }).await
+            }
+            GameThreadMessage::ConnectionQuality { storm_id, late_turns, dropped_turns } => {
+                let msg = ConnectionQuality { storm_id: storm_id.0, late_turns, dropped_turns };
+                send_to_app(&mut ws_send, "/game/connectionQuality", msg).await
+            }
+            GameThreadMessage::ClipMarker { frame } => {
+                send_to_app(&mut ws_send, "/game/clipMarker", ClipMarker { frame }).await
+            }
+            GameThreadMessage::PlayerAlert { kind, x, y, frame } => {
+                let msg = PlayerAlert { kind, x, y, frame };
+                send_to_app(&mut ws_send, "/game/playerAlert", msg).await
+            }
+            GameThreadMessage::StateHash { frame, hash } => {
+                let msg = StateHash { frame, hash: format!("{:016x}", hash) };
+                send_to_app(&mut ws_send, "/game/stateHash", msg).await
+            }
+            GameThreadMessage::ArmyValue { frame, values } => {
+                send_to_app(&mut ws_send, "/game/armyValue", ArmyValue { frame, values }).await
+            }
+            GameThreadMessage::PlayerIdle { storm_id, idle_ms } => {
+                let msg = PlayerIdle { storm_id: storm_id.0, idle_ms };
+                send_to_app(&mut ws_send, "/game/playerIdle", msg).await
+            }
+            GameThreadMessage::GamePaused { by_storm_id, paused, frame } => {
+                let msg = GamePaused { by_storm_id: by_storm_id.0, paused, frame };
+                send_to_app(&mut ws_send, "/game/gamePaused", msg).await
+            }
+            GameThreadMessage::PlayerActive { storm_id } => {
+                let msg = PlayerActive { storm_id: storm_id.0 };
+                send_to_app(&mut ws_send, "/game/playerActive", msg).await
+            }
+            GameThreadMessage::SelectionDetails { frame, units } => {
+                let units = units
+                    .into_iter()
+                    .map(|x| SelectedUnit {
+                        unit_id: x.unit_id,
+                        order: x.order,
+                        target_unit_id: x.target_unit_id,
+                        target_x: x.target_pos.x,
+                        target_y: x.target_pos.y,
+                    })
+                    .collect();
+                let msg = SelectionDetails { frame, units };
+                send_to_app(&mut ws_send, "/game/selectionDetails", msg).await
+            }
+            GameThreadMessage::CameraMove { frame, x, y } => {
+                let msg = CameraMove { frame, x, y };
+                send_to_app(&mut ws_send, "/game/cameraMove", msg).await
+            }
+            GameThreadMessage::IncomeRate { frame, minerals_per_min, gas_per_min } => {
+                let msg = IncomeRate { frame, minerals_per_min, gas_per_min };
+                send_to_app(&mut ws_send, "/game/incomeRate", msg).await
+            }
+            GameThreadMessage::LiveApm { frame, apm } => {
+                send_to_app(&mut ws_send, "/game/liveApm", LiveApm { frame, apm }).await
+            }
+            GameThreadMessage::Capabilities(capabilities) => {
+                let msg = Capabilities {
+                    trigger_reports: capabilities.trigger_reports,
+                    send_chat: capabilities.send_chat,
+                    palette_kinds: capabilities.palette_kinds,
+                    disable_frame_delay: capabilities.disable_frame_delay,
+                    pause: capabilities.pause,
+                };
+                send_to_app(&mut ws_send, "/game/capabilities", msg).await
+            }
+            GameThreadMessage::TriggerFired { player, action, frame } => {
+                let msg = TriggerFired { player: player.0, action, frame };
+                send_to_app(&mut ws_send, "/game/triggerFired", msg).await
+            }
+            GameThreadMessage::ActualSetup { controllers, races, teams } => {
+                let msg = ActualSetup { controllers, races, teams };
+                send_to_app(&mut ws_send, "/game/actualSetup", msg).await
+            }
+            GameThreadMessage::GameStartMetadata {
+                map_tile_width,
+                map_tile_height,
+                random_seed,
+                turn_rate,
+                game_fingerprint,
+            } => {
+                let msg = GameStartMetadata {
+                    map_tile_width,
+                    map_tile_height,
+                    random_seed,
+                    turn_rate,
+                    game_fingerprint,
+                };
+                send_to_app(&mut ws_send, "/game/startMetadata", msg).await
+            }
+            GameThreadMessage::ResourceLayout(resources) => {
+                let msg = ResourceLayout { resources };
+                send_to_app(&mut ws_send, "/game/resourceLayout", msg).await
+            }
+            GameThreadMessage::ReplaySaved(path) => {
+                let msg = ReplaySaved { path: path.to_string_lossy().into() };
+                send_to_app(&mut ws_send, "/game/replaySaved", msg).await
+            }
+            GameThreadMessage::ActionTimeline(players) => {
+                let players = players
+                    .into_iter()
+                    .map(|x| PlayerActionBuckets {
+                        player: x.player,
+                        bucket_frames: x.bucket_frames,
+                        buckets: x
+                            .buckets
+                            .iter()
+                            .map(|&[selection, hotkey, production, order]| ActionBucket {
+                                selection,
+                                hotkey,
+                                production,
+                                order,
+                            })
+                            .collect(),
+                    })
+                    .collect();
+                send_to_app(&mut ws_send, "/game/actionTimeline", ActionTimeline { players }).await
+            }
+            GameThreadMessage::PlayerResync { storm_id, stalled_frames } => {
+                let msg = PlayerResync { storm_id: storm_id.0, stalled_frames };
+                send_to_app(&mut ws_send, "/game/playerResync", msg).await
+            }
+            GameThreadMessage::SpectatorChanged { storm_id, joined } => {
+                let msg = SpectatorChanged { storm_id: storm_id.0, joined };
+                send_to_app(&mut ws_send, "/game/spectatorChanged", msg).await
+            }
+            GameThreadMessage::GameplayStarted { frame, unix_time } => {
+                let msg = GameplayStarted { frame, unix_time };
+                send_to_app(&mut ws_send, "/game/gameplayStarted", msg).await
+            }
+            GameThreadMessage::PlayerColors(colors) => {
+                send_to_app(&mut ws_send, "/game/playerColors", PlayerColors { colors }).await
+            }
+            GameThreadMessage::ResourceDepleted { unit_id, x, y, frame } => {
+                let msg = ResourceDepleted { unit_id, x, y, frame };
+                send_to_app(&mut ws_send, "/game/resourceDepleted", msg).await
+            }
+            GameThreadMessage::Screenshot { screenshot, .. } => match save_screenshot(&screenshot) {
+                Ok(path) => {
+                    let msg = Screenshot { path: path.to_string_lossy().into() };
+                    send_to_app(&mut ws_send, "/game/screenshot", msg).await
+                }
+                Err(e) => {
+                    error!("Couldn't save screenshot: {}", e);
                     Ok(())
                 }
+            },
+            GameThreadMessage::CurrentFrame { request_id, frame } => {
+                send_to_app(&mut ws_send, "/game/currentFrame", CurrentFrame { request_id, frame })
+                    .await
+            }
+            GameThreadMessage::CurrentGameState { request_id, state } => {
+                let msg = CurrentGameState {
+                    request_id,
+                    frame: state.frame,
+                    paused: state.paused,
+                    speed: state.speed,
+                    lagging: state.lagging,
+                };
+                send_to_app(&mut ws_send, "/game/currentGameState", msg).await
+            }
+            GameThreadMessage::UnitSnapshots { request_id, units } => {
+                let units = units
+                    .into_iter()
+                    .map(|x| UnitSnapshot {
+                        unit_id: x.unit_id,
+                        player: x.player,
+                        x: x.position.x,
+                        y: x.position.y,
+                        hitpoints: x.hitpoints,
+                        shields: x.shields,
+                        energy: x.energy,
+                        order: x.order,
+                        target_unit_id: x.target.map(|x| x.0),
+                        target_x: x.target.map(|x| (x.1).x),
+                        target_y: x.target.map(|x| (x.1).y),
+                    })
+                    .collect();
+                let msg = UnitSnapshots { request_id, units };
+                send_to_app(&mut ws_send, "/game/unitSnapshots", msg).await
+            }
+            GameThreadMessage::UnitDump { request_id, dump } => {
+                send_to_app(&mut ws_send, "/game/unitDump", UnitDump { request_id, dump }).await
+            }
+            GameThreadMessage::MinimapPingResult { request_id, result } => {
+                let msg = RequestResult { request_id, error: result.err().map(|e| e.to_string()) };
+                send_to_app(&mut ws_send, "/game/minimapPingResult", msg).await
             }
+            GameThreadMessage::IssueCommandResult { request_id, result } => {
+                let msg = RequestResult { request_id, error: result.err().map(|e| e.to_string()) };
+                send_to_app(&mut ws_send, "/game/issueCommandResult", msg).await
+            }
+            GameThreadMessage::CommandLog { data, truncated } => match save_command_log(&data) {
+                Ok(path) => {
+                    let msg = CommandLog { path: path.to_string_lossy().into(), truncated };
+                    send_to_app(&mut ws_send, "/game/commandLog", msg).await
+                }
+                Err(e) => {
+                    error!("Couldn't save command log: {}", e);
+                    Ok(())
+                }
+            },
             other => {
                 game_send.send(GameStateMessage::GameThread(other)).await.map_err(|_| ())
             }
@@ -376,6 +660,46 @@ async fn handle_messages_from_game_thread(
     }
 }
 
+fn save_screenshot(screenshot: &forge::Screenshot) -> Result<PathBuf, io::Error> {
+    let args = parse_args();
+    let dir = args.user_data_path.join("screenshots");
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(game_file_name(&args.game_id, "bmp"));
+    std::fs::write(&path, screenshot.to_bmp())?;
+    Ok(path)
+}
+
+fn save_command_log(data: &[u8]) -> Result<PathBuf, io::Error> {
+    let args = parse_args();
+    let dir = args.user_data_path.join("command_logs");
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(game_file_name(&args.game_id, "bin"));
+    std::fs::write(&path, data)?;
+    Ok(path)
+}
+
+/// Games after the first one that the process plays get their index appended, so that
+/// they don't overwrite the previous games' files.
+fn game_file_name(game_id: &str, extension: &str) -> String {
+    match game_thread::game_index() {
+        0 => format!("{}.{}", game_id, extension),
+        index => format!("{}-{}.{}", game_id, index, extension),
+    }
+}
+
+/// Sends a message to the app. Messages that fail to encode are logged and skipped,
+/// only a closed websocket channel is considered an error.
+async fn send_to_app<T: serde::Serialize>(
+    ws_send: &mut app_socket::SendMessages,
+    command: &str,
+    data: T,
+) -> Result<(), ()> {
+    match app_socket::encode_message(command, data) {
+        Some(msg) => ws_send.send(msg).await.map_err(|_| ()),
+        None => Ok(()),
+    }
+}
+
 fn async_thread(main_thread: std::sync::mpsc::Sender<()>) {
     use futures::prelude::*;
     // Main async tasks are:
@@ -443,6 +767,7 @@ fn async_thread(main_thread: std::sync::mpsc::Sender<()>) {
             game_requests_send,
         );
         let messages_from_game = handle_messages_from_game_thread(websocket_send, game_state_send);
+        tokio::spawn(crate::game_thread::watchdog::run());
         let main_task = future::join3(game_state, websocket_connection, messages_from_game)
             .map(|_| ());
         cancel_token.bind(main_task.boxed()).inspect(|_| {
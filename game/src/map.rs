@@ -0,0 +1,587 @@
+//! Reads map files without launching BW, so that unusable maps can be rejected before
+//! the game is started.
+//!
+//! Maps are MPQ archives that contain `staredit\scenario.chk`, though BW accepts a bare
+//! CHK file as well. The CHK is a list of sections, each being a 4-byte name, a signed
+//! 32-bit length and the data. Map protectors abuse the format (negative lengths,
+//! duplicate sections, bogus archive sizes), so this tries to accept whatever BW would,
+//! and only reports problems that would make BW fail to load the map.
+//!
+//! `write_archive` goes the other way, packaging a bare CHK (e.g. one embedded in a
+//! replay) to an archive that BW and map editors can open.
+//!
+//! Archive files can be uncompressed, zlib-compressed or imploded (Which most maps saved
+//! by StarEdit are), other compressions are reported with `UnsupportedCompression`.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use byteorder::{ByteOrder, LittleEndian};
+use flate2::read::ZlibDecoder;
+use once_cell::sync::Lazy;
+use quick_error::quick_error;
+
+/// Sanity limit for the map file, larger than any map BW can load.
+const MAX_MAP_FILE_SIZE: u64 = 0x4000000;
+/// Sanity limit for the scenario.chk size read from the archive.
+const MAX_CHK_SIZE: usize = 0x2000000;
+/// Limit for sections that are parsed from a CHK, so that negative section lengths
+/// can't loop forever.
+const MAX_CHK_SECTIONS: usize = 0x10000;
+const CHK_PATH: &str = "staredit\\scenario.chk";
+
+const MPQ_SIGNATURE: &[u8; 4] = b"MPQ\x1a";
+const MPQ_HEADER_SIZE: usize = 0x20;
+/// MPQ headers can be anywhere in the file, as long as they are aligned to this.
+const MPQ_HEADER_ALIGN: usize = 0x200;
+
+const FILE_IMPLODE: u32 = 0x100;
+const FILE_COMPRESS: u32 = 0x200;
+const FILE_ENCRYPTED: u32 = 0x10000;
+const FILE_FIX_KEY: u32 = 0x20000;
+const FILE_SINGLE_UNIT: u32 = 0x1000000;
+const FILE_EXISTS: u32 = 0x80000000;
+
+const COMPRESSION_ZLIB: u8 = 0x2;
+const COMPRESSION_IMPLODE: u8 = 0x8;
+
+const HASH_TABLE_INDEX: u32 = 0;
+const HASH_NAME_A: u32 = 1;
+const HASH_NAME_B: u32 = 2;
+const HASH_FILE_KEY: u32 = 3;
+const HASH_ENTRY_EMPTY: u32 = 0xffff_ffff;
+const HASH_ENTRY_DELETED: u32 = 0xffff_fffe;
+
+/// Tile values of OWNR section that BW turns into player slots.
+const OWNER_COMPUTER: u8 = 5;
+const OWNER_HUMAN: u8 = 6;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum MapError {
+        Io(e: io::Error) {
+            from()
+            display("I/O error: {}", e)
+        }
+        Missing {
+            display("Map file doesn't exist")
+        }
+        TooLarge(size: u64) {
+            display("Map file is too large ({} bytes)", size)
+        }
+        NotAMap {
+            display("File is not a map")
+        }
+        Corrupted(reason: &'static str) {
+            display("Map is corrupted: {}", reason)
+        }
+        UnsupportedCompression(mask: u8) {
+            display("Map uses compression {:x} that is not supported", mask)
+        }
+        MissingSection(name: &'static str) {
+            display("Map doesn't have a {} section", name)
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MapInfo {
+    pub tile_width: u16,
+    pub tile_height: u16,
+    pub tileset: u16,
+    /// Human and computer slots that the map has.
+    pub player_slots: u8,
+}
+
+pub fn validate(path: &Path) -> Result<MapInfo, MapError> {
+    let metadata = match fs::metadata(path) {
+        Ok(o) => o,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(MapError::Missing),
+        Err(e) => return Err(e.into()),
+    };
+    if metadata.len() > MAX_MAP_FILE_SIZE {
+        return Err(MapError::TooLarge(metadata.len()));
+    }
+    validate_data(&fs::read(path)?)
+}
+
+fn validate_data(data: &[u8]) -> Result<MapInfo, MapError> {
+    let mpq_offset = (0..data.len())
+        .step_by(MPQ_HEADER_ALIGN)
+        .find(|&offset| data[offset..].starts_with(MPQ_SIGNATURE));
+    match mpq_offset {
+        Some(offset) => parse_chk(&read_scenario_chk(&data[offset..])?),
+        None => {
+            // Bare CHK files start with a section name.
+            let is_chk = data.len() >= 8 &&
+                data[..4].iter().all(|&x| x.is_ascii_graphic() || x == b' ');
+            if !is_chk {
+                return Err(MapError::NotAMap);
+            }
+            parse_chk(data)
+        }
+    }
+}
+
+static CRYPT_TABLE: Lazy<[u32; 0x500]> = Lazy::new(|| {
+    let mut table = [0u32; 0x500];
+    let mut seed = 0x0010_0001u32;
+    for i in 0..0x100 {
+        for j in 0..5 {
+            seed = (seed * 125 + 3) % 0x2a_aaab;
+            let high = (seed & 0xffff) << 0x10;
+            seed = (seed * 125 + 3) % 0x2a_aaab;
+            table[j * 0x100 + i] = high | (seed & 0xffff);
+        }
+    }
+    table
+});
+
+fn hash_string(name: &str, hash_type: u32) -> u32 {
+    let mut seed1 = 0x7fed_7fedu32;
+    let mut seed2 = 0xeeee_eeeeu32;
+    for byte in name.bytes() {
+        let byte = match byte.to_ascii_uppercase() {
+            b'/' => b'\\',
+            x => x,
+        } as u32;
+        seed1 = CRYPT_TABLE[(hash_type * 0x100 + byte) as usize] ^ seed1.wrapping_add(seed2);
+        seed2 = byte
+            .wrapping_add(seed1)
+            .wrapping_add(seed2)
+            .wrapping_add(seed2 << 5)
+            .wrapping_add(3);
+    }
+    seed1
+}
+
+fn decrypt(data: &mut [u32], mut key: u32) {
+    let mut seed = 0xeeee_eeeeu32;
+    for value in data {
+        seed = seed.wrapping_add(CRYPT_TABLE[0x400 + (key & 0xff) as usize]);
+        let plain = *value ^ key.wrapping_add(seed);
+        key = ((!key << 0x15).wrapping_add(0x1111_1111)) | (key >> 0xb);
+        seed = plain.wrapping_add(seed).wrapping_add(seed << 5).wrapping_add(3);
+        *value = plain;
+    }
+}
+
+fn encrypt(data: &mut [u32], mut key: u32) {
+    let mut seed = 0xeeee_eeeeu32;
+    for value in data {
+        seed = seed.wrapping_add(CRYPT_TABLE[0x400 + (key & 0xff) as usize]);
+        let plain = *value;
+        *value = plain ^ key.wrapping_add(seed);
+        key = ((!key << 0x15).wrapping_add(0x1111_1111)) | (key >> 0xb);
+        seed = plain.wrapping_add(seed).wrapping_add(seed << 5).wrapping_add(3);
+    }
+}
+
+/// Decrypts whole words of `data`, any trailing bytes are left as is.
+fn decrypt_bytes(data: &mut [u8], key: u32) {
+    let mut words = data.chunks_exact(4).map(LittleEndian::read_u32).collect::<Vec<_>>();
+    decrypt(&mut words, key);
+    for (out, word) in data.chunks_exact_mut(4).zip(words) {
+        LittleEndian::write_u32(out, word);
+    }
+}
+
+fn read_u32s(archive: &[u8], offset: usize, count: usize) -> Option<Vec<u32>> {
+    let bytes = archive.get(offset..)?.get(..count.checked_mul(4)?)?;
+    Some(bytes.chunks_exact(4).map(LittleEndian::read_u32).collect())
+}
+
+fn read_table(archive: &[u8], offset: u32, entries: u32, name: &str) -> Option<Vec<u32>> {
+    let mut table = read_u32s(archive, offset as usize, (entries as usize).checked_mul(4)?)?;
+    decrypt(&mut table, hash_string(name, HASH_FILE_KEY));
+    Some(table)
+}
+
+fn read_scenario_chk(archive: &[u8]) -> Result<Vec<u8>, MapError> {
+    if archive.len() < MPQ_HEADER_SIZE {
+        return Err(MapError::Corrupted("Archive header is truncated"));
+    }
+    let sector_size_shift = LittleEndian::read_u16(&archive[0xe..]);
+    if sector_size_shift > 0x10 {
+        return Err(MapError::Corrupted("Invalid sector size"));
+    }
+    let sector_size = 0x200usize << sector_size_shift;
+    let hash_table = read_table(
+        archive,
+        LittleEndian::read_u32(&archive[0x10..]),
+        LittleEndian::read_u32(&archive[0x18..]),
+        "(hash table)",
+    ).ok_or(MapError::Corrupted("Hash table is out of bounds"))?;
+    let block_table = read_table(
+        archive,
+        LittleEndian::read_u32(&archive[0x14..]),
+        LittleEndian::read_u32(&archive[0x1c..]),
+        "(block table)",
+    ).ok_or(MapError::Corrupted("Block table is out of bounds"))?;
+
+    let block_index = find_file(&hash_table, CHK_PATH)
+        .ok_or(MapError::Corrupted("Archive doesn't contain scenario.chk"))?;
+    let block = block_table
+        .get(block_index as usize * 4..)
+        .and_then(|x| x.get(..4))
+        .ok_or(MapError::Corrupted("Invalid block index"))?;
+    let (offset, compressed_size, size, flags) =
+        (block[0] as usize, block[1] as usize, block[2] as usize, block[3]);
+    if flags & FILE_EXISTS == 0 {
+        return Err(MapError::Corrupted("scenario.chk has been deleted"));
+    }
+    if size > MAX_CHK_SIZE {
+        return Err(MapError::Corrupted("scenario.chk is too large"));
+    }
+    let key = match flags & FILE_ENCRYPTED != 0 {
+        true => {
+            let key = hash_string("scenario.chk", HASH_FILE_KEY);
+            match flags & FILE_FIX_KEY != 0 {
+                true => key.wrapping_add(offset as u32) ^ size as u32,
+                false => key,
+            }
+        }
+        false => 0,
+    };
+    let data = archive.get(offset..).ok_or(MapError::Corrupted("File is out of bounds"))?;
+    let compressed = flags & (FILE_COMPRESS | FILE_IMPLODE) != 0;
+
+    // (start, end) of each sector within `data`
+    let sectors = if flags & FILE_SINGLE_UNIT != 0 {
+        vec![(0, compressed_size)]
+    } else if compressed {
+        let count = (size + sector_size - 1) / sector_size;
+        let mut offsets = read_u32s(data, 0, count + 1)
+            .ok_or(MapError::Corrupted("Sector offsets are out of bounds"))?;
+        if key != 0 {
+            decrypt(&mut offsets, key.wrapping_sub(1));
+        }
+        offsets.windows(2).map(|x| (x[0] as usize, x[1] as usize)).collect()
+    } else {
+        (0..size)
+            .step_by(sector_size)
+            .map(|start| (start, (start + sector_size).min(size)))
+            .collect()
+    };
+
+    let mut out = Vec::with_capacity(size);
+    for (i, &(start, end)) in sectors.iter().enumerate() {
+        let expected_size = (size - out.len()).min(match flags & FILE_SINGLE_UNIT {
+            0 => sector_size,
+            _ => size,
+        });
+        let mut sector = data
+            .get(start..end)
+            .ok_or(MapError::Corrupted("Sector is out of bounds"))?
+            .to_vec();
+        if key != 0 {
+            decrypt_bytes(&mut sector, key.wrapping_add(i as u32));
+        }
+        if !compressed || sector.len() >= expected_size {
+            out.extend_from_slice(&sector[..expected_size.min(sector.len())]);
+            continue;
+        }
+        // Imploded files are from before MPQs had other compressions, and their sectors
+        // don't start with a compression mask.
+        let (mask, sector) = match flags & FILE_IMPLODE != 0 {
+            true => (COMPRESSION_IMPLODE, &sector[..]),
+            false => match sector.split_first() {
+                Some((&mask, rest)) => (mask, rest),
+                None => return Err(MapError::Corrupted("Empty sector")),
+            },
+        };
+        out.extend_from_slice(&decompress_sector(sector, mask, expected_size)?);
+    }
+    if out.len() != size {
+        return Err(MapError::Corrupted("scenario.chk is truncated"));
+    }
+    Ok(out)
+}
+
+/// Undoes each compression in `mask`, in reverse order of how Storm applies them
+/// (zlib first, then implode).
+fn decompress_sector(data: &[u8], mask: u8, size: usize) -> Result<Vec<u8>, MapError> {
+    if mask == 0 || mask & !(COMPRESSION_IMPLODE | COMPRESSION_ZLIB) != 0 {
+        return Err(MapError::UnsupportedCompression(mask));
+    }
+    let mut data = data.to_vec();
+    if mask & COMPRESSION_IMPLODE != 0 {
+        data = crate::implode::explode(&data, size)
+            .map_err(|_| MapError::Corrupted("Invalid imploded data"))?;
+    }
+    if mask & COMPRESSION_ZLIB != 0 {
+        let mut out = vec![0; size];
+        ZlibDecoder::new(&data[..])
+            .read_exact(&mut out)
+            .map_err(|_| MapError::Corrupted("Invalid compressed data"))?;
+        data = out;
+    }
+    if data.len() != size {
+        return Err(MapError::Corrupted("Sector decompresses to wrong size"));
+    }
+    Ok(data)
+}
+
+/// Returns block index of the file.
+fn find_file(hash_table: &[u32], name: &str) -> Option<u32> {
+    let entries = hash_table.len() / 4;
+    if entries == 0 {
+        return None;
+    }
+    let (name_a, name_b) = (hash_string(name, HASH_NAME_A), hash_string(name, HASH_NAME_B));
+    let start = hash_string(name, HASH_TABLE_INDEX) as usize % entries;
+    (0..entries)
+        .map(|i| &hash_table[(start + i) % entries * 4..][..4])
+        .take_while(|entry| entry[3] != HASH_ENTRY_EMPTY)
+        .find(|entry| entry[0] == name_a && entry[1] == name_b && entry[3] != HASH_ENTRY_DELETED)
+        .map(|entry| entry[3])
+}
+
+fn parse_chk(chk: &[u8]) -> Result<MapInfo, MapError> {
+    let mut dimensions = None;
+    let mut tileset = None;
+    let mut owners = None;
+    let mut has_version = false;
+    let mut pos = 0usize;
+    for _ in 0..MAX_CHK_SECTIONS {
+        let header = match chk.get(pos..).and_then(|x| x.get(..8)) {
+            Some(s) => s,
+            None => break,
+        };
+        let length = LittleEndian::read_i32(&header[4..]);
+        let data_start = pos + 8;
+        // Like BW, later sections replace earlier ones, and sections that go past the
+        // end of the file are used as far as there is data.
+        if length >= 0 {
+            let data = &chk[data_start..chk.len().min(data_start + length as usize)];
+            match &header[..4] {
+                b"VER " => has_version = true,
+                b"DIM " if data.len() >= 4 => {
+                    dimensions =
+                        Some((LittleEndian::read_u16(data), LittleEndian::read_u16(&data[2..])));
+                }
+                b"ERA " if data.len() >= 2 => tileset = Some(LittleEndian::read_u16(data) & 0x7),
+                b"OWNR" if data.len() >= 8 => {
+                    owners = Some(
+                        data[..8]
+                            .iter()
+                            .filter(|&&x| x == OWNER_COMPUTER || x == OWNER_HUMAN)
+                            .count() as u8,
+                    );
+                }
+                _ => (),
+            }
+        }
+        // Negative lengths make BW continue reading from an earlier position.
+        match (data_start as i64).checked_add(length as i64) {
+            Some(next) if next >= 0 => pos = next as usize,
+            _ => break,
+        }
+    }
+    if !has_version {
+        return Err(MapError::MissingSection("VER"));
+    }
+    let (tile_width, tile_height) = dimensions.ok_or(MapError::MissingSection("DIM"))?;
+    if tile_width == 0 || tile_height == 0 || tile_width > 256 || tile_height > 256 {
+        return Err(MapError::Corrupted("Invalid map dimensions"));
+    }
+    Ok(MapInfo {
+        tile_width,
+        tile_height,
+        tileset: tileset.ok_or(MapError::MissingSection("ERA"))?,
+        player_slots: owners.ok_or(MapError::MissingSection("OWNR"))?,
+    })
+}
+
+fn push_u32s(out: &mut Vec<u8>, values: &[u32]) {
+    for value in values {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Creates a map archive (.scm/.scx) that contains `chk` as an uncompressed
+/// scenario.chk.
+pub fn write_archive(chk: &[u8]) -> Vec<u8> {
+    build_archive(chk.to_vec(), chk.len(), FILE_EXISTS)
+}
+
+/// `file` is scenario.chk as it is stored in the archive, `size` its uncompressed size.
+fn build_archive(file: Vec<u8>, size: usize, flags: u32) -> Vec<u8> {
+    let file_offset = MPQ_HEADER_SIZE;
+    let hash_entries = 4usize;
+    let hash_offset = file_offset + file.len();
+    let block_offset = hash_offset + hash_entries * 16;
+    let mut hash_table = vec![HASH_ENTRY_EMPTY; hash_entries * 4];
+    let index = hash_string(CHK_PATH, HASH_TABLE_INDEX) as usize % hash_entries;
+    hash_table[index * 4..][..4].copy_from_slice(&[
+        hash_string(CHK_PATH, HASH_NAME_A),
+        hash_string(CHK_PATH, HASH_NAME_B),
+        0,
+        0,
+    ]);
+    encrypt(&mut hash_table, hash_string("(hash table)", HASH_FILE_KEY));
+    let mut block_table = [file_offset as u32, file.len() as u32, size as u32, flags];
+    encrypt(&mut block_table, hash_string("(block table)", HASH_FILE_KEY));
+
+    let mut out = Vec::new();
+    out.extend_from_slice(MPQ_SIGNATURE);
+    push_u32s(&mut out, &[MPQ_HEADER_SIZE as u32, (block_offset + 16) as u32]);
+    // Format version and sector size shift
+    out.extend_from_slice(&[0, 0, 0, 0]);
+    push_u32s(
+        &mut out,
+        &[hash_offset as u32, block_offset as u32, hash_entries as u32, 1],
+    );
+    out.extend_from_slice(&file);
+    push_u32s(&mut out, &hash_table);
+    push_u32s(&mut out, &block_table);
+    out
+}
+
+#[cfg(test)]
+fn sample_chk() -> Vec<u8> {
+    let mut owners = [0u8; 12];
+    owners[..4].copy_from_slice(&[OWNER_HUMAN, OWNER_HUMAN, OWNER_COMPUTER, 0x7]);
+    let sections: [(&[u8; 4], &[u8]); 5] = [
+        (b"VER ", &[0xcd, 0x00]),
+        (b"DIM ", &[0x80, 0x00, 0x60, 0x00]),
+        (b"ERA ", &[0x04, 0x00]),
+        (b"UNIT", &[0x1; 0x24]),
+        (b"OWNR", &owners),
+    ];
+    let mut out = Vec::new();
+    for &(name, data) in sections.iter() {
+        out.extend_from_slice(name);
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(data);
+    }
+    out
+}
+
+/// Creates an archive that contains only scenario.chk.
+/// With a non-zero compression `mask`, sectors are compressed and encrypted with a fixed key.
+/// `COMPRESSION_IMPLODE` alone is stored the way old maps are, with the file implode flag
+/// instead of the mask.
+#[cfg(test)]
+fn sample_mpq(chk: &[u8], mask: u8) -> Vec<u8> {
+    use std::io::Write;
+
+    let sector_size = 0x200;
+    let file_offset = MPQ_HEADER_SIZE;
+    let (file, flags) = if mask != 0 {
+        let compress_flag = match mask {
+            COMPRESSION_IMPLODE => FILE_IMPLODE,
+            _ => FILE_COMPRESS,
+        };
+        let flags = FILE_EXISTS | compress_flag | FILE_ENCRYPTED | FILE_FIX_KEY;
+        let key = hash_string("scenario.chk", HASH_FILE_KEY)
+            .wrapping_add(file_offset as u32) ^ chk.len() as u32;
+        let sectors = chk.chunks(sector_size).map(|sector| {
+            let mut compressed = sector.to_vec();
+            if mask & COMPRESSION_ZLIB != 0 {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(Vec::new(), Default::default());
+                encoder.write_all(&compressed).unwrap();
+                compressed = encoder.finish().unwrap();
+            }
+            if mask & COMPRESSION_IMPLODE != 0 {
+                compressed = crate::implode::implode_runs(&compressed);
+            }
+            if compress_flag == FILE_COMPRESS {
+                compressed.insert(0, mask);
+            }
+            // Sectors that don't get smaller are stored as is.
+            if compressed.len() < sector.len() {
+                compressed
+            } else {
+                sector.to_vec()
+            }
+        }).collect::<Vec<_>>();
+        let mut offsets = vec![(sectors.len() as u32 + 1) * 4];
+        for sector in &sectors {
+            offsets.push(offsets.last().unwrap() + sector.len() as u32);
+        }
+        encrypt(&mut offsets, key.wrapping_sub(1));
+        let mut file = Vec::new();
+        push_u32s(&mut file, &offsets);
+        for (i, mut sector) in sectors.into_iter().enumerate() {
+            let mut words = sector.chunks_exact(4).map(LittleEndian::read_u32).collect::<Vec<_>>();
+            encrypt(&mut words, key.wrapping_add(i as u32));
+            for (out, word) in sector.chunks_exact_mut(4).zip(words) {
+                LittleEndian::write_u32(out, word);
+            }
+            file.extend_from_slice(&sector);
+        }
+        (file, flags)
+    } else {
+        (chk.to_vec(), FILE_EXISTS)
+    };
+    build_archive(file, chk.len(), flags)
+}
+
+#[test]
+fn validate_valid_maps() {
+    let expected = MapInfo {
+        tile_width: 128,
+        tile_height: 96,
+        tileset: 4,
+        player_slots: 3,
+    };
+    let chk = sample_chk();
+    assert_eq!(validate_data(&chk).unwrap(), expected);
+    assert_eq!(validate_data(&sample_mpq(&chk, 0)).unwrap(), expected);
+    assert_eq!(read_scenario_chk(&write_archive(&chk)).unwrap(), chk);
+    // Spans several sectors
+    let mut large_chk = chk.clone();
+    large_chk.extend_from_slice(b"MTXM");
+    large_chk.extend_from_slice(&0x1000u32.to_le_bytes());
+    large_chk.extend((0..0x1000u32).map(|x| (x / 5) as u8));
+    assert_eq!(validate_data(&sample_mpq(&large_chk, COMPRESSION_ZLIB)).unwrap(), expected);
+}
+
+#[test]
+fn validate_imploded_maps() {
+    let expected = MapInfo {
+        tile_width: 128,
+        tile_height: 96,
+        tileset: 4,
+        player_slots: 3,
+    };
+    let mut chk = sample_chk();
+    chk.extend_from_slice(b"MTXM");
+    chk.extend_from_slice(&0x1000u32.to_le_bytes());
+    chk.extend((0..0x1000u32).map(|x| (x / 40) as u8));
+    let imploded = sample_mpq(&chk, COMPRESSION_IMPLODE);
+    assert_eq!(read_scenario_chk(&imploded).unwrap(), chk);
+    assert_eq!(validate_data(&imploded).unwrap(), expected);
+    let multi = sample_mpq(&chk, COMPRESSION_IMPLODE | COMPRESSION_ZLIB);
+    assert_eq!(read_scenario_chk(&multi).unwrap(), chk);
+    // Huffman coding isn't supported
+    let huffman = sample_mpq(&chk, 0x1 | COMPRESSION_ZLIB);
+    assert!(matches!(validate_data(&huffman), Err(MapError::UnsupportedCompression(0x3))));
+}
+
+#[test]
+fn validate_invalid_maps() {
+    assert!(matches!(validate_data(b"\x00\x01garbage"), Err(MapError::NotAMap)));
+    let chk = sample_chk();
+    let truncated_chk = &chk[..chk.len() - 20];
+    assert!(matches!(validate_data(truncated_chk), Err(MapError::MissingSection("OWNR"))));
+    let mpq = sample_mpq(&chk, COMPRESSION_ZLIB);
+    assert!(matches!(validate_data(&mpq[..mpq.len() - 8]), Err(MapError::Corrupted(_))));
+    assert!(matches!(validate_data(&mpq[..0x18]), Err(MapError::Corrupted(_))));
+    let mut small_map = chk.clone();
+    small_map[8 + 2 + 8..][..2].copy_from_slice(&0u16.to_le_bytes());
+    assert!(matches!(validate_data(&small_map), Err(MapError::Corrupted(_))));
+}
+
+#[test]
+fn archive_layout() {
+    let chk = b"VER \x02\x00\x00\x00\xcd\x00";
+    let archive = write_archive(chk);
+    assert_eq!(&archive[..4], MPQ_SIGNATURE);
+    assert_eq!(&archive[MPQ_HEADER_SIZE..][..chk.len()], &chk[..]);
+    // Hash table of 4 entries and a single block table entry follow the file.
+    assert_eq!(archive.len(), MPQ_HEADER_SIZE + chk.len() + 4 * 16 + 16);
+}
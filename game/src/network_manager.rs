@@ -3,7 +3,6 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 use std::time::{Duration};
 
-use bytes::Bytes;
 use futures::prelude::*;
 use futures::{pin_mut, select};
 use quick_error::quick_error;
@@ -11,6 +10,8 @@ use tokio::sync::{mpsc, oneshot};
 
 use crate::app_messages;
 use crate::app_messages::Route as RouteInput;
+use crate::app_socket;
+use crate::bw::StormPlayerId;
 use crate::cancel_token::{CancelToken, Canceler};
 use crate::rally_point::{PlayerId, RallyPoint, RallyPointError, RouteId};
 use crate::snp::{self, SnpMessage};
@@ -27,6 +28,9 @@ pub enum NetworkManagerMessage {
     PingResult((String, u16), Result<RallyPointServer>),
     StartKeepAlive(Arc<Route>),
     SetGameInfo(Arc<app_messages::GameSetupInfo>),
+    /// (lobby player id, storm id) of players who have joined.
+    PeerStormIds(Vec<(String, StormPlayerId)>),
+    PortBound(Result<u16>),
 }
 
 quick_error! {
@@ -66,6 +70,7 @@ enum NetworkState {
 
 struct ReadyNetwork {
     ip_to_routes: HashMap<Ipv4Addr, Arc<Route>>,
+    wire_format: Arc<dyn snp::WireFormat>,
 }
 
 #[derive(Default)]
@@ -74,6 +79,11 @@ struct IncompleteNetwork {
     game_info: Option<Arc<app_messages::GameSetupInfo>>,
     // This existing means that storm side is active
     snp_send_messages: Option<snp::SendMessages>,
+    /// Set once the game info has been received and rally-point has been rebound
+    /// to the requested port range, if there was one. Routes can't be joined before that.
+    port_ready: bool,
+    /// Routes that were received before the port was ready.
+    pending_routes: Option<Vec<RouteInput>>,
 }
 
 struct State {
@@ -84,6 +94,7 @@ struct State {
     cancel_child_tasks: Vec<Canceler>,
     keep_routes_alive: Vec<Canceler>,
     send_messages: mpsc::Sender<NetworkManagerMessage>,
+    ws_send: app_socket::SendMessages,
 }
 
 #[derive(Default)]
@@ -258,23 +269,34 @@ impl State {
         self.clean_child_tasks();
         match message {
             NetworkManagerMessage::Routes(setup) => {
-                let future = self.join_routes(setup);
-                let mut send = self.send_messages.clone();
-                let (cancel_token, canceler) = CancelToken::new();
-                let cancelable = async move {
-                    let task = async move {
-                        let result = future.await;
-                        let _ = send.send(NetworkManagerMessage::RoutesReady(result)).await;
-                    };
-                    pin_mut!(task);
-                    let _ = cancel_token.bind(task).await;
-                };
-                self.cancel_child_tasks.push(canceler);
-                tokio::spawn(cancelable);
+                if let NetworkState::Incomplete(ref mut incomplete) = self.network {
+                    if !incomplete.port_ready {
+                        incomplete.pending_routes = Some(setup);
+                        return;
+                    }
+                }
+                self.start_joining_routes(setup);
             }
             NetworkManagerMessage::PingResult(key, result) => {
                 self.handle_ping_result(key, result);
             }
+            NetworkManagerMessage::PeerStormIds(peers) => {
+                if let NetworkState::Ready(ref network) = self.network {
+                    let storm_ids = network
+                        .ip_to_routes
+                        .iter()
+                        .filter_map(|(&ip, route)| {
+                            peers
+                                .iter()
+                                .find(|x| x.0 == route.lobby_player_id)
+                                .map(|x| (ip, x.1))
+                        })
+                        .collect();
+                    snp::set_peer_storm_ids(storm_ids);
+                } else {
+                    warn!("Received storm ids before network was ready");
+                }
+            }
             NetworkManagerMessage::RoutesReady(result) => {
                 match result {
                     Ok(routes) => {
@@ -307,7 +329,7 @@ impl State {
                 }
                 SnpMessage::Send(targets, data) => {
                     if let NetworkState::Ready(ref network) = self.network {
-                        let data: Bytes = data.into();
+                        let data = network.wire_format.encode(&data);
                         let sends = targets
                             .iter()
                             .filter_map(|addr| network.ip_to_routes.get(&addr))
@@ -332,9 +354,45 @@ impl State {
                 }
             },
             NetworkManagerMessage::SetGameInfo(info) => {
+                let port_range = info.port_range;
                 if let NetworkState::Incomplete(ref mut incomplete) = self.network {
                     incomplete.game_info = Some(info);
                 }
+                match port_range {
+                    Some((first, last)) => {
+                        let bind = self.rally_point.bind_port_range(first, last);
+                        let mut send = self.send_messages.clone();
+                        let (cancel_token, canceler) = CancelToken::new();
+                        let cancelable = async move {
+                            let task = async move {
+                                let result = bind.await
+                                    .map_err(|e| NetworkError::RallyPoint(Arc::new(e)));
+                                let _ = send.send(NetworkManagerMessage::PortBound(result)).await;
+                            };
+                            pin_mut!(task);
+                            let _ = cancel_token.bind(task).await;
+                        };
+                        self.cancel_child_tasks.push(canceler);
+                        tokio::spawn(cancelable);
+                    }
+                    None => self.port_ready(),
+                }
+                self.check_network_ready();
+            }
+            NetworkManagerMessage::PortBound(result) => {
+                match result {
+                    Ok(port) => {
+                        debug!("Bound rally-point socket to port {}", port);
+                        let mut ws_send = self.ws_send.clone();
+                        tokio::spawn(async move {
+                            let message = app_messages::NetBound { port };
+                            let command = "/game/netBound";
+                            let _ = app_socket::send_message(&mut ws_send, command, message).await;
+                        });
+                        self.port_ready();
+                    }
+                    Err(e) => self.network.set_error(e),
+                }
                 self.check_network_ready();
             }
             NetworkManagerMessage::StartKeepAlive(route) => {
@@ -365,6 +423,35 @@ impl State {
     }
 
     // If we have all parts needed to init network, do all of the remaining work
+    fn start_joining_routes(&mut self, setup: Vec<RouteInput>) {
+        let future = self.join_routes(setup);
+        let mut send = self.send_messages.clone();
+        let (cancel_token, canceler) = CancelToken::new();
+        let cancelable = async move {
+            let task = async move {
+                let result = future.await;
+                let _ = send.send(NetworkManagerMessage::RoutesReady(result)).await;
+            };
+            pin_mut!(task);
+            let _ = cancel_token.bind(task).await;
+        };
+        self.cancel_child_tasks.push(canceler);
+        tokio::spawn(cancelable);
+    }
+
+    fn port_ready(&mut self) {
+        let pending = match self.network {
+            NetworkState::Incomplete(ref mut incomplete) => {
+                incomplete.port_ready = true;
+                incomplete.pending_routes.take()
+            }
+            _ => None,
+        };
+        if let Some(routes) = pending {
+            self.start_joining_routes(routes);
+        }
+    }
+
     fn check_network_ready(&mut self) {
         let game_info;
         let routes;
@@ -414,11 +501,13 @@ impl State {
                 }
             })
             .collect::<HashMap<_, _>>();
+        let wire_format = game_info.wire_format.format();
         // Create the task which receives packets and forwards them to Storm
         let streams_done = ip_to_routes
             .iter()
             .map(|(&ip, route)| {
                 let snp_send = snp_send_messages.clone();
+                let wire_format = wire_format.clone();
                 let stream = self
                     .rally_point
                     .listen_route_data(&route.route_id, &route.address);
@@ -426,13 +515,13 @@ impl State {
                     pin_mut!(stream);
                     while let Some(message) = stream.next().await {
                         match message {
-                            Ok(message) => {
-                                let message = snp::ReceivedMessage {
-                                    from: ip,
-                                    data: message,
-                                };
-                                snp_send.send(message);
-                            }
+                            Ok(message) => match wire_format.decode(message) {
+                                Ok(data) => {
+                                    let message = snp::ReceivedMessage { from: ip, data };
+                                    snp_send.send(message);
+                                }
+                                Err(e) => warn!("Dropping invalid packet from {:?}: {}", ip, e),
+                            },
                             Err(e) => {
                                 // I don't think there's much sense to kill network
                                 // for this error, should never happen and if it does
@@ -450,7 +539,10 @@ impl State {
         self.cancel_child_tasks.push(canceler);
         tokio::spawn(cancel_token.bind(recv_task));
 
-        let ready = ReadyNetwork { ip_to_routes };
+        let ready = ReadyNetwork {
+            ip_to_routes,
+            wire_format,
+        };
         self.network = NetworkState::Ready(ready);
         for waiting in self.waiting_for_network.drain(..) {
             let _ = waiting.send(Ok(()));
@@ -496,7 +588,7 @@ fn ping_server(
 }
 
 impl NetworkManager {
-    pub fn new() -> NetworkManager {
+    pub fn new(ws_send: app_socket::SendMessages) -> NetworkManager {
         let (send_messages, receive_messages) = mpsc::channel(64);
         let (internal_send_messages, internal_receive_messages) = mpsc::channel(16);
         let mut state = State {
@@ -507,6 +599,7 @@ impl NetworkManager {
             cancel_child_tasks: Vec::new(),
             keep_routes_alive: Vec::new(),
             pings: PingState::default(),
+            ws_send,
         };
         let task = async move {
             let mut internal_receive_messages = internal_receive_messages.fuse();
@@ -556,6 +649,17 @@ impl NetworkManager {
         }
     }
 
+    pub fn set_peer_storm_ids(
+        &self,
+        peers: Vec<(String, StormPlayerId)>,
+    ) -> impl Future<Output = Result<()>> {
+        let mut send = self.send_messages.clone();
+        async move {
+            send.send(NetworkManagerMessage::PeerStormIds(peers)).await
+                .map_err(|_| NetworkError::NotActive)
+        }
+    }
+
     pub fn send_snp_message(&self, message: SnpMessage) -> impl Future<Output = Result<()>> {
         let mut send = self.send_messages.clone();
         async move {
@@ -4,7 +4,7 @@ use std::collections::{
 };
 
 use std::io;
-use std::net::{SocketAddr, SocketAddrV6};
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
 
@@ -40,6 +40,12 @@ quick_error! {
         RouteNotActive {
             display("Route is not active")
         }
+        NoPortAvailable(first: u16, last: u16) {
+            display("Couldn't bind any UDP port in range {}-{}", first, last)
+        }
+        RoutesInUse {
+            display("Cannot change the UDP port while routes are in use")
+        }
     }
 }
 
@@ -80,12 +86,15 @@ struct State {
     active_routes: HashMap<RouteKey, ActiveRoute>,
     joined_servers: HashSet<SocketAddrV6>,
     send_requests: mpsc::Sender<Request>,
-    send_bytes: mpsc::Sender<(Bytes, SocketAddrV6, Option<oneshot::Sender<RallyPointError>>)>,
+    send_bytes: SendBytes,
     pings: HashMap<(u32, SocketAddrV6), Ping>,
     #[allow(dead_code)]
     end_recv_task: Canceler,
 }
 
+type SendBytes =
+    mpsc::Sender<(Bytes, SocketAddrV6, Option<oneshot::Sender<RallyPointError>>)>;
+
 struct Ping {
     start: Instant,
     done: oneshot::Sender<Duration>,
@@ -250,6 +259,10 @@ impl State {
                 tokio::spawn(task);
                 send.boxed()
             }
+            ExternalRequest::Bind(first, last, done) => {
+                let _ = done.send(self.bind_port_range(first, last));
+                future::ready(()).boxed()
+            }
             ExternalRequest::Forward(route, player, data, address) => {
                 let message = forward_message(&route, player, &data);
                 let mut send_bytes = self.send_bytes.clone();
@@ -402,25 +415,33 @@ impl State {
         }
     }
 
+    /// Replaces the socket with one bound to the first free port in `first..=last`.
+    /// Only possible before any routes have been joined, as the servers know the
+    /// routes by the address they were joined from.
+    fn bind_port_range(&mut self, first: u16, last: u16) -> Result<u16, RallyPointError> {
+        if !self.joins.is_empty() || !self.active_routes.is_empty() {
+            return Err(RallyPointError::RoutesInUse);
+        }
+        for port in first..=last {
+            let addr = SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port);
+            match open_socket(&addr, &self.send_requests) {
+                Ok((send_bytes, end_recv_task)) => {
+                    // Dropping the old sender and canceler stops the old socket's tasks.
+                    self.send_bytes = send_bytes;
+                    self.end_recv_task = end_recv_task;
+                    return Ok(port);
+                }
+                Err(e) => debug!("Couldn't bind port {}: {}", port, e),
+            }
+        }
+        Err(RallyPointError::NoPortAvailable(first, last))
+    }
+
     fn new(
         addr: &SocketAddr,
         send_requests: mpsc::Sender<Request>,
     ) -> Result<State, RallyPointError> {
-        let (udp_send, udp_recv) = match udp::udp_socket(addr) {
-            Ok(o) => o,
-            Err(e) => return Err(RallyPointError::Bind(e)),
-        };
-        let (send_bytes, recv_bytes) = mpsc::channel(16);
-        // Send task closes from send_bytes dropping, but recv task will not notice
-        // recv_requests dropping if it doesn't receive anyhing, so use an explicit canceler.
-        let (cancel_token, end_recv_task) = CancelToken::new();
-        let send_task = udp_send_task(udp_send, recv_bytes);
-        let recv_task = udp_recv_task(udp_recv, send_requests.clone());
-        tokio::spawn(send_task);
-        tokio::spawn(async move {
-            pin_mut!(recv_task);
-            cancel_token.bind(recv_task).await
-        });
+        let (send_bytes, end_recv_task) = open_socket(addr, &send_requests)?;
         Ok(State {
             joins: HashMap::default(),
             active_routes: HashMap::default(),
@@ -433,6 +454,28 @@ impl State {
     }
 }
 
+fn open_socket(
+    addr: &SocketAddr,
+    send_requests: &mpsc::Sender<Request>,
+) -> Result<(SendBytes, Canceler), RallyPointError> {
+    let (udp_send, udp_recv) = match udp::udp_socket(addr) {
+        Ok(o) => o,
+        Err(e) => return Err(RallyPointError::Bind(e)),
+    };
+    let (send_bytes, recv_bytes) = mpsc::channel(16);
+    // Send task closes from send_bytes dropping, but recv task will not notice
+    // recv_requests dropping if it doesn't receive anyhing, so use an explicit canceler.
+    let (cancel_token, end_recv_task) = CancelToken::new();
+    let send_task = udp_send_task(udp_send, recv_bytes);
+    let recv_task = udp_recv_task(udp_recv, send_requests.clone());
+    tokio::spawn(send_task);
+    tokio::spawn(async move {
+        pin_mut!(recv_task);
+        cancel_token.bind(recv_task).await
+    });
+    Ok((send_bytes, end_recv_task))
+}
+
 async fn udp_send_task(
     mut udp_send: UdpSend,
     mut recv_bytes: mpsc::Receiver<(Bytes, SocketAddrV6, Option<oneshot::Sender<RallyPointError>>)>,
@@ -544,6 +587,7 @@ enum ExternalRequest {
         oneshot::Sender<Result<(), RallyPointError>>,
     ),
     KeepAlive(RouteId, PlayerId, SocketAddrV6),
+    Bind(u16, u16, oneshot::Sender<Result<u16, RallyPointError>>),
     ListenData(RouteId, SocketAddrV6, mpsc::Sender<Bytes>),
     Forward(RouteId, PlayerId, Bytes, SocketAddrV6),
 }
@@ -634,6 +678,24 @@ impl RallyPoint {
         }
     }
 
+    /// Rebinds the UDP socket to the first port in `first..=last` that is available,
+    /// returning the port. Must be done before joining any routes.
+    pub fn bind_port_range(
+        &self,
+        first: u16,
+        last: u16,
+    ) -> impl Future<Output = Result<u16, RallyPointError>> {
+        let (send, recv) = oneshot::channel();
+        let request = ExternalRequest::Bind(first, last, send);
+        let mut sender = self.send_requests.clone();
+        async move {
+            sender.send(Request::External(request)).await
+                .map_err(|_| RallyPointError::NotActive)?;
+            recv.await
+                .map_err(|_| RallyPointError::NotActive)?
+        }
+    }
+
     pub fn keep_alive(
         &self,
         route: &RouteId,
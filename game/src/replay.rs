@@ -0,0 +1,562 @@
+//! Reads metadata from BW replay files without having to launch the game.
+//!
+//! A replay consists of sections, each of which starts with a checksum and chunk count,
+//! followed by the chunks. A chunk that is smaller than the data it is supposed to contain
+//! is compressed; SC:R replays use zlib, while 1.16.1 replays use PKWare DCL implode.
+//! The sections after the header are the length and data of player commands, followed
+//! by the length and data of the map's scenario.chk.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+use flate2::read::ZlibDecoder;
+use quick_error::quick_error;
+use serde::Serialize;
+
+use crate::app_messages::Race;
+use crate::bw::{self, commands, StormPlayerId};
+use crate::implode;
+use crate::map;
+
+const REPLAY_ID_LEGACY: &[u8; 4] = b"reRS";
+const REPLAY_ID_SCR: &[u8; 4] = b"seRS";
+const HEADER_SIZE: usize = 0x279;
+const MAX_CHUNK_SIZE: usize = 0x2000;
+const PLAYER_COUNT: usize = 12;
+const PLAYER_RECORD_SIZE: usize = 0x24;
+/// Sanity limit for sizes read from the replay, so that a corrupted length doesn't
+/// make us allocate gigabytes.
+const MAX_SECTION_SIZE: usize = 0x4000000;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum ReplayError {
+        Io(e: io::Error) {
+            from()
+            display("I/O error: {}", e)
+        }
+        NotAReplay {
+            display("File is not a replay")
+        }
+        Corrupted(reason: &'static str) {
+            display("Replay is corrupted: {}", reason)
+        }
+        UnsupportedCompression {
+            display("Replay uses compression that is not supported")
+        }
+        MapNotEmbedded {
+            display("Replay doesn't contain the map")
+        }
+    }
+}
+
+/// Ordered from oldest to newest; BW versions can play replays up to the newest
+/// format they know of.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReplayFormat {
+    /// Used by 1.16.1, and SC:R before 1.21.
+    Legacy,
+    /// SC:R 1.21 and later.
+    Scr,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReplayHeader {
+    pub format: ReplayFormat,
+    pub frames: u32,
+    pub game_title: String,
+    pub map_name: String,
+    pub map_tile_width: u16,
+    pub map_tile_height: u16,
+    pub players: Vec<ReplayPlayer>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReplayPlayer {
+    pub player_id: bw::GamePlayerId,
+    pub name: String,
+    /// `None` if the race in the replay was something else than zerg, terran or protoss.
+    pub race: Option<Race>,
+    pub team: u8,
+    pub is_computer: bool,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReplayChat {
+    pub frame: u32,
+    pub storm_id: StormPlayerId,
+    pub text: String,
+}
+
+/// Not used by the game itself, but meant for tools that want to show replay info
+/// before launching it, see the `replayHeader` app command.
+pub fn parse_header(path: &Path) -> Result<ReplayHeader, ReplayError> {
+    let file = File::open(path)?;
+    parse_header_from(BufReader::new(file))
+}
+
+/// Reads only the start of the file, so this works even if the rest of the replay
+/// is corrupted.
+pub fn replay_format(path: &Path) -> Result<ReplayFormat, ReplayError> {
+    let file = File::open(path)?;
+    read_format(&mut BufReader::new(file))
+}
+
+/// Writes the map that the replay contains to `out_path` as a map archive.
+///
+/// Replays only store the scenario.chk, so the archive won't have any other files
+/// that the original map may have had. The caller decides whether it is named
+/// .scm or .scx.
+pub fn extract_map(replay_path: &Path, out_path: &Path) -> Result<(), ReplayError> {
+    let file = File::open(replay_path)?;
+    let chk = read_map_data(BufReader::new(file))?;
+    std::fs::write(out_path, &map::write_archive(&chk))?;
+    Ok(())
+}
+
+/// Reads all chat messages from the replay's commands, in the order they were sent.
+///
+/// Messages that can't be parsed are skipped, as is the rest of the commands if they
+/// turn out to be truncated or contain commands that aren't known.
+pub fn extract_chat(path: &Path) -> Result<Vec<ReplayChat>, ReplayError> {
+    let file = File::open(path)?;
+    let mut input = BufReader::new(file);
+    let (_, compression) = read_start(&mut input)?;
+    let commands = read_command_data(&mut input, compression)?;
+    Ok(parse_chat(&commands))
+}
+
+fn parse_header_from<R: Read>(mut input: R) -> Result<ReplayHeader, ReplayError> {
+    let (format, compression) = read_start(&mut input)?;
+    let header = read_section(&mut input, HEADER_SIZE, compression)?;
+    Ok(parse_header_data(format, &header))
+}
+
+fn read_map_data<R: Read>(mut input: R) -> Result<Vec<u8>, ReplayError> {
+    let (_, compression) = read_start(&mut input)?;
+    read_command_data(&mut input, compression)?;
+    let map_size = match read_section_size(&mut input, compression) {
+        Ok(o) => o,
+        Err(ReplayError::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            return Err(ReplayError::MapNotEmbedded);
+        }
+        Err(e) => return Err(e),
+    };
+    if map_size == 0 {
+        return Err(ReplayError::MapNotEmbedded);
+    }
+    read_section(&mut input, map_size, compression)
+}
+
+/// Skips the header and reads the command section that follows it.
+fn read_command_data<R: Read>(
+    input: &mut R,
+    compression: Compression,
+) -> Result<Vec<u8>, ReplayError> {
+    read_section(input, HEADER_SIZE, compression)?;
+    let commands_size = read_section_size(input, compression)?;
+    read_section(input, commands_size, compression)
+}
+
+/// Reads the replay id, and for SC:R replays the value following it.
+fn read_start<R: Read>(input: &mut R) -> Result<(ReplayFormat, Compression), ReplayError> {
+    let format = read_format(input)?;
+    let compression = match format {
+        ReplayFormat::Scr => {
+            // SC:R replays have an additional u32 after the replay id.
+            input.read_u32::<LittleEndian>()?;
+            Compression::Zlib
+        }
+        ReplayFormat::Legacy => Compression::Implode,
+    };
+    Ok((format, compression))
+}
+
+/// Reads a section which contains the size of the next section.
+fn read_section_size<R: Read>(
+    input: &mut R,
+    compression: Compression,
+) -> Result<usize, ReplayError> {
+    let data = read_section(input, 4, compression)?;
+    let size = LittleEndian::read_u32(&data) as usize;
+    if size > MAX_SECTION_SIZE {
+        return Err(ReplayError::Corrupted("Section is too large"));
+    }
+    Ok(size)
+}
+
+fn read_format<R: Read>(input: &mut R) -> Result<ReplayFormat, ReplayError> {
+    let id = match read_section(input, 4, Compression::Unknown) {
+        Ok(o) => o,
+        // Something that doesn't have even the first section can't be a replay
+        Err(ReplayError::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            return Err(ReplayError::NotAReplay);
+        }
+        Err(e) => return Err(e),
+    };
+    if &id[..] == REPLAY_ID_SCR {
+        Ok(ReplayFormat::Scr)
+    } else if &id[..] == REPLAY_ID_LEGACY {
+        Ok(ReplayFormat::Legacy)
+    } else {
+        Err(ReplayError::NotAReplay)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Compression {
+    /// Used for the replay id section, which is always small enough to be stored
+    /// uncompressed.
+    Unknown,
+    Zlib,
+    Implode,
+}
+
+fn read_section<R: Read>(
+    input: &mut R,
+    size: usize,
+    compression: Compression,
+) -> Result<Vec<u8>, ReplayError> {
+    let _checksum = input.read_u32::<LittleEndian>()?;
+    let chunk_count = input.read_u32::<LittleEndian>()? as usize;
+    if chunk_count != (size + MAX_CHUNK_SIZE - 1) / MAX_CHUNK_SIZE {
+        return Err(ReplayError::Corrupted("Unexpected chunk count"));
+    }
+    let mut out = Vec::with_capacity(size);
+    for _ in 0..chunk_count {
+        let out_len = (size - out.len()).min(MAX_CHUNK_SIZE);
+        let chunk_len = input.read_u32::<LittleEndian>()? as usize;
+        if chunk_len > out_len {
+            return Err(ReplayError::Corrupted("Chunk is too large"));
+        }
+        let mut chunk = vec![0u8; chunk_len];
+        input.read_exact(&mut chunk)?;
+        if chunk_len == out_len {
+            out.extend_from_slice(&chunk);
+            continue;
+        }
+        match compression {
+            Compression::Zlib => {
+                let start = out.len();
+                out.resize(start + out_len, 0);
+                ZlibDecoder::new(&chunk[..])
+                    .read_exact(&mut out[start..])
+                    .map_err(|_| ReplayError::Corrupted("Invalid compressed data"))?;
+            }
+            Compression::Implode => {
+                let data = implode::explode(&chunk, out_len)
+                    .ok()
+                    .filter(|x| x.len() == out_len)
+                    .ok_or(ReplayError::Corrupted("Invalid compressed data"))?;
+                out.extend_from_slice(&data);
+            }
+            Compression::Unknown => return Err(ReplayError::UnsupportedCompression),
+        }
+    }
+    Ok(out)
+}
+
+fn parse_header_data(format: ReplayFormat, data: &[u8]) -> ReplayHeader {
+    let players = data[0xa1..][..PLAYER_COUNT * PLAYER_RECORD_SIZE]
+        .chunks_exact(PLAYER_RECORD_SIZE)
+        .filter_map(|record| {
+            let player_type = record[0x8];
+            let is_computer = match player_type {
+                bw::PLAYER_TYPE_HUMAN => false,
+                bw::PLAYER_TYPE_COMPUTER | bw::PLAYER_TYPE_LOBBY_COMPUTER => true,
+                _ => return None,
+            };
+            let race = match record[0x9] {
+                bw::RACE_ZERG => Some(Race::Zerg),
+                bw::RACE_TERRAN => Some(Race::Terran),
+                bw::RACE_PROTOSS => Some(Race::Protoss),
+                _ => None,
+            };
+            Some(ReplayPlayer {
+                player_id: bw::GamePlayerId(record[0x4]),
+                name: read_string(&record[0xb..][..25]),
+                race,
+                team: record[0xa],
+                is_computer,
+            })
+        })
+        .collect();
+    ReplayHeader {
+        format,
+        frames: LittleEndian::read_u32(&data[0x1..]),
+        game_title: read_string(&data[0x18..][..28]),
+        map_name: read_string(&data[0x61..][..26]),
+        map_tile_width: LittleEndian::read_u16(&data[0x34..]),
+        map_tile_height: LittleEndian::read_u16(&data[0x36..]),
+        players,
+    }
+}
+
+/// Reads a null-terminated string, see `decode_bw_text`.
+fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|&x| x == 0).unwrap_or(data.len());
+    decode_bw_text(&data[..end])
+}
+
+/// Code page of Korean Windows, which most non-Latin 1.16.1 names were written in.
+const CODEPAGE_EUC_KR: u32 = 949;
+
+/// SC:R writes text as UTF-8, while 1.16.1 used the system code page. There's no way
+/// to know what the code page was, so text that isn't valid UTF-8 is decoded as EUC-KR
+/// if it is only Korean and ASCII, and as Windows-1252 otherwise.
+fn decode_bw_text(data: &[u8]) -> String {
+    if let Ok(text) = std::str::from_utf8(data) {
+        return text.into();
+    }
+    let is_korean = |text: &String| {
+        text.chars().all(|x| x.is_ascii() || ('\u{ac00}'..='\u{d7a3}').contains(&x))
+    };
+    match crate::windows::string_from_codepage(data, CODEPAGE_EUC_KR).filter(is_korean) {
+        Some(text) => text,
+        None => data.iter().map(|&x| windows_1252_char(x)).collect(),
+    }
+}
+
+/// The command data consists of `{ u32 frame, u8 length, u8 data[length] }` blocks,
+/// where the data is a list of commands, each prefixed with the storm id of the player
+/// who sent it.
+fn parse_chat(mut data: &[u8]) -> Vec<ReplayChat> {
+    let mut result = Vec::new();
+    while data.len() >= 5 {
+        let frame = LittleEndian::read_u32(data);
+        let length = data[4] as usize;
+        let mut block = match data.get(5..5 + length) {
+            Some(s) => s,
+            None => break,
+        };
+        data = &data[5 + length..];
+        while let Some((&storm_id, rest)) = block.split_first() {
+            let command = match commands::iter_commands(rest, commands::COMMAND_LENGTHS).next() {
+                Some(s) => s,
+                None => return result,
+            };
+            block = &rest[command.len()..];
+            // { u8 id, u8 sender, u8 text[80] }
+            if let [commands::id::REPLAY_CHAT, _, ref text @ ..] = *command {
+                let text = decode_chat_text(text);
+                if (storm_id as usize) < PLAYER_COUNT && !text.is_empty() {
+                    result.push(ReplayChat {
+                        frame,
+                        storm_id: StormPlayerId(storm_id),
+                        text,
+                    });
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Decodes like `read_string`, and removes color codes and other control characters.
+fn decode_chat_text(data: &[u8]) -> String {
+    let text = read_string(data);
+    text.chars().filter(|x| !x.is_control()).collect::<String>().trim().into()
+}
+
+fn windows_1252_char(byte: u8) -> char {
+    const HIGH_CHARS: [char; 0x20] = [
+        '\u{20ac}', '\u{fffd}', '\u{201a}', '\u{0192}', '\u{201e}', '\u{2026}', '\u{2020}',
+        '\u{2021}', '\u{02c6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{fffd}',
+        '\u{017d}', '\u{fffd}', '\u{fffd}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}',
+        '\u{2022}', '\u{2013}', '\u{2014}', '\u{02dc}', '\u{2122}', '\u{0161}', '\u{203a}',
+        '\u{0153}', '\u{fffd}', '\u{017e}', '\u{0178}',
+    ];
+    match byte {
+        0x80..=0x9f => HIGH_CHARS[byte as usize - 0x80],
+        // The rest match Latin-1, which are the first 256 code points.
+        _ => byte as char,
+    }
+}
+
+/// Creates a replay with only the header, which is compressed as `format` would
+/// compress it if `compress_header` is set.
+#[cfg(test)]
+fn sample_replay(format: ReplayFormat, compress_header: bool) -> Vec<u8> {
+    use byteorder::WriteBytesExt;
+
+    let mut header = vec![0u8; HEADER_SIZE];
+    header[0] = 1;
+    LittleEndian::write_u32(&mut header[0x1..], 12345);
+    header[0x18..][..9].copy_from_slice(b"Test game");
+    LittleEndian::write_u16(&mut header[0x34..], 128);
+    LittleEndian::write_u16(&mut header[0x36..], 96);
+    header[0x61..][..15].copy_from_slice(b"Fighting Spirit");
+    let players: [(u8, u8, u8, &[u8]); 3] = [
+        (bw::PLAYER_TYPE_HUMAN, bw::RACE_PROTOSS, 1, b"tec27"),
+        (bw::PLAYER_TYPE_COMPUTER, bw::RACE_ZERG, 2, b"Computer"),
+        (bw::PLAYER_TYPE_HUMAN, bw::RACE_RANDOM, 1, b"random\xe9"),
+    ];
+    for (i, &(ty, race, team, name)) in players.iter().enumerate() {
+        let record = &mut header[0xa1 + i * PLAYER_RECORD_SIZE..][..PLAYER_RECORD_SIZE];
+        record[0x4] = i as u8;
+        record[0x8] = ty;
+        record[0x9] = race;
+        record[0xa] = team;
+        record[0xb..][..name.len()].copy_from_slice(name);
+    }
+
+    let mut out = Vec::new();
+    let compression = match format {
+        ReplayFormat::Scr => {
+            write_test_section(&mut out, REPLAY_ID_SCR, None);
+            out.write_u32::<LittleEndian>(0).unwrap();
+            Compression::Zlib
+        }
+        ReplayFormat::Legacy => {
+            write_test_section(&mut out, REPLAY_ID_LEGACY, None);
+            Compression::Implode
+        }
+    };
+    write_test_section(&mut out, &header, Some(compression).filter(|_| compress_header));
+    out
+}
+
+#[cfg(test)]
+fn write_test_section(out: &mut Vec<u8>, data: &[u8], compression: Option<Compression>) {
+    use byteorder::WriteBytesExt;
+    use std::io::Write;
+
+    let chunks = data.chunks(MAX_CHUNK_SIZE).collect::<Vec<_>>();
+    out.write_u32::<LittleEndian>(0).unwrap();
+    out.write_u32::<LittleEndian>(chunks.len() as u32).unwrap();
+    for chunk in chunks {
+        let compressed = match compression {
+            Some(Compression::Zlib) => {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(Vec::new(), Default::default());
+                encoder.write_all(chunk).unwrap();
+                encoder.finish().unwrap()
+            }
+            Some(Compression::Implode) => implode::implode_runs(chunk),
+            _ => chunk.to_vec(),
+        };
+        // BW stores chunks that don't get smaller as is.
+        let chunk = if compressed.len() < chunk.len() { compressed } else { chunk.to_vec() };
+        out.write_u32::<LittleEndian>(chunk.len() as u32).unwrap();
+        out.extend_from_slice(&chunk);
+    }
+}
+
+#[test]
+fn parse_sample_header() {
+    let formats = [ReplayFormat::Scr, ReplayFormat::Legacy];
+    let cases = formats.iter().flat_map(|&format| vec![(format, true), (format, false)]);
+    for (format, compress) in cases {
+        let data = sample_replay(format, compress);
+        let header = parse_header_from(&data[..]).unwrap();
+        assert_eq!(header.format, format);
+        assert_eq!(header.frames, 12345);
+        assert_eq!(header.game_title, "Test game");
+        assert_eq!(header.map_name, "Fighting Spirit");
+        assert_eq!((header.map_tile_width, header.map_tile_height), (128, 96));
+        assert_eq!(header.players.len(), 3);
+        assert_eq!(header.players[0].name, "tec27");
+        assert_eq!(header.players[0].race, Some(Race::Protoss));
+        assert_eq!(header.players[0].team, 1);
+        assert!(!header.players[0].is_computer);
+        assert_eq!(header.players[1].player_id, bw::GamePlayerId(1));
+        assert_eq!(header.players[1].race, Some(Race::Zerg));
+        assert!(header.players[1].is_computer);
+        assert_eq!(header.players[2].name, "random\u{e9}");
+        assert_eq!(header.players[2].race, None);
+    }
+}
+
+#[test]
+fn parse_invalid_replays() {
+    assert!(matches!(parse_header_from(&b""[..]), Err(ReplayError::NotAReplay)));
+    let mut data = sample_replay(ReplayFormat::Scr, true);
+    data[12..16].copy_from_slice(b"abcd");
+    assert!(matches!(parse_header_from(&data[..]), Err(ReplayError::NotAReplay)));
+    data[12..16].copy_from_slice(REPLAY_ID_LEGACY);
+    assert_eq!(read_format(&mut &data[..]).unwrap(), ReplayFormat::Legacy);
+    let data = sample_replay(ReplayFormat::Scr, true);
+    assert!(matches!(
+        parse_header_from(&data[..data.len() - 10]),
+        Err(ReplayError::Io(_)),
+    ));
+}
+
+#[test]
+fn extract_embedded_map() {
+    let commands = [0x0u8, 0x0, 0x0, 0x0, 0x1, 0x0, 0x5, 0x0];
+    // Large enough to need multiple chunks, and compressible
+    let map = (0..0x3000u32).map(|x| (x / 7) as u8).collect::<Vec<_>>();
+    let mut data = sample_replay(ReplayFormat::Scr, true);
+    write_test_section(&mut data, &(commands.len() as u32).to_le_bytes(), None);
+    write_test_section(&mut data, &commands, None);
+    let without_map = data.clone();
+    write_test_section(&mut data, &(map.len() as u32).to_le_bytes(), None);
+    write_test_section(&mut data, &map, Some(Compression::Zlib));
+    assert_eq!(read_map_data(&data[..]).unwrap(), map);
+
+    assert!(matches!(read_map_data(&without_map[..]), Err(ReplayError::MapNotEmbedded)));
+    let mut empty_map = without_map.clone();
+    write_test_section(&mut empty_map, &0u32.to_le_bytes(), None);
+    assert!(matches!(read_map_data(&empty_map[..]), Err(ReplayError::MapNotEmbedded)));
+}
+
+#[test]
+fn parse_replay_chat() {
+    fn chat_command(storm_id: u8, text: &[u8]) -> Vec<u8> {
+        let mut command = vec![storm_id, commands::id::REPLAY_CHAT, storm_id];
+        command.extend_from_slice(text);
+        command.resize(83, 0);
+        command
+    }
+    let mut data = Vec::new();
+    // Frame 10: Stop from player 0, then chat from player 1
+    data.extend_from_slice(&10u32.to_le_bytes());
+    let mut block = vec![0, 0x1a, 0x00];
+    block.extend_from_slice(&chat_command(1, b"gl hf"));
+    data.push(block.len() as u8);
+    data.extend_from_slice(&block);
+    // Frame 500: Windows-1252 text with a color code, and an empty message
+    data.extend_from_slice(&500u32.to_le_bytes());
+    let mut block = chat_command(0, b"\x03caf\xe9 \x93gg\x94");
+    block.extend_from_slice(&chat_command(0, b"  "));
+    data.push(block.len() as u8);
+    data.extend_from_slice(&block);
+    let chat = parse_chat(&data);
+    assert_eq!(chat.len(), 2);
+    assert_eq!(chat[0].frame, 10);
+    assert_eq!(chat[0].storm_id, StormPlayerId(1));
+    assert_eq!(chat[0].text, "gl hf");
+    assert_eq!(chat[1].frame, 500);
+    assert_eq!(chat[1].text, "caf\u{e9} \u{201c}gg\u{201d}");
+
+    // Truncated block keeps what was read before it
+    let mut truncated = data.clone();
+    truncated.extend_from_slice(&600u32.to_le_bytes());
+    truncated.push(83);
+    truncated.extend_from_slice(&chat_command(1, b"lost")[..40]);
+    assert_eq!(parse_chat(&truncated), chat);
+    // Unknown command stops parsing
+    let mut garbage = data.clone();
+    garbage.extend_from_slice(&600u32.to_le_bytes());
+    garbage.extend_from_slice(&[2, 0, 0x16]);
+    garbage.extend_from_slice(&700u32.to_le_bytes());
+    garbage.push(83);
+    garbage.extend_from_slice(&chat_command(1, b"unreachable"));
+    assert_eq!(parse_chat(&garbage), chat);
+}
+
+#[test]
+fn read_non_utf8_strings() {
+    assert_eq!(read_string(b"Flash\0garbage"), "Flash");
+    assert_eq!(read_string("\u{d55c}\u{ae00}".as_bytes()), "\u{d55c}\u{ae00}");
+    // EUC-KR
+    assert_eq!(read_string(b"\xc7\xd1\xb1\xdb 1\0"), "\u{d55c}\u{ae00} 1");
+    // Windows-1252, which isn't valid EUC-KR
+    assert_eq!(read_string(b"caf\xe9\0"), "caf\u{e9}");
+    // Valid EUC-KR, but Hanja instead of Hangul
+    assert_eq!(read_string(b"\xca\xa1"), "\u{ca}\u{a1}");
+}
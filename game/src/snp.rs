@@ -1,11 +1,14 @@
+use std::collections::HashMap;
 use std::mem;
 use std::net::Ipv4Addr;
 use std::ptr::null_mut;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use bytes::Bytes;
 use lazy_static::lazy_static;
 use libc::{c_void, sockaddr};
+use serde::Deserialize;
 use winapi::shared::ntdef::HANDLE;
 use winapi::shared::ws2def::{AF_INET, SOCKADDR_IN};
 use winapi::um::synchapi::SetEvent;
@@ -15,6 +18,12 @@ use crate::bw;
 use crate::game_thread::{send_game_msg_to_async, GameThreadMessage};
 use crate::windows::{OwnedHandle};
 
+#[cfg(test)]
+mod loopback;
+pub mod wire_format;
+
+pub use self::wire_format::{WireFormat, WireFormatKind};
+
 // 'SBAT'
 pub const PROVIDER_ID: u32 = 0x53424154;
 // min-MTU - (rally-point-overhead) - (max-IP-header-size + udp-header-size)
@@ -89,6 +98,123 @@ struct State {
     spoofed_game_dirty: bool,
     current_client_info: Option<bw::ClientInfo>,
     messages: Vec<ReceivedMessage>,
+    abuse_limits: NetAbuseLimits,
+    /// Storm ids of the peers, keyed by the address that their packets come from.
+    /// Set by the network manager once players have joined, see `set_peer_storm_ids`.
+    peer_storm_ids: HashMap<Ipv4Addr, bw::StormPlayerId>,
+    receive_rates: HashMap<Ipv4Addr, RateWindow>,
+    peer_timings: HashMap<bw::StormPlayerId, PeerTiming>,
+}
+
+/// Gap between packets from a peer that means that a turn arrived late.
+/// During a game BW sends turns to every peer several times per second,
+/// even if the player isn't doing anything.
+const LATE_TURN_GAP: Duration = Duration::from_millis(300);
+/// Gap that is long enough that the turn most likely had to be resent.
+const DROPPED_TURN_GAP: Duration = Duration::from_millis(1000);
+const CONNECTION_QUALITY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Tracks packet arrival times of a peer for `GameThreadMessage::ConnectionQuality`
+/// and `peers_caught_up_after`.
+struct PeerTiming {
+    last_packet: Instant,
+    /// Time between the latest packet and the one before it.
+    previous_gap: Duration,
+    last_report: Instant,
+    late_turns: u32,
+    dropped_turns: u32,
+}
+
+impl PeerTiming {
+    fn new(now: Instant) -> PeerTiming {
+        PeerTiming {
+            last_packet: now,
+            previous_gap: Duration::from_secs(0),
+            last_report: now,
+            late_turns: 0,
+            dropped_turns: 0,
+        }
+    }
+
+    /// Returns `(late_turns, dropped_turns)` if it is time to report them.
+    fn packet_received(&mut self, now: Instant) -> Option<(u32, u32)> {
+        let gap = now.duration_since(self.last_packet);
+        self.last_packet = now;
+        self.previous_gap = gap;
+        if gap >= DROPPED_TURN_GAP {
+            self.dropped_turns = self.dropped_turns.saturating_add(1);
+        } else if gap >= LATE_TURN_GAP {
+            self.late_turns = self.late_turns.saturating_add(1);
+        }
+        if now.duration_since(self.last_report) >= CONNECTION_QUALITY_INTERVAL {
+            self.last_report = now;
+            Some((self.late_turns, self.dropped_turns))
+        } else {
+            None
+        }
+    }
+}
+
+struct RateWindow {
+    start: Instant,
+    packets: u32,
+    /// Abuse is only reported once per window to avoid flooding the game thread channel.
+    abuse_reported: bool,
+}
+
+/// Limits for packets received from a single peer. Packets exceeding these
+/// are dropped and reported with `GameThreadMessage::NetAbuse`.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NetAbuseLimits {
+    pub max_packet_size: usize,
+    pub max_packets_per_second: u32,
+}
+
+impl Default for NetAbuseLimits {
+    fn default() -> NetAbuseLimits {
+        NetAbuseLimits {
+            max_packet_size: SNP_PACKET_SIZE as usize,
+            // Normal games send less than a hundred packets per second to each peer,
+            // this leaves plenty of room for resends.
+            max_packets_per_second: 1000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NetAbuseReason {
+    Oversized(usize),
+    RateLimited,
+}
+
+pub fn set_abuse_limits(limits: NetAbuseLimits) {
+    with_state(|state| state.abuse_limits = limits);
+}
+
+/// Once a peer's storm id is known, packets from its address that claim to be
+/// from someone else are dropped.
+pub fn set_peer_storm_ids(storm_ids: HashMap<Ipv4Addr, bw::StormPlayerId>) {
+    with_state(|state| state.peer_storm_ids = storm_ids);
+}
+
+enum DroppedPacket {
+    WrongStormId,
+    /// Whether the abuse should be reported.
+    Abuse(NetAbuseReason, bool),
+}
+
+/// Forgets the previous game's lobby and peer statistics. Abuse limits are set again
+/// from the next game's setup info, so they are reset too.
+pub fn reset_for_next_game() {
+    with_state(|state| {
+        state.spoofed_game = None;
+        state.spoofed_game_dirty = false;
+        state.abuse_limits = NetAbuseLimits::default();
+        state.peer_storm_ids.clear();
+        state.receive_rates.clear();
+        state.peer_timings.clear();
+    });
 }
 
 lazy_static! {
@@ -98,9 +224,29 @@ lazy_static! {
         spoofed_game_dirty: false,
         current_client_info: None,
         messages: Vec::with_capacity(32),
+        abuse_limits: NetAbuseLimits::default(),
+        peer_storm_ids: HashMap::new(),
+        receive_rates: HashMap::new(),
+        peer_timings: HashMap::new(),
     });
 }
 
+/// Peers that sent nothing for most of `stall` before their latest packet.
+///
+/// Meant to be called when the game continues after having waited `stall` for turns;
+/// the peers that it waited for are the ones whose packets only arrived again now.
+/// Peers that got dropped instead haven't sent anything, so they aren't included.
+pub fn peers_caught_up_after(stall: Duration) -> Vec<bw::StormPlayerId> {
+    with_state(|state| {
+        state
+            .peer_timings
+            .iter()
+            .filter(|(_, timing)| timing.previous_gap >= stall / 2)
+            .map(|(&storm_id, _)| storm_id)
+            .collect()
+    })
+}
+
 fn with_state<F: FnOnce(&mut State) -> R, R>(func: F) -> R {
     let mut state = STATE.lock().unwrap();
     func(&mut state)
@@ -177,10 +323,96 @@ pub struct SendMessages {
 
 impl SendMessages {
     pub fn send(&self, message: ReceivedMessage) {
-        with_state(|state| {
-            state.messages.push(message);
+        let from = message.from;
+        // Lobby traffic is irregular, so turn timing is only tracked during the game.
+        let in_game = crate::game_thread::game_loop_running();
+        // Storm packet header is
+        // checksum, length, sent and received sequence (u16 each), class, command,
+        // sending player's storm id, flags.
+        // The peer can write anything to the header, so packets are accounted to the
+        // address they came from, and the id is only checked against it.
+        let claimed_storm_id = match message.data.get(10) {
+            Some(&id) => bw::StormPlayerId(id),
+            None => {
+                // Storm would ignore this anyway.
+                debug!("Dropping packet without storm header from {}", from);
+                return;
+            }
+        };
+        let mut quality_report = None;
+        let (sender_storm_id, result) = with_state(|state| {
+            let sender_storm_id = state.peer_storm_ids.get(&from).copied();
+            if sender_storm_id.map(|x| x != claimed_storm_id).unwrap_or(false) {
+                return (sender_storm_id, Err(DroppedPacket::WrongStormId));
+            }
+            let limits = state.abuse_limits;
+            let now = Instant::now();
+            let window = state.receive_rates.entry(from).or_insert_with(|| RateWindow {
+                start: now,
+                packets: 0,
+                abuse_reported: false,
+            });
+            if now.duration_since(window.start) >= Duration::from_secs(1) {
+                window.start = now;
+                window.packets = 0;
+                window.abuse_reported = false;
+            }
+            window.packets = window.packets.saturating_add(1);
+            let abuse = if message.data.len() > limits.max_packet_size {
+                Some(NetAbuseReason::Oversized(message.data.len()))
+            } else if window.packets > limits.max_packets_per_second {
+                Some(NetAbuseReason::RateLimited)
+            } else {
+                None
+            };
+            match abuse {
+                Some(reason) => {
+                    let report = !window.abuse_reported;
+                    window.abuse_reported = true;
+                    (sender_storm_id, Err(DroppedPacket::Abuse(reason, report)))
+                }
+                None => {
+                    // Turns are only counted for peers whose address is known to belong
+                    // to a storm id.
+                    if let (true, Some(storm_id)) = (in_game, sender_storm_id) {
+                        let timing = state
+                            .peer_timings
+                            .entry(storm_id)
+                            .or_insert_with(|| PeerTiming::new(now));
+                        quality_report = timing.packet_received(now).map(|x| (storm_id, x));
+                    }
+                    state.messages.push(message);
+                    (sender_storm_id, Ok(()))
+                }
+            }
         });
-        (self.receive_callback)();
+        if let Some((storm_id, (late_turns, dropped_turns))) = quality_report {
+            send_game_msg_to_async(GameThreadMessage::ConnectionQuality {
+                storm_id,
+                late_turns,
+                dropped_turns,
+            });
+        }
+        match result {
+            Ok(()) => (self.receive_callback)(),
+            Err(DroppedPacket::WrongStormId) => {
+                let claimed = claimed_storm_id.0;
+                debug!("Dropping packet from {} claiming to be player {}", from, claimed);
+            }
+            Err(DroppedPacket::Abuse(reason, report)) => {
+                if !report {
+                    return;
+                }
+                match sender_storm_id {
+                    Some(storm_id) => {
+                        warn!("Dropping packets from player {}: {:?}", storm_id.0, reason);
+                        send_game_msg_to_async(GameThreadMessage::NetAbuse { storm_id, reason });
+                    }
+                    // Not joined yet, so there's no player to report.
+                    None => warn!("Dropping packets from {}: {:?}", from, reason),
+                }
+            }
+        }
     }
 }
 
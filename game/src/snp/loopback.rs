@@ -0,0 +1,112 @@
+//! In-process transport for SNP packets, so that code exchanging them can be tested
+//! without sockets or rally-point.
+//!
+//! Each endpoint handles `SnpMessage::Send` the way `NetworkManager` does, except that the
+//! packets are delivered directly to the other endpoints' queues. Delivery is immediate
+//! and in order, which keeps tests deterministic.
+//!
+//! This isn't selectable as a transport for actual games: SNP state is global to the
+//! process, so one game process can only ever be a single endpoint.
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+
+use super::{ReceivedMessage, SnpMessage};
+
+#[derive(Clone, Default)]
+pub struct LoopbackTransport {
+    endpoints: Arc<Mutex<HashMap<Ipv4Addr, Sender<ReceivedMessage>>>>,
+}
+
+pub struct LoopbackEndpoint {
+    address: Ipv4Addr,
+    transport: LoopbackTransport,
+    receive: Receiver<ReceivedMessage>,
+}
+
+impl LoopbackTransport {
+    pub fn new() -> LoopbackTransport {
+        LoopbackTransport::default()
+    }
+
+    /// Creates an endpoint that receives packets sent to `address`.
+    /// Creating a second endpoint for the same address replaces the first one.
+    pub fn endpoint(&self, address: Ipv4Addr) -> LoopbackEndpoint {
+        let (send, receive) = mpsc::channel();
+        self.endpoints.lock().unwrap().insert(address, send);
+        LoopbackEndpoint {
+            address,
+            transport: self.clone(),
+            receive,
+        }
+    }
+}
+
+impl LoopbackEndpoint {
+    /// Sends `data` to each of `targets`. Targets without an endpoint are skipped,
+    /// like packets to players without a route. Returns amount of packets delivered.
+    pub fn send(&self, targets: &[Ipv4Addr], data: &[u8]) -> usize {
+        let data = Bytes::copy_from_slice(data);
+        let endpoints = self.transport.endpoints.lock().unwrap();
+        let mut delivered = 0;
+        for send in targets.iter().filter_map(|target| endpoints.get(target)) {
+            let message = ReceivedMessage {
+                from: self.address,
+                data: data.clone(),
+            };
+            // Fails if the endpoint was dropped.
+            if send.send(message).is_ok() {
+                delivered += 1;
+            }
+        }
+        delivered
+    }
+
+    /// Handles a message that SNP sent to the network task.
+    pub fn handle_snp_message(&self, message: SnpMessage) {
+        match message {
+            SnpMessage::Send(targets, data) => {
+                self.send(&targets, &data);
+            }
+            SnpMessage::CreateNetworkHandler(_) | SnpMessage::Destroy => (),
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<ReceivedMessage> {
+        self.receive.try_recv().ok()
+    }
+}
+
+#[test]
+fn exchange_turns() {
+    let transport = LoopbackTransport::new();
+    let host_address = Ipv4Addr::new(10, 27, 27, 0);
+    let peer_address = Ipv4Addr::new(10, 27, 27, 1);
+    let unknown_address = Ipv4Addr::new(10, 27, 27, 7);
+    let host = transport.endpoint(host_address);
+    let peer = transport.endpoint(peer_address);
+
+    for turn in 0u8..4 {
+        host.handle_snp_message(SnpMessage::Send(vec![peer_address], vec![turn, 0x5]));
+        assert_eq!(peer.send(&[host_address, unknown_address], &[turn, 0x6]), 1);
+    }
+    let mut turns = Vec::new();
+    while let Some(message) = peer.try_recv() {
+        assert_eq!(message.from, host_address);
+        turns.push(message.data);
+    }
+    let expected = (0u8..4)
+        .map(|x| Bytes::from(vec![x, 0x5]))
+        .collect::<Vec<_>>();
+    assert_eq!(turns, expected);
+    for turn in 0u8..4 {
+        let message = host.try_recv().unwrap();
+        assert_eq!(message.from, peer_address);
+        assert_eq!(&message.data[..], &[turn, 0x6]);
+    }
+    assert!(host.try_recv().is_none());
+}
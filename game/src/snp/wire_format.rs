@@ -0,0 +1,155 @@
+//! Encoding of the storm packets that are exchanged with other players.
+//!
+//! `Raw` sends the packets exactly as storm gives them to SNP, which is what
+//! ShieldBattery clients have always done. `Framed` wraps them in a small versioned
+//! header and checksum, so that other BW-compatible clients can validate what they
+//! receive without knowing storm's packet layout. All players of a game have to use
+//! the same format.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use quick_error::quick_error;
+use serde::Deserialize;
+
+pub trait WireFormat: Send + Sync {
+    fn encode(&self, data: &[u8]) -> Bytes;
+    fn decode(&self, data: Bytes) -> Result<Bytes, WireFormatError>;
+}
+
+quick_error! {
+    #[derive(Debug, Clone, Eq, PartialEq)]
+    pub enum WireFormatError {
+        TooShort(len: usize) {
+            display("Packet is too short ({} bytes)", len)
+        }
+        UnsupportedVersion(version: u8) {
+            display("Unsupported packet version {}", version)
+        }
+        LengthMismatch(expected: usize, actual: usize) {
+            display("Packet length {} doesn't match header length {}", actual, expected)
+        }
+        BadChecksum {
+            display("Packet checksum doesn't match")
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum WireFormatKind {
+    Raw,
+    Framed,
+}
+
+impl Default for WireFormatKind {
+    fn default() -> WireFormatKind {
+        WireFormatKind::Raw
+    }
+}
+
+impl WireFormatKind {
+    pub fn format(self) -> Arc<dyn WireFormat> {
+        match self {
+            WireFormatKind::Raw => Arc::new(Raw),
+            WireFormatKind::Framed => Arc::new(Framed),
+        }
+    }
+}
+
+pub struct Raw;
+
+impl WireFormat for Raw {
+    fn encode(&self, data: &[u8]) -> Bytes {
+        Bytes::copy_from_slice(data)
+    }
+
+    fn decode(&self, data: Bytes) -> Result<Bytes, WireFormatError> {
+        Ok(data)
+    }
+}
+
+/// Version 1 of the format, all integers are little-endian:
+///
+/// | Offset  | Size | Contents                          |
+/// |---------|------|-----------------------------------|
+/// | 0       | 1    | Version, 1                        |
+/// | 1       | 2    | Payload length `n`                |
+/// | 3       | n    | Storm packet                      |
+/// | 3 + n   | 2    | Fletcher-16 checksum of the packet |
+pub struct Framed;
+
+const FRAMED_VERSION: u8 = 1;
+const FRAMED_HEADER_SIZE: usize = 3;
+const FRAMED_OVERHEAD: usize = FRAMED_HEADER_SIZE + 2;
+
+fn fletcher16(data: &[u8]) -> u16 {
+    let (mut sum1, mut sum2) = (0u16, 0u16);
+    for &byte in data {
+        sum1 = (sum1 + byte as u16) % 255;
+        sum2 = (sum2 + sum1) % 255;
+    }
+    (sum2 << 8) | sum1
+}
+
+impl WireFormat for Framed {
+    fn encode(&self, data: &[u8]) -> Bytes {
+        // Storm packets are limited to `SNP_PACKET_SIZE`, so the length always fits.
+        debug_assert!(data.len() <= u16::max_value() as usize);
+        let mut out = Vec::with_capacity(data.len() + FRAMED_OVERHEAD);
+        out.push(FRAMED_VERSION);
+        out.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        out.extend_from_slice(data);
+        out.extend_from_slice(&fletcher16(data).to_le_bytes());
+        out.into()
+    }
+
+    fn decode(&self, data: Bytes) -> Result<Bytes, WireFormatError> {
+        if data.len() < FRAMED_OVERHEAD {
+            return Err(WireFormatError::TooShort(data.len()));
+        }
+        if data[0] != FRAMED_VERSION {
+            return Err(WireFormatError::UnsupportedVersion(data[0]));
+        }
+        let length = u16::from_le_bytes([data[1], data[2]]) as usize;
+        if data.len() != length + FRAMED_OVERHEAD {
+            return Err(WireFormatError::LengthMismatch(length, data.len() - FRAMED_OVERHEAD));
+        }
+        let payload = data.slice(FRAMED_HEADER_SIZE..FRAMED_HEADER_SIZE + length);
+        let checksum = u16::from_le_bytes([data[data.len() - 2], data[data.len() - 1]]);
+        if checksum != fletcher16(&payload) {
+            return Err(WireFormatError::BadChecksum);
+        }
+        Ok(payload)
+    }
+}
+
+#[test]
+fn round_trip() {
+    let packets: &[&[u8]] = &[&[], &[0x5], &[0x0, 0xff, 0x12, 0x34, 0x56], &[0xab; 461]];
+    for &kind in &[WireFormatKind::Raw, WireFormatKind::Framed] {
+        let format = kind.format();
+        for &packet in packets {
+            let encoded = format.encode(packet);
+            assert_eq!(&format.decode(encoded).unwrap()[..], packet, "{:?}", kind);
+        }
+    }
+    let encoded = Framed.encode(&[0x1, 0x2]);
+    assert_eq!(&encoded[..], &[0x1, 0x2, 0x0, 0x1, 0x2, 0x3, 0x4]);
+}
+
+#[test]
+fn framed_rejects_invalid() {
+    let encoded = Framed.encode(&[0x10, 0x20, 0x30]);
+    let mut corrupted = encoded.to_vec();
+    corrupted[4] ^= 0x1;
+    assert_eq!(Framed.decode(corrupted.into()), Err(WireFormatError::BadChecksum));
+    assert_eq!(Framed.decode(encoded.slice(..6)), Err(WireFormatError::LengthMismatch(3, 1)));
+    assert_eq!(Framed.decode(Bytes::from_static(&[0x1, 0x0])), Err(WireFormatError::TooShort(2)));
+    let mut future_version = encoded.to_vec();
+    future_version[0] = 2;
+    assert_eq!(
+        Framed.decode(future_version.into()),
+        Err(WireFormatError::UnsupportedVersion(2)),
+    );
+}
@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use std::ffi::{OsStr, OsString};
 use std::io;
 use std::os::windows::ffi::{OsStrExt, OsStringExt};
@@ -20,6 +21,69 @@ pub fn winapi_str<T: AsRef<OsStr>>(input: T) -> Vec<u16> {
     buf
 }
 
+/// Decodes text that is in the system's ANSI codepage.
+pub fn string_from_ansi_codepage(input: &[u8]) -> String {
+    use winapi::um::stringapiset::MultiByteToWideChar;
+    use winapi::um::winnls::CP_ACP;
+
+    if input.is_empty() {
+        return String::new();
+    }
+    unsafe {
+        let length = MultiByteToWideChar(
+            CP_ACP,
+            0,
+            input.as_ptr() as *const i8,
+            input.len() as i32,
+            null_mut(),
+            0,
+        );
+        let mut buffer = vec![0u16; length as usize];
+        MultiByteToWideChar(
+            CP_ACP,
+            0,
+            input.as_ptr() as *const i8,
+            input.len() as i32,
+            buffer.as_mut_ptr(),
+            length,
+        );
+        String::from_utf16_lossy(&buffer)
+    }
+}
+
+/// Decodes text that is in `codepage`, returning `None` if it isn't valid in that codepage.
+pub fn string_from_codepage(input: &[u8], codepage: u32) -> Option<String> {
+    use winapi::um::stringapiset::MultiByteToWideChar;
+    use winapi::um::winnls::MB_ERR_INVALID_CHARS;
+
+    if input.is_empty() {
+        return Some(String::new());
+    }
+    unsafe {
+        let length = MultiByteToWideChar(
+            codepage,
+            MB_ERR_INVALID_CHARS,
+            input.as_ptr() as *const i8,
+            input.len() as i32,
+            null_mut(),
+            0,
+        );
+        if length == 0 {
+            return None;
+        }
+        let mut buffer = vec![0u16; length as usize];
+        MultiByteToWideChar(
+            codepage,
+            MB_ERR_INVALID_CHARS,
+            input.as_ptr() as *const i8,
+            input.len() as i32,
+            buffer.as_mut_ptr(),
+            length,
+        );
+        String::from_utf16(&buffer).ok()
+    }
+}
+
 // If the conversion was lossy, returns Err(lossy_result)
 pub fn ansi_codepage_cstring<T: AsRef<OsStr>>(input: T) -> Result<Vec<u8>, Vec<u8>> {
     use winapi::um::stringapiset::WideCharToMultiByte;
@@ -123,6 +187,23 @@ pub fn module_from_address(address: *mut c_void) -> Option<(OsString, HMODULE)>
     }
 }
 
+/// Returns link timestamp, image size and checksum from the PE header of a loaded module.
+///
+/// These are at same offsets in both 32- and 64-bit PE files.
+pub unsafe fn pe_header_info(handle: HMODULE) -> Option<(u32, u32, u32)> {
+    let base = handle as *const u8;
+    if base.is_null() || *(base as *const u16) != 0x5a4d {
+        return None;
+    }
+    let nt_headers = base.add(*(base.add(0x3c) as *const u32) as usize);
+    if *(nt_headers as *const u32) != 0x4550 {
+        return None;
+    }
+    let read_u32 = |offset: usize| (nt_headers.add(offset) as *const u32).read_unaligned();
+    // Signature (4 bytes) + IMAGE_FILE_HEADER (0x14 bytes) precede the optional header.
+    Some((read_u32(0x8), read_u32(0x18 + 0x38), read_u32(0x18 + 0x40)))
+}
+
 pub fn module_name(handle: HMODULE) -> Option<OsString> {
     unsafe {
         let mut buf_size = 128;
@@ -150,7 +231,13 @@ pub fn module_name(handle: HMODULE) -> Option<OsString> {
     }
 }
 
+thread_local! {
+    static SHOWING_MESSAGE_BOX: Cell<bool> = Cell::new(false);
+}
+
 pub fn message_box(caption: &str, msg: &str) {
+    SHOWING_MESSAGE_BOX.with(|x| x.set(true));
+    defer!(SHOWING_MESSAGE_BOX.with(|x| x.set(false)));
     unsafe {
         MessageBoxW(
             null_mut(),
@@ -161,6 +248,12 @@ pub fn message_box(caption: &str, msg: &str) {
     }
 }
 
+/// True while `message_box` is being called on this thread, so that the hooks which
+/// hide BW's message boxes can let ours through.
+pub fn is_showing_own_message_box() -> bool {
+    SHOWING_MESSAGE_BOX.with(|x| x.get())
+}
+
 pub fn load_library<T: AsRef<OsStr>>(name: T) -> Result<Library, io::Error> {
     use winapi::um::libloaderapi::LoadLibraryW;
     unsafe {